@@ -0,0 +1,253 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    time::Duration,
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::{
+    ArmedCommand, Bandwidth, BulkCommandRequest, BulkCommandResponse, ClientEvent, ClientSummary,
+    Command, CommandRequest, CommandResponse, ErrorResponse, FleetRollup, Input, InputRequest,
+    InputResponse, Latency, ServerStatus, TelemetrySample, Version,
+};
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// the server answered with an error status
+    Api(u16, ErrorResponse),
+    Transport(Box<ureq::Transport>),
+    Decode(std::io::Error),
+    /// an event in the event stream was not valid json
+    Event(serde_json::Error),
+}
+
+impl From<ureq::Error> for ClientError {
+    fn from(value: ureq::Error) -> Self {
+        match value {
+            ureq::Error::Status(status, response) => {
+                let error = response.into_json().unwrap_or_else(|error| ErrorResponse {
+                    error: error.to_string(),
+                });
+
+                ClientError::Api(status, error)
+            }
+            ureq::Error::Transport(transport) => ClientError::Transport(Box::new(transport)),
+        }
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(value: std::io::Error) -> Self {
+        ClientError::Decode(value)
+    }
+}
+
+/// blocking client for the pdtserver json api
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    base_url: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl ApiClient {
+    /// `base_url` is the web interface address, e.g. `http://pdt.lan:2040`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: None,
+            agent: ureq::Agent::new(),
+        }
+    }
+
+    pub fn with_token(self, token: impl Into<String>) -> Self {
+        Self {
+            token: Some(token.into()),
+            ..self
+        }
+    }
+
+    /// give up on requests the server does not answer within `timeout`
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+            ..self
+        }
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let request = self
+            .agent
+            .request(method, &format!("{}/api/v1{}", self.base_url, path));
+
+        match &self.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {token}")),
+            None => request,
+        }
+    }
+
+    fn json<T: DeserializeOwned>(response: ureq::Response) -> Result<T, ClientError> {
+        Ok(response.into_json()?)
+    }
+
+    pub fn clients(&self) -> Result<Vec<ClientSummary>, ClientError> {
+        Self::json(self.request("GET", "/clients").call()?)
+    }
+
+    pub fn client(&self, id: &str) -> Result<ClientSummary, ClientError> {
+        Self::json(self.request("GET", &format!("/clients/{id}")).call()?)
+    }
+
+    /// stored telemetry of a client, oldest first
+    pub fn telemetry(&self, id: &str) -> Result<Vec<TelemetrySample>, ClientError> {
+        Self::json(
+            self.request("GET", &format!("/clients/{id}/telemetry"))
+                .call()?,
+        )
+    }
+
+    /// bytes that went over the connection of a client
+    pub fn bandwidth(&self, id: &str) -> Result<Bandwidth, ClientError> {
+        Self::json(
+            self.request("GET", &format!("/clients/{id}/bandwidth"))
+                .call()?,
+        )
+    }
+
+    pub fn rollup(&self) -> Result<FleetRollup, ClientError> {
+        Self::json(self.request("GET", "/fleet/rollup").call()?)
+    }
+
+    pub fn latency(&self, id: &str) -> Result<Latency, ClientError> {
+        Self::json(
+            self.request("GET", &format!("/clients/{id}/latency"))
+                .call()?,
+        )
+    }
+
+    pub fn command(&self, id: &str, command: Command) -> Result<CommandResponse, ClientError> {
+        Self::json(
+            self.request("POST", &format!("/clients/{id}/commands"))
+                .send_json(CommandRequest { command })?,
+        )
+    }
+
+    /// send `command` to every client in `client_ids`, failures are reported
+    /// per client in the response rather than as an error
+    ///
+    /// destructive commands to several clients need the `confirmation` of
+    /// [`ApiClient::arm_bulk_command`]
+    pub fn bulk_command(
+        &self,
+        client_ids: Vec<String>,
+        command: Command,
+        confirmation: Option<String>,
+    ) -> Result<BulkCommandResponse, ClientError> {
+        Self::json(
+            self.request("POST", "/commands/bulk")
+                .send_json(BulkCommandRequest {
+                    client_ids,
+                    command,
+                    confirmation,
+                })?,
+        )
+    }
+
+    /// arm a destructive command for several clients, the first of the two
+    /// steps of sending it
+    pub fn arm_bulk_command(
+        &self,
+        client_ids: Vec<String>,
+        command: Command,
+    ) -> Result<ArmedCommand, ClientError> {
+        Self::json(
+            self.request("POST", "/commands/bulk/arm")
+                .send_json(BulkCommandRequest {
+                    client_ids,
+                    command,
+                    confirmation: None,
+                })?,
+        )
+    }
+
+    /// what [`ApiClient::bulk_command`] would answer, without sending
+    /// anything
+    pub fn preview_bulk_command(
+        &self,
+        client_ids: Vec<String>,
+        command: Command,
+    ) -> Result<BulkCommandResponse, ClientError> {
+        Self::json(
+            self.request("POST", "/commands/bulk?dry_run=true")
+                .send_json(BulkCommandRequest {
+                    client_ids,
+                    command,
+                    confirmation: None,
+                })?,
+        )
+    }
+
+    pub fn inputs(&self) -> Result<Vec<Input>, ClientError> {
+        Self::json(self.request("GET", "/inputs").call()?)
+    }
+
+    /// set an input, running the rules of the server waiting for it to
+    /// change to `value`
+    pub fn set_input(&self, name: &str, value: bool) -> Result<InputResponse, ClientError> {
+        Self::json(
+            self.request("PUT", &format!("/inputs/{name}"))
+                .send_json(InputRequest {
+                    value,
+                    confirmation: None,
+                })?,
+        )
+    }
+
+    pub fn status(&self) -> Result<ServerStatus, ClientError> {
+        Self::json(self.request("GET", "/status").call()?)
+    }
+
+    pub fn version(&self) -> Result<Version, ClientError> {
+        Self::json(self.request("GET", "/version").call()?)
+    }
+
+    /// client events as they happen, blocking until the next one arrives
+    pub fn events(&self) -> Result<Events, ClientError> {
+        let response = self
+            .request("GET", "/events")
+            .set("Accept", "text/event-stream")
+            .call()?;
+
+        Ok(Events {
+            reader: BufReader::new(response.into_reader()),
+        })
+    }
+}
+
+/// stream of client events, ends when the server closes the connection
+pub struct Events {
+    reader: BufReader<Box<dyn Read + Send + Sync + 'static>>,
+}
+
+impl Iterator for Events {
+    type Item = Result<ClientEvent, ClientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+
+            match self.reader.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(error) => return Some(Err(error.into())),
+            }
+
+            // keep alive comments and event names carry nothing of interest
+            if let Some(data) = line.trim_end().strip_prefix("data:") {
+                return Some(serde_json::from_str(data.trim()).map_err(ClientError::Event));
+            }
+        }
+    }
+}
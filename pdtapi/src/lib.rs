@@ -0,0 +1,471 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[cfg(feature = "client")]
+mod client;
+
+#[cfg(feature = "client")]
+pub use client::{ApiClient, ClientError, Events};
+
+fn unix_seconds(at: std::time::SystemTime) -> u64 {
+    at.duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// connection state of a client as seen by the server
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+}
+
+impl From<pdtcore::ConnectionState> for ConnectionState {
+    fn from(value: pdtcore::ConnectionState) -> Self {
+        match value {
+            pdtcore::ConnectionState::Connecting => ConnectionState::Connecting,
+            pdtcore::ConnectionState::Connected => ConnectionState::Connected,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub os: String,
+    pub os_version: String,
+    pub uptime: String,
+    // servers from before the inventory fields answer without them
+    #[serde(default)]
+    pub vendor: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub serial: String,
+    #[serde(default)]
+    pub chassis: String,
+    #[serde(default)]
+    pub virtualization: Virtualization,
+}
+
+impl From<pdtcore::DeviceInfo> for DeviceInfo {
+    fn from(value: pdtcore::DeviceInfo) -> Self {
+        Self {
+            name: value.name,
+            os: value.os,
+            os_version: value.os_version,
+            uptime: value.uptime,
+            vendor: value.vendor,
+            model: value.model,
+            serial: value.serial,
+            chassis: value.chassis,
+            virtualization: value.virtualization.into(),
+        }
+    }
+}
+
+/// what a client runs on
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Virtualization {
+    #[default]
+    Unknown,
+    Physical,
+    VirtualMachine,
+    Container,
+}
+
+impl From<pdtcore::Virtualization> for Virtualization {
+    fn from(value: pdtcore::Virtualization) -> Self {
+        match value {
+            pdtcore::Virtualization::Unknown => Virtualization::Unknown,
+            pdtcore::Virtualization::Physical => Virtualization::Physical,
+            pdtcore::Virtualization::VirtualMachine => Virtualization::VirtualMachine,
+            pdtcore::Virtualization::Container => Virtualization::Container,
+        }
+    }
+}
+
+/// socket type a client connected to the server through
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    Tcp,
+    Unix,
+}
+
+impl From<pdtcore::Transport> for Transport {
+    fn from(value: pdtcore::Transport) -> Self {
+        match value {
+            pdtcore::Transport::Tcp => Transport::Tcp,
+            pdtcore::Transport::Unix => Transport::Unix,
+        }
+    }
+}
+
+/// where a client connected from
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct NetworkInfo {
+    /// peer address, the listener name for unix sockets
+    pub address: String,
+    pub transport: Transport,
+    /// seconds since the unix epoch the server accepted the connection
+    pub connected_at: u64,
+}
+
+impl From<pdtcore::NetworkInfo> for NetworkInfo {
+    fn from(value: pdtcore::NetworkInfo) -> Self {
+        Self {
+            address: value.address,
+            transport: value.transport.into(),
+            connected_at: unix_seconds(value.connected_at),
+        }
+    }
+}
+
+/// a client connected to the server
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct ClientSummary {
+    pub id: String,
+    pub state: ConnectionState,
+    pub device_info: DeviceInfo,
+    /// seconds since the unix epoch the server last heard from the client
+    pub last_seen: u64,
+    pub network: NetworkInfo,
+    /// pdtcore version the client introduced itself with
+    pub protocol_version: Option<String>,
+}
+
+impl From<pdtcore::Client> for ClientSummary {
+    fn from(value: pdtcore::Client) -> Self {
+        Self {
+            id: value.id,
+            state: value.state.into(),
+            device_info: value.device_info.into(),
+            last_seen: unix_seconds(value.last_seen),
+            network: value.network.into(),
+            protocol_version: value.protocol_version,
+        }
+    }
+}
+
+/// a client of one of the servers rolled up by a federating server
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct FederatedClient {
+    /// name of the server the client is connected to
+    pub server: String,
+    pub client: ClientSummary,
+}
+
+/// an upstream server whose clients could not be listed
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct UnreachableServer {
+    pub server: String,
+    pub error: String,
+}
+
+/// clients of the federating server followed by those of its upstreams
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct FederatedClients {
+    pub clients: Vec<FederatedClient>,
+    pub unreachable: Vec<UnreachableServer>,
+}
+
+/// resource usage of a client at a point in time
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct TelemetrySample {
+    /// seconds since the unix epoch
+    pub timestamp: u64,
+    /// one minute load average
+    pub load: f64,
+    pub memory_total: u64,
+    pub memory_used: u64,
+    /// bytes received since the client booted
+    pub network_received: u64,
+    /// bytes transmitted since the client booted
+    pub network_transmitted: u64,
+}
+
+impl TelemetrySample {
+    pub fn new(at: std::time::SystemTime, telemetry: pdtcore::Telemetry) -> Self {
+        Self {
+            timestamp: unix_seconds(at),
+            load: telemetry.load,
+            memory_total: telemetry.memory_total,
+            memory_used: telemetry.memory_used,
+            network_received: telemetry.network_received,
+            network_transmitted: telemetry.network_transmitted,
+        }
+    }
+}
+
+/// bytes that went over a client connection since it was accepted
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bandwidth {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// bytes per second bulk messages to the client are paced to, if limited
+    pub bulk_limit: Option<u64>,
+}
+
+/// figures over the latest telemetry of every client
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq)]
+pub struct FleetRollup {
+    pub clients: u64,
+    /// clients that sent telemetry, the figures below cover only these
+    pub reporting: u64,
+    /// sum of the one minute load averages
+    pub total_load: f64,
+    pub average_load: Option<f64>,
+    pub memory_used: u64,
+    pub memory_total: u64,
+    /// clients using more than 80% of their memory
+    pub memory_pressure: u64,
+    /// seconds every client was up over the past week, summed
+    pub uptime_this_week: u64,
+}
+
+/// how well a client answers the telemetry requests of the server
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionQuality {
+    /// not asked anything yet
+    Unknown,
+    Good,
+    /// answers are slow or some go missing
+    Flaky,
+    /// most answers are very slow or go missing
+    Poor,
+}
+
+/// round trips of the recent telemetry requests to a client
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq)]
+pub struct Latency {
+    /// of the answered requests, none when none were
+    pub average_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+    /// share of the requests that went unanswered, from 0 to 1
+    pub loss: f64,
+    pub requests: u64,
+    pub quality: ConnectionQuality,
+}
+
+/// action a client can be asked to perform
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Command {
+    ScreenOff,
+    ScreenOn,
+    PowerOff,
+    Restart,
+}
+
+impl Command {
+    /// sent to several clients at once only with the confirmation of arming
+    /// it first, see [`ArmedCommand`]
+    pub fn is_destructive(&self) -> bool {
+        matches!(self, Command::PowerOff)
+    }
+}
+
+impl From<Command> for pdtcore::ClientMessage {
+    fn from(value: Command) -> Self {
+        match value {
+            Command::ScreenOff => pdtcore::ClientMessage::ScreenOff,
+            Command::ScreenOn => pdtcore::ClientMessage::ScreenOn,
+            Command::PowerOff => pdtcore::ClientMessage::PowerOff,
+            Command::Restart => pdtcore::ClientMessage::Restart,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct CommandRequest {
+    pub command: Command,
+}
+
+/// a command that was handed to the client connection
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct CommandResponse {
+    pub client_id: String,
+    pub command: Command,
+}
+
+/// the same command for several clients at once
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct BulkCommandRequest {
+    pub client_ids: Vec<String>,
+    pub command: Command,
+    /// from arming the same command for the same clients, required for
+    /// destructive commands to several clients
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<String>,
+}
+
+/// a destructive command armed for several clients, sent by repeating the
+/// bulk command request with the confirmation before it expires
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct ArmedCommand {
+    pub confirmation: String,
+    /// seconds the confirmation can be used for
+    pub expires_in: u64,
+}
+
+/// outcome of a bulk command for a single client
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct BulkCommandResult {
+    pub client_id: String,
+    /// http status the single client command would have answered with
+    pub status: u16,
+    /// why the command was not sent, absent on success
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct BulkCommandResponse {
+    pub command: Command,
+    /// nothing was sent, the results are what sending would have answered
+    #[serde(default)]
+    pub dry_run: bool,
+    pub results: Vec<BulkCommandResult>,
+}
+
+/// a named boolean flipped by an external source, such as a presence
+/// sensor
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    pub name: String,
+    pub value: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct InputRequest {
+    pub value: bool,
+    /// from arming the command of a rule for the clients it applies to,
+    /// required when the change runs a destructive rule on several clients
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<String>,
+}
+
+/// commands a rule sent when its input changed
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct RuleOutcome {
+    pub rule: String,
+    pub command: Command,
+    pub results: Vec<BulkCommandResult>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct InputResponse {
+    pub name: String,
+    pub value: bool,
+    /// the input had another value or none before, rules only run on a
+    /// change
+    pub changed: bool,
+    pub rules: Vec<RuleOutcome>,
+}
+
+/// a command sent through the web interface or api
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// seconds since the unix epoch
+    pub timestamp: u64,
+    pub client_id: String,
+    pub device_name: String,
+    pub command: String,
+    /// user or access token that sent the command
+    pub actor: String,
+    /// `sent`, `failed`, `denied` or `coalesced`
+    pub outcome: String,
+    /// hash of the entry before it, all zeros for the first command since
+    /// the server started
+    pub previous_hash: String,
+    /// hex encoded blake2b-256 over the previous hash and the fields above
+    pub hash: String,
+}
+
+/// outcome of checking exported history entries against the server
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryVerification {
+    /// the entries form an unbroken chain ending in a command the server
+    /// still knows
+    pub valid: bool,
+    pub entries: usize,
+    /// index of the first entry that was edited, removed or reordered
+    pub first_invalid: Option<usize>,
+    /// hash of the last entry is part of the history kept by the server
+    pub known: bool,
+}
+
+/// change to the connected clients, streamed as server sent events
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum ClientEvent {
+    Connected {
+        client: ClientSummary,
+    },
+    Updated {
+        client: ClientSummary,
+    },
+    Disconnected {
+        client_id: String,
+    },
+    /// periodic presence refresh
+    Heartbeat {
+        client: ClientSummary,
+    },
+    /// outcome of handing a command to a client connection
+    CommandResult {
+        client_id: String,
+        result: String,
+    },
+}
+
+/// a socket the server listens on
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct ServerEndpoint {
+    /// `pdt`, `relay`, `web` or `grpc`
+    pub service: String,
+    /// listener name, the address unless it was named or socket activated
+    pub address: String,
+}
+
+/// the server itself, to sanity check a deployment
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct ServerStatus {
+    pub version: String,
+    pub target: String,
+    pub profile: String,
+    /// seconds since the unix epoch the server started
+    pub started: u64,
+    pub endpoints: Vec<ServerEndpoint>,
+    pub connected: usize,
+    pub connecting: usize,
+    /// client events queued for subscribers that have not received them yet
+    pub event_queue_depth: usize,
+    /// times handling client messages failed and was restarted
+    pub handler_restarts: u64,
+    /// handling client messages failed and did not recover yet, see
+    /// `/readyz`
+    pub degraded: bool,
+    /// where clients, telemetry and history are kept
+    pub persistence: String,
+}
+
+/// exact build of the server, for support requests
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub version: String,
+    pub target: String,
+    pub host: String,
+    pub profile: String,
+    /// commit the server was built from, `-dirty` when built with local
+    /// changes
+    pub git_commit_hash: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct ErrorResponse {
+    pub error: String,
+}
@@ -0,0 +1,221 @@
+//! unix socket telling whether the client is connected to its servers, for
+//! `pdtclient healthcheck`, which servers welcomed it, for
+//! `pdtclient server`, and stopping it, for `pdtclient stop`
+//!
+//! every connection sends one request line and is answered with one line,
+//! or one per configured server when asked for the servers
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use pdtcore::Welcome;
+use tracing::warn;
+
+use crate::{servers::Attachments, shutdown::Shutdown, welcome};
+
+/// time the healthcheck waits for the client to answer
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const STATUS: &str = "status";
+const SERVER: &str = "server";
+const SHUTDOWN: &str = "shutdown";
+
+const CONNECTED: &str = "connected";
+const DISCONNECTED: &str = "disconnected";
+const STOPPING: &str = "stopping";
+const NOT_WELCOMED: &str = "none";
+
+/// `CONTROL_SOCKET`, or `pdtclient.sock` in the runtime directory of the user
+pub fn path() -> PathBuf {
+    if let Some(path) = std::env::var_os("CONTROL_SOCKET") {
+        return PathBuf::from(path);
+    }
+
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("pdtclient.sock")
+}
+
+/// answer every connection on the control socket at `path`, with the
+/// connection state, the welcomes of the servers or by requesting shutdown
+pub fn serve(path: &Path, attachments: Attachments, shutdown: Shutdown) -> std::io::Result<()> {
+    // left behind by a client that did not exit cleanly
+    if UnixStream::connect(path).is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let answered = stream.and_then(|stream| answer(stream, &attachments, &shutdown));
+
+            if let Err(error) = answered {
+                warn!(error =? error, "answering control socket");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn answer(
+    stream: UnixStream,
+    attachments: &Attachments,
+    shutdown: &Shutdown,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+
+    let answer = match request.trim() {
+        SHUTDOWN => {
+            shutdown.request();
+            STOPPING.to_string()
+        }
+        SERVER => attachments
+            .get()
+            .iter()
+            .map(|attachment| match &attachment.welcome {
+                Some(welcome) => format!("{} {}", attachment.address, welcome::describe(welcome)),
+                None => format!("{} {NOT_WELCOMED}", attachment.address),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ if attachments.all_connected() => CONNECTED.to_string(),
+        _ => DISCONNECTED.to_string(),
+    };
+
+    writeln!(&stream, "{answer}")
+}
+
+/// send a request to the client listening at `path`, returning its answer
+fn ask(path: &Path, request: &str) -> Result<String, String> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|error| format!("connecting to {}: {error}", path.display()))?;
+
+    let mut answer = String::new();
+
+    writeln!(stream, "{request}")
+        .and_then(|_| stream.shutdown(std::net::Shutdown::Write))
+        .and_then(|_| stream.set_read_timeout(Some(TIMEOUT)))
+        .and_then(|_| stream.read_to_string(&mut answer))
+        .map_err(|error| format!("asking {request}: {error}"))?;
+
+    Ok(answer.trim().to_string())
+}
+
+/// ask a running client whether it is connected to every server
+pub fn check() -> Result<(), String> {
+    match ask(&path(), STATUS)?.as_str() {
+        CONNECTED => Ok(()),
+        state => Err(format!("unhealthy: {state}")),
+    }
+}
+
+/// ask a running client which servers welcomed it, by the address of each
+/// configured server
+pub fn servers() -> Result<Vec<(String, Option<Welcome>)>, String> {
+    ask(&path(), SERVER)?
+        .lines()
+        .map(|line| {
+            let (address, welcome) = line
+                .split_once(' ')
+                .ok_or_else(|| format!("unexpected answer: {line}"))?;
+
+            Ok((address.to_string(), welcome::parse(welcome)))
+        })
+        .collect()
+}
+
+/// ask a running client to say goodbye and exit
+pub fn stop() -> Result<(), String> {
+    match ask(&path(), SHUTDOWN)?.as_str() {
+        STOPPING => Ok(()),
+        answer => Err(format!("unexpected answer: {answer}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Served {
+        path: PathBuf,
+        attachments: Attachments,
+        shutdown: Shutdown,
+    }
+
+    fn served(name: &str) -> Served {
+        let path =
+            std::env::temp_dir().join(format!("pdtclient-{}-{name}.sock", std::process::id()));
+        let attachments = Attachments::new(["192.0.2.1:2039".parse().unwrap()]);
+        let shutdown = Shutdown::default();
+
+        serve(&path, attachments.clone(), shutdown.clone()).unwrap();
+
+        Served {
+            path,
+            attachments,
+            shutdown,
+        }
+    }
+
+    #[test]
+    fn answers_the_connection_state() {
+        let served = served("status");
+
+        assert_eq!(ask(&served.path, STATUS).unwrap(), DISCONNECTED);
+
+        served.attachments.set_connected(0, true);
+
+        assert_eq!(ask(&served.path, STATUS).unwrap(), CONNECTED);
+    }
+
+    #[test]
+    fn answers_the_welcome_of_the_server() {
+        let served = served("server");
+
+        assert_eq!(ask(&served.path, SERVER).unwrap(), "192.0.2.1:2039 none");
+
+        let welcome = Welcome {
+            assigned_id: "01H0000000000000000000000".to_string(),
+            server: pdtcore::ServerInfo {
+                name: "home".to_string(),
+                version: "0.1.0".to_string(),
+                up_since: 1_700_000_000,
+            },
+            protocol: pdtcore::ProtocolConfig::default(),
+        };
+
+        let saved =
+            std::env::temp_dir().join(format!("pdtclient-{}-control.welcome", std::process::id()));
+
+        served
+            .attachments
+            .welcome(0, welcome.clone(), &saved)
+            .unwrap();
+
+        let answer = ask(&served.path, SERVER).unwrap();
+        let (address, described) = answer.split_once(' ').unwrap();
+
+        assert_eq!(address, "192.0.2.1:2039");
+        assert_eq!(welcome::parse(described), Some(welcome));
+
+        std::fs::remove_file(saved).unwrap();
+    }
+
+    #[test]
+    fn shutdown_request_stops_the_client() {
+        let served = served("shutdown");
+
+        assert_eq!(ask(&served.path, SHUTDOWN).unwrap(), STOPPING);
+        assert!(served.shutdown.is_requested());
+    }
+}
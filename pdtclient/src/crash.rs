@@ -0,0 +1,179 @@
+//! panics of the client, written to a file by the panic hook so the crash is
+//! reported to the first server the client connects to once it runs again
+//!
+//! reports are kept encoded like the message they are sent in, one file per
+//! crash
+
+use std::{
+    backtrace::Backtrace,
+    fs::File,
+    panic::PanicHookInfo,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use pdtcore::{
+    BuiltInfo, CrashReport, Message, Particularity, Protocol, ProtocolError, ServerMessage,
+};
+use tracing::*;
+
+use crate::welcome;
+
+/// `CRASH_DIR`, or `pdtclient.crashes` in the state directory of the user
+pub fn dir() -> PathBuf {
+    match std::env::var_os("CRASH_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => welcome::state_dir().join("pdtclient.crashes"),
+    }
+}
+
+fn report(info: &PanicHookInfo) -> CrashReport {
+    let payload = info.payload();
+
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "panicked".to_string());
+
+    let message = match info.location() {
+        Some(location) => format!("{message} at {location}"),
+        None => message,
+    };
+
+    CrashReport {
+        at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        message,
+        backtrace: Backtrace::force_capture().to_string(),
+        built_info: BuiltInfo::default(),
+    }
+}
+
+fn write(dir: &Path, report: CrashReport) -> Result<PathBuf, ProtocolError> {
+    std::fs::create_dir_all(dir)?;
+
+    let path = dir.join(format!(
+        "{}-{}.crash",
+        report.at,
+        std::thread::current().name().unwrap_or("thread")
+    ));
+
+    let mut file = File::create(&path)?;
+
+    Message::from(ServerMessage::CrashReport(Box::new(report))).send(&mut file)?;
+
+    Ok(path)
+}
+
+/// write a report for every panic to `dir` before the panic is handled as
+/// it would have been otherwise
+pub fn install(dir: PathBuf) {
+    let previous = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        match write(&dir, report(info)) {
+            Ok(path) => eprintln!("crash report written to {}", path.display()),
+            Err(error) => eprintln!("writing crash report: {error:?}"),
+        }
+
+        previous(info);
+    }));
+}
+
+/// crash reports not sent yet, shared by the connections to every server
+#[derive(Debug, Clone)]
+pub struct Crashes {
+    dir: PathBuf,
+    /// held while taking reports, so only one server gets each
+    taking: Particularity<()>,
+}
+
+impl Crashes {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            taking: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// the reports written so far, forgotten once taken, unreadable ones
+    /// are logged and left behind
+    pub fn take(&self) -> Vec<CrashReport> {
+        let _taking = self.taking.lock();
+
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "crash")
+            })
+            .collect();
+
+        // oldest first, the names start with the time of the crash
+        paths.sort();
+
+        paths
+            .into_iter()
+            .filter_map(|path| {
+                let report = File::open(&path)
+                    .map_err(ProtocolError::IO)
+                    .and_then(|mut file| Message::receive(&mut file));
+
+                match report {
+                    Ok(Message::Server(ServerMessage::CrashReport(report))) => {
+                        if let Err(error) = std::fs::remove_file(&path) {
+                            warn!(error =? error, path = %path.display(), "removing crash report");
+                        }
+
+                        Some(*report)
+                    }
+                    other => {
+                        warn!(result =? other, path = %path.display(), "unreadable crash report");
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_are_taken_once_oldest_first() {
+        let dir = std::env::temp_dir().join(format!("pdtclient-{}-crashes", std::process::id()));
+
+        for at in [1_700_000_100, 1_700_000_000] {
+            write(
+                &dir,
+                CrashReport {
+                    at,
+                    message: "index out of bounds at src/main.rs:1:1".to_string(),
+                    backtrace: String::new(),
+                    built_info: BuiltInfo::default(),
+                },
+            )
+            .unwrap();
+        }
+
+        let crashes = Crashes::new(dir.clone());
+        let reports = crashes.take();
+
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].at, 1_700_000_000);
+        assert!(crashes.take().is_empty());
+
+        std::fs::remove_dir(dir).unwrap();
+    }
+}
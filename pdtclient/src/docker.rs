@@ -0,0 +1,253 @@
+//! containers of the docker daemon next to the client, sampled on a thread
+//! of their own as the daemon takes a second or two to answer with usage,
+//! and sent along with every telemetry answer like the forwarded logs
+//!
+//! reported only when `DOCKER_SOCKET` is set to the unix socket of the
+//! daemon, like `/var/run/docker.sock`
+
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use pdtcore::{ContainerStats, Containers, Particularity};
+use serde_json::Value;
+use tracing::*;
+
+/// time between samples of the containers
+const INTERVAL: Duration = Duration::from_secs(10);
+
+/// longest wait for an answer of the daemon
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// characters of the ids `docker ps` shows
+const SHORT_ID: usize = 12;
+
+/// `DOCKER_SOCKET`, none unless containers are to be reported
+pub fn socket() -> Option<PathBuf> {
+    std::env::var_os("DOCKER_SOCKET").map(PathBuf::from)
+}
+
+// fields are only read through Debug when logging
+#[allow(dead_code)]
+#[derive(Debug)]
+enum DockerError {
+    IO(std::io::Error),
+    Status(String),
+    Json(serde_json::Error),
+}
+
+impl From<std::io::Error> for DockerError {
+    fn from(value: std::io::Error) -> Self {
+        DockerError::IO(value)
+    }
+}
+
+/// answer of the daemon to a request for `path`
+fn get(socket: &Path, path: &str) -> Result<Value, DockerError> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+
+    // http 1.0 has the daemon close the connection after the answer rather
+    // than chunk it
+    write!(stream, "GET {path} HTTP/1.0\r\nHost: docker\r\n\r\n")?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let Some(end) = response.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return Err(DockerError::Status("no http answer".to_string()));
+    };
+
+    let head = String::from_utf8_lossy(&response[..end]);
+    let status = head.lines().next().unwrap_or_default();
+
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(DockerError::Status(status.to_string()));
+    }
+
+    serde_json::from_slice(&response[end + 4..]).map_err(DockerError::Json)
+}
+
+/// percent of a single cpu used between the two samples the daemon took
+fn cpu_percent(stats: &Value) -> f64 {
+    let (cpu, precpu) = (&stats["cpu_stats"], &stats["precpu_stats"]);
+
+    let used = cpu["cpu_usage"]["total_usage"]
+        .as_u64()
+        .unwrap_or(0)
+        .saturating_sub(precpu["cpu_usage"]["total_usage"].as_u64().unwrap_or(0));
+    let elapsed = cpu["system_cpu_usage"]
+        .as_u64()
+        .unwrap_or(0)
+        .saturating_sub(precpu["system_cpu_usage"].as_u64().unwrap_or(0));
+    let cpus = cpu["online_cpus"].as_u64().unwrap_or(1);
+
+    if elapsed == 0 {
+        return 0.0;
+    }
+
+    used as f64 / elapsed as f64 * cpus as f64 * 100.0
+}
+
+/// usage of a container, from its entry in the container list and its
+/// stats
+fn container_stats(container: &Value, stats: &Value) -> ContainerStats {
+    let memory = &stats["memory_stats"];
+
+    // the page cache is reclaimed under pressure, docker stats leaves it out
+    // as well, `inactive_file` on cgroup v2 and `cache` on v1
+    let cache = memory["stats"]["inactive_file"]
+        .as_u64()
+        .or_else(|| memory["stats"]["cache"].as_u64())
+        .unwrap_or(0);
+
+    let name = container["Names"][0].as_str().unwrap_or_default();
+
+    ContainerStats {
+        id: container["Id"]
+            .as_str()
+            .unwrap_or_default()
+            .chars()
+            .take(SHORT_ID)
+            .collect(),
+        name: name.trim_start_matches('/').to_string(),
+        image: container["Image"].as_str().unwrap_or_default().to_string(),
+        cpu: cpu_percent(stats),
+        memory_used: memory["usage"].as_u64().unwrap_or(0).saturating_sub(cache),
+        memory_limit: memory["limit"].as_u64().unwrap_or(0),
+    }
+}
+
+fn sample(socket: &Path) -> Result<Containers, DockerError> {
+    let listed = get(socket, "/containers/json?all=true")?;
+
+    let mut containers = Containers::default();
+
+    for container in listed.as_array().into_iter().flatten() {
+        if container["State"] != "running" {
+            containers.stopped += 1;
+            continue;
+        }
+
+        containers.running += 1;
+
+        let id = container["Id"].as_str().unwrap_or_default();
+
+        // the container may have stopped since it was listed
+        match get(socket, &format!("/containers/{id}/stats?stream=false")) {
+            Ok(stats) => containers.stats.push(container_stats(container, &stats)),
+            Err(error) => debug!(error =? error, id, "reading container stats"),
+        }
+    }
+
+    Ok(containers)
+}
+
+/// the containers last sampled, shared by the connections to every server
+#[derive(Debug, Clone, Default)]
+pub struct Docker {
+    latest: Particularity<Option<Containers>>,
+}
+
+impl Docker {
+    /// sample the containers of the daemon behind `socket` until the client
+    /// exits
+    pub fn spawn(socket: PathBuf) -> Self {
+        let docker = Docker::default();
+        let latest = docker.latest.clone();
+
+        std::thread::Builder::new()
+            .name("docker".to_string())
+            .spawn(move || {
+                let mut failing = false;
+
+                loop {
+                    match sample(&socket) {
+                        Ok(containers) => {
+                            *latest.lock() = Some(containers);
+                            failing = false;
+                        }
+                        // logged once, the daemon may be down for a while
+                        Err(error) if !failing => {
+                            warn!(error =? error, socket = %socket.display(), "sampling containers");
+                            failing = true;
+                        }
+                        Err(_) => {}
+                    }
+
+                    std::thread::sleep(INTERVAL);
+                }
+            })
+            .expect("docker thread can be spawned");
+
+        docker
+    }
+
+    /// none until the daemon answered
+    pub fn latest(&self) -> Option<Containers> {
+        self.latest.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn usage_is_read_like_docker_stats_shows_it() {
+        let container = json!({
+            "Id": "4f1c2e3d5b6a7980a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718",
+            "Names": ["/home-assistant"],
+            "Image": "ghcr.io/home-assistant/home-assistant:stable",
+            "State": "running",
+        });
+
+        let stats = json!({
+            "cpu_stats": {
+                "cpu_usage": { "total_usage": 1_500_000_000u64 },
+                "system_cpu_usage": 20_000_000_000u64,
+                "online_cpus": 4,
+            },
+            "precpu_stats": {
+                "cpu_usage": { "total_usage": 1_000_000_000u64 },
+                "system_cpu_usage": 16_000_000_000u64,
+            },
+            "memory_stats": {
+                "usage": 600u64 << 20,
+                "limit": 8u64 << 30,
+                "stats": { "inactive_file": 88u64 << 20 },
+            },
+        });
+
+        assert_eq!(
+            container_stats(&container, &stats),
+            ContainerStats {
+                id: "4f1c2e3d5b6a".to_string(),
+                name: "home-assistant".to_string(),
+                image: "ghcr.io/home-assistant/home-assistant:stable".to_string(),
+                cpu: 50.0,
+                memory_used: 512 << 20,
+                memory_limit: 8 << 30,
+            }
+        );
+    }
+
+    #[test]
+    fn cpu_is_idle_when_no_time_passed_between_samples() {
+        let sample = json!({
+            "cpu_usage": { "total_usage": 1_500_000_000u64 },
+            "system_cpu_usage": 20_000_000_000u64,
+            "online_cpus": 4,
+        });
+
+        let stats = json!({ "cpu_stats": sample.clone(), "precpu_stats": sample });
+
+        assert_eq!(cpu_percent(&stats), 0.0);
+        assert_eq!(cpu_percent(&json!({})), 0.0);
+    }
+}
@@ -0,0 +1,73 @@
+//! the key the client presents to servers after introducing itself, made up
+//! once and kept in a file only the user can read, so a server approving the
+//! client knows it again while another device reporting the same name stays
+//! pending
+
+use std::{
+    fs::OpenOptions,
+    io::{Read, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use crate::welcome;
+
+/// `IDENTITY_PATH`, or `pdtclient.identity` in the state directory of the
+/// user
+pub fn path() -> PathBuf {
+    match std::env::var_os("IDENTITY_PATH") {
+        Some(path) => PathBuf::from(path),
+        None => welcome::state_dir().join("pdtclient.identity"),
+    }
+}
+
+/// the key kept at `path`, made up and written there the first time
+pub fn load_or_create(path: &Path) -> std::io::Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(key) if !key.trim().is_empty() => return Ok(key.trim().to_string()),
+        Ok(_) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error),
+    }
+
+    let mut bytes = [0; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+    let key: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(key.as_bytes())?;
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_is_kept_across_runs() {
+        let path = std::env::temp_dir().join(format!("pdtclient-{}.identity", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let key = load_or_create(&path).unwrap();
+
+        assert_eq!(key.len(), 64);
+        assert_eq!(load_or_create(&path).unwrap(), key);
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_ne!(load_or_create(&path).unwrap(), key);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+//! commands being executed, kept in a file so a command cut short by the
+//! client crashing or the device going down is reported to its server once
+//! the client is welcomed again, instead of the server never hearing back
+//!
+//! one line per command, the address of the server that sent it followed by
+//! the command
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+use pdtcore::{ClientMessage, CommandExecution, Particularity, TraceContext};
+use tracing::*;
+
+use crate::welcome;
+
+/// `IN_FLIGHT_PATH`, or `pdtclient.inflight` in the state directory of the
+/// user
+pub fn path() -> PathBuf {
+    match std::env::var_os("IN_FLIGHT_PATH") {
+        Some(path) => PathBuf::from(path),
+        None => welcome::state_dir().join("pdtclient.inflight"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    server: SocketAddr,
+    command: String,
+}
+
+impl Entry {
+    fn parse(line: &str) -> Option<Self> {
+        let (server, command) = line.split_once(' ')?;
+
+        Some(Self {
+            server: server.parse().ok()?,
+            command: command.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// left in the file by the previous run, until reported
+    interrupted: Vec<Entry>,
+    running: Vec<Entry>,
+}
+
+/// commands in flight on every server connection
+#[derive(Debug, Clone)]
+pub struct InFlight {
+    path: PathBuf,
+    entries: Particularity<Entries>,
+}
+
+impl InFlight {
+    /// start with the commands the previous run left unfinished, a missing
+    /// or unreadable file leaves nothing to report
+    pub fn load(path: PathBuf) -> Self {
+        let interrupted = match std::fs::read_to_string(&path) {
+            Ok(text) => text.lines().filter_map(Entry::parse).collect(),
+            Err(_) => vec![],
+        };
+
+        Self {
+            path,
+            entries: Arc::new(Mutex::new(Entries {
+                interrupted,
+                running: vec![],
+            })),
+        }
+    }
+
+    /// keep `message` in the file until the returned guard is dropped, for
+    /// the commands changing the device
+    pub fn track(&self, server: SocketAddr, message: &ClientMessage) -> Option<Tracked> {
+        if !tracked(message) {
+            return None;
+        }
+
+        let entry = Entry {
+            server,
+            command: format!("{message:?}"),
+        };
+
+        self.update(|entries| entries.running.push(entry.clone()));
+
+        Some(Tracked {
+            in_flight: self.clone(),
+            entry,
+        })
+    }
+
+    /// commands sent by `server` the previous run did not finish, as
+    /// executions failed by the interruption, forgotten once taken
+    pub fn interrupted(&self, server: SocketAddr) -> Vec<CommandExecution> {
+        let mut taken = vec![];
+
+        self.update(|entries| {
+            entries.interrupted.retain(|entry| {
+                let ours = entry.server == server;
+
+                if ours {
+                    taken.push(entry.command.clone());
+                }
+
+                !ours
+            })
+        });
+
+        taken
+            .into_iter()
+            .map(|command| CommandExecution {
+                trace: TraceContext::default(),
+                command,
+                error: Some("interrupted, the client stopped while executing it".to_string()),
+            })
+            .collect()
+    }
+
+    /// change the entries and save them under the lock, so concurrent
+    /// changes are saved in order, failing to save is logged only
+    fn update(&self, update: impl FnOnce(&mut Entries)) {
+        let mut entries = self.entries.lock();
+
+        update(&mut entries);
+
+        if let Err(error) = save(&self.path, &entries) {
+            warn!(error =? error, "saving commands in flight");
+        }
+    }
+}
+
+fn save(path: &Path, entries: &Entries) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let text: String = entries
+        .interrupted
+        .iter()
+        .chain(&entries.running)
+        .map(|entry| format!("{} {}\n", entry.server, entry.command))
+        .collect();
+
+    std::fs::write(path, text)
+}
+
+/// the commands changing the device, a traced command is tracked as the
+/// command it carries
+fn tracked(message: &ClientMessage) -> bool {
+    match message {
+        ClientMessage::ScreenOff
+        | ClientMessage::ScreenOn
+        | ClientMessage::PowerOff
+        | ClientMessage::Restart
+        | ClientMessage::Notify(_) => true,
+        ClientMessage::Goodbye
+        | ClientMessage::RequestDeviceInfo
+        | ClientMessage::RequestTelemetry
+        | ClientMessage::Traced(..)
+        | ClientMessage::Welcome(_)
+        | ClientMessage::ConfigUpdate(_)
+        | ClientMessage::Custom { .. }
+        | ClientMessage::Nack { .. } => false,
+    }
+}
+
+/// a command in flight, finished when dropped
+#[derive(Debug)]
+pub struct Tracked {
+    in_flight: InFlight,
+    entry: Entry,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.in_flight.update(|entries| {
+            if let Some(index) = entries
+                .running
+                .iter()
+                .position(|entry| *entry == self.entry)
+            {
+                entries.running.remove(index);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pdtclient-{}-{name}.inflight", std::process::id()))
+    }
+
+    #[test]
+    fn finished_commands_are_forgotten() {
+        let path = path("finished");
+        let server = "192.0.2.1:2039".parse().unwrap();
+
+        let in_flight = InFlight::load(path.clone());
+
+        assert!(in_flight
+            .track(server, &ClientMessage::RequestTelemetry)
+            .is_none());
+
+        drop(in_flight.track(server, &ClientMessage::ScreenOff));
+
+        assert!(InFlight::load(path.clone()).interrupted(server).is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unfinished_commands_are_reported_once_to_their_server() {
+        let path = path("unfinished");
+        let server = "192.0.2.1:2039".parse().unwrap();
+        let other = "198.51.100.1:2039".parse().unwrap();
+
+        let crashed = InFlight::load(path.clone());
+        std::mem::forget(crashed.track(server, &ClientMessage::ScreenOff));
+        std::mem::forget(crashed.track(other, &ClientMessage::Restart));
+
+        let restarted = InFlight::load(path.clone());
+
+        let interrupted = restarted.interrupted(server);
+
+        assert_eq!(interrupted.len(), 1);
+        assert_eq!(interrupted[0].command, "ScreenOff");
+        assert!(interrupted[0].error.is_some());
+        assert!(restarted.interrupted(server).is_empty());
+
+        // still there for the other server should this run crash as well
+        let crashed_again = InFlight::load(path.clone());
+
+        assert_eq!(crashed_again.interrupted(other)[0].command, "Restart");
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+//! what the machine is, read from the DMI tables the kernel exposes and from
+//! traces hypervisors and container runtimes leave, so the fleet list of the
+//! server doubles as an asset inventory
+//!
+//! anything that cannot be read is reported as unknown, the serial number
+//! is only readable by root on most systems
+
+use std::path::Path;
+
+use pdtcore::Virtualization;
+
+const DMI: &str = "/sys/class/dmi/id";
+
+/// DMI vendors and products of virtual machines that do not set the
+/// hypervisor cpu flag, like those on arm
+const HYPERVISORS: [&str; 8] = [
+    "QEMU",
+    "KVM",
+    "VMware",
+    "VirtualBox",
+    "Xen",
+    "Bochs",
+    "Parallels",
+    "Virtual Machine",
+];
+
+/// cgroup names of the common container runtimes
+const CONTAINER_RUNTIMES: [&str; 5] = ["docker", "kubepods", "containerd", "libpod", "lxc"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inventory {
+    pub vendor: String,
+    pub model: String,
+    pub serial: String,
+    pub chassis: String,
+    pub virtualization: Virtualization,
+}
+
+/// a DMI field, none when missing, unreadable or empty
+fn dmi(field: &str) -> Option<String> {
+    let value = std::fs::read_to_string(Path::new(DMI).join(field)).ok()?;
+    let value = value.trim();
+
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// name of an SMBIOS chassis type
+fn chassis(chassis_type: u8) -> Option<&'static str> {
+    let name = match chassis_type {
+        1 => "other",
+        3 => "desktop",
+        4 => "low profile desktop",
+        5 => "pizza box",
+        6 => "mini tower",
+        7 => "tower",
+        8 => "portable",
+        9 => "laptop",
+        10 => "notebook",
+        11 => "hand held",
+        12 => "docking station",
+        13 => "all in one",
+        14 => "sub notebook",
+        15 => "space-saving",
+        16 => "lunch box",
+        17 => "main server chassis",
+        18 => "expansion chassis",
+        19 => "sub chassis",
+        20 => "bus expansion chassis",
+        21 => "peripheral chassis",
+        22 => "raid chassis",
+        23 => "rack mount",
+        24 => "sealed-case pc",
+        25 => "multi-system chassis",
+        26 => "compact pci",
+        27 => "advanced tca",
+        28 => "blade",
+        29 => "blade enclosure",
+        30 => "tablet",
+        31 => "convertible",
+        32 => "detachable",
+        33 => "iot gateway",
+        34 => "embedded pc",
+        35 => "mini pc",
+        36 => "stick pc",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// whether the cgroups of a process, as in `/proc/<pid>/cgroup`, are those
+/// of a container
+fn containerized(cgroup: &str) -> bool {
+    cgroup.lines().any(|line| {
+        CONTAINER_RUNTIMES
+            .iter()
+            .any(|runtime| line.contains(runtime))
+    })
+}
+
+/// whether `/proc/cpuinfo` tells the cpu is virtualized
+fn hypervisor_flag(cpuinfo: &str) -> bool {
+    cpuinfo
+        .lines()
+        .filter(|line| line.starts_with("flags"))
+        .any(|line| line.split_whitespace().any(|flag| flag == "hypervisor"))
+}
+
+fn virtualization(vendor: Option<&str>, model: Option<&str>) -> Virtualization {
+    let container = Path::new("/.dockerenv").exists()
+        || Path::new("/run/.containerenv").exists()
+        || std::fs::read_to_string("/proc/1/cgroup").is_ok_and(|cgroup| containerized(&cgroup));
+
+    if container {
+        return Virtualization::Container;
+    }
+
+    let dmi_hypervisor = [vendor, model].into_iter().flatten().any(|name| {
+        HYPERVISORS
+            .iter()
+            .any(|hypervisor| name.contains(hypervisor))
+    });
+
+    match std::fs::read_to_string("/proc/cpuinfo") {
+        Ok(cpuinfo) if hypervisor_flag(&cpuinfo) || dmi_hypervisor => {
+            Virtualization::VirtualMachine
+        }
+        Ok(_) => Virtualization::Physical,
+        Err(_) if dmi_hypervisor => Virtualization::VirtualMachine,
+        Err(_) => Virtualization::Unknown,
+    }
+}
+
+pub fn read() -> Inventory {
+    let vendor = dmi("sys_vendor");
+    let model = dmi("product_name");
+
+    let chassis = dmi("chassis_type")
+        .and_then(|chassis_type| chassis_type.parse().ok())
+        .and_then(chassis);
+
+    let unknown = || "unknown".to_string();
+
+    Inventory {
+        virtualization: virtualization(vendor.as_deref(), model.as_deref()),
+        vendor: vendor.unwrap_or_else(unknown),
+        model: model.unwrap_or_else(unknown),
+        serial: dmi("product_serial").unwrap_or_else(unknown),
+        chassis: chassis.map(str::to_string).unwrap_or_else(unknown),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chassis_types_are_named() {
+        assert_eq!(chassis(10), Some("notebook"));
+        assert_eq!(chassis(23), Some("rack mount"));
+        assert_eq!(chassis(2), None);
+        assert_eq!(chassis(200), None);
+    }
+
+    #[test]
+    fn containers_are_told_by_their_cgroups() {
+        assert!(containerized(
+            "0::/system.slice/docker-4f1c2e.scope\n1:name=systemd:/docker/4f1c2e"
+        ));
+        assert!(containerized("0::/kubepods/besteffort/pod1234/4f1c2e"));
+        assert!(!containerized("0::/init.scope"));
+    }
+
+    #[test]
+    fn hypervisor_flag_is_found_among_the_cpu_flags() {
+        assert!(hypervisor_flag(
+            "processor\t: 0\nflags\t\t: fpu vme de pse hypervisor lahf_lm\n"
+        ));
+        assert!(!hypervisor_flag(
+            "processor\t: 0\nflags\t\t: fpu vme de pse lahf_lm\n"
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+//! log events of the client kept for its servers, which are sent what was
+//! logged since along with every telemetry answer, so the logs of a device
+//! can be followed from the web interface of the server
+//!
+//! answering with the telemetry keeps the connection to a single writer, at
+//! the cost of records arriving as often as the server asks for telemetry
+
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use pdtcore::{LogLevel, LogRecord, Particularity};
+use tracing::{Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// records kept for servers that did not ask in a while, older ones are
+/// dropped
+const CAPACITY: usize = 500;
+
+/// `FORWARD_LOG_LEVEL`, the least severe level sent to servers, `info` unless
+/// set and nothing when `off`
+pub fn level() -> Option<LogLevel> {
+    match std::env::var("FORWARD_LOG_LEVEL") {
+        Ok(level) if level == "off" => None,
+        Ok(level) => Some(level.parse().unwrap_or(LogLevel::Info)),
+        Err(_) => Some(LogLevel::Info),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    /// sequence number of the first record kept
+    first: u64,
+    records: VecDeque<LogRecord>,
+}
+
+/// the most recent log records, numbered so every server connection takes
+/// what it was not sent yet
+#[derive(Debug, Clone)]
+pub struct ForwardedLogs {
+    level: LogLevel,
+    records: Particularity<Records>,
+}
+
+impl ForwardedLogs {
+    pub fn new(level: LogLevel) -> Self {
+        Self {
+            level,
+            records: Arc::new(Mutex::new(Records::default())),
+        }
+    }
+
+    fn push(&self, record: LogRecord) {
+        let mut records = self.records.lock();
+
+        if records.records.len() == CAPACITY {
+            records.records.pop_front();
+            records.first += 1;
+        }
+
+        records.records.push_back(record);
+    }
+
+    /// records from sequence number `next` on, with the number to ask from
+    /// the next time
+    pub fn since(&self, next: u64) -> (Vec<LogRecord>, u64) {
+        let records = self.records.lock();
+
+        let skip = next.saturating_sub(records.first) as usize;
+        let taken = records.records.iter().skip(skip).cloned().collect();
+
+        (taken, records.first + records.records.len() as u64)
+    }
+}
+
+fn log_level(level: &Level) -> LogLevel {
+    match *level {
+        Level::ERROR => LogLevel::Error,
+        Level::WARN => LogLevel::Warn,
+        Level::INFO => LogLevel::Info,
+        Level::DEBUG => LogLevel::Debug,
+        Level::TRACE => LogLevel::Trace,
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ForwardedLogs {
+    fn on_event(&self, event: &tracing::Event<'_>, _context: Context<'_, S>) {
+        let level = log_level(event.metadata().level());
+
+        if level > self.level {
+            return;
+        }
+
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        self.push(LogRecord {
+            at,
+            level,
+            target: event.metadata().target().to_string(),
+            message: pdttracing::fields::line(event),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{debug, info, warn};
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn servers_take_what_they_were_not_sent() {
+        let logs = ForwardedLogs::new(LogLevel::Info);
+
+        let subscriber = tracing_subscriber::Registry::default().with(logs.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            info!(target: "pdtclient", retry = 1, "reconnected");
+            debug!(target: "pdtclient", "too verbose to forward");
+        });
+
+        let (records, next) = logs.since(0);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].level, LogLevel::Info);
+        assert_eq!(records[0].message, "reconnected retry=1");
+
+        tracing::subscriber::with_default(
+            tracing_subscriber::Registry::default().with(logs.clone()),
+            || warn!(target: "pdtclient", "connection lost"),
+        );
+
+        let (records, next) = logs.since(next);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message, "connection lost");
+        assert!(logs.since(next).0.is_empty());
+    }
+
+    #[test]
+    fn dropped_records_are_skipped() {
+        let logs = ForwardedLogs::new(LogLevel::Trace);
+
+        for at in 0..CAPACITY as u64 + 10 {
+            logs.push(LogRecord {
+                at,
+                level: LogLevel::Info,
+                target: "pdtclient".to_string(),
+                message: String::new(),
+            });
+        }
+
+        let (records, next) = logs.since(3);
+
+        assert_eq!(records.len(), CAPACITY);
+        assert_eq!(records[0].at, 10);
+        assert_eq!(next, CAPACITY as u64 + 10);
+    }
+
+    #[test]
+    fn records_are_kept_after_a_thread_panicked_holding_them() {
+        let logs = ForwardedLogs::new(LogLevel::Info);
+        let held = logs.clone();
+
+        let thread = std::thread::spawn(move || {
+            let _records = held.records.lock();
+            panic!("thread failed while holding the records");
+        });
+
+        assert!(thread.join().is_err());
+
+        logs.push(LogRecord {
+            at: 0,
+            level: LogLevel::Warn,
+            target: "pdtclient".to_string(),
+            message: "still forwarding".to_string(),
+        });
+
+        let (records, _) = logs.since(0);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message, "still forwarding");
+    }
+}
@@ -1,37 +1,133 @@
+use std::io::ErrorKind;
 use std::net::{SocketAddr, TcpStream};
-use std::process::Command;
+use std::path::Path;
+use std::process::{Command, ExitCode};
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use pdtcore::codec::{MessageReader, MessageWriter};
+use pdtcore::recording::{Direction, Recorder};
 use pdtcore::*;
-use tracing::{info, instrument, warn};
-use tracing_subscriber::filter::LevelFilter;
-use tracing_subscriber::prelude::*;
-use tracing_subscriber::{EnvFilter, Registry};
+use pdttracing::otel;
+use tracing::{debug, info, info_span, instrument, warn};
 
+mod control;
+mod crash;
+mod docker;
+mod identity;
+mod in_flight;
+mod inventory;
+mod log_forward;
+mod servers;
+mod shutdown;
+mod welcome;
+
+use crash::Crashes;
+use docker::Docker;
+use in_flight::InFlight;
+use log_forward::ForwardedLogs;
+use servers::{Attachments, Capabilities, ServerConfig};
+use shutdown::Shutdown;
+
+/// the connection to one of the configured servers
 #[derive(Debug)]
 struct ClientConnection {
-    addr: SocketAddr,
+    server: ServerConfig,
+    /// position of the server among the configured ones
+    index: usize,
+    attachments: Attachments,
+    in_flight: InFlight,
+    crashes: Crashes,
+    forwarded_logs: Option<ForwardedLogs>,
+    docker: Option<Docker>,
+    shutdown: Shutdown,
+    recorder: Option<Recorder>,
+    /// connection settings proposed to the server
+    protocol: ProtocolConfig,
+    /// presented to the server after the introduction
+    identity: String,
 }
 
-impl ClientConnection {
-    fn connect(self) -> Result<(Client, TcpStream), ClientError> {
-        let tcp_stream = TcpStream::connect(self.addr).map_err(ClientError::Connect)?;
-        let read = tcp_stream.try_clone().map_err(ClientError::Connect)?;
+/// connect to the server, failing reads and writes that make no progress
+/// for `idle_timeout` so a half-open connection is noticed
+fn connect(addr: SocketAddr, idle_timeout: Duration) -> Result<TcpStream, ClientError> {
+    let tcp_stream = TcpStream::connect(addr).map_err(ClientError::Connect)?;
+
+    tcp_stream
+        .set_read_timeout(Some(idle_timeout))
+        .and_then(|_| tcp_stream.set_write_timeout(Some(idle_timeout)))
+        .map_err(ClientError::Connect)?;
 
-        let client = Client::new(tcp_stream)?;
+    Ok(tcp_stream)
+}
+
+/// reader and writer on clones of `tcp_stream`, reading frames up to the
+/// size the client proposed, writes keep to the default until welcomed
+fn streams(
+    tcp_stream: &TcpStream,
+    protocol: &ProtocolConfig,
+) -> Result<(MessageReader<TcpStream>, MessageWriter<TcpStream>), ClientError> {
+    let mut reader = MessageReader::new(tcp_stream.try_clone().map_err(ClientError::Connect)?);
+    reader.set_max_frame_size(protocol.max_frame_size());
 
-        Ok((client, read))
+    let writer = MessageWriter::new(tcp_stream.try_clone().map_err(ClientError::Connect)?);
+
+    Ok((reader, writer))
+}
+
+impl ClientConnection {
+    fn connect(self) -> Result<Client, ClientError> {
+        let tcp_stream = connect(self.server.address, self.protocol.idle_timeout())?;
+
+        Client::new(tcp_stream, self)
     }
 }
 
 #[derive(Debug)]
 struct Client {
+    /// where the server is, reconnected to when the connection is lost
+    address: SocketAddr,
     tcp_stream: TcpStream,
-    shutdown_request_flag_ref: Particularity<bool>,
+    /// reads from a clone of `tcp_stream`, replaced along with it
+    reader: MessageReader<TcpStream>,
+    /// writes to a clone of `tcp_stream`, replaced along with it
+    writer: MessageWriter<TcpStream>,
+    shutdown: Shutdown,
+    /// commands the server may send, others are refused
+    capabilities: Capabilities,
+    /// connection state and welcome of every server, answered on the
+    /// control socket
+    attachments: Attachments,
+    /// position of the server in `attachments`
+    index: usize,
+    /// commands being executed, reported as interrupted after a crash
+    in_flight: InFlight,
+    /// crash reports of earlier runs, sent to the first server asking for
+    /// the device info
+    crashes: Crashes,
+    /// log records sent along with the telemetry, none when not forwarding
+    forwarded_logs: Option<ForwardedLogs>,
+    /// sequence number of the first log record not sent to the server yet
+    next_log: u64,
+    /// containers sent along with the telemetry, none when not reporting
+    docker: Option<Docker>,
+    /// device info last sent in this connection, later requests are
+    /// answered with what changed since
+    sent_device_info: Option<DeviceInfo>,
+    recorder: Option<Recorder>,
+    /// connection settings proposed to the server, the ones it welcomed the
+    /// client with apply until reconnecting
+    protocol: ProtocolConfig,
+    /// key presented to the server after the introduction, see [`identity`]
+    identity: String,
+    /// telemetry the server asked for, the default until it pushes another
+    telemetry: TelemetryConfig,
+    /// when the last telemetry sample was sent in this connection
+    sampled_at: Option<Instant>,
 }
 
+// fields are only read through Debug when logging
+#[allow(dead_code)]
 #[derive(Debug)]
 enum ClientError {
     Connect(std::io::Error),
@@ -43,10 +139,34 @@ enum ClientError {
 }
 
 impl Client {
-    fn new(tcp_stream: TcpStream) -> Result<Self, ClientError> {
+    fn new(tcp_stream: TcpStream, connection: ClientConnection) -> Result<Self, ClientError> {
+        let (reader, writer) = streams(&tcp_stream, &connection.protocol)?;
+
+        connection
+            .shutdown
+            .watch(&tcp_stream)
+            .map_err(ClientError::Connect)?;
+
         Ok(Self {
-            shutdown_request_flag_ref: Arc::new(Mutex::new(false)),
+            address: connection.server.address,
+            reader,
+            writer,
+            shutdown: connection.shutdown,
+            capabilities: connection.server.capabilities,
+            attachments: connection.attachments,
+            index: connection.index,
+            in_flight: connection.in_flight,
+            crashes: connection.crashes,
+            forwarded_logs: connection.forwarded_logs,
+            docker: connection.docker,
+            next_log: 0,
+            sent_device_info: None,
             tcp_stream,
+            recorder: connection.recorder,
+            protocol: connection.protocol,
+            identity: connection.identity,
+            telemetry: TelemetryConfig::default(),
+            sampled_at: None,
         })
     }
 
@@ -55,88 +175,338 @@ impl Client {
         info!(message =? message);
 
         match message {
-            Message::Server(_) => unreachable!(),
-            Message::Client(action) => match action {
-                ClientMessage::ScreenOff => {
-                    Command::new("xset")
-                        .args(["dpms", "force", "off"])
-                        .spawn()
-                        .map_err(ClientError::Command)?
-                        .wait()
-                        .map_err(ClientError::Command)?;
-                }
-                ClientMessage::ScreenOn => {
-                    Command::new("xset")
-                        .args(["dpms", "force", "on"])
-                        .spawn()
-                        .map_err(ClientError::Command)?
-                        .wait()
-                        .map_err(ClientError::Command)?;
-                }
-                ClientMessage::PowerOff => todo!(),
-                ClientMessage::Restart => todo!(),
-                ClientMessage::Goodbye => {
-                    self.request_shutdown();
-
-                    info!("ending");
-                    self.end()?;
-                    return Ok(false);
-                }
-                ClientMessage::RequestDeviceInfo => {
-                    Message::from(ServerMessage::DeviceInfo(device_info()))
-                        .send(&mut self.tcp_stream)
-                        .map_err(ClientError::Send)?;
+            Message::Server(message) => warn!(message =? message, "ignoring server message"),
+            Message::Client(action) if self.capabilities.missing(&action).is_some() => {
+                self.refuse(action)?;
+            }
+            Message::Client(action) => {
+                let _tracked = self.in_flight.track(self.address, &action);
+
+                return self.execute(action);
+            }
+        };
+
+        Ok(true)
+    }
+
+    /// carry out a command the server has the capability for
+    fn execute(&mut self, action: ClientMessage) -> Result<bool, ClientError> {
+        match action {
+            ClientMessage::ScreenOff => {
+                Command::new("xset")
+                    .args(["dpms", "force", "off"])
+                    .spawn()
+                    .map_err(ClientError::Command)?
+                    .wait()
+                    .map_err(ClientError::Command)?;
+            }
+            ClientMessage::ScreenOn => {
+                Command::new("xset")
+                    .args(["dpms", "force", "on"])
+                    .spawn()
+                    .map_err(ClientError::Command)?
+                    .wait()
+                    .map_err(ClientError::Command)?;
+            }
+            ClientMessage::PowerOff => {
+                Command::new("systemctl")
+                    .arg("poweroff")
+                    .spawn()
+                    .map_err(ClientError::Command)?
+                    .wait()
+                    .map_err(ClientError::Command)?;
+            }
+            ClientMessage::Restart => {
+                Command::new("systemctl")
+                    .arg("reboot")
+                    .spawn()
+                    .map_err(ClientError::Command)?
+                    .wait()
+                    .map_err(ClientError::Command)?;
+            }
+            // ends this server's connection only, the others are kept
+            ClientMessage::Goodbye => return Ok(false),
+            ClientMessage::RequestDeviceInfo => {
+                self.send_device_info(device_info())?;
+                self.report_crashes()?;
+            }
+            ClientMessage::RequestTelemetry => {
+                self.answer_telemetry()?;
+                self.forward_logs()?;
+                self.report_containers()?;
+            }
+            ClientMessage::Notify(notification) => {
+                Command::new("notify-send")
+                    .args([notification.title, notification.body])
+                    .spawn()
+                    .map_err(ClientError::Command)?
+                    .wait()
+                    .map_err(ClientError::Command)?;
+            }
+            ClientMessage::Welcome(welcome) => {
+                self.agree(welcome.protocol)?;
+                self.keep_welcome(welcome);
+                self.report_interrupted()?;
+            }
+            // no namespaces are known to the stock client
+            ClientMessage::Custom { namespace, .. } => {
+                debug!(namespace, "custom message of an unknown namespace");
+
+                self.send(ServerMessage::Nack { namespace })?;
+            }
+            ClientMessage::Nack { namespace } => {
+                debug!(namespace, "custom message refused by the server");
+            }
+            ClientMessage::ConfigUpdate(telemetry) => {
+                info!(telemetry =? telemetry, "telemetry configured by the server");
+
+                self.telemetry = telemetry;
+            }
+            ClientMessage::Traced(trace, message) => {
+                let command = format!("{message:?}");
+
+                let span = info_span!("execute", command);
+                otel::set_parent(&span, &trace);
+
+                let result = span.in_scope(|| self.handle_message(Message::Client(*message)));
+
+                if let Ok(false) = result {
+                    return result;
                 }
-            },
+
+                self.send(ServerMessage::Executed(CommandExecution {
+                    trace,
+                    command,
+                    error: result.as_ref().err().map(|error| format!("{error:?}")),
+                }))?;
+
+                return result;
+            }
         };
 
         Ok(true)
     }
 
-    #[instrument(skip_all)]
-    fn request_shutdown(&mut self) {
-        let mut guard = self.shutdown_request_flag_ref.lock().unwrap();
-        let shutdown_request_ref = &mut *guard;
-        *shutdown_request_ref = false;
+    fn send(&mut self, message: ServerMessage) -> Result<(), ClientError> {
+        let message = Message::from(message);
+
+        self.writer.send(&message).map_err(ClientError::Send)?;
+
+        self.record(Direction::Sent, &message);
+
+        Ok(())
     }
 
-    #[instrument(skip_all)]
-    fn shutdown_requested(&self) -> bool {
-        let guard = self.shutdown_request_flag_ref.lock().unwrap();
-        *guard
+    /// refuse a command the server lacks the capability for, telling the
+    /// server when it traced the command
+    fn refuse(&mut self, action: ClientMessage) -> Result<(), ClientError> {
+        let Some(capability) = self.capabilities.missing(&action) else {
+            return Ok(());
+        };
+
+        warn!(message =? action, capability = %capability, "command not permitted for this server");
+
+        if let ClientMessage::Traced(trace, message) = action {
+            self.send(ServerMessage::Executed(CommandExecution {
+                trace,
+                command: format!("{message:?}"),
+                error: Some(format!("not permitted, needs {capability}")),
+            }))?;
+        }
+
+        Ok(())
     }
 
-    #[instrument(skip_all)]
-    fn end(&mut self) -> Result<(), ClientError> {
+    /// keep to the connection settings the server welcomed the client with
+    fn agree(&mut self, protocol: ProtocolConfig) -> Result<(), ClientError> {
+        info!(protocol =? protocol, "connection settings agreed on");
+
         self.tcp_stream
-            .shutdown(std::net::Shutdown::Both)
-            .map_err(ClientError::Shutdown)?;
+            .set_read_timeout(Some(protocol.idle_timeout()))
+            .and_then(|_| {
+                self.tcp_stream
+                    .set_write_timeout(Some(protocol.idle_timeout()))
+            })
+            .map_err(ClientError::Connect)?;
+
+        self.writer.set_max_frame_size(protocol.max_frame_size());
 
         Ok(())
     }
 
+    /// send a sample when one is due, or tell the server the client is
+    /// still there
+    ///
+    /// requests arrive every heartbeat, a sample is due half a heartbeat
+    /// before the interval passed so it is not put off by a whole one
+    fn answer_telemetry(&mut self) -> Result<(), ClientError> {
+        let due = self.sampled_at.is_none_or(|sampled_at| {
+            sampled_at.elapsed()
+                >= self
+                    .telemetry
+                    .interval()
+                    .saturating_sub(HEARTBEAT_INTERVAL / 2)
+        });
+
+        if !due {
+            return self.send(ServerMessage::Alive);
+        }
+
+        match telemetry(&self.telemetry) {
+            Some(telemetry) => {
+                self.sampled_at = Some(Instant::now());
+
+                self.send(ServerMessage::Telemetry(telemetry))
+            }
+            None => self.send(ServerMessage::Alive),
+        }
+    }
+
+    /// keep the welcome for the control socket and in the welcome file,
+    /// failing to write it is logged but does not affect the connection
+    fn keep_welcome(&self, welcome: Welcome) {
+        info!(
+            assigned_id = welcome.assigned_id,
+            server_name = welcome.server.name,
+            server_version = welcome.server.version,
+            server_up_since = %humantime::format_rfc3339_seconds(welcome.server.up_since()),
+            "welcomed"
+        );
+
+        if let Err(error) = self
+            .attachments
+            .welcome(self.index, welcome, &welcome::path())
+        {
+            warn!(error =? error, "saving welcome");
+        }
+    }
+
+    /// tell the server about the commands it sent that were cut short by
+    /// the client stopping, once it welcomed the client again
+    fn report_interrupted(&mut self) -> Result<(), ClientError> {
+        for execution in self.in_flight.interrupted(self.address) {
+            warn!(command = execution.command, "reporting interrupted command");
+
+            self.send(ServerMessage::Executed(execution))?;
+        }
+
+        Ok(())
+    }
+
+    /// tell the server about the crashes of earlier runs not reported yet,
+    /// after the device info so the server knows which device crashed
+    fn report_crashes(&mut self) -> Result<(), ClientError> {
+        for report in self.crashes.take() {
+            warn!(message = report.message, "reporting crash");
+
+            self.send(ServerMessage::CrashReport(Box::new(report)))?;
+        }
+
+        Ok(())
+    }
+
+    /// send the log records not sent to the server yet, records kept from
+    /// an earlier connection included
+    fn forward_logs(&mut self) -> Result<(), ClientError> {
+        let Some(forwarded_logs) = &self.forwarded_logs else {
+            return Ok(());
+        };
+
+        let (records, next) = forwarded_logs.since(self.next_log);
+
+        if !records.is_empty() {
+            self.send(ServerMessage::Logs(records))?;
+        }
+
+        self.next_log = next;
+
+        Ok(())
+    }
+
+    /// send the containers last sampled, once there are any
+    fn report_containers(&mut self) -> Result<(), ClientError> {
+        let Some(containers) = self.docker.as_ref().and_then(Docker::latest) else {
+            return Ok(());
+        };
+
+        self.send(ServerMessage::Containers(containers))
+    }
+
+    /// send the full device info the first time in a connection, what
+    /// changed since after that
+    fn send_device_info(&mut self, info: DeviceInfo) -> Result<(), ClientError> {
+        let message = match &self.sent_device_info {
+            Some(sent) => ServerMessage::DeviceInfoDelta(sent.changes(&info)),
+            None => ServerMessage::DeviceInfo(info.clone()),
+        };
+
+        self.send(message)?;
+        self.sent_device_info = Some(info);
+
+        Ok(())
+    }
+
+    /// record a message if recording, a failure is logged but does not
+    /// affect the connection
+    fn record(&self, direction: Direction, message: &Message) {
+        let Some(recorder) = &self.recorder else {
+            return;
+        };
+
+        if let Err(error) = recorder.record(direction, &self.address.to_string(), message) {
+            warn!(error =? error, "recording message");
+        }
+    }
+
+    /// shut the connection down, it may already be if shutdown was
+    /// requested
+    #[instrument(skip_all)]
+    fn end(&mut self) -> Result<(), ClientError> {
+        match self.tcp_stream.shutdown(std::net::Shutdown::Both) {
+            Err(error) if error.kind() != ErrorKind::NotConnected => {
+                Err(ClientError::Shutdown(error))
+            }
+            _ => Ok(()),
+        }
+    }
+
     #[instrument(skip_all)]
     fn introduction(&mut self) -> Result<(), ClientError> {
         let device_info = pdtcore::ClientIntroduction {
             name: String::from("ASH"),
             pdtcore_built_info: BuiltInfo::default(),
+            protocol: self.protocol,
         };
 
-        Message::from(ServerMessage::Hello(Box::new(device_info)))
-            .send(&mut self.tcp_stream)
-            .map_err(ClientError::Send)?;
+        // a new connection is a new client to the server, knowing nothing
+        // the changes could apply to and welcoming it anew
+        self.sent_device_info = None;
+        self.telemetry = TelemetryConfig::default();
+        self.sampled_at = None;
+        self.attachments.forget_welcome(self.index);
+
+        self.send(ServerMessage::Hello(Box::new(device_info)))?;
+        self.send(ServerMessage::Identity(self.identity.clone()))?;
+        self.set_connected(true);
 
         Ok(())
     }
 
+    fn set_connected(&self, connected: bool) {
+        self.attachments.set_connected(self.index, connected);
+    }
+
     fn reconnect(&mut self) -> Result<(), ClientError> {
-        if self.shutdown_requested() {
+        if self.shutdown.is_requested() {
             return Err(ClientError::Closed);
         }
-        let peer_addr = self.tcp_stream.peer_addr().map_err(ClientError::Connect)?;
-        info!(addr =? peer_addr, "reconnecting");
+        info!(addr =? self.address, "reconnecting");
 
-        self.tcp_stream = TcpStream::connect(peer_addr).map_err(ClientError::Connect)?;
+        self.tcp_stream = connect(self.address, self.protocol.idle_timeout())?;
+        self.shutdown
+            .watch(&self.tcp_stream)
+            .map_err(ClientError::Connect)?;
+        (self.reader, self.writer) = streams(&self.tcp_stream, &self.protocol)?;
 
         self.introduction()?;
 
@@ -145,20 +515,23 @@ impl Client {
 
     #[instrument(skip_all)]
     fn receive(&mut self, max_retries: usize) -> Result<Option<Message>, ClientError> {
-        if self.shutdown_requested() {
+        if self.shutdown.is_requested() {
             warn!("shutdown requested not reading more messages");
             return Ok(None);
         }
-        match Message::receive(&mut self.tcp_stream) {
+        match self.reader.receive() {
             Ok(message) => {
                 info!(message =? message);
+                self.record(Direction::Received, &message);
                 Ok(Some(message))
             }
             Err(error) => {
-                if self.shutdown_requested() {
+                if self.shutdown.is_requested() {
                     info!("shutdown requested");
                     Ok(None)
                 } else {
+                    self.set_connected(false);
+
                     let mut retry = 0;
 
                     while retry <= max_retries {
@@ -170,6 +543,7 @@ impl Client {
                                 info!(retry = retry, max_retries = max_retries, "reconnected");
                                 return self.receive(max_retries);
                             }
+                            Err(ClientError::Closed) => return Ok(None),
                             Err(_) => {
                                 if retry > 10 {
                                     std::thread::sleep(Duration::from_millis(1000 * retry as u64))
@@ -203,33 +577,169 @@ impl Client {
     }
 }
 
-fn setup_tracing() {
-    let layer = tracing_logfmt::builder().with_target(false).layer();
+/// set up the log and return the records kept for servers, if forwarding
+fn setup_tracing() -> Option<ForwardedLogs> {
+    let tracer = otel::pipeline("pdtclient").and_then(|pipeline| {
+        pipeline
+            .with_exporter(opentelemetry_otlp::new_exporter().http())
+            .install_simple()
+            .map_err(|error| eprintln!("otlp exporter: {error}"))
+            .ok()
+    });
 
-    let filter = match EnvFilter::try_from_default_env() {
-        Ok(filter) => filter,
-        _ => EnvFilter::builder()
-            .with_default_directive(LevelFilter::INFO.into())
-            .parse("")
-            .unwrap(),
-    };
+    let forwarded_logs = log_forward::level().map(ForwardedLogs::new);
+
+    pdttracing::init(tracer, forwarded_logs.clone()).expect("log format is json, logfmt or pretty");
 
-    let subscriber = Registry::default().with(layer).with(filter);
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    forwarded_logs
 }
 
 #[instrument]
-fn main() {
-    setup_tracing();
+fn main() -> ExitCode {
+    match std::env::args().nth(1).as_deref() {
+        Some("healthcheck") => {
+            return match control::check() {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(error) => {
+                    eprintln!("{error}");
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Some("server") => {
+            return match control::servers() {
+                Ok(servers) => {
+                    for (address, welcome) in servers {
+                        match welcome {
+                            Some(welcome) => {
+                                println!("{address}: {}", welcome::summary(&welcome))
+                            }
+                            None => println!("{address}: not welcomed"),
+                        }
+                    }
 
-    let addr = SocketAddr::from_str("127.0.0.1:2039").unwrap();
+                    ExitCode::SUCCESS
+                }
+                Err(error) => {
+                    eprintln!("{error}");
 
-    let (mut client, _) = ClientConnection { addr }.connect().unwrap();
+                    for welcome in welcome::load(&welcome::path()).unwrap_or_default() {
+                        println!("last welcomed by {}", welcome::summary(&welcome));
+                    }
 
-    match client.run() {
-        Ok(_) => info!("goodbye"),
-        Err(error) => warn!(error =? error, "exited"),
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Some("stop") => {
+            return match control::stop() {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(error) => {
+                    eprintln!("{error}");
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Some("--version" | "-V") => {
+            println!("pdtclient\n{}", BuiltInfo::default());
+            return ExitCode::SUCCESS;
+        }
+        _ => {}
+    }
+
+    let forwarded_logs = setup_tracing();
+
+    crash::install(crash::dir());
+
+    let servers = std::env::var("SERVERS")
+        .ok()
+        .map(|servers| {
+            ServerConfig::parse_list(&servers)
+                .expect("SERVERS lists addresses, each with optional capabilities")
+        })
+        .filter(|servers| !servers.is_empty())
+        .unwrap_or_else(|| {
+            vec![ServerConfig {
+                address: SocketAddr::from_str("127.0.0.1:2039").unwrap(),
+                capabilities: Capabilities::default(),
+            }]
+        });
+
+    // every message is recorded to this file for replaying with pdt-replay
+    let recorder = std::env::var_os("RECORD_PATH")
+        .map(|path| Recorder::create(Path::new(&path)).expect("recording file can be opened"));
+
+    let mut protocol = ProtocolConfig::default();
+
+    if let Some(timeout) = std::env::var("SERVER_IDLE_TIMEOUT")
+        .ok()
+        .and_then(|timeout| humantime::parse_duration(&timeout).ok())
+    {
+        protocol = protocol.with_idle_timeout(timeout);
     }
+
+    if let Some(size) = std::env::var("MAX_FRAME_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+    {
+        protocol = protocol.with_max_frame_size(size);
+    }
+
+    let shutdown = Shutdown::default();
+    let attachments = Attachments::new(servers.iter().map(|server| server.address));
+    let in_flight = InFlight::load(in_flight::path());
+    let identity =
+        identity::load_or_create(&identity::path()).expect("identity can be read or created");
+    let crashes = Crashes::new(crash::dir());
+    let docker = docker::socket().map(Docker::spawn);
+
+    if let Err(error) = shutdown.on_signals() {
+        warn!(error =? error, "signal handlers unavailable");
+    }
+
+    if let Err(error) = control::serve(&control::path(), attachments.clone(), shutdown.clone()) {
+        warn!(error =? error, "control socket unavailable, healthcheck will fail");
+    }
+
+    let sessions: Vec<_> = servers
+        .into_iter()
+        .enumerate()
+        .map(|(index, server)| {
+            let connection = ClientConnection {
+                server,
+                index,
+                attachments: attachments.clone(),
+                in_flight: in_flight.clone(),
+                crashes: crashes.clone(),
+                forwarded_logs: forwarded_logs.clone(),
+                docker: docker.clone(),
+                shutdown: shutdown.clone(),
+                recorder: recorder.clone(),
+                protocol,
+                identity: identity.clone(),
+            };
+
+            std::thread::spawn(move || {
+                let span = info_span!("server", address = %connection.server.address);
+                let _entered = span.enter();
+
+                match connection.connect().and_then(|mut client| client.run()) {
+                    Ok(_) => info!("goodbye"),
+                    Err(error) => warn!(error =? error, "exited"),
+                }
+            })
+        })
+        .collect();
+
+    for session in sessions {
+        if session.join().is_err() {
+            warn!("server connection panicked");
+        }
+    }
+
+    otel::shutdown();
+
+    ExitCode::SUCCESS
 }
 
 fn device_info() -> DeviceInfo {
@@ -241,11 +751,299 @@ fn device_info() -> DeviceInfo {
     let sys_info = sysinfo().unwrap();
     let uptime = sys_info.uptime();
     let formatted_uptime = format_duration(uptime);
+    let inventory = inventory::read();
 
     DeviceInfo {
         name: uts_name.nodename().to_string_lossy().to_string(),
         os: uts_name.sysname().to_string_lossy().to_string(),
         os_version: uts_name.release().to_string_lossy().to_string(),
         uptime: formatted_uptime.to_string(),
+        vendor: inventory.vendor,
+        model: inventory.model,
+        serial: inventory.serial,
+        chassis: inventory.chassis,
+        virtualization: inventory.virtualization,
+    }
+}
+
+/// the families `config` collects, the others left at zero, none when the
+/// load and memory of the system cannot be read, the sample is skipped
+/// rather than reported as idle
+fn telemetry(config: &TelemetryConfig) -> Option<Telemetry> {
+    use nix::sys::sysinfo::sysinfo;
+
+    let sys_info = match sysinfo() {
+        Ok(sys_info) => sys_info,
+        Err(error) => {
+            warn!(error =? error, "reading system load, skipping the sample");
+            return None;
+        }
+    };
+    let (load, _, _) = sys_info.load_average();
+
+    let (network_received, network_transmitted) = if config.collects(MetricFamily::Network) {
+        network_totals().unwrap_or_else(|error| {
+            warn!(error =? error, "reading network statistics");
+            (0, 0)
+        })
+    } else {
+        (0, 0)
+    };
+
+    let memory = config.collects(MetricFamily::Memory);
+
+    Some(Telemetry {
+        load: if config.collects(MetricFamily::Load) {
+            load
+        } else {
+            0.0
+        },
+        memory_total: if memory { sys_info.ram_total() } else { 0 },
+        memory_used: if memory {
+            sys_info.ram_total().saturating_sub(sys_info.ram_unused())
+        } else {
+            0
+        },
+        network_received,
+        network_transmitted,
+    })
+}
+
+/// received and transmitted bytes summed over every interface but loopback
+fn network_totals() -> std::io::Result<(u64, u64)> {
+    let dev = std::fs::read_to_string("/proc/net/dev")?;
+
+    let totals = dev
+        .lines()
+        .skip(2)
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(interface, _)| interface.trim() != "lo")
+        .map(|(_, counters)| {
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .map(|counter| counter.parse().unwrap_or(0))
+                .collect();
+
+            (
+                counters.first().copied().unwrap_or(0),
+                counters.get(8).copied().unwrap_or(0),
+            )
+        })
+        .fold((0, 0), |(received, transmitted), (rx, tx)| {
+            (received + rx, transmitted + tx)
+        });
+
+    Ok(totals)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use tracing_subscriber::{prelude::*, Registry};
+
+    use super::*;
+
+    fn connected() -> (Client, MessageReader<TcpStream>) {
+        connected_with(Capabilities::default())
+    }
+
+    fn connected_with(capabilities: Capabilities) -> (Client, MessageReader<TcpStream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let tcp_stream = TcpStream::connect(address).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let connection = ClientConnection {
+            server: ServerConfig {
+                address,
+                capabilities,
+            },
+            index: 0,
+            attachments: Attachments::new([address]),
+            in_flight: InFlight::load(std::env::temp_dir().join(format!(
+                "pdtclient-{}-{}.inflight",
+                std::process::id(),
+                address.port()
+            ))),
+            crashes: Crashes::new(std::env::temp_dir().join(format!(
+                "pdtclient-{}-{}.crashes",
+                std::process::id(),
+                address.port()
+            ))),
+            forwarded_logs: Some(ForwardedLogs::new(LogLevel::Info)),
+            docker: None,
+            shutdown: Shutdown::default(),
+            recorder: None,
+            protocol: ProtocolConfig::default(),
+            identity: "key".to_string(),
+        };
+
+        let client = Client::new(tcp_stream, connection).unwrap();
+
+        (client, MessageReader::new(server))
+    }
+
+    #[test]
+    fn device_info_is_sent_in_full_then_as_changes() {
+        let (mut client, mut server) = connected();
+
+        let info = DeviceInfo::default();
+        let later = DeviceInfo {
+            uptime: "1h".to_string(),
+            ..DeviceInfo::default()
+        };
+
+        client.send_device_info(info.clone()).unwrap();
+        client.send_device_info(later.clone()).unwrap();
+
+        assert_eq!(
+            server.receive().unwrap(),
+            Message::from(ServerMessage::DeviceInfo(info.clone()))
+        );
+        assert_eq!(
+            server.receive().unwrap(),
+            Message::from(ServerMessage::DeviceInfoDelta(info.changes(&later)))
+        );
+
+        client.introduction().unwrap();
+        client.send_device_info(later.clone()).unwrap();
+
+        assert!(matches!(
+            server.receive().unwrap(),
+            Message::Server(ServerMessage::Hello(_))
+        ));
+        assert_eq!(
+            server.receive().unwrap(),
+            Message::from(ServerMessage::Identity("key".to_string()))
+        );
+        assert_eq!(
+            server.receive().unwrap(),
+            Message::from(ServerMessage::DeviceInfo(later))
+        );
+    }
+
+    #[test]
+    fn welcome_is_kept_until_reintroduced() {
+        let (mut client, _server) = connected();
+
+        let welcome = Welcome {
+            assigned_id: "01H0000000000000000000000".to_string(),
+            server: ServerInfo {
+                name: "home".to_string(),
+                version: "0.1.0".to_string(),
+                up_since: 1_700_000_000,
+            },
+            protocol: ProtocolConfig::default(),
+        };
+
+        std::env::set_var(
+            "WELCOME_PATH",
+            std::env::temp_dir().join(format!("pdtclient-{}-main.welcome", std::process::id())),
+        );
+
+        assert!(client
+            .handle_message(ClientMessage::Welcome(welcome.clone()).into())
+            .unwrap());
+        assert_eq!(client.attachments.get()[0].welcome, Some(welcome.clone()));
+        assert_eq!(welcome::load(&welcome::path()).unwrap(), vec![welcome]);
+
+        client.introduction().unwrap();
+
+        assert_eq!(client.attachments.get()[0].welcome, None);
+    }
+
+    #[test]
+    fn traced_command_without_capability_is_refused() {
+        let (mut client, mut server) = connected_with("view".parse().unwrap());
+
+        let trace = TraceContext {
+            traceparent: "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_string(),
+        };
+
+        assert!(client
+            .handle_message(
+                ClientMessage::Traced(trace.clone(), Box::new(ClientMessage::PowerOff)).into()
+            )
+            .unwrap());
+
+        assert_eq!(
+            server.receive().unwrap(),
+            Message::from(ServerMessage::Executed(CommandExecution {
+                trace,
+                command: "PowerOff".to_string(),
+                error: Some(format!(
+                    "not permitted, needs {}",
+                    servers::Capability::Power
+                )),
+            }))
+        );
+    }
+
+    #[test]
+    fn log_records_are_sent_once_along_with_the_telemetry() {
+        let (mut client, mut server) = connected();
+
+        let forwarded_logs = client.forwarded_logs.clone().unwrap();
+
+        tracing::subscriber::with_default(Registry::default().with(forwarded_logs), || {
+            warn!(retry = 1, "connection lost")
+        });
+
+        client.forward_logs().unwrap();
+        client.forward_logs().unwrap();
+        client.send(ServerMessage::Goodbye).unwrap();
+
+        let Message::Server(ServerMessage::Logs(records)) = server.receive().unwrap() else {
+            panic!("log records expected");
+        };
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message, "connection lost retry=1");
+        assert_eq!(
+            server.receive().unwrap(),
+            Message::from(ServerMessage::Goodbye)
+        );
+    }
+
+    #[test]
+    fn telemetry_is_sampled_at_the_configured_interval() {
+        let (mut client, mut server) = connected();
+
+        client
+            .handle_message(
+                ClientMessage::ConfigUpdate(TelemetryConfig {
+                    interval: 60_000,
+                    families: vec![MetricFamily::Memory],
+                })
+                .into(),
+            )
+            .unwrap();
+
+        client.answer_telemetry().unwrap();
+        client.answer_telemetry().unwrap();
+
+        let Message::Server(ServerMessage::Telemetry(telemetry)) = server.receive().unwrap() else {
+            panic!("telemetry expected");
+        };
+
+        assert!(telemetry.memory_total > 0);
+        assert_eq!(telemetry.load, 0.0);
+        assert_eq!(telemetry.network_received, 0);
+        assert_eq!(
+            server.receive().unwrap(),
+            Message::from(ServerMessage::Alive)
+        );
+    }
+
+    #[test]
+    fn goodbye_ends_only_the_connection_of_its_server() {
+        let (mut client, _server) = connected();
+
+        assert!(!client
+            .handle_message(ClientMessage::Goodbye.into())
+            .unwrap());
+        assert!(!client.shutdown.is_requested());
+        assert!(client.end().is_ok());
     }
 }
@@ -0,0 +1,278 @@
+//! the servers the client reports to, each over its own connection and each
+//! limited to the commands it was given the capabilities for
+//!
+//! configured with `SERVERS`, a comma separated list of addresses each
+//! optionally followed by `=` and the capabilities joined by `+`, like
+//! `192.0.2.1:2039,198.51.100.1:2039=view+notify`, servers without a list
+//! may ask for everything
+
+use std::{collections::BTreeSet, fmt, net::SocketAddr, path::Path, str::FromStr, sync::Arc};
+
+use parking_lot::Mutex;
+use pdtcore::{ClientMessage, Particularity, Welcome};
+
+use crate::welcome;
+
+/// what a server may ask of the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Capability {
+    /// device info and telemetry
+    View,
+    /// turning the screen on and off
+    Screen,
+    /// powering off and restarting
+    Power,
+    Notify,
+}
+
+impl Capability {
+    const ALL: [Capability; 4] = [
+        Capability::View,
+        Capability::Screen,
+        Capability::Power,
+        Capability::Notify,
+    ];
+
+    /// capability `message` needs, none for those every server may send
+    pub fn required_by(message: &ClientMessage) -> Option<Self> {
+        match message {
+            ClientMessage::RequestDeviceInfo | ClientMessage::RequestTelemetry => {
+                Some(Capability::View)
+            }
+            ClientMessage::ScreenOff | ClientMessage::ScreenOn => Some(Capability::Screen),
+            ClientMessage::PowerOff | ClientMessage::Restart => Some(Capability::Power),
+            ClientMessage::Notify(_) => Some(Capability::Notify),
+            ClientMessage::Traced(_, message) => Self::required_by(message),
+            ClientMessage::Goodbye
+            | ClientMessage::Welcome(_)
+            | ClientMessage::ConfigUpdate(_)
+            | ClientMessage::Custom { .. }
+            | ClientMessage::Nack { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capability::View => write!(f, "view"),
+            Capability::Screen => write!(f, "screen"),
+            Capability::Power => write!(f, "power"),
+            Capability::Notify => write!(f, "notify"),
+        }
+    }
+}
+
+impl FromStr for Capability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Capability::ALL
+            .into_iter()
+            .find(|capability| capability.to_string() == s)
+            .ok_or_else(|| format!("unknown capability {s}"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities(BTreeSet<Capability>);
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self(Capability::ALL.into_iter().collect())
+    }
+}
+
+impl Capabilities {
+    /// capability `message` needs but was not given
+    pub fn missing(&self, message: &ClientMessage) -> Option<Capability> {
+        Capability::required_by(message).filter(|capability| !self.0.contains(capability))
+    }
+}
+
+impl FromStr for Capabilities {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split('+')
+            .map(|capability| capability.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub address: SocketAddr,
+    pub capabilities: Capabilities,
+}
+
+impl ServerConfig {
+    /// parse a comma separated list of servers, see the module
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, String> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (address, capabilities) = match entry.split_once('=') {
+                    Some((address, capabilities)) => (address, capabilities.parse()?),
+                    None => (entry, Capabilities::default()),
+                };
+
+                let address = address
+                    .trim()
+                    .parse()
+                    .map_err(|error| format!("server address {address}: {error}"))?;
+
+                Ok(Self {
+                    address,
+                    capabilities,
+                })
+            })
+            .collect()
+    }
+}
+
+/// what the client knows of a server it reports to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub address: SocketAddr,
+    /// introduced and not yet lost the connection
+    pub connected: bool,
+    /// welcome of the server in this connection
+    pub welcome: Option<Welcome>,
+}
+
+/// every server's attachment, shared between the connections and the
+/// control socket, indexed in the order the servers were configured
+#[derive(Debug, Clone, Default)]
+pub struct Attachments(Particularity<Vec<Attachment>>);
+
+impl Attachments {
+    pub fn new(addresses: impl IntoIterator<Item = SocketAddr>) -> Self {
+        let attachments = addresses
+            .into_iter()
+            .map(|address| Attachment {
+                address,
+                connected: false,
+                welcome: None,
+            })
+            .collect();
+
+        Self(Arc::new(Mutex::new(attachments)))
+    }
+
+    fn update(&self, index: usize, update: impl FnOnce(&mut Attachment)) {
+        if let Some(attachment) = self.0.lock().get_mut(index) {
+            update(attachment);
+        }
+    }
+
+    pub fn set_connected(&self, index: usize, connected: bool) {
+        self.update(index, |attachment| attachment.connected = connected);
+    }
+
+    /// keep the welcome of a server and save the welcomes of every server
+    /// to `path`, under the lock so concurrent welcomes are saved in order
+    pub fn welcome(&self, index: usize, welcome: Welcome, path: &Path) -> std::io::Result<()> {
+        let mut attachments = self.0.lock();
+
+        if let Some(attachment) = attachments.get_mut(index) {
+            attachment.welcome = Some(welcome);
+        }
+
+        let welcomes: Vec<_> = attachments
+            .iter()
+            .filter_map(|attachment| attachment.welcome.clone())
+            .collect();
+
+        welcome::save(path, &welcomes)
+    }
+
+    /// forget the welcome of a server, the saved welcomes are kept until the
+    /// next one
+    pub fn forget_welcome(&self, index: usize) {
+        self.update(index, |attachment| attachment.welcome = None);
+    }
+
+    pub fn get(&self) -> Vec<Attachment> {
+        self.0.lock().clone()
+    }
+
+    /// healthy only when reporting to every server
+    pub fn all_connected(&self) -> bool {
+        let attachments = self.get();
+
+        !attachments.is_empty() && attachments.iter().all(|attachment| attachment.connected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pdtcore::{Notification, TraceContext};
+
+    use super::*;
+
+    #[test]
+    fn servers_parse_with_and_without_capabilities() {
+        let servers =
+            ServerConfig::parse_list("192.0.2.1:2039, 198.51.100.1:2039=view+notify").unwrap();
+
+        assert_eq!(
+            servers,
+            vec![
+                ServerConfig {
+                    address: "192.0.2.1:2039".parse().unwrap(),
+                    capabilities: Capabilities::default(),
+                },
+                ServerConfig {
+                    address: "198.51.100.1:2039".parse().unwrap(),
+                    capabilities: Capabilities(
+                        [Capability::View, Capability::Notify].into_iter().collect()
+                    ),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_capability_is_rejected() {
+        assert!(ServerConfig::parse_list("192.0.2.1:2039=view+everything").is_err());
+    }
+
+    #[test]
+    fn commands_need_their_capability() {
+        let view: Capabilities = "view".parse().unwrap();
+
+        assert_eq!(view.missing(&ClientMessage::RequestTelemetry), None);
+        assert_eq!(view.missing(&ClientMessage::Goodbye), None);
+        assert_eq!(
+            view.missing(&ClientMessage::PowerOff),
+            Some(Capability::Power)
+        );
+        assert_eq!(
+            view.missing(&ClientMessage::Traced(
+                TraceContext::default(),
+                Box::new(ClientMessage::Notify(Notification {
+                    title: "title".to_string(),
+                    body: "body".to_string(),
+                }))
+            )),
+            Some(Capability::Notify)
+        );
+    }
+
+    #[test]
+    fn healthy_once_every_server_is_connected() {
+        let attachments = Attachments::new([
+            "192.0.2.1:2039".parse().unwrap(),
+            "198.51.100.1:2039".parse().unwrap(),
+        ]);
+
+        attachments.set_connected(0, true);
+        assert!(!attachments.all_connected());
+
+        attachments.set_connected(1, true);
+        assert!(attachments.all_connected());
+    }
+}
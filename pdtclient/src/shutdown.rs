@@ -0,0 +1,188 @@
+//! the client stopping for good, asked for by SIGINT or SIGTERM or through
+//! the control socket, a server saying goodbye ends only its own connection
+//!
+//! once requested the client stops reading messages and does not reconnect,
+//! the connections are shut down so reads waiting on the servers return
+
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use nix::{
+    libc::c_int,
+    sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
+};
+use pdtcore::Particularity;
+use tracing::{info, warn};
+
+/// how often signals caught are checked for
+const SIGNAL_POLL: Duration = Duration::from_millis(100);
+
+/// set from the signal handler, where nothing but atomics may be touched
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: c_int) {
+    SIGNALLED.store(true, Ordering::SeqCst);
+}
+
+/// shared by everything that may stop the client, clones request the same
+/// shutdown
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    /// connection to each server, replaced on reconnect
+    connections: Particularity<HashMap<SocketAddr, TcpStream>>,
+}
+
+impl Shutdown {
+    pub fn request(&self) {
+        if self.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        info!("shutdown requested");
+
+        for stream in self.connections.lock().values() {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// shut down `stream` once shutdown is requested, or right away if it
+    /// already was, replacing the connection watched to the same server
+    pub fn watch(&self, stream: &TcpStream) -> std::io::Result<()> {
+        let server = stream.peer_addr()?;
+        let stream = stream.try_clone()?;
+
+        let mut connections = self.connections.lock();
+
+        if self.is_requested() {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+
+        connections.insert(server, stream);
+
+        Ok(())
+    }
+
+    /// request shutdown on SIGINT and SIGTERM
+    pub fn on_signals(&self) -> nix::Result<()> {
+        let action = SigAction::new(
+            SigHandler::Handler(on_signal),
+            SaFlags::empty(),
+            SigSet::empty(),
+        );
+
+        for signal in [Signal::SIGINT, Signal::SIGTERM] {
+            // SAFETY: the handler only stores to an atomic
+            unsafe { signal::sigaction(signal, &action) }?;
+        }
+
+        let shutdown = self.clone();
+
+        std::thread::spawn(move || {
+            while !SIGNALLED.load(Ordering::SeqCst) {
+                if shutdown.is_requested() {
+                    return;
+                }
+
+                std::thread::sleep(SIGNAL_POLL);
+            }
+
+            warn!("signalled");
+            shutdown.request();
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::TcpListener, time::Instant};
+
+    use super::*;
+
+    fn connected() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        (client, server)
+    }
+
+    #[test]
+    fn clones_share_the_request() {
+        let shutdown = Shutdown::default();
+
+        shutdown.clone().request();
+
+        assert!(shutdown.is_requested());
+    }
+
+    #[test]
+    fn request_ends_a_waiting_read() {
+        let (mut client, _server) = connected();
+
+        let shutdown = Shutdown::default();
+        shutdown.watch(&client).unwrap();
+
+        let requester = shutdown.clone();
+        std::thread::spawn(move || requester.request());
+
+        let mut buffer = [0; 1];
+        assert!(matches!(client.read(&mut buffer), Ok(0) | Err(_)));
+    }
+
+    #[test]
+    fn request_ends_waiting_reads_of_every_server() {
+        let (mut home, _home_server) = connected();
+        let (mut office, _office_server) = connected();
+
+        let shutdown = Shutdown::default();
+        shutdown.watch(&home).unwrap();
+        shutdown.watch(&office).unwrap();
+
+        shutdown.request();
+
+        let mut buffer = [0; 1];
+        assert!(matches!(home.read(&mut buffer), Ok(0) | Err(_)));
+        assert!(matches!(office.read(&mut buffer), Ok(0) | Err(_)));
+    }
+
+    #[test]
+    fn connection_watched_after_the_request_is_shut_down() {
+        let (mut client, _server) = connected();
+
+        let shutdown = Shutdown::default();
+        shutdown.request();
+        shutdown.watch(&client).unwrap();
+
+        let mut buffer = [0; 1];
+        assert!(matches!(client.read(&mut buffer), Ok(0) | Err(_)));
+    }
+
+    #[test]
+    fn signal_requests_shutdown() {
+        let shutdown = Shutdown::default();
+        shutdown.on_signals().unwrap();
+
+        signal::raise(Signal::SIGTERM).unwrap();
+
+        let started = Instant::now();
+
+        while !shutdown.is_requested() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(SIGNAL_POLL);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! the welcomes of the servers the client is attached to, kept in a file so
+//! the ids the servers know the client by can be looked up to correlate
+//! logs, even once the client exited
+
+use std::{
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use pdtcore::{ProtocolConfig, ServerInfo, Welcome};
+
+/// where the client keeps its files, the state directory of the user
+pub fn state_dir() -> PathBuf {
+    std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// `WELCOME_PATH`, or `pdtclient.welcome` in the state directory of the user
+pub fn path() -> PathBuf {
+    if let Some(path) = std::env::var_os("WELCOME_PATH") {
+        return PathBuf::from(path);
+    }
+
+    state_dir().join("pdtclient.welcome")
+}
+
+/// one line, the name last as it is the only field that may have spaces,
+/// the connection settings are only of use while connected and left out
+pub fn describe(welcome: &Welcome) -> String {
+    let server = &welcome.server;
+
+    format!(
+        "{} {} {} {}",
+        welcome.assigned_id, server.version, server.up_since, server.name
+    )
+}
+
+pub fn parse(text: &str) -> Option<Welcome> {
+    let mut fields = text.trim_end().splitn(4, ' ');
+
+    let assigned_id = fields.next()?.to_string();
+    let version = fields.next()?.to_string();
+    let up_since = fields.next()?.parse().ok()?;
+    let name = fields.next()?.to_string();
+
+    Some(Welcome {
+        assigned_id,
+        server: ServerInfo {
+            name,
+            version,
+            up_since,
+        },
+        protocol: ProtocolConfig::default(),
+    })
+}
+
+/// which server, for people
+pub fn summary(welcome: &Welcome) -> String {
+    let server = &welcome.server;
+
+    format!(
+        "{} (pdtserver {}, up since {}) as {}",
+        server.name,
+        server.version,
+        humantime::format_rfc3339_seconds(server.up_since()),
+        welcome.assigned_id
+    )
+}
+
+/// one line per welcome
+pub fn save(path: &Path, welcomes: &[Welcome]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let text: String = welcomes
+        .iter()
+        .map(|welcome| describe(welcome) + "\n")
+        .collect();
+
+    std::fs::write(path, text)
+}
+
+pub fn load(path: &Path) -> std::io::Result<Vec<Welcome>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .map(|line| {
+            parse(line).ok_or_else(|| Error::new(ErrorKind::InvalidData, "incomplete welcome"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_welcomes_load() {
+        let path =
+            std::env::temp_dir().join(format!("pdtclient-{}-saved.welcome", std::process::id()));
+
+        let welcome = Welcome {
+            assigned_id: "01H0000000000000000000000".to_string(),
+            server: ServerInfo {
+                name: "home office".to_string(),
+                version: "0.1.0".to_string(),
+                up_since: 1_700_000_000,
+            },
+            protocol: ProtocolConfig::default(),
+        };
+
+        let other = Welcome {
+            assigned_id: "01H0000000000000000000001".to_string(),
+            ..welcome.clone()
+        };
+
+        save(&path, &[welcome.clone(), other.clone()]).unwrap();
+
+        assert_eq!(load(&path).unwrap(), vec![welcome, other]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn incomplete_welcome_is_rejected() {
+        assert_eq!(parse("01H0000000000000000000000 0.1.0 1700000000"), None);
+        assert_eq!(
+            parse("01H0000000000000000000000 0.1.0 yesterday home"),
+            None
+        );
+    }
+}
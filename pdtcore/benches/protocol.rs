@@ -0,0 +1,129 @@
+//! messages per second through the protocol, run with
+//! `cargo bench -p pdtcore --features testing`
+
+use std::{thread, time::Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use pdtcore::{
+    codec::MessageWriter, duplex, BuiltInfo, ClientIntroduction, ClientMessage, Message,
+    Notification, Protocol, ProtocolConfig, ServerMessage, Telemetry,
+};
+
+/// the messages a server and its clients exchange most, from small to large
+fn messages() -> Vec<(&'static str, Message)> {
+    vec![
+        ("command", ClientMessage::ScreenOff.into()),
+        (
+            "telemetry",
+            ServerMessage::Telemetry(Telemetry {
+                load: 0.42,
+                memory_total: 16 << 30,
+                memory_used: 5 << 30,
+                network_received: 123_456_789,
+                network_transmitted: 98_765_432,
+            })
+            .into(),
+        ),
+        (
+            "hello",
+            ServerMessage::Hello(Box::new(ClientIntroduction {
+                name: "bench".to_string(),
+                pdtcore_built_info: BuiltInfo::default(),
+                protocol: ProtocolConfig::default(),
+            }))
+            .into(),
+        ),
+        (
+            "notification",
+            ClientMessage::Notify(Notification {
+                title: "x".repeat(64),
+                body: "x".repeat(4096),
+            })
+            .into(),
+        ),
+    ]
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, message) in messages() {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut bytes = Vec::new();
+                message.send(&mut bytes).unwrap();
+                bytes
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// encoding into one buffer kept across messages, as connections do
+fn encode_reused(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_reused");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, message) in messages() {
+        let mut writer = MessageWriter::new(std::io::sink());
+
+        group.bench_function(name, |b| b.iter(|| writer.send(&message).unwrap()));
+    }
+
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, message) in messages() {
+        let mut bytes = Vec::new();
+        message.send(&mut bytes).unwrap();
+
+        group.bench_function(name, |b| {
+            b.iter(|| Message::receive(&mut &bytes[..]).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+/// a thread sending over one end of a duplex pair while the other end
+/// receives, like a connection between server and client
+fn send_receive(c: &mut Criterion) {
+    let mut group = c.benchmark_group("send_receive");
+    group.throughput(Throughput::Elements(1));
+
+    for (index, (name, _)) in messages().into_iter().enumerate() {
+        group.bench_function(name, |b| {
+            b.iter_custom(|iterations| {
+                let (_, message) = messages().swap_remove(index);
+                let (mut sending, mut receiving) = duplex::pair();
+
+                let start = Instant::now();
+
+                let sender = thread::spawn(move || {
+                    for _ in 0..iterations {
+                        message.send(&mut sending).unwrap();
+                    }
+                });
+
+                for _ in 0..iterations {
+                    Message::receive(&mut receiving).unwrap();
+                }
+
+                sender.join().unwrap();
+
+                start.elapsed()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, encode, encode_reused, decode, send_receive);
+criterion_main!(benches);
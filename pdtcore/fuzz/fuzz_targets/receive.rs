@@ -0,0 +1,13 @@
+//! arbitrary bytes read as a stream of messages, like a connection sending
+//! garbage would be
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pdtcore::{Message, Protocol};
+
+fuzz_target!(|data: &[u8]| {
+    let mut stream = data;
+
+    while !stream.is_empty() && Message::receive(&mut stream).is_ok() {}
+});
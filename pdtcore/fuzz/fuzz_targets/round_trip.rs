@@ -0,0 +1,23 @@
+//! any message decoded from arbitrary bytes encodes and decodes to the same
+//! bytes again
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pdtcore::{Message, Protocol};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(message) = Message::receive(&mut &data[..]) else {
+        return;
+    };
+
+    let mut encoded = Vec::new();
+    message.send(&mut encoded).unwrap();
+
+    let decoded = Message::receive(&mut &encoded[..]).unwrap();
+
+    let mut reencoded = Vec::new();
+    decoded.send(&mut reencoded).unwrap();
+
+    assert_eq!(encoded, reencoded);
+});
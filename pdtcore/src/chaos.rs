@@ -0,0 +1,121 @@
+//! fault injection for any stream a connection runs over, to check that
+//! both ends cope with a misbehaving network
+//!
+//! messages are written in a single write, so faults apply to whole
+//! messages: they can be delayed, dropped, duplicated or cut off by a
+//! disconnect partway through
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    time::Duration,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// chance of each fault per read or write, from 0 to 1
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Faults {
+    pub delay: f64,
+    /// longest injected delay
+    pub max_delay: Duration,
+    pub drop: f64,
+    pub duplicate: f64,
+    /// write half of a message and fail every operation after it
+    pub disconnect: f64,
+}
+
+impl Faults {
+    /// every fault with the same chance, delays of up to a second
+    pub fn uniform(probability: f64) -> Self {
+        Self {
+            delay: probability,
+            max_delay: Duration::from_secs(1),
+            drop: probability,
+            duplicate: probability,
+            disconnect: probability,
+        }
+    }
+}
+
+/// a stream injecting [`Faults`], decided by a seeded generator so failures
+/// can be reproduced
+///
+/// after a disconnect the inner stream stays open until the wrapper is
+/// dropped, the peer then sees a message cut off by the connection closing
+#[derive(Debug)]
+pub struct Chaos<S> {
+    inner: S,
+    faults: Faults,
+    rng: StdRng,
+    disconnected: bool,
+}
+
+impl<S> Chaos<S> {
+    pub fn new(inner: S, faults: Faults, seed: u64) -> Self {
+        Self {
+            inner,
+            faults,
+            rng: StdRng::seed_from_u64(seed),
+            disconnected: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    fn check_connected(&self) -> std::io::Result<()> {
+        if self.disconnected {
+            return Err(ErrorKind::ConnectionReset.into());
+        }
+
+        Ok(())
+    }
+
+    fn maybe_delay(&mut self) {
+        if self.rng.gen_bool(self.faults.delay) {
+            std::thread::sleep(self.faults.max_delay.mul_f64(self.rng.gen()));
+        }
+    }
+}
+
+impl<S: Read> Read for Chaos<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.check_connected()?;
+        self.maybe_delay();
+
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Write> Write for Chaos<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.check_connected()?;
+        self.maybe_delay();
+
+        if self.rng.gen_bool(self.faults.disconnect) {
+            self.inner.write_all(&buf[..buf.len() / 2])?;
+            self.disconnected = true;
+
+            return Err(ErrorKind::ConnectionReset.into());
+        }
+
+        if self.rng.gen_bool(self.faults.drop) {
+            return Ok(buf.len());
+        }
+
+        self.inner.write_all(buf)?;
+
+        if self.rng.gen_bool(self.faults.duplicate) {
+            self.inner.write_all(buf)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.check_connected()?;
+
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,108 @@
+//! message streams keeping their buffers across messages, for connections
+//! sending many small messages
+
+use std::io::{BufReader, Read, Write};
+
+use crate::{config, Message, ProtocolError, MAX_MESSAGE_SIZE};
+
+/// writes messages through one encode buffer, which grows to the largest
+/// message sent and is reused from then on
+#[derive(Debug)]
+pub struct MessageWriter<W> {
+    inner: W,
+    buffer: Vec<u8>,
+    /// largest encoded message the other end accepts
+    max_frame_size: usize,
+}
+
+impl<W: Write> MessageWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            buffer: Vec::new(),
+            max_frame_size: MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// refuse messages larger than `size` from now on, like the
+    /// [`crate::ProtocolConfig`] agreed on says
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.max_frame_size = size.min(MAX_MESSAGE_SIZE);
+    }
+
+    /// encode a message and write it with a single write call, like
+    /// [`crate::Protocol::send`] but without allocating, a message too large
+    /// is not written at all
+    pub fn send(&mut self, message: &Message) -> Result<(), ProtocolError> {
+        self.buffer.clear();
+
+        bincode::encode_into_std_write(message, &mut self.buffer, config())?;
+
+        if self.buffer.len() > self.max_frame_size {
+            return Err(ProtocolError::FrameTooLarge(self.buffer.len()));
+        }
+
+        self.inner.write_all(&self.buffer)?;
+
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// reads messages through one buffer owned for the life of the stream, so
+/// bytes read ahead belong to the next message instead of being dropped
+#[derive(Debug)]
+pub struct MessageReader<R> {
+    inner: BufReader<R>,
+    /// largest encoded message accepted
+    max_frame_size: usize,
+}
+
+impl<R: Read> MessageReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner: BufReader::new(inner),
+            max_frame_size: MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// fail on messages larger than `size` from now on, a message cut off
+    /// at the limit does not decode
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.max_frame_size = size.min(MAX_MESSAGE_SIZE);
+    }
+
+    /// wait for the next message, like [`crate::Protocol::receive`] but with
+    /// far fewer reads of the underlying stream
+    pub fn receive(&mut self) -> Result<Message, ProtocolError> {
+        let mut limited = (&mut self.inner).take(self.max_frame_size as u64);
+
+        Ok(bincode::decode_from_std_read(&mut limited, config())?)
+    }
+
+    /// bytes read from the stream but not decoded yet
+    pub fn buffered(&self) -> &[u8] {
+        self.inner.buffer()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.inner.get_ref()
+    }
+
+    /// the underlying stream, reading from it directly skips what is
+    /// [`MessageReader::buffered`]
+    pub fn get_mut(&mut self) -> &mut R {
+        self.inner.get_mut()
+    }
+}
@@ -0,0 +1,336 @@
+//! messages as encoded by earlier releases, kept in `corpus/<version>` so a
+//! change to the protocol that would break a fleet running mixed versions
+//! fails here instead of in the field
+//!
+//! messages of releases compatible with this one have to decode, and have to
+//! be encoded byte for byte the same, which the decoders of those releases
+//! were checked against when they were current. of incompatible releases
+//! only the introduction has to decode, so the server can tell the client
+//! it is refused. a change to the wire format therefore needs a new minor
+//! version. `PDT_BLESS_CORPUS` writes the samples missing from the corpus
+//! of the current release
+
+use std::path::{Path, PathBuf};
+
+use crate::*;
+
+fn corpus_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus")
+}
+
+fn built_info() -> BuiltInfo {
+    BuiltInfo {
+        pkg_version: "0.0.1".to_string(),
+        pkg_version_major: "0".to_string(),
+        pkg_version_minor: "0".to_string(),
+        pkg_version_patch: "1".to_string(),
+        pkg_version_pre: String::new(),
+        target: "x86_64-unknown-linux-gnu".to_string(),
+        host: "x86_64-unknown-linux-gnu".to_string(),
+        profile: "release".to_string(),
+        git_commit_hash: Some("0123456789abcdef0123456789abcdef01234567".to_string()),
+    }
+}
+
+fn trace() -> TraceContext {
+    TraceContext {
+        traceparent: "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+    }
+}
+
+/// one message of every kind, named after the file it is kept in
+fn samples() -> Vec<(&'static str, Message)> {
+    vec![
+        ("client-screen-off", ClientMessage::ScreenOff.into()),
+        ("client-screen-on", ClientMessage::ScreenOn.into()),
+        ("client-power-off", ClientMessage::PowerOff.into()),
+        ("client-restart", ClientMessage::Restart.into()),
+        ("client-goodbye", ClientMessage::Goodbye.into()),
+        (
+            "client-request-device-info",
+            ClientMessage::RequestDeviceInfo.into(),
+        ),
+        (
+            "client-request-telemetry",
+            ClientMessage::RequestTelemetry.into(),
+        ),
+        (
+            "client-notify",
+            ClientMessage::Notify(Notification {
+                title: "backup".to_string(),
+                body: "finished in 3m".to_string(),
+            })
+            .into(),
+        ),
+        (
+            "client-traced",
+            ClientMessage::Traced(trace(), Box::new(ClientMessage::Restart)).into(),
+        ),
+        (
+            "client-welcome",
+            ClientMessage::Welcome(Welcome {
+                assigned_id: "01HGW2N7XKZ8Q6D7V3J5R9T0YB".to_string(),
+                server: ServerInfo {
+                    name: "home office".to_string(),
+                    version: "0.0.1".to_string(),
+                    up_since: 1_700_000_000,
+                },
+                protocol: ProtocolConfig::default(),
+            })
+            .into(),
+        ),
+        (
+            "client-custom",
+            ClientMessage::Custom {
+                namespace: "org.example.kiosk".to_string(),
+                payload: vec![0, 1, 2, 255],
+            }
+            .into(),
+        ),
+        (
+            "client-nack",
+            ClientMessage::Nack {
+                namespace: "org.example.kiosk".to_string(),
+            }
+            .into(),
+        ),
+        (
+            "client-config-update",
+            ClientMessage::ConfigUpdate(TelemetryConfig {
+                interval: 60_000,
+                families: vec![MetricFamily::Load, MetricFamily::Network],
+            })
+            .into(),
+        ),
+        (
+            "server-hello",
+            ServerMessage::Hello(Box::new(ClientIntroduction {
+                name: "ASH".to_string(),
+                pdtcore_built_info: built_info(),
+                protocol: ProtocolConfig::default(),
+            }))
+            .into(),
+        ),
+        (
+            "server-device-info",
+            ServerMessage::DeviceInfo(DeviceInfo {
+                name: "ash".to_string(),
+                os: "Arch Linux".to_string(),
+                os_version: "rolling".to_string(),
+                uptime: "3h 12m".to_string(),
+                vendor: "LENOVO".to_string(),
+                model: "ThinkPad T14 Gen 3".to_string(),
+                serial: "PF3ABCDE".to_string(),
+                chassis: "notebook".to_string(),
+                virtualization: Virtualization::Physical,
+            })
+            .into(),
+        ),
+        (
+            "server-device-info-delta",
+            ServerMessage::DeviceInfoDelta(DeviceInfoDelta {
+                uptime: Some("3h 13m".to_string()),
+                virtualization: Some(Virtualization::VirtualMachine),
+                ..DeviceInfoDelta::default()
+            })
+            .into(),
+        ),
+        ("server-goodbye", ServerMessage::Goodbye.into()),
+        (
+            "server-telemetry",
+            ServerMessage::Telemetry(Telemetry {
+                load: 0.5,
+                memory_total: 8 << 30,
+                memory_used: 2 << 30,
+                network_received: 1_000_000,
+                network_transmitted: 100_000,
+            })
+            .into(),
+        ),
+        (
+            "server-executed",
+            ServerMessage::Executed(CommandExecution {
+                trace: trace(),
+                command: "Restart".to_string(),
+                error: Some("permission denied".to_string()),
+            })
+            .into(),
+        ),
+        (
+            "server-logs",
+            ServerMessage::Logs(vec![LogRecord {
+                at: 1_700_000_000_000,
+                level: LogLevel::Warn,
+                target: "pdtclient".to_string(),
+                message: "connection lost".to_string(),
+            }])
+            .into(),
+        ),
+        (
+            "server-crash-report",
+            ServerMessage::CrashReport(Box::new(CrashReport {
+                at: 1_700_000_000,
+                message: "index out of bounds at src/main.rs:1:1".to_string(),
+                backtrace: String::new(),
+                built_info: built_info(),
+            }))
+            .into(),
+        ),
+        (
+            "server-custom",
+            ServerMessage::Custom {
+                namespace: "org.example.kiosk".to_string(),
+                payload: vec![0, 1, 2, 255],
+            }
+            .into(),
+        ),
+        (
+            "server-nack",
+            ServerMessage::Nack {
+                namespace: "org.example.kiosk".to_string(),
+            }
+            .into(),
+        ),
+        (
+            "server-containers",
+            ServerMessage::Containers(Containers {
+                running: 1,
+                stopped: 2,
+                stats: vec![ContainerStats {
+                    id: "4f1c2e3d5b6a".to_string(),
+                    name: "home-assistant".to_string(),
+                    image: "ghcr.io/home-assistant/home-assistant:stable".to_string(),
+                    cpu: 12.5,
+                    memory_used: 512 << 20,
+                    memory_limit: 8 << 30,
+                }],
+            })
+            .into(),
+        ),
+        (
+            "server-identity",
+            ServerMessage::Identity(
+                "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08".to_string(),
+            )
+            .into(),
+        ),
+        ("server-alive", ServerMessage::Alive.into()),
+    ]
+}
+
+fn encode(message: &Message) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    message.send(&mut bytes).unwrap();
+    bytes
+}
+
+/// a release and its encoded messages by name
+type Release = (String, Vec<(String, Vec<u8>)>);
+
+/// the releases kept in the corpus, with the messages of each
+fn releases() -> Vec<Release> {
+    let mut releases: Vec<_> = std::fs::read_dir(corpus_dir())
+        .unwrap()
+        .map(|release| {
+            let release = release.unwrap();
+
+            let mut messages: Vec<_> = std::fs::read_dir(release.path())
+                .unwrap()
+                .map(|file| {
+                    let path = file.unwrap().path();
+                    let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+
+                    (name, std::fs::read(&path).unwrap())
+                })
+                .collect();
+
+            messages.sort();
+
+            (release.file_name().to_string_lossy().into_owned(), messages)
+        })
+        .collect();
+
+    releases.sort();
+    releases
+}
+
+/// whether peers of `release` and this one talk to each other, as
+/// [`BuiltInfo::compatible`] decides in the handshake
+fn compatible(release: &str) -> bool {
+    let mut version = release.split('.');
+    let current = BuiltInfo::default();
+
+    version.next() == Some(current.pkg_version_major.as_str())
+        && version.next() == Some(current.pkg_version_minor.as_str())
+}
+
+#[test]
+fn messages_of_every_release_decode() {
+    for (release, messages) in releases() {
+        let compatible = compatible(&release);
+
+        for (name, bytes) in messages {
+            if !compatible && name != "server-hello" {
+                continue;
+            }
+
+            let mut stream = &bytes[..];
+
+            if let Err(error) = Message::receive(&mut stream) {
+                panic!("{release}/{name} no longer decodes: {error:?}");
+            }
+
+            assert!(stream.is_empty(), "{release}/{name} decodes short");
+        }
+    }
+}
+
+#[test]
+fn compatible_releases_decode_current_encodings() {
+    for (release, messages) in releases()
+        .into_iter()
+        .filter(|(release, _)| compatible(release))
+    {
+        let samples = samples();
+
+        for (name, bytes) in messages {
+            let Some((_, message)) = samples.iter().find(|(sample, _)| *sample == name) else {
+                continue;
+            };
+
+            assert_eq!(
+                encode(message),
+                bytes,
+                "{name} is encoded differently than {release} expects"
+            );
+        }
+    }
+}
+
+#[test]
+fn current_release_has_every_message() {
+    let dir = corpus_dir().join(env!("CARGO_PKG_VERSION"));
+    let bless = std::env::var_os("PDT_BLESS_CORPUS").is_some();
+
+    let mut missing = vec![];
+
+    for (name, message) in samples() {
+        let path = dir.join(name).with_extension("bin");
+
+        if path.exists() {
+            continue;
+        }
+
+        if bless {
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(&path, encode(&message)).unwrap();
+        } else {
+            missing.push(name);
+        }
+    }
+
+    assert!(
+        missing.is_empty(),
+        "missing from the corpus, PDT_BLESS_CORPUS=1 writes them: {missing:?}"
+    );
+}
@@ -0,0 +1,81 @@
+//! paired in-memory streams standing in for a socket, so a server and its
+//! clients can be exercised in-process
+
+use std::{
+    collections::VecDeque,
+    io::{ErrorKind, Read, Write},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+#[derive(Debug)]
+struct Incoming {
+    receiver: Receiver<Vec<u8>>,
+    /// bytes received but not read yet
+    buffer: VecDeque<u8>,
+}
+
+/// one end of an in-memory connection, clones share the same end like a
+/// cloned socket does
+///
+/// reading blocks until the other end writes, and reports the end of the
+/// stream once every clone of the other end is dropped, writing fails with
+/// [`ErrorKind::BrokenPipe`] from then on
+#[derive(Debug, Clone)]
+pub struct DuplexStream {
+    incoming: Arc<Mutex<Incoming>>,
+    outgoing: Sender<Vec<u8>>,
+}
+
+/// two connected ends, what is written to one is read from the other
+pub fn pair() -> (DuplexStream, DuplexStream) {
+    let (left_sender, right_receiver) = mpsc::channel();
+    let (right_sender, left_receiver) = mpsc::channel();
+
+    let end = |receiver, outgoing| DuplexStream {
+        incoming: Arc::new(Mutex::new(Incoming {
+            receiver,
+            buffer: VecDeque::new(),
+        })),
+        outgoing,
+    };
+
+    (
+        end(left_receiver, left_sender),
+        end(right_receiver, right_sender),
+    )
+}
+
+impl Read for DuplexStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut incoming = self
+            .incoming
+            .lock()
+            .map_err(|_| std::io::Error::from(ErrorKind::Other))?;
+
+        if incoming.buffer.is_empty() {
+            match incoming.receiver.recv() {
+                Ok(bytes) => incoming.buffer.extend(bytes),
+                Err(_) => return Ok(0),
+            }
+        }
+
+        incoming.buffer.read(buf)
+    }
+}
+
+impl Write for DuplexStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.outgoing
+            .send(buf.to_vec())
+            .map_err(|_| std::io::Error::from(ErrorKind::BrokenPipe))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -3,15 +3,31 @@ use bincode::{
     Decode, Encode,
 };
 use std::{
-    io::{BufReader, Read, Write},
-    sync::{Arc, Mutex},
+    fmt,
+    io::{Read, Write},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod codec;
+#[cfg(test)]
+mod corpus;
+#[cfg(feature = "testing")]
+pub mod duplex;
+pub mod mux;
+pub mod recording;
+#[cfg(test)]
+mod round_trip;
+
 mod built_info {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
 }
 
-pub type Particularity<T> = Arc<Mutex<T>>;
+/// state shared between threads, a panic while holding the lock leaves it
+/// usable as it was instead of poisoning it for every later holder
+pub type Particularity<T> = Arc<parking_lot::Mutex<T>>;
 
 #[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
 pub struct DeviceInfo {
@@ -19,6 +35,15 @@ pub struct DeviceInfo {
     pub os: String,
     pub os_version: String,
     pub uptime: String,
+    /// hardware vendor, like `LENOVO`
+    pub vendor: String,
+    /// hardware model, like `ThinkPad T14 Gen 3`
+    pub model: String,
+    /// serial number of the machine, on most systems only root may read it
+    pub serial: String,
+    /// kind of enclosure, like `laptop` or `rack mount`
+    pub chassis: String,
+    pub virtualization: Virtualization,
 }
 
 impl Default for DeviceInfo {
@@ -28,14 +53,168 @@ impl Default for DeviceInfo {
             os: String::from("unknown"),
             os_version: String::from("unknown"),
             uptime: String::from("unknown"),
+            vendor: String::from("unknown"),
+            model: String::from("unknown"),
+            serial: String::from("unknown"),
+            chassis: String::from("unknown"),
+            virtualization: Virtualization::Unknown,
         }
     }
 }
 
-#[derive(Debug)]
+/// what a client runs on
+#[derive(Encode, Decode, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Virtualization {
+    #[default]
+    Unknown,
+    /// no hypervisor or container found
+    Physical,
+    VirtualMachine,
+    Container,
+}
+
+impl fmt::Display for Virtualization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Virtualization::Unknown => write!(f, "unknown"),
+            Virtualization::Physical => write!(f, "physical"),
+            Virtualization::VirtualMachine => write!(f, "virtual machine"),
+            Virtualization::Container => write!(f, "container"),
+        }
+    }
+}
+
+impl DeviceInfo {
+    /// fields of `newer` that differ from these
+    pub fn changes(&self, newer: &DeviceInfo) -> DeviceInfoDelta {
+        fn changed(old: &str, new: &str) -> Option<String> {
+            (old != new).then(|| new.to_string())
+        }
+
+        DeviceInfoDelta {
+            name: changed(&self.name, &newer.name),
+            os: changed(&self.os, &newer.os),
+            os_version: changed(&self.os_version, &newer.os_version),
+            uptime: changed(&self.uptime, &newer.uptime),
+            vendor: changed(&self.vendor, &newer.vendor),
+            model: changed(&self.model, &newer.model),
+            serial: changed(&self.serial, &newer.serial),
+            chassis: changed(&self.chassis, &newer.chassis),
+            virtualization: (self.virtualization != newer.virtualization)
+                .then_some(newer.virtualization),
+        }
+    }
+
+    pub fn apply(&mut self, delta: DeviceInfoDelta) {
+        let DeviceInfoDelta {
+            name,
+            os,
+            os_version,
+            uptime,
+            vendor,
+            model,
+            serial,
+            chassis,
+            virtualization,
+        } = delta;
+
+        for (field, value) in [
+            (&mut self.name, name),
+            (&mut self.os, os),
+            (&mut self.os_version, os_version),
+            (&mut self.uptime, uptime),
+            (&mut self.vendor, vendor),
+            (&mut self.model, model),
+            (&mut self.serial, serial),
+            (&mut self.chassis, chassis),
+        ] {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+
+        if let Some(virtualization) = virtualization {
+            self.virtualization = virtualization;
+        }
+    }
+}
+
+/// fields of a [`DeviceInfo`] changed since the one sent before, `None` for
+/// those unchanged
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInfoDelta {
+    pub name: Option<String>,
+    pub os: Option<String>,
+    pub os_version: Option<String>,
+    pub uptime: Option<String>,
+    pub vendor: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub chassis: Option<String>,
+    pub virtualization: Option<Virtualization>,
+}
+
+impl DeviceInfoDelta {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// connection accepted, waiting for the client introduction
+    Connecting,
+    Connected,
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "connecting"),
+            ConnectionState::Connected => write!(f, "connected"),
+        }
+    }
+}
+
+/// socket type a client connected to the server through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    Unix,
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Tcp => write!(f, "tcp"),
+            Transport::Unix => write!(f, "unix"),
+        }
+    }
+}
+
+/// where a client connected from, as seen when the server accepted it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkInfo {
+    /// peer address, or the listener name for unix sockets which have no
+    /// meaningful peer address
+    pub address: String,
+    pub transport: Transport,
+    pub connected_at: SystemTime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Client {
     pub id: String,
+    pub state: ConnectionState,
     pub device_info: DeviceInfo,
+    /// when the server last received a message from the client
+    pub last_seen: SystemTime,
+    pub network: NetworkInfo,
+    /// pdtcore version the client introduced itself with, the protocol both
+    /// ends speak, unknown until the introduction arrived
+    pub protocol_version: Option<String>,
+    /// fingerprint of the identity the client presented, none until it did
+    pub identity: Option<String>,
 }
 
 #[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
@@ -48,6 +227,8 @@ pub struct BuiltInfo {
     pub target: String,
     pub host: String,
     pub profile: String,
+    /// commit the binary was built from, unknown outside a git checkout
+    pub git_commit_hash: Option<String>,
 }
 
 impl Default for BuiltInfo {
@@ -61,6 +242,10 @@ impl Default for BuiltInfo {
             target: built_info::TARGET.to_string(),
             host: built_info::HOST.to_string(),
             profile: built_info::PROFILE.to_string(),
+            git_commit_hash: built_info::GIT_COMMIT_HASH.map(|hash| match built_info::GIT_DIRTY {
+                Some(true) => format!("{hash}-dirty"),
+                _ => hash.to_string(),
+            }),
         }
     }
 }
@@ -72,14 +257,350 @@ impl BuiltInfo {
     }
 }
 
+/// one field per line, for `--version` output and support requests
+impl fmt::Display for BuiltInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "version {}", self.pkg_version)?;
+        writeln!(
+            f,
+            "commit  {}",
+            self.git_commit_hash.as_deref().unwrap_or("unknown")
+        )?;
+        writeln!(f, "target  {}", self.target)?;
+        writeln!(f, "host    {}", self.host)?;
+        write!(f, "profile {}", self.profile)
+    }
+}
+
 #[derive(Encode, Decode, Debug, PartialEq, Eq)]
 pub struct ClientIntroduction {
     pub name: String,
     pub pdtcore_built_info: BuiltInfo,
+    /// connection settings the client proposes
+    pub protocol: ProtocolConfig,
+}
+
+/// how often the server asks clients for telemetry unless configured, the
+/// requests double as heartbeats keeping connections alive
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// time either end of a connection waits for the other before dropping the
+/// connection unless configured
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// settings of a connection both ends keep to, the client proposes its own
+/// in its introduction and the server answers with the reconciled ones in
+/// its welcome
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolConfig {
+    /// milliseconds between heartbeats
+    pub heartbeat_interval: u64,
+    /// milliseconds of silence after which the connection is dropped
+    pub idle_timeout: u64,
+    /// largest encoded message accepted, at most [`MAX_MESSAGE_SIZE`]
+    pub max_frame_size: u32,
+    /// encoded size from which messages may be compressed, none while either
+    /// end does not compress, which neither does yet
+    pub compression_threshold: Option<u32>,
+}
+
+impl Default for ProtocolConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: HEARTBEAT_INTERVAL.as_millis() as u64,
+            idle_timeout: IDLE_TIMEOUT.as_millis() as u64,
+            max_frame_size: MAX_MESSAGE_SIZE as u32,
+            compression_threshold: None,
+        }
+    }
+}
+
+impl ProtocolConfig {
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_interval)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_millis(self.idle_timeout)
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size as usize
+    }
+
+    pub fn with_heartbeat_interval(self, interval: Duration) -> Self {
+        Self {
+            heartbeat_interval: interval.as_millis() as u64,
+            ..self
+        }
+    }
+
+    pub fn with_idle_timeout(self, timeout: Duration) -> Self {
+        Self {
+            idle_timeout: timeout.as_millis() as u64,
+            ..self
+        }
+    }
+
+    /// capped at [`MAX_MESSAGE_SIZE`]
+    pub fn with_max_frame_size(self, size: usize) -> Self {
+        Self {
+            max_frame_size: size.min(MAX_MESSAGE_SIZE) as u32,
+            ..self
+        }
+    }
+
+    /// the settings of the server reconciled with the ones a client
+    /// proposed
+    ///
+    /// heartbeats are sent by the server so its interval stands, the more
+    /// patient idle timeout is kept but never shorter than two heartbeats,
+    /// messages are no larger than either end accepts and compressed only
+    /// when both ends compress
+    pub fn reconcile(&self, proposed: &ProtocolConfig) -> ProtocolConfig {
+        let heartbeat_interval = self.heartbeat_interval;
+
+        let idle_timeout = self
+            .idle_timeout
+            .max(proposed.idle_timeout)
+            .max(heartbeat_interval.saturating_mul(2));
+
+        let compression_threshold =
+            match (self.compression_threshold, proposed.compression_threshold) {
+                (Some(own), Some(proposed)) => Some(own.max(proposed)),
+                _ => None,
+            };
+
+        ProtocolConfig {
+            heartbeat_interval,
+            idle_timeout,
+            max_frame_size: self
+                .max_frame_size
+                .min(proposed.max_frame_size)
+                .min(MAX_MESSAGE_SIZE as u32),
+            compression_threshold,
+        }
+    }
+}
+
+/// resource usage sample reported by a client
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub struct Telemetry {
+    /// one minute load average
+    pub load: f64,
+    pub memory_total: u64,
+    pub memory_used: u64,
+    /// bytes received on every interface but loopback since boot
+    pub network_received: u64,
+    /// bytes transmitted on every interface but loopback since boot
+    pub network_transmitted: u64,
+}
+
+/// a group of the fields of [`Telemetry`] collected together
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MetricFamily {
+    /// [`Telemetry::load`]
+    Load,
+    /// [`Telemetry::memory_total`] and [`Telemetry::memory_used`]
+    Memory,
+    /// [`Telemetry::network_received`] and [`Telemetry::network_transmitted`]
+    Network,
+}
+
+impl MetricFamily {
+    pub const ALL: [MetricFamily; 3] = [
+        MetricFamily::Load,
+        MetricFamily::Memory,
+        MetricFamily::Network,
+    ];
+}
+
+/// which telemetry a client collects and how often, sent by the server with
+/// [`ClientMessage::ConfigUpdate`]
+///
+/// telemetry requests keep coming every heartbeat so the connection stays
+/// alive, the ones arriving before the interval passed are answered with
+/// [`ServerMessage::Alive`]
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct TelemetryConfig {
+    /// milliseconds between samples, zero for a sample with every request
+    pub interval: u64,
+    /// families collected, the fields of the others are left at zero
+    pub families: Vec<MetricFamily>,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            interval: 0,
+            families: MetricFamily::ALL.to_vec(),
+        }
+    }
+}
+
+impl TelemetryConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval)
+    }
+
+    pub fn collects(&self, family: MetricFamily) -> bool {
+        self.families.contains(&family)
+    }
+}
+
+/// resource usage of a container running on a client
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub struct ContainerStats {
+    /// short id, as `docker ps` shows it
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    /// percent of a single cpu, above 100 when using several
+    pub cpu: f64,
+    /// bytes, without the page cache
+    pub memory_used: u64,
+    /// bytes the container may use, the memory of the host when unlimited
+    pub memory_limit: u64,
+}
+
+/// containers on a client running docker, sent along with the telemetry by
+/// clients configured to report them
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq)]
+pub struct Containers {
+    pub running: u32,
+    /// created, paused, exited and dead containers
+    pub stopped: u32,
+    /// usage of the running containers
+    pub stats: Vec<ContainerStats>,
+}
+
+/// desktop notification shown on a client
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+}
+
+/// w3c trace context of the operation a message is part of
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceContext {
+    /// `traceparent` header value, empty when the sender was not tracing
+    pub traceparent: String,
+}
+
+/// outcome of a traced command as executed by the client
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct CommandExecution {
+    pub trace: TraceContext,
+    /// debug representation of the command
+    pub command: String,
+    /// why the command failed, `None` when it succeeded
+    pub error: Option<String>,
+}
+
+/// severity of a log record, the most severe first so more verbose levels
+/// compare greater
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogLevel::Error => write!(f, "error"),
+            LogLevel::Warn => write!(f, "warn"),
+            LogLevel::Info => write!(f, "info"),
+            LogLevel::Debug => write!(f, "debug"),
+            LogLevel::Trace => write!(f, "trace"),
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+/// log event of a client, forwarded to its servers
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// milliseconds since the unix epoch
+    pub at: u64,
+    pub level: LogLevel,
+    /// module path the event came from
+    pub target: String,
+    /// the message followed by the other fields as `name=value`
+    pub message: String,
+}
+
+impl LogRecord {
+    pub fn at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.at)
+    }
+}
+
+/// a panic of a client, reported to a server once the client runs again
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    /// seconds since the unix epoch
+    pub at: u64,
+    /// the panic message along with where it happened
+    pub message: String,
+    pub backtrace: String,
+    /// build of the client that crashed
+    pub built_info: BuiltInfo,
+}
+
+impl CrashReport {
+    pub fn at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.at)
+    }
+}
+
+/// the server a client is attached to, telling staging and production
+/// instances apart
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub name: String,
+    /// pdtserver version
+    pub version: String,
+    /// seconds since the unix epoch
+    pub up_since: u64,
+}
+
+impl ServerInfo {
+    pub fn up_since(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.up_since)
+    }
+}
+
+/// sent once the server accepted the introduction of a client
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Welcome {
+    /// id the server knows the client by in its logs and web interface
+    pub assigned_id: String,
+    pub server: ServerInfo,
+    /// connection settings reconciled with the ones the client proposed
+    pub protocol: ProtocolConfig,
 }
 
 /// message for a client
-#[derive(Encode, Decode, Debug, PartialEq, Eq)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
 pub enum ClientMessage {
     ScreenOff,
     ScreenOn,
@@ -87,14 +608,64 @@ pub enum ClientMessage {
     Restart,
     Goodbye,
     RequestDeviceInfo,
+    RequestTelemetry,
+    Notify(Notification),
+    /// a command sent as part of a traced operation, answered with
+    /// [`ServerMessage::Executed`]
+    Traced(TraceContext, Box<ClientMessage>),
+    Welcome(Welcome),
+    /// a message added by a fork or plugin, `namespace` tells whose it is
+    /// and how to decode `payload`, clients without it answer with
+    /// [`ServerMessage::Nack`]
+    Custom {
+        namespace: String,
+        payload: Vec<u8>,
+    },
+    /// the client sent a [`ServerMessage::Custom`] of a namespace the
+    /// server does not know
+    Nack {
+        namespace: String,
+    },
+    /// telemetry to collect from now on, replacing the one sent before
+    ConfigUpdate(TelemetryConfig),
 }
 
 /// message for a server
-#[derive(Encode, Decode, Debug, PartialEq, Eq)]
+#[derive(Encode, Decode, Debug, PartialEq)]
 pub enum ServerMessage {
     Hello(Box<ClientIntroduction>),
     DeviceInfo(DeviceInfo),
+    /// device info changed since the last sent in this connection, which
+    /// starts with a full [`ServerMessage::DeviceInfo`]
+    DeviceInfoDelta(DeviceInfoDelta),
     Goodbye,
+    Telemetry(Telemetry),
+    Executed(CommandExecution),
+    /// log records of the client since the ones sent before, oldest first
+    Logs(Vec<LogRecord>),
+    /// a crash of the client from before it connected
+    CrashReport(Box<CrashReport>),
+    /// a message added by a fork or plugin, `namespace` tells whose it is
+    /// and how to decode `payload`, servers without it answer with
+    /// [`ClientMessage::Nack`]
+    Custom {
+        namespace: String,
+        payload: Vec<u8>,
+    },
+    /// the server sent a [`ClientMessage::Custom`] of a namespace the
+    /// client does not know
+    Nack {
+        namespace: String,
+    },
+    /// containers on the client, replacing the ones sent before
+    Containers(Containers),
+    /// a secret key the client generated once and keeps, sent right after
+    /// the introduction so the server can tell the client it approved from
+    /// one merely reporting the same device name
+    Identity(String),
+    /// answer to [`ClientMessage::RequestTelemetry`] while no sample is due,
+    /// see [`TelemetryConfig::interval`]
+    Alive,
 }
 
 impl From<ClientMessage> for Message {
@@ -110,7 +681,7 @@ impl From<ServerMessage> for Message {
 }
 
 /// complete representation of pdt protocol messages
-#[derive(Encode, Decode, Debug, PartialEq, Eq)]
+#[derive(Encode, Decode, Debug, PartialEq)]
 pub enum Message {
     Client(ClientMessage),
     Server(ServerMessage),
@@ -121,6 +692,8 @@ pub enum ProtocolError {
     IO(std::io::Error),
     Encode(EncodeError),
     Decode(DecodeError),
+    /// an encoded message larger than the other end accepts, in bytes
+    FrameTooLarge(usize),
 }
 
 impl From<EncodeError> for ProtocolError {
@@ -141,6 +714,14 @@ impl From<DecodeError> for ProtocolError {
     }
 }
 
+/// largest encoded message accepted, a corrupt length prefix would otherwise
+/// allocate whatever it claims before any of the data arrives
+pub const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+fn config() -> impl bincode::config::Config {
+    bincode::config::standard().with_limit::<MAX_MESSAGE_SIZE>()
+}
+
 /// read and write trait for pdt protocol
 pub trait Protocol {
     fn send(&self, write_stream: &mut dyn Write) -> Result<(), ProtocolError>;
@@ -152,17 +733,17 @@ pub trait Protocol {
 /// read and write impl for pdt protocol
 impl Protocol for Message {
     fn send(&self, write_stream: &mut dyn Write) -> Result<(), ProtocolError> {
-        let bytes = bincode::encode_to_vec(self, bincode::config::standard())?;
+        let bytes = bincode::encode_to_vec(self, config())?;
 
         write_stream.write_all(&bytes)?;
 
         Ok(())
     }
 
-    fn receive(read_stream: &mut dyn Read) -> Result<Self, ProtocolError> {
-        let buf_read = BufReader::new(read_stream);
-
-        let decoded: Message = bincode::decode_from_reader(buf_read, bincode::config::standard())?;
+    fn receive(mut read_stream: &mut dyn Read) -> Result<Self, ProtocolError> {
+        // unbuffered, a buffer dropped after decoding would swallow the start
+        // of any message sent right behind this one
+        let decoded: Message = bincode::decode_from_std_read(&mut read_stream, config())?;
 
         Ok(decoded)
     }
@@ -0,0 +1,636 @@
+//! several independent streams over one connection, so bulk transfers do not
+//! hold back control messages
+//!
+//! every frame is a header of stream id, kind and payload length followed by
+//! the payload, all big endian. writes are split into frames of at most
+//! [`MAX_FRAME`] bytes, so a large write on one stream interleaves with the
+//! writes of every other stream instead of blocking them until it is done
+//!
+//! each end of a stream may send [`WINDOW`] bytes the other end has not read
+//! yet, reading grants them back, so a stream nobody reads stops its writer
+//! instead of filling the memory of the reader. an end sending past its
+//! window or opening more than [`MAX_STREAMS`] streams at once does not keep
+//! to the protocol, the connection is dropped or the streams closed
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{ErrorKind, Read, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    time::Duration,
+};
+
+use parking_lot::{Condvar, Mutex};
+
+/// largest payload of a single frame
+pub const MAX_FRAME: usize = 16 * 1024;
+
+/// bytes a stream may have received and not read yet
+pub const WINDOW: usize = 256 * 1024;
+
+/// streams the other end may have open at once, streams it opens beyond are
+/// closed right away
+pub const MAX_STREAMS: usize = 1024;
+
+/// the stream both ends have open from the start, for protocol messages
+pub const CONTROL: u32 = 0;
+
+const HEADER: usize = 9;
+
+const DATA: u8 = 0;
+const CLOSE: u8 = 1;
+/// the payload is the number of bytes read from the stream, granting them
+/// back to the writer
+const CREDIT: u8 = 2;
+
+/// which end of the connection, the ends open streams with odd and even ids
+/// so they never pick the same one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+impl Side {
+    /// whether the other end opened the stream
+    fn opened_by_peer(self, id: u32) -> bool {
+        let odd = id % 2 == 1;
+
+        id != CONTROL
+            && match self {
+                Side::Client => !odd,
+                Side::Server => odd,
+            }
+    }
+}
+
+struct Shared {
+    writer: Mutex<Box<dyn Write + Send>>,
+    /// open streams, removed when either end closes them
+    streams: Mutex<HashMap<u32, Receiving>>,
+    /// none once the ids of this end ran out
+    next_id: Mutex<Option<u32>>,
+}
+
+impl std::fmt::Debug for Shared {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shared").finish_non_exhaustive()
+    }
+}
+
+impl Shared {
+    fn write_frame(&self, id: u32, kind: u8, payload: &[u8]) -> std::io::Result<()> {
+        let mut frame = Vec::with_capacity(HEADER + payload.len());
+        frame.extend(id.to_be_bytes());
+        frame.push(kind);
+        frame.extend((payload.len() as u32).to_be_bytes());
+        frame.extend(payload);
+
+        let mut writer = self.writer.lock();
+
+        writer.write_all(&frame)?;
+        writer.flush()
+    }
+}
+
+/// what the demultiplexer needs of an open stream
+struct Receiving {
+    sender: Sender<Vec<u8>>,
+    /// bytes received and not read yet
+    unread: Arc<AtomicUsize>,
+    /// of sending on the stream, granted by the other end
+    credit: Arc<Credit>,
+}
+
+/// bytes a stream may send before the other end has to read them
+#[derive(Debug)]
+struct Credit {
+    /// none once the stream is closed
+    available: Mutex<Option<usize>>,
+    granted: Condvar,
+}
+
+impl Credit {
+    fn new() -> Self {
+        Self {
+            available: Mutex::new(Some(WINDOW)),
+            granted: Condvar::new(),
+        }
+    }
+
+    fn grant(&self, bytes: usize) {
+        if let Some(available) = self.available.lock().as_mut() {
+            *available = available.saturating_add(bytes);
+        }
+
+        self.granted.notify_all();
+    }
+
+    fn close(&self) {
+        *self.available.lock() = None;
+        self.granted.notify_all();
+    }
+
+    /// wait until some of `wanted` bytes may be sent and take them, failing
+    /// once the stream is closed
+    fn take(&self, wanted: usize) -> std::io::Result<usize> {
+        let mut available = self.available.lock();
+
+        loop {
+            match *available {
+                None => return Err(ErrorKind::BrokenPipe.into()),
+                Some(0) => self.granted.wait(&mut available),
+                Some(bytes) => {
+                    let taken = bytes.min(wanted);
+                    *available = Some(bytes - taken);
+                    return Ok(taken);
+                }
+            }
+        }
+    }
+}
+
+/// a connection carrying streams, dropping it leaves open streams usable
+#[derive(Debug)]
+pub struct Mux {
+    shared: Arc<Shared>,
+    /// streams the other end opened, no more than are open at once
+    incoming: Receiver<MuxStream>,
+}
+
+impl Mux {
+    /// multiplex the connection read from `reader` and written to `writer`,
+    /// returning the control stream alongside
+    pub fn new<R, W>(reader: R, writer: W, side: Side) -> (Self, MuxStream)
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            writer: Mutex::new(Box::new(writer)),
+            streams: Mutex::new(HashMap::new()),
+            next_id: Mutex::new(Some(match side {
+                Side::Client => 1,
+                Side::Server => 2,
+            })),
+        });
+
+        let control = MuxStream::new(CONTROL, shared.clone());
+
+        let (incoming_sender, incoming) = mpsc::channel();
+
+        let reader_shared = shared.clone();
+        std::thread::spawn(move || demultiplex(reader, reader_shared, side, incoming_sender));
+
+        (Self { shared, incoming }, control)
+    }
+
+    /// open a new stream, the other end sees it once something is written,
+    /// failing once the ids of this end ran out
+    pub fn open(&self) -> std::io::Result<MuxStream> {
+        let id = {
+            let mut next_id = self.shared.next_id.lock();
+
+            let id = next_id.ok_or_else(|| std::io::Error::other("stream ids ran out"))?;
+            *next_id = id.checked_add(2);
+            id
+        };
+
+        Ok(MuxStream::new(id, self.shared.clone()))
+    }
+
+    /// wait for the other end to open a stream, `None` once the connection
+    /// ended
+    pub fn accept(&self) -> Option<MuxStream> {
+        self.incoming.recv().ok()
+    }
+}
+
+/// read frames until the connection ends or the other end breaks the
+/// protocol, then end every stream
+fn demultiplex<R: Read>(
+    mut reader: R,
+    shared: Arc<Shared>,
+    side: Side,
+    incoming: Sender<MuxStream>,
+) {
+    let mut header = [0; HEADER];
+    // ids only grow, lower ones of the other end were closed here already
+    let mut last_accepted = CONTROL;
+
+    while reader.read_exact(&mut header).is_ok() {
+        let id = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let kind = header[4];
+        let length = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as usize;
+
+        if length > MAX_FRAME {
+            break;
+        }
+
+        let mut payload = vec![0; length];
+
+        if reader.read_exact(&mut payload).is_err() {
+            break;
+        }
+
+        let mut streams = shared.streams.lock();
+
+        match kind {
+            DATA => {}
+            CLOSE => {
+                if let Some(stream) = streams.remove(&id) {
+                    stream.credit.close();
+                }
+                continue;
+            }
+            CREDIT => {
+                let Ok(bytes) = <[u8; 4]>::try_from(payload.as_slice()) else {
+                    break;
+                };
+
+                if let Some(stream) = streams.get(&id) {
+                    stream.credit.grant(u32::from_be_bytes(bytes) as usize);
+                }
+                continue;
+            }
+            _ => break,
+        }
+
+        if let Some(stream) = streams.get(&id) {
+            if stream.unread.fetch_add(length, Ordering::Relaxed) + length > WINDOW {
+                break;
+            }
+
+            let _ = stream.sender.send(payload);
+            continue;
+        }
+
+        // streams not open anymore were closed here
+        if !side.opened_by_peer(id) || id <= last_accepted {
+            continue;
+        }
+
+        last_accepted = id;
+
+        let open = streams
+            .keys()
+            .filter(|&&id| side.opened_by_peer(id))
+            .count();
+
+        if open >= MAX_STREAMS {
+            drop(streams);
+
+            let _ = shared.write_frame(id, CLOSE, &[]);
+            continue;
+        }
+
+        let (stream, receiving) = MuxStream::opened(id, shared.clone());
+
+        receiving.unread.store(length, Ordering::Relaxed);
+        let _ = receiving.sender.send(payload);
+        streams.insert(id, receiving);
+
+        drop(streams);
+
+        let _ = incoming.send(stream);
+    }
+
+    for (_, stream) in shared.streams.lock().drain() {
+        stream.credit.close();
+    }
+}
+
+/// one stream of a [`Mux`], reading reports the end of the stream once the
+/// other end closed it or the connection ended, it is closed once every clone
+/// of it is dropped
+#[derive(Debug, Clone)]
+pub struct MuxStream(Arc<Stream>);
+
+#[derive(Debug)]
+struct Stream {
+    id: u32,
+    shared: Arc<Shared>,
+    reading: Mutex<Reading>,
+    read_timeout: Mutex<Option<Duration>>,
+    unread: Arc<AtomicUsize>,
+    credit: Arc<Credit>,
+}
+
+#[derive(Debug)]
+struct Reading {
+    receiver: Receiver<Vec<u8>>,
+    /// bytes received but not read yet
+    buffer: VecDeque<u8>,
+    /// bytes read and not granted back to the writer yet
+    ungranted: usize,
+}
+
+impl MuxStream {
+    /// open a stream of this end
+    fn new(id: u32, shared: Arc<Shared>) -> Self {
+        let (stream, receiving) = Self::opened(id, shared.clone());
+
+        shared.streams.lock().insert(id, receiving);
+
+        stream
+    }
+
+    /// a stream along with what the demultiplexer needs of it
+    fn opened(id: u32, shared: Arc<Shared>) -> (Self, Receiving) {
+        let (sender, receiver) = mpsc::channel();
+        let unread = Arc::new(AtomicUsize::new(0));
+        let credit = Arc::new(Credit::new());
+
+        let stream = Self(Arc::new(Stream {
+            id,
+            shared,
+            reading: Mutex::new(Reading {
+                receiver,
+                buffer: VecDeque::new(),
+                ungranted: 0,
+            }),
+            read_timeout: Mutex::new(None),
+            unread: unread.clone(),
+            credit: credit.clone(),
+        }));
+
+        (
+            stream,
+            Receiving {
+                sender,
+                unread,
+                credit,
+            },
+        )
+    }
+
+    pub fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    /// fail reads that wait longer than `timeout`, for clones of the stream
+    /// too
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) {
+        *self.0.read_timeout.lock() = timeout;
+    }
+
+    /// close the stream for both ends before every clone of it is dropped,
+    /// ending their reads and failing their writes
+    pub fn shutdown(&self) -> std::io::Result<()> {
+        self.0.close()
+    }
+}
+
+impl Stream {
+    fn close(&self) -> std::io::Result<()> {
+        self.shared.streams.lock().remove(&self.id);
+        self.credit.close();
+
+        self.shared.write_frame(self.id, CLOSE, &[])
+    }
+}
+
+impl Read for MuxStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let timeout = *self.0.read_timeout.lock();
+        let mut reading = self.0.reading.lock();
+
+        if reading.buffer.is_empty() {
+            let received = match timeout {
+                Some(timeout) => reading.receiver.recv_timeout(timeout),
+                None => reading
+                    .receiver
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match received {
+                Ok(bytes) => reading.buffer.extend(bytes),
+                Err(RecvTimeoutError::Timeout) => return Err(ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+
+        let read = reading.buffer.read(buf)?;
+
+        self.0.unread.fetch_sub(read, Ordering::Relaxed);
+        reading.ungranted += read;
+
+        // granted in batches rather than a frame per read
+        if reading.ungranted >= WINDOW / 4 {
+            let granted = std::mem::take(&mut reading.ungranted) as u32;
+
+            // a connection failing to carry it ends the reads anyway
+            let _ = self
+                .0
+                .shared
+                .write_frame(self.0.id, CREDIT, &granted.to_be_bytes());
+        }
+
+        Ok(read)
+    }
+}
+
+impl Write for MuxStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let length = self.0.credit.take(buf.len().min(MAX_FRAME))?;
+
+        self.0.shared.write_frame(self.0.id, DATA, &buf[..length])?;
+
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+    use crate::{ClientMessage, Message, Protocol};
+
+    fn connected() -> ((Mux, MuxStream), (Mux, MuxStream)) {
+        let (client, server) = UnixStream::pair().unwrap();
+
+        (
+            Mux::new(client.try_clone().unwrap(), client, Side::Client),
+            Mux::new(server.try_clone().unwrap(), server, Side::Server),
+        )
+    }
+
+    #[test]
+    fn control_messages_share_the_connection_with_a_transfer() {
+        let ((client, mut client_control), (server, mut server_control)) = connected();
+
+        let mut bulk = client.open().unwrap();
+
+        let transfer = std::thread::spawn(move || {
+            bulk.write_all(&vec![7; 4 * 1024 * 1024]).unwrap();
+        });
+
+        Message::from(ClientMessage::PowerOff)
+            .send(&mut client_control)
+            .unwrap();
+
+        assert_eq!(
+            Message::receive(&mut server_control).unwrap(),
+            Message::from(ClientMessage::PowerOff)
+        );
+
+        let mut received = vec![];
+        server.accept().unwrap().read_to_end(&mut received).unwrap();
+
+        transfer.join().unwrap();
+
+        assert_eq!(received, vec![7; 4 * 1024 * 1024]);
+    }
+
+    #[test]
+    fn unread_stream_holds_back_its_writer() {
+        let ((client, _client_control), (server, _server_control)) = connected();
+
+        let mut stream = client.open().unwrap();
+        stream.write_all(&vec![7; WINDOW]).unwrap();
+
+        let (written, done) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            stream.write_all(&[7]).unwrap();
+            written.send(()).unwrap();
+        });
+
+        assert!(done.recv_timeout(Duration::from_millis(100)).is_err());
+
+        let mut accepted = server.accept().unwrap();
+        accepted.read_exact(&mut vec![0; WINDOW / 4]).unwrap();
+
+        done.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn streams_past_the_limit_are_closed() {
+        let ((client, _client_control), (server, _server_control)) = connected();
+
+        let mut streams = vec![];
+
+        for _ in 0..=MAX_STREAMS {
+            let mut stream = client.open().unwrap();
+            stream.write_all(b"open").unwrap();
+            streams.push(stream);
+        }
+
+        let accepted: Vec<_> = (0..MAX_STREAMS).map(|_| server.accept().unwrap()).collect();
+
+        let mut received = vec![];
+        streams
+            .last_mut()
+            .unwrap()
+            .read_to_end(&mut received)
+            .unwrap();
+
+        assert!(received.is_empty());
+        assert_eq!(accepted.len(), MAX_STREAMS);
+    }
+
+    #[test]
+    fn running_out_of_ids_fails_opening() {
+        let ((client, _client_control), _server) = connected();
+
+        *client.shared.next_id.lock() = Some(u32::MAX);
+
+        assert!(client.open().is_ok());
+        assert!(client.open().is_err());
+    }
+
+    #[test]
+    fn both_ends_open_streams() {
+        let ((client, _client_control), (server, _server_control)) = connected();
+
+        let mut from_client = client.open().unwrap();
+        let mut from_server = server.open().unwrap();
+
+        from_client.write_all(b"client").unwrap();
+        from_server.write_all(b"server").unwrap();
+
+        let mut accepted_by_server = server.accept().unwrap();
+        let mut accepted_by_client = client.accept().unwrap();
+
+        assert_eq!(accepted_by_server.id(), from_client.id());
+        assert_eq!(accepted_by_client.id(), from_server.id());
+        assert_ne!(from_client.id(), from_server.id());
+
+        let mut buffer = [0; 6];
+
+        accepted_by_server.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"client");
+
+        accepted_by_client.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"server");
+    }
+
+    #[test]
+    fn closed_stream_ends() {
+        let ((client, _client_control), (server, _server_control)) = connected();
+
+        let mut stream = client.open().unwrap();
+        stream.write_all(b"done").unwrap();
+        drop(stream);
+
+        let mut accepted = server.accept().unwrap();
+        let mut received = vec![];
+        accepted.read_to_end(&mut received).unwrap();
+
+        assert_eq!(received, b"done");
+    }
+
+    #[test]
+    fn shut_down_stream_ends_for_every_clone() {
+        let ((client, _client_control), (server, _server_control)) = connected();
+
+        let stream = client.open().unwrap();
+        let mut clone = stream.clone();
+        clone.write_all(b"open").unwrap();
+
+        let mut accepted = server.accept().unwrap();
+
+        stream.shutdown().unwrap();
+
+        let mut received = vec![];
+        accepted.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"open");
+
+        received.clear();
+        clone.read_to_end(&mut received).unwrap();
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn lost_connection_ends_every_stream() {
+        let (client, server) = UnixStream::pair().unwrap();
+
+        let (_mux, mut control) = Mux::new(server.try_clone().unwrap(), server, Side::Server);
+
+        drop(client);
+
+        let mut received = vec![];
+        control.read_to_end(&mut received).unwrap();
+
+        assert!(received.is_empty());
+    }
+}
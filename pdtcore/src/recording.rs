@@ -0,0 +1,108 @@
+//! a log of every message that went over the connections of a process, for
+//! diagnosing sessions after the fact
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bincode::{Decode, Encode};
+
+use crate::{config, Message, Protocol, ProtocolError};
+
+/// which way a message went, seen from the process that recorded it
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// a message as it went over a connection
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// microseconds since the unix epoch
+    pub at: u64,
+    pub direction: Direction,
+    /// connection the message went over, the client id on the server
+    pub connection: String,
+    /// the message as encoded on the wire
+    pub bytes: Vec<u8>,
+}
+
+impl Frame {
+    pub fn at(&self) -> SystemTime {
+        UNIX_EPOCH + std::time::Duration::from_micros(self.at)
+    }
+
+    pub fn message(&self) -> Result<Message, ProtocolError> {
+        Message::receive(&mut &self.bytes[..])
+    }
+}
+
+/// appends frames to a file, clones share the file so every connection can
+/// record to it
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<File>>,
+}
+
+impl Recorder {
+    /// record to the end of the file at `path`, creating it if needed
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// write a frame for `message`, unbuffered so a recording is complete up
+    /// to the moment a process dies
+    pub fn record(
+        &self,
+        direction: Direction,
+        connection: &str,
+        message: &Message,
+    ) -> Result<(), ProtocolError> {
+        let mut bytes = Vec::new();
+        message.send(&mut bytes)?;
+
+        let frame = Frame {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64,
+            direction,
+            connection: connection.to_string(),
+            bytes,
+        };
+
+        let encoded = bincode::encode_to_vec(&frame, config())?;
+
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| std::io::Error::other("recording lock poisoned"))?;
+
+        file.write_all(&encoded)?;
+
+        Ok(())
+    }
+}
+
+/// every frame of a recording, in the order they were recorded
+pub fn frames(mut recording: &[u8]) -> Result<Vec<Frame>, ProtocolError> {
+    let mut frames = Vec::new();
+
+    while !recording.is_empty() {
+        let (frame, length) = bincode::decode_from_slice(recording, config())?;
+
+        frames.push(frame);
+        recording = &recording[length..];
+    }
+
+    Ok(frames)
+}
@@ -0,0 +1,451 @@
+//! every message encodes and decodes to itself, so changes to the protocol
+//! types that break the wire format show up here
+
+use proptest::{prelude::*, strategy::LazyJust};
+
+use crate::*;
+
+/// empty, arbitrary unicode, and long enough for multi-byte length prefixes
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![Just(String::new()), any::<String>(), "\\PC{256,2048}"]
+}
+
+fn virtualization() -> impl Strategy<Value = Virtualization> {
+    prop_oneof![
+        Just(Virtualization::Unknown),
+        Just(Virtualization::Physical),
+        Just(Virtualization::VirtualMachine),
+        Just(Virtualization::Container),
+    ]
+}
+
+fn device_info() -> impl Strategy<Value = DeviceInfo> {
+    (
+        (text(), text(), text(), text()),
+        (text(), text(), text(), text(), virtualization()),
+    )
+        .prop_map(
+            |((name, os, os_version, uptime), (vendor, model, serial, chassis, virtualization))| {
+                DeviceInfo {
+                    name,
+                    os,
+                    os_version,
+                    uptime,
+                    vendor,
+                    model,
+                    serial,
+                    chassis,
+                    virtualization,
+                }
+            },
+        )
+}
+
+fn device_info_delta() -> impl Strategy<Value = DeviceInfoDelta> {
+    (
+        (
+            prop::option::of(text()),
+            prop::option::of(text()),
+            prop::option::of(text()),
+            prop::option::of(text()),
+        ),
+        (
+            prop::option::of(text()),
+            prop::option::of(text()),
+            prop::option::of(text()),
+            prop::option::of(text()),
+            prop::option::of(virtualization()),
+        ),
+    )
+        .prop_map(
+            |((name, os, os_version, uptime), (vendor, model, serial, chassis, virtualization))| {
+                DeviceInfoDelta {
+                    name,
+                    os,
+                    os_version,
+                    uptime,
+                    vendor,
+                    model,
+                    serial,
+                    chassis,
+                    virtualization,
+                }
+            },
+        )
+}
+
+fn built_info() -> impl Strategy<Value = BuiltInfo> {
+    (
+        (text(), text(), text(), text()),
+        (text(), text(), text(), text()),
+        proptest::option::of(text()),
+    )
+        .prop_map(
+            |(
+                (pkg_version, pkg_version_major, pkg_version_minor, pkg_version_patch),
+                (pkg_version_pre, target, host, profile),
+                git_commit_hash,
+            )| BuiltInfo {
+                pkg_version,
+                pkg_version_major,
+                pkg_version_minor,
+                pkg_version_patch,
+                pkg_version_pre,
+                target,
+                host,
+                profile,
+                git_commit_hash,
+            },
+        )
+}
+
+fn telemetry() -> impl Strategy<Value = Telemetry> {
+    (
+        // NaN never equals itself
+        any::<f64>().prop_filter("NaN", |load| !load.is_nan()),
+        any::<u64>(),
+        any::<u64>(),
+        any::<u64>(),
+        any::<u64>(),
+    )
+        .prop_map(
+            |(load, memory_total, memory_used, network_received, network_transmitted)| Telemetry {
+                load,
+                memory_total,
+                memory_used,
+                network_received,
+                network_transmitted,
+            },
+        )
+}
+
+fn server_info() -> impl Strategy<Value = ServerInfo> {
+    (text(), text(), any::<u64>()).prop_map(|(name, version, up_since)| ServerInfo {
+        name,
+        version,
+        up_since,
+    })
+}
+
+fn protocol_config() -> impl Strategy<Value = ProtocolConfig> {
+    (
+        any::<u64>(),
+        any::<u64>(),
+        any::<u32>(),
+        prop::option::of(any::<u32>()),
+    )
+        .prop_map(
+            |(heartbeat_interval, idle_timeout, max_frame_size, compression_threshold)| {
+                ProtocolConfig {
+                    heartbeat_interval,
+                    idle_timeout,
+                    max_frame_size,
+                    compression_threshold,
+                }
+            },
+        )
+}
+
+fn trace_context() -> impl Strategy<Value = TraceContext> {
+    text().prop_map(|traceparent| TraceContext { traceparent })
+}
+
+fn log_record() -> impl Strategy<Value = LogRecord> {
+    let level = prop_oneof![
+        Just(LogLevel::Error),
+        Just(LogLevel::Warn),
+        Just(LogLevel::Info),
+        Just(LogLevel::Debug),
+        Just(LogLevel::Trace),
+    ];
+
+    (any::<u64>(), level, text(), text()).prop_map(|(at, level, target, message)| LogRecord {
+        at,
+        level,
+        target,
+        message,
+    })
+}
+
+fn container_stats() -> impl Strategy<Value = ContainerStats> {
+    (
+        text(),
+        text(),
+        text(),
+        0.0..6400.0,
+        any::<u64>(),
+        any::<u64>(),
+    )
+        .prop_map(
+            |(id, name, image, cpu, memory_used, memory_limit)| ContainerStats {
+                id,
+                name,
+                image,
+                cpu,
+                memory_used,
+                memory_limit,
+            },
+        )
+}
+
+fn telemetry_config() -> impl Strategy<Value = TelemetryConfig> {
+    let family = prop_oneof![
+        Just(MetricFamily::Load),
+        Just(MetricFamily::Memory),
+        Just(MetricFamily::Network),
+    ];
+
+    (any::<u64>(), prop::collection::vec(family, 0..4))
+        .prop_map(|(interval, families)| TelemetryConfig { interval, families })
+}
+
+fn client_message() -> impl Strategy<Value = ClientMessage> {
+    prop_oneof![
+        Just(ClientMessage::ScreenOff),
+        Just(ClientMessage::ScreenOn),
+        Just(ClientMessage::PowerOff),
+        Just(ClientMessage::Restart),
+        Just(ClientMessage::Goodbye),
+        Just(ClientMessage::RequestDeviceInfo),
+        Just(ClientMessage::RequestTelemetry),
+        (text(), text())
+            .prop_map(|(title, body)| ClientMessage::Notify(Notification { title, body })),
+        (text(), server_info(), protocol_config()).prop_map(|(assigned_id, server, protocol)| {
+            ClientMessage::Welcome(Welcome {
+                assigned_id,
+                server,
+                protocol,
+            })
+        }),
+        (text(), any::<Vec<u8>>())
+            .prop_map(|(namespace, payload)| ClientMessage::Custom { namespace, payload }),
+        text().prop_map(|namespace| ClientMessage::Nack { namespace }),
+        telemetry_config().prop_map(ClientMessage::ConfigUpdate),
+    ]
+    .prop_recursive(3, 8, 1, |inner| {
+        (trace_context(), inner)
+            .prop_map(|(trace, message)| ClientMessage::Traced(trace, Box::new(message)))
+    })
+}
+
+fn server_message() -> impl Strategy<Value = ServerMessage> {
+    prop_oneof![
+        (text(), built_info(), protocol_config()).prop_map(
+            |(name, pdtcore_built_info, protocol)| {
+                ServerMessage::Hello(Box::new(ClientIntroduction {
+                    name,
+                    pdtcore_built_info,
+                    protocol,
+                }))
+            }
+        ),
+        device_info().prop_map(ServerMessage::DeviceInfo),
+        device_info_delta().prop_map(ServerMessage::DeviceInfoDelta),
+        LazyJust::new(|| ServerMessage::Goodbye),
+        telemetry().prop_map(ServerMessage::Telemetry),
+        (trace_context(), text(), prop::option::of(text())).prop_map(|(trace, command, error)| {
+            ServerMessage::Executed(CommandExecution {
+                trace,
+                command,
+                error,
+            })
+        }),
+        prop::collection::vec(log_record(), 0..4).prop_map(ServerMessage::Logs),
+        (any::<u64>(), text(), text(), built_info()).prop_map(
+            |(at, message, backtrace, built_info)| {
+                ServerMessage::CrashReport(Box::new(CrashReport {
+                    at,
+                    message,
+                    backtrace,
+                    built_info,
+                }))
+            }
+        ),
+        (text(), any::<Vec<u8>>())
+            .prop_map(|(namespace, payload)| ServerMessage::Custom { namespace, payload }),
+        text().prop_map(|namespace| ServerMessage::Nack { namespace }),
+        (
+            any::<u32>(),
+            any::<u32>(),
+            prop::collection::vec(container_stats(), 0..4)
+        )
+            .prop_map(|(running, stopped, stats)| {
+                ServerMessage::Containers(Containers {
+                    running,
+                    stopped,
+                    stats,
+                })
+            }),
+        text().prop_map(ServerMessage::Identity),
+        LazyJust::new(|| ServerMessage::Alive),
+    ]
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    prop_oneof![
+        client_message().prop_map(Message::Client),
+        server_message().prop_map(Message::Server),
+    ]
+}
+
+fn round_trip(message: &Message) -> Message {
+    let mut bytes = Vec::new();
+    message.send(&mut bytes).unwrap();
+
+    let mut stream = &bytes[..];
+    let decoded = Message::receive(&mut stream).unwrap();
+
+    assert!(stream.is_empty(), "decoding left bytes behind");
+
+    decoded
+}
+
+proptest! {
+    #[test]
+    fn messages_round_trip(message in message()) {
+        prop_assert_eq!(round_trip(&message), message);
+    }
+
+    #[test]
+    fn consecutive_messages_decode_in_order(messages in prop::collection::vec(message(), 0..8)) {
+        let mut bytes = Vec::new();
+
+        for message in &messages {
+            message.send(&mut bytes).unwrap();
+        }
+
+        let mut stream = &bytes[..];
+
+        for message in &messages {
+            prop_assert_eq!(&Message::receive(&mut stream).unwrap(), message);
+        }
+
+        prop_assert!(stream.is_empty());
+    }
+
+    #[test]
+    fn reader_keeps_read_ahead_bytes_for_the_next_message(
+        messages in prop::collection::vec(message(), 0..8)
+    ) {
+        let mut bytes = Vec::new();
+
+        for message in &messages {
+            message.send(&mut bytes).unwrap();
+        }
+
+        let mut reader = crate::codec::MessageReader::new(&bytes[..]);
+
+        for message in &messages {
+            prop_assert_eq!(&reader.receive().unwrap(), message);
+        }
+
+        prop_assert!(reader.buffered().is_empty());
+        prop_assert!(reader.get_ref().is_empty());
+    }
+
+    #[test]
+    fn reused_buffer_encodes_like_send(messages in prop::collection::vec(message(), 0..8)) {
+        let mut sent = Vec::new();
+        let mut writer = crate::codec::MessageWriter::new(Vec::new());
+
+        for message in &messages {
+            message.send(&mut sent).unwrap();
+            writer.send(message).unwrap();
+        }
+
+        prop_assert_eq!(writer.into_inner(), sent);
+    }
+
+    #[test]
+    fn applied_changes_rebuild_the_newer_device_info(
+        older in device_info(),
+        newer in device_info(),
+    ) {
+        let changes = older.changes(&newer);
+        let mut rebuilt = older.clone();
+        rebuilt.apply(changes.clone());
+
+        prop_assert_eq!(&rebuilt, &newer);
+        prop_assert_eq!(changes.is_empty(), older == newer);
+    }
+
+    #[test]
+    fn reconciled_config_suits_both_ends(
+        own in protocol_config(),
+        proposed in protocol_config(),
+    ) {
+        let reconciled = own.reconcile(&proposed);
+
+        prop_assert_eq!(reconciled.heartbeat_interval, own.heartbeat_interval);
+        prop_assert!(reconciled.idle_timeout >= own.idle_timeout.max(proposed.idle_timeout));
+        prop_assert!(reconciled.idle_timeout >= reconciled.heartbeat_interval.saturating_mul(2));
+        prop_assert!(reconciled.max_frame_size <= own.max_frame_size.min(proposed.max_frame_size));
+        prop_assert!(reconciled.max_frame_size() <= MAX_MESSAGE_SIZE);
+        prop_assert_eq!(
+            reconciled.compression_threshold.is_some(),
+            own.compression_threshold.is_some() && proposed.compression_threshold.is_some()
+        );
+    }
+}
+
+fn notification(length: usize) -> Message {
+    Message::from(ClientMessage::Notify(Notification {
+        title: "x".repeat(length),
+        body: String::new(),
+    }))
+}
+
+#[test]
+fn messages_within_size_limit_round_trip() {
+    // the limit counts decoded sizes, where variant tags take four bytes
+    // and lengths eight
+    let largest = notification(MAX_MESSAGE_SIZE - 32);
+
+    assert_eq!(round_trip(&largest), largest);
+}
+
+#[test]
+fn messages_over_size_limit_are_rejected() {
+    let mut bytes = Vec::new();
+    notification(MAX_MESSAGE_SIZE + 1).send(&mut bytes).unwrap();
+
+    assert!(Message::receive(&mut &bytes[..]).is_err());
+}
+
+#[test]
+fn messages_over_agreed_frame_size_are_refused() {
+    let message = notification(1024);
+
+    let mut writer = crate::codec::MessageWriter::new(Vec::new());
+    writer.set_max_frame_size(512);
+
+    assert!(matches!(
+        writer.send(&message),
+        Err(ProtocolError::FrameTooLarge(_))
+    ));
+    assert!(writer.get_ref().is_empty());
+
+    let mut bytes = Vec::new();
+    message.send(&mut bytes).unwrap();
+
+    let mut reader = crate::codec::MessageReader::new(&bytes[..]);
+    reader.set_max_frame_size(512);
+
+    assert!(reader.receive().is_err());
+}
+
+/// custom messages are added after the stock ones, which keep the tags peers
+/// without them know
+#[test]
+fn stock_messages_keep_their_tags() {
+    let mut bytes = Vec::new();
+
+    Message::from(ClientMessage::RequestTelemetry)
+        .send(&mut bytes)
+        .unwrap();
+    Message::from(ServerMessage::Goodbye)
+        .send(&mut bytes)
+        .unwrap();
+
+    assert_eq!(bytes, [0, 6, 1, 3]);
+}
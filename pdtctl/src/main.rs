@@ -0,0 +1,319 @@
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    process::ExitCode,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+mod tui;
+
+use pdtapi::{ApiClient, ClientError, ClientEvent, ClientSummary, Command, ConnectionState};
+
+const USAGE: &str = "usage: pdtctl [--url URL] [--token TOKEN] [--dry-run] COMMAND
+
+commands:
+  clients                                  list connected clients
+  screen-off | screen-on | restart | power-off NAME
+                                           send a command to the clients named NAME
+  broadcast COMMAND [--name PART] [--state STATE]
+                                           send a command to every matching client
+  watch                                    print client events as they happen
+  tui                                      full screen dashboard with live status,
+                                           telemetry and key bindings for commands
+
+with --dry-run commands only report the clients that would receive them,
+powering off several clients asks for a confirmation
+
+the url and token default to PDT_URL and PDT_TOKEN";
+
+const DEFAULT_URL: &str = "http://localhost:2040";
+
+#[derive(Debug)]
+enum CtlError {
+    Usage(String),
+    Api(ClientError),
+    NoClients,
+    /// a destructive command was armed and not confirmed
+    NotConfirmed,
+    Terminal(std::io::Error),
+}
+
+impl From<ClientError> for CtlError {
+    fn from(value: ClientError) -> Self {
+        CtlError::Api(value)
+    }
+}
+
+impl CtlError {
+    fn describe(&self) -> String {
+        match self {
+            CtlError::Usage(message) => format!("{message}\n\n{USAGE}"),
+            CtlError::Api(ClientError::Api(status, response)) => {
+                format!("server answered {status}: {}", response.error)
+            }
+            CtlError::Api(ClientError::Transport(transport)) => transport.to_string(),
+            CtlError::Api(ClientError::Decode(error)) => format!("invalid response: {error}"),
+            CtlError::Api(ClientError::Event(error)) => format!("invalid event: {error}"),
+            CtlError::NoClients => "no matching clients".to_string(),
+            CtlError::NotConfirmed => "not confirmed, nothing was sent".to_string(),
+            CtlError::Terminal(error) => format!("terminal: {error}"),
+        }
+    }
+}
+
+/// positional arguments and `--name value` options
+struct Arguments {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    help: bool,
+    dry_run: bool,
+}
+
+impl Arguments {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, CtlError> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut help = false;
+        let mut dry_run = false;
+
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                help = true;
+                continue;
+            }
+
+            if arg == "--dry-run" {
+                dry_run = true;
+                continue;
+            }
+
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| CtlError::Usage(format!("--{name} needs a value")))?;
+
+                    options.insert(name.to_string(), value);
+                }
+                None => positional.push(arg),
+            }
+        }
+
+        Ok(Self {
+            positional,
+            options,
+            help,
+            dry_run,
+        })
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+}
+
+fn command(name: &str) -> Option<Command> {
+    match name {
+        "screen-off" => Some(Command::ScreenOff),
+        "screen-on" => Some(Command::ScreenOn),
+        "restart" => Some(Command::Restart),
+        "power-off" => Some(Command::PowerOff),
+        _ => None,
+    }
+}
+
+fn state(state: ConnectionState) -> &'static str {
+    match state {
+        ConnectionState::Connecting => "connecting",
+        ConnectionState::Connected => "connected",
+    }
+}
+
+/// time since a unix timestamp, e.g. `12s ago`
+fn ago(timestamp: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    format!("{}s ago", now.saturating_sub(timestamp))
+}
+
+fn print_clients(clients: &[ClientSummary]) {
+    println!(
+        "{:<26}  {:<10}  {:<20}  {:<20}  last seen",
+        "id", "state", "name", "os"
+    );
+
+    for client in clients {
+        println!(
+            "{:<26}  {:<10}  {:<20}  {:<20}  {}",
+            client.id,
+            state(client.state),
+            client.device_info.name,
+            format!(
+                "{} {}",
+                client.device_info.os, client.device_info.os_version
+            ),
+            ago(client.last_seen)
+        );
+    }
+}
+
+/// send `command` to `clients`, printing the outcome per client, or with
+/// `dry_run` only what the outcome would be
+fn send(
+    api: &ApiClient,
+    clients: Vec<ClientSummary>,
+    command: Command,
+    dry_run: bool,
+) -> Result<(), CtlError> {
+    if clients.is_empty() {
+        return Err(CtlError::NoClients);
+    }
+
+    let names: HashMap<String, String> = clients
+        .iter()
+        .map(|client| (client.id.clone(), client.device_info.name.clone()))
+        .collect();
+
+    let client_ids: Vec<String> = clients.into_iter().map(|client| client.id).collect();
+
+    let response = if dry_run {
+        api.preview_bulk_command(client_ids, command)?
+    } else if command.is_destructive() && client_ids.len() > 1 {
+        let armed = api.arm_bulk_command(client_ids.clone(), command)?;
+
+        println!(
+            "{command:?} is armed for {} clients, type yes within {}s to send it",
+            client_ids.len(),
+            armed.expires_in
+        );
+
+        let mut answer = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut answer)
+            .map_err(CtlError::Terminal)?;
+
+        if answer.trim() != "yes" {
+            return Err(CtlError::NotConfirmed);
+        }
+
+        api.bulk_command(client_ids, command, Some(armed.confirmation))?
+    } else {
+        api.bulk_command(client_ids, command, None)?
+    };
+
+    for result in response.results {
+        let name = names.get(&result.client_id).map_or("", String::as_str);
+
+        match result.error {
+            None if response.dry_run => println!("{} {name}: would send", result.client_id),
+            None => println!("{} {name}: sent", result.client_id),
+            Some(error) => println!("{} {name}: {error}", result.client_id),
+        }
+    }
+
+    Ok(())
+}
+
+fn watch(api: &ApiClient) -> Result<(), CtlError> {
+    for event in api.events()? {
+        match event? {
+            ClientEvent::Connected { client } => {
+                println!("{} {}: connected", client.id, client.device_info.name)
+            }
+            ClientEvent::Updated { client } => println!(
+                "{} {}: {}",
+                client.id,
+                client.device_info.name,
+                state(client.state)
+            ),
+            ClientEvent::Disconnected { client_id } => println!("{client_id}: disconnected"),
+            ClientEvent::Heartbeat { .. } => {}
+            ClientEvent::CommandResult { client_id, result } => println!("{client_id}: {result}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn run() -> Result<(), CtlError> {
+    let arguments = Arguments::parse(std::env::args().skip(1))?;
+
+    if arguments.help {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let url = arguments
+        .option("url")
+        .map(String::from)
+        .or_else(|| std::env::var("PDT_URL").ok())
+        .unwrap_or_else(|| DEFAULT_URL.to_string());
+
+    let token = arguments
+        .option("token")
+        .map(String::from)
+        .or_else(|| std::env::var("PDT_TOKEN").ok());
+
+    let api = match token {
+        Some(token) => ApiClient::new(url).with_token(token),
+        None => ApiClient::new(url),
+    };
+
+    let positional: Vec<&str> = arguments.positional.iter().map(String::as_str).collect();
+
+    match positional.as_slice() {
+        ["clients"] => {
+            print_clients(&api.clients()?);
+            Ok(())
+        }
+        ["watch"] => watch(&api),
+        ["tui"] => tui::run(&api),
+        ["broadcast", name] => {
+            let command =
+                command(name).ok_or_else(|| CtlError::Usage(format!("unknown command {name}")))?;
+
+            let part = arguments.option("name").unwrap_or("").to_lowercase();
+            let state_filter = arguments.option("state");
+
+            let clients = api
+                .clients()?
+                .into_iter()
+                .filter(|client| client.device_info.name.to_lowercase().contains(&part))
+                .filter(|client| state_filter.is_none_or(|filter| state(client.state) == filter))
+                .collect();
+
+            send(&api, clients, command, arguments.dry_run)
+        }
+        [name, target] => {
+            let command =
+                command(name).ok_or_else(|| CtlError::Usage(format!("unknown command {name}")))?;
+
+            let clients = api
+                .clients()?
+                .into_iter()
+                .filter(|client| client.id == *target || client.device_info.name == *target)
+                .collect();
+
+            send(&api, clients, command, arguments.dry_run)
+        }
+        [] => Err(CtlError::Usage("missing command".to_string())),
+        _ => Err(CtlError::Usage(format!(
+            "unknown command {}",
+            positional.join(" ")
+        ))),
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error.describe());
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use pdtapi::{ApiClient, ClientEvent, ClientSummary, Command, TelemetrySample};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Sparkline, Table, TableState},
+    DefaultTerminal, Frame,
+};
+
+use crate::{ago, state, CtlError};
+
+/// how often the telemetry of the selected client is fetched
+const TELEMETRY_REFRESH: Duration = Duration::from_secs(5);
+/// how long to wait for a key press before redrawing
+const TICK: Duration = Duration::from_millis(250);
+
+const HELP: &str = "↑↓ select  o screen off  n screen on  r restart  p power off  q quit";
+
+struct Dashboard {
+    api: ApiClient,
+    clients: Vec<ClientSummary>,
+    table: TableState,
+    telemetry: Vec<TelemetrySample>,
+    telemetry_fetched: Option<Instant>,
+    /// restart or power off waiting for confirmation
+    pending: Option<Command>,
+    status: String,
+}
+
+impl Dashboard {
+    fn selected(&self) -> Option<&ClientSummary> {
+        self.table
+            .selected()
+            .and_then(|index| self.clients.get(index))
+    }
+
+    fn apply(&mut self, event: ClientEvent) {
+        match event {
+            ClientEvent::Connected { client }
+            | ClientEvent::Updated { client }
+            | ClientEvent::Heartbeat { client } => {
+                match self.clients.iter_mut().find(|known| known.id == client.id) {
+                    Some(known) => *known = client,
+                    None => self.clients.push(client),
+                }
+            }
+            ClientEvent::Disconnected { client_id } => {
+                self.clients.retain(|client| client.id != client_id)
+            }
+            ClientEvent::CommandResult { client_id, result } => {
+                self.status = format!("{client_id}: {result}")
+            }
+        }
+
+        if self
+            .table
+            .selected()
+            .is_none_or(|index| index >= self.clients.len())
+        {
+            self.table.select((!self.clients.is_empty()).then_some(0));
+        }
+    }
+
+    fn refresh_telemetry(&mut self) {
+        let due = self
+            .telemetry_fetched
+            .is_none_or(|fetched| fetched.elapsed() >= TELEMETRY_REFRESH);
+
+        if !due {
+            return;
+        }
+
+        self.telemetry_fetched = Some(Instant::now());
+
+        self.telemetry = match self.selected() {
+            Some(client) => self.api.telemetry(&client.id).unwrap_or_default(),
+            None => Vec::new(),
+        };
+    }
+
+    fn select(&mut self, offset: isize) {
+        if self.clients.is_empty() {
+            return;
+        }
+
+        let index = self.table.selected().unwrap_or(0) as isize + offset;
+
+        self.table.select(Some(
+            index.clamp(0, self.clients.len() as isize - 1) as usize
+        ));
+        self.telemetry_fetched = None;
+    }
+
+    fn send(&mut self, command: Command) {
+        let Some(client) = self.selected().cloned() else {
+            return;
+        };
+
+        self.status = match self.api.command(&client.id, command) {
+            Ok(_) => format!("{command:?} sent to {}", client.device_info.name),
+            Err(error) => CtlError::from(error).describe(),
+        };
+    }
+
+    /// handle a key press, false when the dashboard should close
+    fn key(&mut self, key: KeyCode) -> bool {
+        if let Some(command) = self.pending.take() {
+            match key {
+                KeyCode::Char('y') => self.send(command),
+                _ => self.status = "cancelled".to_string(),
+            }
+
+            return true;
+        }
+
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.select(1),
+            KeyCode::Char('o') => self.send(Command::ScreenOff),
+            KeyCode::Char('n') => self.send(Command::ScreenOn),
+            KeyCode::Char(key @ ('r' | 'p')) => {
+                let command = match key {
+                    'r' => Command::Restart,
+                    _ => Command::PowerOff,
+                };
+
+                if let Some(name) = self
+                    .selected()
+                    .map(|client| client.device_info.name.clone())
+                {
+                    self.status = format!("{command:?} {name}? y to confirm");
+                    self.pending = Some(command);
+                }
+            }
+            _ => {}
+        }
+
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [table_area, charts_area, status_area] = Layout::vertical([
+            Constraint::Min(5),
+            Constraint::Length(10),
+            Constraint::Length(2),
+        ])
+        .areas(frame.area());
+
+        let rows = self.clients.iter().map(|client| {
+            Row::new([
+                client.device_info.name.clone(),
+                state(client.state).to_string(),
+                format!(
+                    "{} {}",
+                    client.device_info.os, client.device_info.os_version
+                ),
+                client.device_info.uptime.clone(),
+                ago(client.last_seen),
+            ])
+        });
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Percentage(25),
+                Constraint::Length(10),
+                Constraint::Percentage(30),
+                Constraint::Percentage(20),
+                Constraint::Length(10),
+            ],
+        )
+        .header(
+            Row::new(["name", "state", "os", "uptime", "last seen"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(format!(" pdt, {} clients ", self.clients.len())));
+
+        frame.render_stateful_widget(table, table_area, &mut self.table);
+
+        let [load_area, memory_area] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(charts_area);
+
+        // sparklines take integers, load is kept to two decimals
+        let load: Vec<u64> = self
+            .telemetry
+            .iter()
+            .map(|sample| (sample.load * 100.0) as u64)
+            .collect();
+
+        let memory: Vec<u64> = self
+            .telemetry
+            .iter()
+            .map(|sample| sample.memory_used * 100 / sample.memory_total.max(1))
+            .collect();
+
+        let latest = self.telemetry.last();
+
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(format!(
+                    " load {:.2} ",
+                    latest.map_or(0.0, |sample| sample.load)
+                )))
+                .data(&load[load.len().saturating_sub(load_area.width as usize)..]),
+            load_area,
+        );
+
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(format!(
+                    " memory {}% ",
+                    memory.last().copied().unwrap_or_default()
+                )))
+                .max(100)
+                .data(&memory[memory.len().saturating_sub(memory_area.width as usize)..]),
+            memory_area,
+        );
+
+        frame.render_widget(
+            Paragraph::new(vec![Line::from(self.status.as_str()), Line::from(HELP)]),
+            status_area,
+        );
+    }
+}
+
+/// client events read on a separate thread, the event stream blocks
+fn events(api: ApiClient) -> Receiver<ClientEvent> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let Ok(events) = api.events() else {
+            return;
+        };
+
+        for event in events.flatten() {
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+fn run_dashboard(terminal: &mut DefaultTerminal, mut dashboard: Dashboard) -> Result<(), CtlError> {
+    let events = events(dashboard.api.clone());
+
+    loop {
+        while let Ok(event) = events.try_recv() {
+            dashboard.apply(event);
+        }
+
+        dashboard.refresh_telemetry();
+
+        terminal
+            .draw(|frame| dashboard.draw(frame))
+            .map_err(CtlError::Terminal)?;
+
+        if !event::poll(TICK).map_err(CtlError::Terminal)? {
+            continue;
+        }
+
+        if let Event::Key(key) = event::read().map_err(CtlError::Terminal)? {
+            if key.kind == KeyEventKind::Press && !dashboard.key(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// full screen dashboard of the connected clients
+pub fn run(api: &ApiClient) -> Result<(), CtlError> {
+    let clients = api.clients()?;
+
+    let dashboard = Dashboard {
+        api: api.clone(),
+        table: TableState::default().with_selected((!clients.is_empty()).then_some(0)),
+        clients,
+        telemetry: Vec::new(),
+        telemetry_fetched: None,
+        pending: None,
+        status: String::new(),
+    };
+
+    let mut terminal = ratatui::init();
+    let result = run_dashboard(&mut terminal, dashboard);
+    ratatui::restore();
+
+    result
+}
@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::{SocketAddr, TcpStream},
+    process::ExitCode,
+    str::FromStr,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use pdtcore::{
+    recording::{self, Direction, Frame},
+    Message, Protocol, ProtocolError,
+};
+
+const USAGE: &str = "usage: pdt-replay COMMAND RECORDING [OPTIONS]
+
+commands:
+  transcript RECORDING                     print every recorded message
+  play RECORDING [--address ADDRESS] [--connection ID] [--speed FACTOR]
+                                           connect to a server and send what the
+                                           client sent on one connection, with the
+                                           recorded timing
+
+recordings are written by pdtserver and pdtclient when RECORD_PATH is set
+
+the address defaults to PDT_ADDRESS or 127.0.0.1:2039, the connection to the
+first one in the recording and the speed to 1";
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:2039";
+
+/// how long to keep printing answers after the last message was sent
+const LINGER: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+enum ReplayError {
+    Usage(String),
+    Io(std::io::Error),
+    Recording(ProtocolError),
+    NoMessages,
+}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(value: std::io::Error) -> Self {
+        ReplayError::Io(value)
+    }
+}
+
+impl ReplayError {
+    fn describe(&self) -> String {
+        match self {
+            ReplayError::Usage(message) => format!("{message}\n\n{USAGE}"),
+            ReplayError::Io(error) => error.to_string(),
+            ReplayError::Recording(error) => format!("invalid recording: {error:?}"),
+            ReplayError::NoMessages => "no messages to replay".to_string(),
+        }
+    }
+}
+
+/// positional arguments and `--name value` options
+struct Arguments {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    help: bool,
+}
+
+impl Arguments {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, ReplayError> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut help = false;
+
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                help = true;
+                continue;
+            }
+
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| ReplayError::Usage(format!("--{name} needs a value")))?;
+
+                    options.insert(name.to_string(), value);
+                }
+                None => positional.push(arg),
+            }
+        }
+
+        Ok(Self {
+            positional,
+            options,
+            help,
+        })
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+}
+
+fn direction(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Sent => "sent",
+        Direction::Received => "received",
+    }
+}
+
+fn describe(frame: &Frame) -> String {
+    match frame.message() {
+        Ok(message) => format!("{message:?}"),
+        Err(error) => format!("{} undecodable bytes: {error:?}", frame.bytes.len()),
+    }
+}
+
+fn transcript(frames: &[Frame]) {
+    for frame in frames {
+        println!(
+            "{}  {}  {:<8}  {}",
+            humantime::format_rfc3339_micros(frame.at()),
+            frame.connection,
+            direction(frame.direction),
+            describe(frame)
+        );
+    }
+}
+
+/// send the messages a client sent on `connection` to the server at
+/// `address`, printing them along with the answers
+fn play(
+    frames: &[Frame],
+    address: SocketAddr,
+    connection: &str,
+    speed: f64,
+) -> Result<(), ReplayError> {
+    // messages for a server, whichever side recorded them
+    let outgoing: Vec<&Frame> = frames
+        .iter()
+        .filter(|frame| frame.connection == connection)
+        .filter(|frame| matches!(frame.message(), Ok(Message::Server(_))))
+        .collect();
+
+    let Some(first) = outgoing.first() else {
+        return Err(ReplayError::NoMessages);
+    };
+
+    let mut stream = TcpStream::connect(address)?;
+    let mut read = stream.try_clone()?;
+
+    thread::spawn(move || {
+        while let Ok(message) = Message::receive(&mut read) {
+            println!(
+                "{}  received  {message:?}",
+                humantime::format_rfc3339_micros(SystemTime::now())
+            );
+        }
+    });
+
+    let mut previous = first.at;
+
+    for frame in outgoing {
+        thread::sleep(Duration::from_micros(frame.at.saturating_sub(previous)).div_f64(speed));
+        previous = frame.at;
+
+        stream.write_all(&frame.bytes)?;
+
+        println!(
+            "{}  sent      {}",
+            humantime::format_rfc3339_micros(SystemTime::now()),
+            describe(frame)
+        );
+    }
+
+    thread::sleep(LINGER);
+
+    Ok(())
+}
+
+fn run() -> Result<(), ReplayError> {
+    let arguments = Arguments::parse(std::env::args().skip(1))?;
+
+    if arguments.help {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let positional: Vec<&str> = arguments.positional.iter().map(String::as_str).collect();
+
+    let (command, path) = match positional.as_slice() {
+        [command, path] => (*command, *path),
+        [] => return Err(ReplayError::Usage("missing command".to_string())),
+        _ => {
+            return Err(ReplayError::Usage(format!(
+                "unknown command {}",
+                positional.join(" ")
+            )))
+        }
+    };
+
+    let recording = std::fs::read(path)?;
+    let frames = recording::frames(&recording).map_err(ReplayError::Recording)?;
+
+    match command {
+        "transcript" => {
+            transcript(&frames);
+            Ok(())
+        }
+        "play" => {
+            let address = arguments
+                .option("address")
+                .map(String::from)
+                .or_else(|| std::env::var("PDT_ADDRESS").ok())
+                .unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+
+            let address = SocketAddr::from_str(&address)
+                .map_err(|_| ReplayError::Usage(format!("invalid address {address}")))?;
+
+            let connection = match arguments.option("connection") {
+                Some(connection) => connection,
+                None => frames
+                    .first()
+                    .ok_or(ReplayError::NoMessages)?
+                    .connection
+                    .as_str(),
+            };
+
+            let speed = match arguments.option("speed") {
+                Some(speed) => f64::from_str(speed)
+                    .ok()
+                    .filter(|speed| *speed > 0.0)
+                    .ok_or_else(|| ReplayError::Usage(format!("invalid speed {speed}")))?,
+                None => 1.0,
+            };
+
+            play(&frames, address, connection, speed)
+        }
+        _ => Err(ReplayError::Usage(format!("unknown command {command}"))),
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error.describe());
+            ExitCode::FAILURE
+        }
+    }
+}
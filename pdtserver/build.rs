@@ -0,0 +1,10 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // a vendored protoc keeps the build free of system dependencies
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+
+    tonic_build::configure()
+        .build_client(false)
+        .compile(&["proto/pdt.proto"], &["proto"])?;
+
+    Ok(())
+}
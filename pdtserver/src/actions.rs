@@ -0,0 +1,373 @@
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    routing, Form, Router,
+};
+use pdtapi::Command;
+use pdtcore::{Client, ClientMessage};
+use serde::Deserialize;
+use tracing::*;
+use ulid::Ulid;
+
+use crate::{
+    approval::{Approval, Approved},
+    arming,
+    auth::Access,
+    command, federation, filters,
+    server::SendError,
+    store, AppError, AppStateReference,
+};
+
+/// command buttons on a dashboard device
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    ScreenOff,
+    ScreenOn,
+    PowerOff,
+    Restart,
+}
+
+impl Action {
+    pub fn path(&self) -> &'static str {
+        match self {
+            Action::ScreenOff => "screen-off",
+            Action::ScreenOn => "screen-on",
+            Action::PowerOff => "power-off",
+            Action::Restart => "restart",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::ScreenOff => "screen off",
+            Action::ScreenOn => "screen on",
+            Action::PowerOff => "power off",
+            Action::Restart => "restart",
+        }
+    }
+}
+
+impl From<Action> for ClientMessage {
+    fn from(value: Action) -> Self {
+        match value {
+            Action::ScreenOff => ClientMessage::ScreenOff,
+            Action::ScreenOn => ClientMessage::ScreenOn,
+            Action::PowerOff => ClientMessage::PowerOff,
+            Action::Restart => ClientMessage::Restart,
+        }
+    }
+}
+
+impl From<Action> for Command {
+    fn from(value: Action) -> Self {
+        match value {
+            Action::ScreenOff => Command::ScreenOff,
+            Action::ScreenOn => Command::ScreenOn,
+            Action::PowerOff => Command::PowerOff,
+            Action::Restart => Command::Restart,
+        }
+    }
+}
+
+pub struct Toast {
+    pub message: String,
+    pub error: bool,
+}
+
+/// feedback for actions, appended to the toast list
+#[derive(Template)]
+#[template(path = "toast.html")]
+pub struct ToastTemplate {
+    pub toasts: Vec<Toast>,
+}
+
+/// a single dashboard device, swapped in place of its previous state
+#[derive(Template)]
+#[template(path = "client_row.html")]
+struct ClientRowTemplate {
+    base_path: String,
+    client: Client,
+    access: Access,
+    approval: Approval,
+    /// outcome of the action that produced the row, shown as a badge
+    status: String,
+}
+
+/// confirmation step shown in place of the device status
+#[derive(Template)]
+#[template(path = "confirm.html")]
+struct ConfirmTemplate {
+    base_path: String,
+    client_id: Ulid,
+    device_name: String,
+    action: Action,
+}
+
+/// the armed bulk action, sent by the dashboard once its button was held
+/// long enough
+#[derive(Template)]
+#[template(path = "armed.html")]
+struct ArmedTemplate {
+    base_path: String,
+    action: Action,
+    confirmation: String,
+}
+
+pub fn router() -> Router<AppStateReference> {
+    Router::new()
+        .route("/clients/bulk/:action", routing::post(run_bulk_action))
+        .route("/clients/bulk/:action/arm", routing::post(arm_bulk_action))
+        .route("/clients/:client_id/row", routing::get(client_row))
+        .route("/clients/:client_id/approve", routing::post(approve))
+        .route("/clients/:client_id/:action", routing::post(run_action))
+        .route(
+            "/clients/:client_id/:action/confirm",
+            routing::get(confirm_action),
+        )
+        .route(
+            "/federation/:server/clients/:client_id/:action",
+            routing::post(run_federated_action),
+        )
+}
+
+#[instrument(skip(state, access))]
+async fn run_action(
+    Path((client_id, action)): Path<(Ulid, Action)>,
+    State(state): State<AppStateReference>,
+    access: Access,
+) -> Result<ClientRowTemplate, AppError> {
+    command(&state, &access, client_id, action.into()).await?;
+
+    row(
+        &state,
+        client_id,
+        access,
+        format!("{} sent", action.label()),
+    )
+    .await
+}
+
+/// current state of a dashboard device
+async fn client_row(
+    Path(client_id): Path<Ulid>,
+    State(state): State<AppStateReference>,
+    access: Access,
+) -> Result<ClientRowTemplate, AppError> {
+    row(&state, client_id, access, String::new()).await
+}
+
+/// what an admin approves a client as, both optional
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ApproveForm {
+    name: String,
+    /// comma separated
+    tags: String,
+}
+
+/// let a pending client receive commands, for as long as it presents the
+/// same identity
+#[instrument(skip(state, access))]
+async fn approve(
+    Path(client_id): Path<Ulid>,
+    State(state): State<AppStateReference>,
+    access: Access,
+    Form(form): Form<ApproveForm>,
+) -> Result<ClientRowTemplate, AppError> {
+    access.check_csrf()?;
+
+    if !access.is_admin() {
+        return Err(AppError::Forbidden);
+    }
+
+    let server = state.server.clone();
+
+    let Some(client) = server.client(client_id).await? else {
+        return Err(AppError::ServerSend(SendError::ClientNotFound));
+    };
+
+    let Some(identity) = client.identity else {
+        return Err(AppError::NoIdentity);
+    };
+
+    let approved = Approved {
+        name: form.name.trim().to_string(),
+        tags: form
+            .tags
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect(),
+    };
+
+    info!(
+        device_name = client.device_info.name,
+        identity,
+        approved =? approved,
+        actor = access.actor(),
+        "client approved"
+    );
+
+    state.web.lock().approvals.approve(identity, approved);
+
+    store::save(&state, |web| &web.approvals)
+        .await
+        .map_err(AppError::Approvals)?;
+
+    row(&state, client_id, access, "approved".to_string()).await
+}
+
+async fn row(
+    state: &AppStateReference,
+    client_id: Ulid,
+    access: Access,
+    status: String,
+) -> Result<ClientRowTemplate, AppError> {
+    let server = state.server.clone();
+
+    let Some(client) = server
+        .client(client_id)
+        .await?
+        .filter(|client| access.may_see(&client.device_info.name))
+    else {
+        return Err(AppError::ServerSend(SendError::ClientNotFound));
+    };
+
+    Ok(ClientRowTemplate {
+        base_path: state.base_path.clone(),
+        approval: Approval::of(state),
+        client,
+        access,
+        status,
+    })
+}
+
+/// the `client_id` fields of a submitted form
+fn client_ids(form: &[(String, String)]) -> Result<Vec<Ulid>, AppError> {
+    form.iter()
+        .filter(|(name, _)| name == "client_id")
+        .map(|(_, client_id)| client_id.parse::<Ulid>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| AppError::InvalidClientId)
+}
+
+/// arm an action for every `client_id` field of the submitted form
+#[instrument(skip(state, access, form))]
+async fn arm_bulk_action(
+    Path(action): Path<Action>,
+    State(state): State<AppStateReference>,
+    access: Access,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Result<ArmedTemplate, AppError> {
+    let client_ids: Vec<String> = client_ids(&form)?.iter().map(Ulid::to_string).collect();
+
+    let confirmation = arming::arm(&state, &access, action.into(), &client_ids).await?;
+
+    Ok(ArmedTemplate {
+        base_path: state.base_path.clone(),
+        action,
+        confirmation,
+    })
+}
+
+/// run an action for every `client_id` field of the submitted form, with the
+/// `confirmation` of arming it when powering off several clients
+#[instrument(skip(state, access, form))]
+async fn run_bulk_action(
+    Path(action): Path<Action>,
+    State(state): State<AppStateReference>,
+    access: Access,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Result<ToastTemplate, AppError> {
+    let client_ids = client_ids(&form)?;
+
+    let confirmation = form
+        .iter()
+        .find(|(name, _)| name == "confirmation")
+        .map(|(_, confirmation)| confirmation.as_str());
+
+    arming::confirm(
+        &state,
+        &access,
+        action.into(),
+        &client_ids.iter().map(Ulid::to_string).collect::<Vec<_>>(),
+        confirmation,
+    )
+    .await?;
+
+    if client_ids.is_empty() {
+        return Ok(ToastTemplate {
+            toasts: vec![Toast {
+                message: "no clients selected".to_string(),
+                error: true,
+            }],
+        });
+    }
+
+    let mut toasts = Vec::with_capacity(client_ids.len());
+
+    for client_id in client_ids {
+        toasts.push(
+            match command(&state, &access, client_id, action.into()).await {
+                Ok(device_name) => Toast {
+                    message: format!("{} sent to {}", action.label(), device_name),
+                    error: false,
+                },
+                Err(error) => Toast {
+                    message: format!(
+                        "{} failed for {}: {}",
+                        action.label(),
+                        client_id,
+                        error.describe().1
+                    ),
+                    error: true,
+                },
+            },
+        );
+    }
+
+    Ok(ToastTemplate { toasts })
+}
+
+/// run an action for a client of this server or an upstream, from the
+/// federation page
+#[instrument(skip(state, access))]
+async fn run_federated_action(
+    Path((server, client_id, action)): Path<(String, String, Action)>,
+    State(state): State<AppStateReference>,
+    access: Access,
+) -> Result<ToastTemplate, AppError> {
+    let device_name = federation::send(&state, &access, &server, client_id, action.into()).await?;
+
+    Ok(ToastTemplate {
+        toasts: vec![Toast {
+            message: format!("{} sent to {} on {}", action.label(), device_name, server),
+            error: false,
+        }],
+    })
+}
+
+async fn confirm_action(
+    Path((client_id, action)): Path<(Ulid, Action)>,
+    State(state): State<AppStateReference>,
+    access: Access,
+) -> Result<ConfirmTemplate, AppError> {
+    let (base_path, server) = (state.base_path.clone(), state.server.clone());
+
+    let Some(client) = server.client(client_id).await? else {
+        return Err(AppError::ServerSend(SendError::ClientNotFound));
+    };
+
+    if !access.may_control(&client.device_info.name) {
+        return Err(AppError::Forbidden);
+    }
+
+    Ok(ConfirmTemplate {
+        base_path,
+        client_id,
+        device_name: client.device_info.name,
+        action,
+    })
+}
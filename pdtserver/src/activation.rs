@@ -0,0 +1,49 @@
+use std::{
+    env,
+    os::fd::{FromRawFd, OwnedFd, RawFd},
+};
+
+/// first file descriptor passed by systemd, see sd_listen_fds(3)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// file descriptor name systemd passes for the web interface socket
+pub const WEB_INTERFACE_NAME: &str = "web";
+
+/// take the sockets passed by systemd socket activation
+///
+/// sockets are named by `FileDescriptorName=` in the socket unit. the
+/// activation environment is cleared so child processes do not inherit it,
+/// which is only sound before any other thread is started.
+pub fn sockets() -> Vec<(String, OwnedFd)> {
+    let pid = env::var("LISTEN_PID").ok();
+    let count = env::var("LISTEN_FDS").ok();
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    if pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(std::process::id()) {
+        return vec![];
+    }
+
+    let Some(count) = count.and_then(|count| count.parse::<RawFd>().ok()) else {
+        return vec![];
+    };
+
+    let mut names = names.split(':');
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            let name = names
+                .next()
+                .filter(|name| !name.is_empty())
+                .unwrap_or("unknown")
+                .to_string();
+
+            // SAFETY: systemd guarantees LISTEN_FDS descriptors starting at
+            // LISTEN_FDS_START are open and owned by this process
+            (name, unsafe { OwnedFd::from_raw_fd(fd) })
+        })
+        .collect()
+}
@@ -0,0 +1,848 @@
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{request::Parts, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing, Json, Router,
+};
+use pdtapi::{
+    ArmedCommand, Bandwidth, BulkCommandRequest, BulkCommandResponse, BulkCommandResult,
+    ClientEvent, ClientSummary, Command, CommandRequest, CommandResponse, ConnectionQuality,
+    ConnectionState, DeviceInfo, ErrorResponse, FederatedClient, FederatedClients, FleetRollup,
+    HistoryEntry, HistoryVerification, Input, InputRequest, InputResponse, Latency, NetworkInfo,
+    RuleOutcome, ServerEndpoint, ServerStatus, TelemetrySample, Transport, UnreachableServer,
+    Version, Virtualization,
+};
+use pdtcore::BuiltInfo;
+use serde::Deserialize;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tracing::*;
+use ulid::Ulid;
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    IntoParams, Modify, OpenApi,
+};
+
+use crate::{
+    arming,
+    auth::Access,
+    automation, command,
+    export::{export, ExportFormat, ExportQuery},
+    federation, history, preview_command,
+    query::{ClientQuery, ClientSort, HistoryQuery},
+    rollup,
+    server::{self, SendError},
+    status::Status,
+    AppError, AppStateReference,
+};
+
+/// response header carrying the size of a paginated listing
+const TOTAL_COUNT: &str = "x-total-count";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "pdt", description = "control pdt clients connected to a pdtserver"),
+    paths(
+        list_clients,
+        export_clients,
+        get_client,
+        get_telemetry,
+        get_bandwidth,
+        get_latency,
+        fleet_rollup,
+        send_command,
+        send_bulk_command,
+        arm_bulk_command,
+        list_federated_clients,
+        send_federated_command,
+        list_history,
+        export_history,
+        verify_history,
+        list_inputs,
+        set_input,
+        events,
+        status,
+        version
+    ),
+    components(schemas(
+        ClientSummary,
+        ConnectionState,
+        DeviceInfo,
+        NetworkInfo,
+        Transport,
+        Virtualization,
+        TelemetrySample,
+        Bandwidth,
+        ConnectionQuality,
+        Latency,
+        FleetRollup,
+        Command,
+        CommandRequest,
+        CommandResponse,
+        ClientSort,
+        BulkCommandRequest,
+        BulkCommandResult,
+        BulkCommandResponse,
+        ArmedCommand,
+        FederatedClient,
+        FederatedClients,
+        UnreachableServer,
+        HistoryEntry,
+        HistoryVerification,
+        Input,
+        InputRequest,
+        InputResponse,
+        RuleOutcome,
+        ClientEvent,
+        ExportFormat,
+        ServerEndpoint,
+        ServerStatus,
+        Version,
+        ErrorResponse
+    )),
+    modifiers(&BearerToken)
+)]
+pub struct ApiDoc;
+
+struct BearerToken;
+
+impl Modify for BearerToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            )
+        }
+    }
+}
+
+/// json variant of [`AppError`] for api endpoints
+pub struct ApiError(AppError);
+
+impl From<AppError> for ApiError {
+    fn from(value: AppError) -> Self {
+        ApiError(value)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> axum::response::Response {
+        let (status, error) = self.0.describe();
+
+        (status, Json(ErrorResponse { error })).into_response()
+    }
+}
+
+/// [`Access`] of an api request, refused with a json error like every other
+/// api error
+pub struct ApiAccess(pub Access);
+
+#[async_trait]
+impl FromRequestParts<AppStateReference> for ApiAccess {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppStateReference,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(ApiAccess(Access::from_headers(&parts.headers, state)?))
+    }
+}
+
+pub fn router() -> Router<AppStateReference> {
+    Router::new()
+        .route("/api/openapi.json", routing::get(openapi))
+        .route("/api/v1/clients", routing::get(list_clients))
+        .route("/api/v1/clients/export", routing::get(export_clients))
+        .route("/api/v1/clients/:client_id", routing::get(get_client))
+        .route(
+            "/api/v1/clients/:client_id/telemetry",
+            routing::get(get_telemetry),
+        )
+        .route(
+            "/api/v1/clients/:client_id/bandwidth",
+            routing::get(get_bandwidth),
+        )
+        .route(
+            "/api/v1/clients/:client_id/latency",
+            routing::get(get_latency),
+        )
+        .route("/api/v1/fleet/rollup", routing::get(fleet_rollup))
+        .route(
+            "/api/v1/clients/:client_id/commands",
+            routing::post(send_command),
+        )
+        .route("/api/v1/commands/bulk", routing::post(send_bulk_command))
+        .route("/api/v1/commands/bulk/arm", routing::post(arm_bulk_command))
+        .route(
+            "/api/v1/federation/clients",
+            routing::get(list_federated_clients),
+        )
+        .route(
+            "/api/v1/federation/:server/clients/:client_id/commands",
+            routing::post(send_federated_command),
+        )
+        .route("/api/v1/history", routing::get(list_history))
+        .route("/api/v1/history/export", routing::get(export_history))
+        .route("/api/v1/history/verify", routing::post(verify_history))
+        .route("/api/v1/inputs", routing::get(list_inputs))
+        .route("/api/v1/inputs/:name", routing::put(set_input))
+        .route("/api/v1/events", routing::get(events))
+        .route("/api/v1/status", routing::get(status))
+        .route("/api/v1/version", routing::get(version))
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// list connected clients, in the order they joined unless sorted otherwise
+#[utoipa::path(
+    get,
+    path = "/api/v1/clients",
+    params(ClientQuery),
+    responses(
+        (status = 200, body = [ClientSummary], headers(
+            ("x-total-count" = usize, description = "number of matching clients before pagination")
+        )),
+        (status = 401, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn list_clients(
+    State(state): State<AppStateReference>,
+    Query(query): Query<ClientQuery>,
+    ApiAccess(access): ApiAccess,
+) -> Result<impl IntoResponse, ApiError> {
+    let server = state.server.clone();
+
+    let page = query.apply(access.visible(server.clients().await?));
+
+    let clients: Vec<ClientSummary> = page.clients.into_iter().map(ClientSummary::from).collect();
+
+    Ok(([(TOTAL_COUNT, page.total.to_string())], Json(clients)))
+}
+
+/// every connected client as a json or csv download
+#[utoipa::path(
+    get,
+    path = "/api/v1/clients/export",
+    params(ExportQuery),
+    responses(
+        (status = 200, body = [ClientSummary], content_type = ["application/json", "text/csv"]),
+        (status = 401, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn export_clients(
+    State(state): State<AppStateReference>,
+    Query(query): Query<ExportQuery>,
+    ApiAccess(access): ApiAccess,
+) -> Result<Response, ApiError> {
+    let server = state.server.clone();
+
+    let clients: Vec<ClientSummary> = access
+        .visible(server.clients().await?)
+        .into_iter()
+        .map(ClientSummary::from)
+        .collect();
+
+    Ok(export("clients", query.format, clients))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/clients/{client_id}",
+    params(("client_id" = String, Path, description = "client id")),
+    responses(
+        (status = 200, body = ClientSummary),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn get_client(
+    Path(client_id): Path<Ulid>,
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+) -> Result<Json<ClientSummary>, ApiError> {
+    let server = state.server.clone();
+
+    match server
+        .client(client_id)
+        .await?
+        .filter(|client| access.may_see(&client.device_info.name))
+    {
+        Some(client) => Ok(Json(client.into())),
+        None => Err(AppError::ServerSend(SendError::ClientNotFound).into()),
+    }
+}
+
+/// telemetry samples of a client, oldest first
+#[utoipa::path(
+    get,
+    path = "/api/v1/clients/{client_id}/telemetry",
+    params(("client_id" = String, Path, description = "client id")),
+    responses(
+        (status = 200, body = [TelemetrySample]),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn get_telemetry(
+    Path(client_id): Path<Ulid>,
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+) -> Result<Json<Vec<TelemetrySample>>, ApiError> {
+    let server = state.server.clone();
+
+    let visible = server
+        .client(client_id)
+        .await?
+        .is_some_and(|client| access.may_see(&client.device_info.name));
+
+    match server.telemetry(client_id).await?.filter(|_| visible) {
+        Some(samples) => Ok(Json(
+            samples
+                .into_iter()
+                .map(|sample| TelemetrySample::new(sample.at, sample.telemetry))
+                .collect(),
+        )),
+        None => Err(AppError::ServerSend(SendError::ClientNotFound).into()),
+    }
+}
+
+/// bytes that went over the connection of a client
+#[utoipa::path(
+    get,
+    path = "/api/v1/clients/{client_id}/bandwidth",
+    params(("client_id" = String, Path, description = "client id")),
+    responses(
+        (status = 200, body = Bandwidth),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn get_bandwidth(
+    Path(client_id): Path<Ulid>,
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+) -> Result<Json<Bandwidth>, ApiError> {
+    let server = state.server.clone();
+
+    let visible = server
+        .client(client_id)
+        .await?
+        .is_some_and(|client| access.may_see(&client.device_info.name));
+
+    match server.bandwidth(client_id).await?.filter(|_| visible) {
+        Some(usage) => Ok(Json(Bandwidth {
+            bytes_sent: usage.sent,
+            bytes_received: usage.received,
+            bulk_limit: usage.bulk_limit,
+        })),
+        None => Err(AppError::ServerSend(SendError::ClientNotFound).into()),
+    }
+}
+
+/// round trips of the recent telemetry requests to a client and how good
+/// its connection is
+#[utoipa::path(
+    get,
+    path = "/api/v1/clients/{client_id}/latency",
+    params(("client_id" = String, Path, description = "client id")),
+    responses(
+        (status = 200, body = Latency),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn get_latency(
+    Path(client_id): Path<Ulid>,
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+) -> Result<Json<Latency>, ApiError> {
+    let server = state.server.clone();
+
+    let visible = server
+        .client(client_id)
+        .await?
+        .is_some_and(|client| access.may_see(&client.device_info.name));
+
+    match server.latency(client_id).await?.filter(|_| visible) {
+        Some(stats) => Ok(Json(Latency {
+            average_ms: stats.average.map(|average| average.as_millis() as u64),
+            p95_ms: stats.p95.map(|p95| p95.as_millis() as u64),
+            loss: stats.loss,
+            requests: stats.requests as u64,
+            quality: stats.quality().into(),
+        })),
+        None => Err(AppError::ServerSend(SendError::ClientNotFound).into()),
+    }
+}
+
+/// figures over the latest telemetry of every client the caller may see
+#[utoipa::path(
+    get,
+    path = "/api/v1/fleet/rollup",
+    responses(
+        (status = 200, body = FleetRollup),
+        (status = 401, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn fleet_rollup(
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+) -> Result<Json<FleetRollup>, ApiError> {
+    let server = state.server.clone();
+
+    let clients: Vec<_> = server
+        .latest_telemetry()
+        .await?
+        .into_iter()
+        .filter(|(client, _)| access.may_see(&client.device_info.name))
+        .collect();
+
+    Ok(Json(rollup::rollup(&clients).into()))
+}
+
+/// send a command to a client
+#[utoipa::path(
+    post,
+    path = "/api/v1/clients/{client_id}/commands",
+    params(("client_id" = String, Path, description = "client id")),
+    request_body = CommandRequest,
+    responses(
+        (status = 200, body = CommandResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 403, body = ErrorResponse),
+        (status = 404, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn send_command(
+    Path(client_id): Path<Ulid>,
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+    Json(request): Json<CommandRequest>,
+) -> Result<Json<CommandResponse>, ApiError> {
+    command(&state, &access, client_id, request.command.into()).await?;
+
+    Ok(Json(CommandResponse {
+        client_id: client_id.to_string(),
+        command: request.command,
+    }))
+}
+
+/// preview bulk commands instead of sending them
+#[derive(Deserialize, IntoParams, Debug, Clone, Copy, Default)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct BulkCommandQuery {
+    /// answer what sending would answer for every client without sending
+    /// anything
+    dry_run: bool,
+}
+
+/// send a command to several clients, reporting the outcome per client
+///
+/// destructive commands need the confirmation of arming them first when
+/// sent to more than one client
+#[utoipa::path(
+    post,
+    path = "/api/v1/commands/bulk",
+    params(BulkCommandQuery),
+    request_body = BulkCommandRequest,
+    responses(
+        (status = 200, body = BulkCommandResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 428, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn send_bulk_command(
+    State(state): State<AppStateReference>,
+    Query(query): Query<BulkCommandQuery>,
+    ApiAccess(access): ApiAccess,
+    Json(request): Json<BulkCommandRequest>,
+) -> Result<Json<BulkCommandResponse>, ApiError> {
+    if !query.dry_run {
+        arming::confirm(
+            &state,
+            &access,
+            request.command,
+            &request.client_ids,
+            request.confirmation.as_deref(),
+        )
+        .await?;
+    }
+
+    let mut results = Vec::with_capacity(request.client_ids.len());
+
+    for client_id in request.client_ids {
+        let message = request.command.into();
+
+        let outcome = match client_id.parse::<Ulid>() {
+            Ok(id) if query.dry_run => preview_command(&state, &access, id, &message).await,
+            Ok(id) => command(&state, &access, id, message).await,
+            Err(_) => Err(AppError::InvalidClientId),
+        };
+
+        let (status, error) = match outcome {
+            Ok(_) => (StatusCode::OK, None),
+            Err(error) => {
+                let (status, error) = error.describe();
+                (status, Some(error))
+            }
+        };
+
+        results.push(BulkCommandResult {
+            client_id,
+            status: status.as_u16(),
+            error,
+        });
+    }
+
+    Ok(Json(BulkCommandResponse {
+        command: request.command,
+        dry_run: query.dry_run,
+        results,
+    }))
+}
+
+/// arm a destructive command for several clients, answering the
+/// confirmation to send it with
+#[utoipa::path(
+    post,
+    path = "/api/v1/commands/bulk/arm",
+    request_body = BulkCommandRequest,
+    responses(
+        (status = 200, body = ArmedCommand),
+        (status = 400, body = ErrorResponse),
+        (status = 401, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn arm_bulk_command(
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+    Json(request): Json<BulkCommandRequest>,
+) -> Result<Json<ArmedCommand>, ApiError> {
+    let confirmation = arming::arm(&state, &access, request.command, &request.client_ids).await?;
+
+    Ok(Json(ArmedCommand {
+        confirmation,
+        expires_in: arming::LIFETIME.as_secs(),
+    }))
+}
+
+/// clients of this server followed by those of its upstreams, upstreams that
+/// did not answer are listed as unreachable
+#[utoipa::path(
+    get,
+    path = "/api/v1/federation/clients",
+    responses(
+        (status = 200, body = FederatedClients),
+        (status = 401, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn list_federated_clients(
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+) -> Result<Json<FederatedClients>, ApiError> {
+    Ok(Json(federation::clients(&state, &access).await?))
+}
+
+/// send a command to a client of this server or one of its upstreams
+#[utoipa::path(
+    post,
+    path = "/api/v1/federation/{server}/clients/{client_id}/commands",
+    params(
+        ("server" = String, Path, description = "name of the server owning the client"),
+        ("client_id" = String, Path, description = "client id on that server")
+    ),
+    request_body = CommandRequest,
+    responses(
+        (status = 200, body = CommandResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 403, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 502, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn send_federated_command(
+    Path((server, client_id)): Path<(String, String)>,
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+    Json(request): Json<CommandRequest>,
+) -> Result<Json<CommandResponse>, ApiError> {
+    federation::send(&state, &access, &server, client_id.clone(), request.command).await?;
+
+    Ok(Json(CommandResponse {
+        client_id,
+        command: request.command,
+    }))
+}
+
+/// commands sent since the server started, oldest first
+#[utoipa::path(
+    get,
+    path = "/api/v1/history",
+    params(HistoryQuery),
+    responses(
+        (status = 200, body = [HistoryEntry], headers(
+            ("x-total-count" = usize, description = "number of commands before pagination")
+        )),
+        (status = 401, body = ErrorResponse),
+        (status = 403, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn list_history(
+    State(state): State<AppStateReference>,
+    Query(query): Query<HistoryQuery>,
+    ApiAccess(access): ApiAccess,
+) -> Result<impl IntoResponse, ApiError> {
+    if !access.is_admin() {
+        return Err(AppError::Forbidden.into());
+    }
+
+    let records = state.web.lock().history.records();
+    let total = records.len();
+
+    let entries: Vec<HistoryEntry> = query
+        .apply(records)
+        .into_iter()
+        .map(HistoryEntry::from)
+        .collect();
+
+    Ok(([(TOTAL_COUNT, total.to_string())], Json(entries)))
+}
+
+/// commands sent since the server started as a json or csv download, oldest
+/// first
+#[utoipa::path(
+    get,
+    path = "/api/v1/history/export",
+    params(ExportQuery),
+    responses(
+        (status = 200, body = [HistoryEntry], content_type = ["application/json", "text/csv"]),
+        (status = 401, body = ErrorResponse),
+        (status = 403, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn export_history(
+    State(state): State<AppStateReference>,
+    Query(query): Query<ExportQuery>,
+    ApiAccess(access): ApiAccess,
+) -> Result<Response, ApiError> {
+    if !access.is_admin() {
+        return Err(AppError::Forbidden.into());
+    }
+
+    let entries: Vec<HistoryEntry> = state
+        .web
+        .lock()
+        .history
+        .records()
+        .into_iter()
+        .map(HistoryEntry::from)
+        .collect();
+
+    Ok(export("history", query.format, entries))
+}
+
+/// check that exported history entries were not edited, removed or
+/// reordered, and that they end in a command the server still knows
+#[utoipa::path(
+    post,
+    path = "/api/v1/history/verify",
+    request_body = [HistoryEntry],
+    responses(
+        (status = 200, body = HistoryVerification),
+        (status = 401, body = ErrorResponse),
+        (status = 403, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn verify_history(
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+    Json(entries): Json<Vec<HistoryEntry>>,
+) -> Result<Json<HistoryVerification>, ApiError> {
+    if !access.is_admin() {
+        return Err(AppError::Forbidden.into());
+    }
+
+    let first_invalid = history::first_broken_link(&entries);
+
+    let known = entries
+        .last()
+        .is_some_and(|entry| state.web.lock().history.contains(&entry.hash));
+
+    Ok(Json(HistoryVerification {
+        valid: first_invalid.is_none() && known,
+        entries: entries.len(),
+        first_invalid,
+        known,
+    }))
+}
+
+/// every input set since the server started
+#[utoipa::path(
+    get,
+    path = "/api/v1/inputs",
+    responses(
+        (status = 200, body = [Input]),
+        (status = 401, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn list_inputs(
+    State(state): State<AppStateReference>,
+    _access: Access,
+) -> Result<Json<Vec<Input>>, ApiError> {
+    Ok(Json(state.web.lock().inputs.list()))
+}
+
+/// set an input, running the rules waiting for it to change to the value
+/// with the access of the caller
+#[utoipa::path(
+    put,
+    path = "/api/v1/inputs/{name}",
+    params(("name" = String, Path, description = "input name")),
+    request_body = InputRequest,
+    responses(
+        (status = 200, body = InputResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 403, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn set_input(
+    Path(name): Path<String>,
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+    Json(request): Json<InputRequest>,
+) -> Result<Json<InputResponse>, ApiError> {
+    Ok(Json(
+        automation::set(
+            &state,
+            &access,
+            &name,
+            request.value,
+            request.confirmation.as_deref(),
+        )
+        .await?,
+    ))
+}
+
+impl From<server::ClientEvent> for ClientEvent {
+    fn from(value: server::ClientEvent) -> Self {
+        match value {
+            server::ClientEvent::Joined(client) => ClientEvent::Connected {
+                client: client.into(),
+            },
+            server::ClientEvent::Updated(client) => ClientEvent::Updated {
+                client: client.into(),
+            },
+            server::ClientEvent::Left(id) => ClientEvent::Disconnected {
+                client_id: id.to_string(),
+            },
+            server::ClientEvent::Heartbeat(client) => ClientEvent::Heartbeat {
+                client: client.into(),
+            },
+            server::ClientEvent::CommandResult(id, _, result) => ClientEvent::CommandResult {
+                client_id: id.to_string(),
+                result,
+            },
+        }
+    }
+}
+
+/// client events as they happen, one json encoded event per server sent event
+#[utoipa::path(
+    get,
+    path = "/api/v1/events",
+    responses(
+        (status = 200, body = ClientEvent, content_type = "text/event-stream"),
+        (status = 401, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn events(
+    State(state): State<AppStateReference>,
+    ApiAccess(access): ApiAccess,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let events = state.server.subscribe();
+
+    let stream = BroadcastStream::new(events).filter_map(move |event| {
+        let event = match event {
+            Ok(event) if !access.may_see_event(&event) => return None,
+            Ok(event) => ClientEvent::from(event),
+            Err(error) => {
+                warn!(error =? error, "api event subscriber lagging behind");
+                return None;
+            }
+        };
+
+        Event::default().json_data(event).ok().map(Ok)
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// build, uptime, endpoints and load of the server itself
+#[utoipa::path(
+    get,
+    path = "/api/v1/status",
+    responses(
+        (status = 200, body = ServerStatus),
+        (status = 401, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn status(
+    State(state): State<AppStateReference>,
+    _access: Access,
+) -> Result<Json<ServerStatus>, ApiError> {
+    let server = state.server.clone();
+    let counts = server.counts().await?;
+
+    Ok(Json(Status::of(&state, counts).into()))
+}
+
+/// exact build of the server
+#[utoipa::path(
+    get,
+    path = "/api/v1/version",
+    responses(
+        (status = 200, body = Version),
+        (status = 401, body = ErrorResponse)
+    ),
+    security(("token" = []))
+)]
+async fn version(_access: ApiAccess) -> Json<Version> {
+    let built_info = BuiltInfo::default();
+
+    Json(Version {
+        version: built_info.pkg_version,
+        target: built_info.target,
+        host: built_info.host,
+        profile: built_info.profile,
+        git_commit_hash: built_info.git_commit_hash,
+    })
+}
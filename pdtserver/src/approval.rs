@@ -0,0 +1,147 @@
+//! clients held back from commands until an admin approved them
+//!
+//! clients are known by the fingerprint of the identity they present after
+//! introducing themselves, so another device reporting the same name is not
+//! approved along. approvals are made ahead of time in the settings file, or
+//! by an admin in the web interface along with a name and tags, which are
+//! kept in the approvals file
+
+use std::collections::{BTreeMap, HashSet};
+
+use blake2::{digest::consts::U32, Blake2b, Digest};
+use pdtcore::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+
+/// blake2b-256 of the identity key of a client, hex encoded, the key itself
+/// is never kept
+pub fn fingerprint(key: &str) -> String {
+    Blake2b::<U32>::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// what an admin approved a client as
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Approved {
+    /// shown next to the device name the client reports, empty for none
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// clients approved in the web interface, by fingerprint
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct Approvals(BTreeMap<String, Approved>);
+
+impl Approvals {
+    pub fn approve(&mut self, fingerprint: String, approved: Approved) {
+        self.0.insert(fingerprint, approved);
+    }
+}
+
+/// which clients may receive commands, captured for a single request or
+/// rendered event
+#[derive(Debug, Clone, Default)]
+pub struct Approval {
+    required: bool,
+    /// fingerprints approved in the settings file
+    preapproved: HashSet<String>,
+    approved: BTreeMap<String, Approved>,
+}
+
+impl Approval {
+    /// the settings and the approvals are locked in turn, so neither may be
+    /// held by the caller
+    pub fn of(state: &AppState) -> Self {
+        let (required, preapproved) = {
+            let settings_guard = state.settings.lock();
+
+            (
+                settings_guard.client_approval,
+                settings_guard.approved_clients.iter().cloned().collect(),
+            )
+        };
+
+        Self {
+            required,
+            preapproved,
+            approved: state.web.lock().approvals.0.clone(),
+        }
+    }
+
+    /// the client is held back from commands until an admin approves it,
+    /// for good while it presented no identity
+    pub fn pending(&self, client: &Client) -> bool {
+        self.required
+            && !client.identity.as_ref().is_some_and(|identity| {
+                self.preapproved.contains(identity) || self.approved.contains_key(identity)
+            })
+    }
+
+    /// what an admin approved the client as in the web interface
+    pub fn approved(&self, client: &Client) -> Option<&Approved> {
+        self.approved.get(client.identity.as_ref()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use pdtcore::{ConnectionState, DeviceInfo, NetworkInfo, Transport};
+
+    use super::*;
+
+    fn client(name: &str, key: Option<&str>) -> Client {
+        Client {
+            id: "01H0000000000000000000000".to_string(),
+            state: ConnectionState::Connected,
+            device_info: DeviceInfo {
+                name: name.to_string(),
+                ..Default::default()
+            },
+            last_seen: SystemTime::UNIX_EPOCH,
+            network: NetworkInfo {
+                address: "192.0.2.1:50000".to_string(),
+                transport: Transport::Tcp,
+                connected_at: SystemTime::UNIX_EPOCH,
+            },
+            protocol_version: None,
+            identity: key.map(fingerprint),
+        }
+    }
+
+    #[test]
+    fn clients_are_approved_by_identity_not_name() {
+        let mut approval = Approval {
+            required: true,
+            preapproved: HashSet::from([fingerprint("kitchen key")]),
+            approved: BTreeMap::new(),
+        };
+
+        approval.approved.insert(
+            fingerprint("office key"),
+            Approved {
+                name: "office".to_string(),
+                tags: vec!["work".to_string()],
+            },
+        );
+
+        assert!(!approval.pending(&client("kitchen", Some("kitchen key"))));
+        assert!(!approval.pending(&client("office", Some("office key"))));
+        assert!(approval.pending(&client("office", Some("guessed key"))));
+        assert!(approval.pending(&client("office", None)));
+
+        assert_eq!(
+            approval
+                .approved(&client("anything", Some("office key")))
+                .map(|approved| approved.name.as_str()),
+            Some("office")
+        );
+    }
+}
@@ -0,0 +1,239 @@
+//! two step sending of destructive commands to several clients at once
+//!
+//! the caller first arms the command for the clients and gets a short lived
+//! confirmation, which has to come back with the same command for the same
+//! clients to send it. arming, and sending without a valid confirmation, are
+//! kept in the command history next to the commands sent
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant, SystemTime},
+};
+
+use pdtapi::Command;
+use pdtcore::ClientMessage;
+use tracing::*;
+use ulid::Ulid;
+
+use crate::{
+    auth::{random_token, Access},
+    history::{CommandOutcome, CommandRecord},
+    visible_device, AppError, AppStateReference,
+};
+
+/// how long a confirmation can be used after arming
+pub const LIFETIME: Duration = Duration::from_secs(30);
+
+/// destructive commands need arming when sent to more than one client
+pub fn needs_arming(command: Command, clients: usize) -> bool {
+    clients > 1 && command.is_destructive()
+}
+
+#[derive(Debug)]
+struct Armed {
+    actor: String,
+    command: Command,
+    /// sorted, the order clients are sent to does not matter
+    client_ids: Vec<String>,
+    expires: Instant,
+}
+
+/// commands armed and not confirmed yet, by confirmation
+#[derive(Debug, Default)]
+pub struct Arming {
+    armed: HashMap<String, Armed>,
+}
+
+fn sorted(client_ids: &[String]) -> Vec<String> {
+    let mut client_ids = client_ids.to_vec();
+    client_ids.sort();
+    client_ids.dedup();
+    client_ids
+}
+
+impl Arming {
+    /// arm `command` for the clients, returning the confirmation
+    fn arm(
+        &mut self,
+        actor: String,
+        command: Command,
+        client_ids: &[String],
+        now: Instant,
+    ) -> String {
+        self.armed.retain(|_, armed| armed.expires > now);
+
+        let confirmation = random_token();
+
+        self.armed.insert(
+            confirmation.clone(),
+            Armed {
+                actor,
+                command,
+                client_ids: sorted(client_ids),
+                expires: now + LIFETIME,
+            },
+        );
+
+        confirmation
+    }
+
+    /// use up the confirmation, whether it armed the same command for the
+    /// same clients by the same caller and did not expire
+    fn confirm(
+        &mut self,
+        confirmation: &str,
+        actor: &str,
+        command: Command,
+        client_ids: &[String],
+        now: Instant,
+    ) -> bool {
+        self.armed.remove(confirmation).is_some_and(|armed| {
+            armed.expires > now
+                && armed.actor == actor
+                && armed.command == command
+                && armed.client_ids == sorted(client_ids)
+        })
+    }
+}
+
+/// record `outcome` of the command for every client the caller may see
+async fn record(
+    state: &AppStateReference,
+    access: &Access,
+    command: Command,
+    client_ids: &[String],
+    outcome: CommandOutcome,
+) {
+    let server = state.server.clone();
+    let at = SystemTime::now();
+
+    for client_id in client_ids {
+        let Ok(client_id) = client_id.parse::<Ulid>() else {
+            continue;
+        };
+
+        let Ok(device_name) = visible_device(&server, access, client_id).await else {
+            continue;
+        };
+
+        state.web.lock().history.record(CommandRecord {
+            at,
+            client_id,
+            device_name,
+            command: format!("{:?}", ClientMessage::from(command)),
+            actor: access.actor(),
+            outcome,
+        });
+    }
+}
+
+/// arm `command` for the clients, answering the confirmation to send it with
+#[instrument(skip(state, access))]
+pub async fn arm(
+    state: &AppStateReference,
+    access: &Access,
+    command: Command,
+    client_ids: &[String],
+) -> Result<String, AppError> {
+    access.check_csrf()?;
+
+    if client_ids.iter().any(|id| id.parse::<Ulid>().is_err()) {
+        return Err(AppError::InvalidClientId);
+    }
+
+    let confirmation =
+        state
+            .web
+            .lock()
+            .arming
+            .arm(access.actor(), command, client_ids, Instant::now());
+
+    info!(actor = access.actor(), "command armed");
+
+    record(state, access, command, client_ids, CommandOutcome::Armed).await;
+
+    Ok(confirmation)
+}
+
+/// check that `command` may be sent to the clients, using up the
+/// confirmation when it needs arming
+#[instrument(skip(state, access, confirmation))]
+pub async fn confirm(
+    state: &AppStateReference,
+    access: &Access,
+    command: Command,
+    client_ids: &[String],
+    confirmation: Option<&str>,
+) -> Result<(), AppError> {
+    if !needs_arming(command, client_ids.len()) {
+        return Ok(());
+    }
+
+    access.check_csrf()?;
+
+    let confirmed = confirmation.is_some_and(|confirmation| {
+        state.web.lock().arming.confirm(
+            confirmation,
+            &access.actor(),
+            command,
+            client_ids,
+            Instant::now(),
+        )
+    });
+
+    if confirmed {
+        info!(actor = access.actor(), "armed command confirmed");
+        return Ok(());
+    }
+
+    warn!(actor = access.actor(), "command not armed");
+
+    record(state, access, command, client_ids, CommandOutcome::Denied).await;
+
+    Err(AppError::NotArmed(command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn only_destructive_broadcasts_need_arming() {
+        assert!(needs_arming(Command::PowerOff, 2));
+        assert!(!needs_arming(Command::PowerOff, 1));
+        assert!(!needs_arming(Command::Restart, 10));
+    }
+
+    #[test]
+    fn confirmations_are_used_once_for_what_was_armed() {
+        let mut arming = Arming::default();
+        let now = Instant::now();
+        let clients = ids(&["a", "b"]);
+
+        let confirmation = arming.arm("alice".to_string(), Command::PowerOff, &clients, now);
+
+        assert!(arming.confirm(
+            &confirmation,
+            "alice",
+            Command::PowerOff,
+            &ids(&["b", "a"]),
+            now
+        ));
+        assert!(!arming.confirm(&confirmation, "alice", Command::PowerOff, &clients, now));
+
+        for (actor, command, client_ids, at) in [
+            ("bob", Command::PowerOff, clients.clone(), now),
+            ("alice", Command::Restart, clients.clone(), now),
+            ("alice", Command::PowerOff, ids(&["a", "b", "c"]), now),
+            ("alice", Command::PowerOff, clients.clone(), now + LIFETIME),
+        ] {
+            let confirmation = arming.arm("alice".to_string(), Command::PowerOff, &clients, now);
+
+            assert!(!arming.confirm(&confirmation, actor, command, &client_ids, at));
+        }
+    }
+}
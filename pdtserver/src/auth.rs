@@ -0,0 +1,396 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, rand_core::RngCore, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use askama::Template;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{
+        header::{AUTHORIZATION, COOKIE, SET_COOKIE},
+        request::Parts,
+        HeaderMap, HeaderName, HeaderValue,
+    },
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use pdtcore::{Client, ClientMessage};
+use serde::Deserialize;
+use tracing::*;
+
+use crate::{
+    proxy::Forwarded,
+    server::ClientEvent,
+    settings::{AccessToken, Permissions},
+    AppError, AppStateReference, SCRIPT, STYLE,
+};
+
+const SESSION_COOKIE: &str = "pdt_session";
+const CSRF_HEADER: &str = "x-csrf-token";
+const SESSION_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// random hex token suitable for session ids and csrf tokens
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// argon2 hash of `password` in PHC string format, for the settings file
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(password_hash) else {
+        warn!("invalid password hash in settings");
+        return false;
+    };
+
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub username: String,
+    pub csrf_token: String,
+    expires: Instant,
+}
+
+/// logged in web interface sessions, by session id
+#[derive(Debug, Default)]
+pub struct Sessions {
+    sessions: HashMap<String, Session>,
+}
+
+impl Sessions {
+    /// start a session for `username`, returning its id
+    fn create(&mut self, username: String) -> String {
+        let now = Instant::now();
+
+        self.sessions.retain(|_, session| session.expires > now);
+
+        let id = random_token();
+
+        self.sessions.insert(
+            id.clone(),
+            Session {
+                username,
+                csrf_token: random_token(),
+                expires: now + SESSION_LIFETIME,
+            },
+        );
+
+        id
+    }
+
+    fn get(&self, id: &str) -> Option<&Session> {
+        self.sessions
+            .get(id)
+            .filter(|session| session.expires > Instant::now())
+    }
+
+    fn remove(&mut self, id: &str) {
+        self.sessions.remove(id);
+    }
+}
+
+fn session_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// `secure` limits the cookie to https, set when the web interface is served
+/// over tls directly or by a reverse proxy
+fn session_cookie(id: &str, max_age: Duration, base_path: &str, secure: bool) -> HeaderValue {
+    let cookie = format!(
+        "{SESSION_COOKIE}={id}; Path={base_path}/; HttpOnly; SameSite=Strict; Max-Age={}{}",
+        max_age.as_secs(),
+        if secure { "; Secure" } else { "" }
+    );
+
+    HeaderValue::from_str(&cookie).expect("session cookie is a valid header value")
+}
+
+/// caller of a web interface endpoint
+pub enum Access {
+    /// no access tokens or users are configured
+    Open,
+    Token(AccessToken),
+    Session {
+        session: Session,
+        permissions: Permissions,
+        /// whether the request carried the session csrf token
+        csrf_valid: bool,
+    },
+}
+
+impl Access {
+    /// permissions of the caller, `None` for open access
+    fn permissions(&self) -> Option<&Permissions> {
+        match self {
+            Access::Open => None,
+            Access::Token(token) => Some(&token.permissions),
+            Access::Session { permissions, .. } => Some(permissions),
+        }
+    }
+
+    pub fn may_see(&self, device_name: &str) -> bool {
+        self.permissions()
+            .is_none_or(|permissions| permissions.may_see(device_name))
+    }
+
+    /// the clients the caller may see, in the same order
+    pub fn visible(&self, clients: Vec<Client>) -> Vec<Client> {
+        clients
+            .into_iter()
+            .filter(|client| self.may_see(&client.device_info.name))
+            .collect()
+    }
+
+    /// events about clients the caller may not see are withheld, events
+    /// only carrying a client id reveal nothing and pass
+    pub fn may_see_event(&self, event: &ClientEvent) -> bool {
+        match event {
+            ClientEvent::Joined(client)
+            | ClientEvent::Updated(client)
+            | ClientEvent::Heartbeat(client) => self.may_see(&client.device_info.name),
+            ClientEvent::CommandResult(_, device_name, _) => self.may_see(device_name),
+            ClientEvent::Left(_) => true,
+        }
+    }
+
+    pub fn may_control(&self, device_name: &str) -> bool {
+        self.permissions()
+            .is_none_or(|permissions| permissions.may_control(device_name))
+    }
+
+    /// whether `message` may be sent to the device, see
+    /// [`Permissions::may_send`]
+    pub fn may_send(&self, device_name: &str, message: &ClientMessage) -> bool {
+        self.permissions()
+            .is_none_or(|permissions| permissions.may_send(device_name, message))
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.permissions()
+            .is_none_or(|permissions| permissions.is_admin())
+    }
+
+    /// state changing requests made with a session cookie must carry the
+    /// session csrf token, token and open access are not cookie based
+    pub fn check_csrf(&self) -> Result<(), AppError> {
+        match self {
+            Access::Session {
+                csrf_valid: false, ..
+            } => Err(AppError::Forbidden),
+            _ => Ok(()),
+        }
+    }
+
+    /// name of the caller for the command history
+    pub fn actor(&self) -> String {
+        match self {
+            Access::Open => "anonymous".to_string(),
+            Access::Token(token) => format!("token:{}", token.name),
+            Access::Session { session, .. } => session.username.clone(),
+        }
+    }
+
+    pub fn session(&self) -> Option<&Session> {
+        match self {
+            Access::Session { session, .. } => Some(session),
+            _ => None,
+        }
+    }
+}
+
+impl Access {
+    /// caller identified by the bearer token or session cookie in `headers`
+    pub fn from_headers(headers: &HeaderMap, state: &AppStateReference) -> Result<Self, AppError> {
+        // looked up before the settings are locked, the two locks are never
+        // held together
+        let session = session_id(headers).and_then(|id| state.web.lock().sessions.get(id).cloned());
+
+        let settings_guard = state.settings.lock();
+
+        let settings = &*settings_guard;
+
+        if settings.tokens.is_empty() && settings.users.is_empty() {
+            return Ok(Access::Open);
+        }
+
+        let bearer = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        if let Some(bearer) = bearer {
+            return match settings.token(bearer) {
+                Some(token) => Ok(Access::Token(token.clone())),
+                None => Err(AppError::Unauthorized),
+            };
+        }
+
+        let session = session.and_then(|session| {
+            let user = settings.user(&session.username)?;
+            Some((session, user))
+        });
+
+        let Some((session, user)) = session else {
+            return Err(AppError::Unauthorized);
+        };
+
+        let csrf_valid = headers
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok())
+            == Some(session.csrf_token.as_str());
+
+        Ok(Access::Session {
+            session,
+            permissions: user.permissions.clone(),
+            csrf_valid,
+        })
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppStateReference> for Access {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppStateReference,
+    ) -> Result<Self, Self::Rejection> {
+        Access::from_headers(&parts.headers, state)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+pub struct LoginTemplate {
+    base_path: String,
+    style: String,
+    script: String,
+    error: Option<String>,
+}
+
+impl LoginTemplate {
+    fn new(base_path: String, error: Option<String>) -> Self {
+        Self {
+            base_path,
+            style: STYLE.into(),
+            script: SCRIPT.into(),
+            error,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+}
+
+pub async fn login_page(State(state): State<AppStateReference>) -> Result<LoginTemplate, AppError> {
+    Ok(LoginTemplate::new(state.base_path.clone(), None))
+}
+
+#[instrument(skip_all, fields(username = form.username, client = ?forwarded.client))]
+pub async fn login(
+    State(state): State<AppStateReference>,
+    forwarded: Forwarded,
+    Form(form): Form<LoginForm>,
+) -> Result<Response, AppError> {
+    let base_path = state.base_path.clone();
+
+    let password_hash = state
+        .settings
+        .lock()
+        .user(&form.username)
+        .map(|user| user.password_hash.clone());
+
+    let password = form.password;
+    let valid = match password_hash {
+        Some(password_hash) => {
+            tokio::task::spawn_blocking(move || verify_password(&password, &password_hash))
+                .await
+                .unwrap_or(false)
+        }
+        None => false,
+    };
+
+    if !valid {
+        warn!("failed login");
+        return Ok(
+            LoginTemplate::new(base_path, Some("Invalid username or password".into()))
+                .into_response(),
+        );
+    }
+
+    info!("logged in");
+
+    let id = state.web.lock().sessions.create(form.username);
+    let secure = state.secure_cookies || forwarded.https;
+
+    Ok((
+        [(
+            SET_COOKIE,
+            session_cookie(&id, SESSION_LIFETIME, &base_path, secure),
+        )],
+        Redirect::to(&format!("{base_path}/")),
+    )
+        .into_response())
+}
+
+#[instrument(skip_all, fields(client = ?forwarded.client))]
+pub async fn logout(
+    State(state): State<AppStateReference>,
+    headers: HeaderMap,
+    forwarded: Forwarded,
+    access: Access,
+) -> Result<Response, AppError> {
+    access.check_csrf()?;
+
+    if let Some(id) = session_id(&headers) {
+        state.web.lock().sessions.remove(id);
+    }
+
+    let base_path = state.base_path.clone();
+    let secure = state.secure_cookies || forwarded.https;
+
+    let login = format!("{base_path}/login");
+
+    Ok((
+        [
+            (
+                SET_COOKIE,
+                session_cookie("", Duration::ZERO, &base_path, secure),
+            ),
+            (
+                HeaderName::from_static("hx-redirect"),
+                HeaderValue::from_str(&login).expect("login url is a valid header value"),
+            ),
+        ],
+        Redirect::to(&login),
+    )
+        .into_response())
+}
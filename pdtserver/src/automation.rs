@@ -0,0 +1,190 @@
+//! named boolean inputs flipped by external sources, like a phone joining
+//! the wifi or occupancy from home automation, and the rules in the settings
+//! sending commands when an input changes, like turning every screen off
+//! once nobody is home
+//!
+//! rules only run when an input changes, and send their commands with the
+//! access of whoever set it, so what it may control limits what the rules do.
+//! a destructive rule applying to several clients needs arming like any other
+//! command sent to them at once, with the confirmation passed along with the
+//! input
+
+use std::collections::BTreeMap;
+
+use axum::http::StatusCode;
+use pdtapi::{BulkCommandResult, Input, InputResponse, RuleOutcome};
+use tracing::*;
+
+use crate::{arming, auth::Access, command, settings::Rule, AppError, AppStateReference};
+
+/// inputs set since the server started, by name
+#[derive(Debug, Default)]
+pub struct Inputs(BTreeMap<String, bool>);
+
+impl Inputs {
+    /// set `name` to `value`, returning whether that changed it, the first
+    /// value of an input always does
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        self.0.insert(name.to_string(), value) != Some(value)
+    }
+
+    pub fn list(&self) -> Vec<Input> {
+        self.0
+            .iter()
+            .map(|(name, value)| Input {
+                name: name.clone(),
+                value: *value,
+            })
+            .collect()
+    }
+}
+
+/// the rules waiting for `input` to change to `value`
+fn triggered(rules: &[Rule], input: &str, value: bool) -> Vec<Rule> {
+    rules
+        .iter()
+        .filter(|rule| rule.input == input && rule.value == value)
+        .cloned()
+        .collect()
+}
+
+/// set an input and, if that changed it, run the rules waiting for the new
+/// value on every client they apply to
+#[instrument(skip(state, access, confirmation))]
+pub async fn set(
+    state: &AppStateReference,
+    access: &Access,
+    name: &str,
+    value: bool,
+    confirmation: Option<&str>,
+) -> Result<InputResponse, AppError> {
+    access.check_csrf()?;
+
+    let changed = state.web.lock().inputs.set(name, value);
+    let rules = triggered(&state.settings.lock().rules, name, value);
+    let server = state.server.clone();
+
+    info!(changed, actor = access.actor(), "input set");
+
+    let mut outcomes = vec![];
+
+    if changed && !rules.is_empty() {
+        let clients = access.visible(server.clients().await?);
+
+        for rule in rules {
+            let client_ids: Vec<String> = clients
+                .iter()
+                .filter(|client| rule.applies_to(&client.device_info.name))
+                .map(|client| client.id.clone())
+                .collect();
+
+            info!(
+                rule = rule.name,
+                command =? rule.command,
+                clients =? client_ids,
+                actor = access.actor(),
+                "running rule"
+            );
+
+            // denied rules are kept in the command history by arming
+            let armed =
+                arming::confirm(state, access, rule.command, &client_ids, confirmation).await;
+
+            let mut results = vec![];
+
+            for client_id in client_ids {
+                let outcome = match (&armed, client_id.parse()) {
+                    (Err(_), _) => Err(AppError::NotArmed(rule.command)),
+                    (Ok(()), Ok(id)) => command(state, access, id, rule.command.into()).await,
+                    (Ok(()), Err(_)) => Err(AppError::InvalidClientId),
+                };
+
+                let (status, error) = match outcome {
+                    Ok(_) => (StatusCode::OK, None),
+                    Err(error) => {
+                        let (status, error) = error.describe();
+                        (status, Some(error))
+                    }
+                };
+
+                results.push(BulkCommandResult {
+                    client_id,
+                    status: status.as_u16(),
+                    error,
+                });
+            }
+
+            outcomes.push(RuleOutcome {
+                rule: rule.name,
+                command: rule.command,
+                results,
+            });
+        }
+    }
+
+    Ok(InputResponse {
+        name: name.to_string(),
+        value,
+        changed,
+        rules: outcomes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pdtapi::Command;
+
+    use super::*;
+    use crate::settings::Settings;
+
+    #[test]
+    fn only_a_different_value_is_a_change() {
+        let mut inputs = Inputs::default();
+
+        assert!(inputs.set("nobody-home", false));
+        assert!(!inputs.set("nobody-home", false));
+        assert!(inputs.set("nobody-home", true));
+
+        assert_eq!(
+            inputs.list(),
+            vec![Input {
+                name: "nobody-home".to_string(),
+                value: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn rules_wait_for_their_input_and_value() {
+        let settings: Settings = toml::from_str(
+            r#"
+            [[rules]]
+            name = "screens off when away"
+            input = "nobody-home"
+            command = "screen-off"
+
+            [[rules]]
+            name = "kitchen screen on when back"
+            input = "nobody-home"
+            value = false
+            command = "screen-on"
+            clients = ["kitchen"]
+            "#,
+        )
+        .unwrap();
+
+        let away = triggered(&settings.rules, "nobody-home", true);
+
+        assert_eq!(away.len(), 1);
+        assert_eq!(away[0].command, Command::ScreenOff);
+        assert!(away[0].applies_to("kitchen"));
+
+        let back = triggered(&settings.rules, "nobody-home", false);
+
+        assert_eq!(back.len(), 1);
+        assert!(back[0].applies_to("kitchen"));
+        assert!(!back[0].applies_to("hallway"));
+
+        assert!(triggered(&settings.rules, "sleeping", true).is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+//! bytes that went over each client connection, and pacing of bulk messages
+//! to clients on slow links
+
+use std::{
+    io::{Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// bytes sent to and received from a client since it connected
+#[derive(Debug, Default)]
+pub struct Bandwidth {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+/// counters of a client and the limit its bulk messages are paced to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub sent: u64,
+    pub received: u64,
+    /// bytes per second, see [`crate::settings::Settings::bandwidth_limits`]
+    pub bulk_limit: Option<u64>,
+}
+
+impl Bandwidth {
+    pub fn usage(&self, bulk_limit: Option<u64>) -> Usage {
+        Usage {
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            bulk_limit,
+        }
+    }
+}
+
+/// counts the bytes read and written through a connection
+pub struct Metered<S> {
+    inner: S,
+    bandwidth: Arc<Bandwidth>,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S, bandwidth: Arc<Bandwidth>) -> Self {
+        Self { inner, bandwidth }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: Read> Read for Metered<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        self.bandwidth
+            .received
+            .fetch_add(read as u64, Ordering::Relaxed);
+
+        Ok(read)
+    }
+}
+
+impl<S: Write> Write for Metered<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+
+        self.bandwidth
+            .sent
+            .fetch_add(written as u64, Ordering::Relaxed);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// spaces out writes so they average at most a number of bytes per second
+#[derive(Debug)]
+pub struct Throttle {
+    next: Instant,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self {
+            next: Instant::now(),
+        }
+    }
+}
+
+impl Throttle {
+    /// block until the bytes written so far are paid for
+    pub fn wait(&self) {
+        let now = Instant::now();
+
+        if self.next > now {
+            std::thread::sleep(self.next - now);
+        }
+    }
+
+    /// account for bytes written under a limit in bytes per second
+    pub fn written(&mut self, bytes: u64, limit: u64) {
+        let cost = Duration::from_secs_f64(bytes as f64 / limit.max(1) as f64);
+
+        self.next = self.next.max(Instant::now()) + cost;
+    }
+}
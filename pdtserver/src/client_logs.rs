@@ -0,0 +1,227 @@
+//! log records clients forward along with their telemetry, kept per client
+//! and tailed live on the log page of the client, so a device misbehaving
+//! can be looked into without a shell on it
+
+use std::{collections::VecDeque, convert::Infallible};
+
+use askama::Template;
+use axum::{
+    extract::{Path, Query, State},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Redirect, Response,
+    },
+};
+use pdtcore::{Client, LogLevel, LogRecord};
+use serde::Deserialize;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use ulid::Ulid;
+
+use crate::{
+    auth::Access, filters, server::SendError, AppError, AppStateReference, SCRIPT, SSE_EXTENSION,
+    STYLE,
+};
+
+/// records kept per client, older ones are dropped
+const HISTORY: usize = 500;
+
+/// most recent log records of a client, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct ClientLogs {
+    records: VecDeque<LogRecord>,
+}
+
+impl ClientLogs {
+    pub fn push(&mut self, records: Vec<LogRecord>) {
+        for record in records {
+            if self.records.len() == HISTORY {
+                self.records.pop_front();
+            }
+
+            self.records.push_back(record);
+        }
+    }
+
+    pub fn records(&self) -> Vec<LogRecord> {
+        self.records.iter().cloned().collect()
+    }
+}
+
+/// log records of a client as they arrive, published to the log pages
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    pub client_id: Ulid,
+    pub records: Vec<LogRecord>,
+}
+
+/// which records to show, everything when empty
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LevelFilter {
+    /// least severe level shown, like `warn`
+    pub level: String,
+}
+
+impl LevelFilter {
+    /// whether `level` is the one picked
+    pub fn selected(&self, level: &str) -> bool {
+        self.level == level
+    }
+
+    fn matches(&self, record: &LogRecord) -> bool {
+        self.level
+            .parse::<LogLevel>()
+            .map_or(true, |level| record.level <= level)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "client_logs.html")]
+struct ClientLogsTemplate {
+    base_path: String,
+    style: String,
+    script: String,
+    client: Client,
+    filter: LevelFilter,
+    records: Vec<LogRecord>,
+}
+
+/// rows appended to the log page of a client
+#[derive(Template)]
+#[template(path = "client_log_records.html")]
+struct ClientLogRecordsTemplate {
+    records: Vec<LogRecord>,
+}
+
+/// the client if the caller may see it
+async fn visible_client(
+    state: &AppStateReference,
+    access: &Access,
+    client_id: Ulid,
+) -> Result<Client, AppError> {
+    let server = state.server.clone();
+
+    match server.client(client_id).await? {
+        Some(client) if access.may_see(&client.device_info.name) => Ok(client),
+        _ => Err(AppError::ServerSend(SendError::ClientNotFound)),
+    }
+}
+
+/// recent log records of a client
+pub async fn page(
+    Path(client_id): Path<Ulid>,
+    State(state): State<AppStateReference>,
+    Query(filter): Query<LevelFilter>,
+    access: Result<Access, AppError>,
+) -> Result<Response, AppError> {
+    let (base_path, server) = (state.base_path.clone(), state.server.clone());
+
+    let access = match access {
+        Ok(access) => access,
+        Err(AppError::Unauthorized) => {
+            return Ok(Redirect::to(&format!("{base_path}/login")).into_response())
+        }
+        Err(error) => return Err(error),
+    };
+
+    let client = visible_client(&state, &access, client_id).await?;
+
+    let records = server
+        .logs(client_id)
+        .await?
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|record| filter.matches(record))
+        .collect();
+
+    let template = ClientLogsTemplate {
+        base_path,
+        style: STYLE.into(),
+        script: [SCRIPT, SSE_EXTENSION].join("\n"),
+        client,
+        filter,
+        records,
+    };
+
+    Ok(template.into_response())
+}
+
+/// log records of a client matching the filter as they arrive, rendered as
+/// rows
+pub async fn tail(
+    Path(client_id): Path<Ulid>,
+    State(state): State<AppStateReference>,
+    Query(filter): Query<LevelFilter>,
+    access: Access,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    visible_client(&state, &access, client_id).await?;
+
+    let batches = state.server.subscribe_logs();
+
+    // a lagging subscriber misses records, the page can be reloaded for
+    // the ones kept
+    let stream = BroadcastStream::new(batches).filter_map(move |batch| {
+        let batch = batch.ok().filter(|batch| batch.client_id == client_id)?;
+
+        let records: Vec<_> = batch
+            .records
+            .into_iter()
+            .filter(|record| filter.matches(record))
+            .collect();
+
+        if records.is_empty() {
+            return None;
+        }
+
+        ClientLogRecordsTemplate { records }
+            .render()
+            .ok()
+            .map(|html| Ok(Event::default().event("log").data(html)))
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(at: u64, level: LogLevel) -> LogRecord {
+        LogRecord {
+            at,
+            level,
+            target: "pdtclient".to_string(),
+            message: "reconnected".to_string(),
+        }
+    }
+
+    #[test]
+    fn only_the_most_recent_records_are_kept() {
+        let mut logs = ClientLogs::default();
+
+        logs.push(
+            (0..HISTORY as u64)
+                .map(|at| record(at, LogLevel::Info))
+                .collect(),
+        );
+        logs.push(vec![record(HISTORY as u64, LogLevel::Warn)]);
+
+        let records = logs.records();
+
+        assert_eq!(records.len(), HISTORY);
+        assert_eq!(records[0].at, 1);
+        assert_eq!(records[HISTORY - 1].level, LogLevel::Warn);
+    }
+
+    #[test]
+    fn filter_keeps_records_at_least_as_severe() {
+        let warnings = LevelFilter {
+            level: "warn".to_string(),
+        };
+
+        assert!(warnings.matches(&record(0, LogLevel::Error)));
+        assert!(warnings.matches(&record(0, LogLevel::Warn)));
+        assert!(!warnings.matches(&record(0, LogLevel::Info)));
+        assert!(LevelFilter::default().matches(&record(0, LogLevel::Trace)));
+    }
+}
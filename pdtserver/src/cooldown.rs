@@ -0,0 +1,106 @@
+//! commands refused for a while after the same kind of command was sent to
+//! a device, so mashing a restart button does not put it in a restart loop
+//!
+//! kept by device name rather than client id, as a restarted client comes
+//! back with another id
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use crate::settings::CommandKind;
+
+#[derive(Debug, Default)]
+pub struct Cooldowns {
+    sent: HashMap<(String, CommandKind), SystemTime>,
+}
+
+impl Cooldowns {
+    /// the command is sent to the device at `at`
+    pub fn sent(&mut self, device_name: &str, command: CommandKind, at: SystemTime) {
+        self.sent.insert((device_name.to_string(), command), at);
+    }
+
+    /// the command sent at `at` never reached the device, unless it was sent
+    /// again since
+    pub fn failed(&mut self, device_name: &str, command: CommandKind, at: SystemTime) {
+        let key = (device_name.to_string(), command);
+
+        if self.sent.get(&key) == Some(&at) {
+            self.sent.remove(&key);
+        }
+    }
+
+    /// how much longer `command` to the device is refused, none when it may
+    /// be sent
+    pub fn remaining(
+        &self,
+        device_name: &str,
+        command: CommandKind,
+        cooldown: Duration,
+        at: SystemTime,
+    ) -> Option<Duration> {
+        let sent = self.sent.get(&(device_name.to_string(), command))?;
+        let elapsed = at.duration_since(*sent).unwrap_or_default();
+
+        cooldown
+            .checked_sub(elapsed)
+            .filter(|remaining| !remaining.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_refused_until_the_cooldown_passed() {
+        let mut cooldowns = Cooldowns::default();
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        let five_minutes = Duration::from_secs(300);
+
+        assert_eq!(
+            cooldowns.remaining("kiosk", CommandKind::Restart, five_minutes, at(0)),
+            None
+        );
+
+        cooldowns.sent("kiosk", CommandKind::Restart, at(0));
+
+        assert_eq!(
+            cooldowns.remaining("kiosk", CommandKind::Restart, five_minutes, at(60)),
+            Some(Duration::from_secs(240))
+        );
+        assert_eq!(
+            cooldowns.remaining("kiosk", CommandKind::ScreenOff, five_minutes, at(60)),
+            None
+        );
+        assert_eq!(
+            cooldowns.remaining("nas", CommandKind::Restart, five_minutes, at(60)),
+            None
+        );
+        assert_eq!(
+            cooldowns.remaining("kiosk", CommandKind::Restart, five_minutes, at(300)),
+            None
+        );
+        assert_eq!(
+            cooldowns.remaining("kiosk", CommandKind::Restart, Duration::ZERO, at(0)),
+            None
+        );
+
+        cooldowns.sent("kiosk", CommandKind::Restart, at(90));
+        cooldowns.failed("kiosk", CommandKind::Restart, at(60));
+
+        assert_eq!(
+            cooldowns.remaining("kiosk", CommandKind::Restart, five_minutes, at(120)),
+            Some(Duration::from_secs(270))
+        );
+
+        cooldowns.failed("kiosk", CommandKind::Restart, at(90));
+
+        assert_eq!(
+            cooldowns.remaining("kiosk", CommandKind::Restart, five_minutes, at(120)),
+            None
+        );
+    }
+}
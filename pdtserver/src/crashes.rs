@@ -0,0 +1,125 @@
+//! crashes clients reported once they ran again, kept for the crashes page
+//! so a panic on a device is noticed without reading its logs
+
+use std::{collections::VecDeque, time::SystemTime};
+
+use askama::Template;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+};
+use pdtcore::CrashReport;
+use ulid::Ulid;
+
+use crate::{auth::Access, filters, AppError, AppStateReference, SCRIPT, STYLE};
+
+/// crashes kept, older ones are dropped
+const HISTORY: usize = 100;
+
+/// a crash as reported by a client
+#[derive(Debug, Clone, PartialEq)]
+pub struct Crash {
+    /// the connection the report came over, not the one that crashed
+    pub client_id: Ulid,
+    pub device_name: String,
+    pub received_at: SystemTime,
+    pub report: CrashReport,
+}
+
+/// most recent crashes of every client
+#[derive(Debug, Default)]
+pub struct Crashes {
+    crashes: VecDeque<Crash>,
+}
+
+impl Crashes {
+    pub fn push(&mut self, crash: Crash) {
+        if self.crashes.len() == HISTORY {
+            self.crashes.pop_front();
+        }
+
+        self.crashes.push_back(crash);
+    }
+
+    /// newest first
+    pub fn recent(&self) -> Vec<Crash> {
+        self.crashes.iter().rev().cloned().collect()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "crashes.html")]
+struct CrashesTemplate {
+    base_path: String,
+    style: String,
+    script: String,
+    crashes: Vec<Crash>,
+}
+
+/// crashes of the clients the caller may see
+pub async fn page(
+    State(state): State<AppStateReference>,
+    access: Result<Access, AppError>,
+) -> Result<Response, AppError> {
+    let (base_path, server) = (state.base_path.clone(), state.server.clone());
+
+    let access = match access {
+        Ok(access) => access,
+        Err(AppError::Unauthorized) => {
+            return Ok(Redirect::to(&format!("{base_path}/login")).into_response())
+        }
+        Err(error) => return Err(error),
+    };
+
+    let crashes = server
+        .crashes()
+        .await?
+        .into_iter()
+        .filter(|crash| access.may_see(&crash.device_name))
+        .collect();
+
+    let template = CrashesTemplate {
+        base_path,
+        style: STYLE.into(),
+        script: SCRIPT.into(),
+        crashes,
+    };
+
+    Ok(template.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use pdtcore::BuiltInfo;
+
+    use super::*;
+
+    fn crash(at: u64) -> Crash {
+        Crash {
+            client_id: Ulid::from(1),
+            device_name: "ash".to_string(),
+            received_at: SystemTime::UNIX_EPOCH,
+            report: CrashReport {
+                at,
+                message: "index out of bounds at pdtclient/src/main.rs:1:1".to_string(),
+                backtrace: String::new(),
+                built_info: BuiltInfo::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn recent_crashes_come_first() {
+        let mut crashes = Crashes::default();
+
+        for at in 0..HISTORY as u64 + 1 {
+            crashes.push(crash(at));
+        }
+
+        let recent = crashes.recent();
+
+        assert_eq!(recent.len(), HISTORY);
+        assert_eq!(recent[0].report.at, HISTORY as u64);
+        assert_eq!(recent[HISTORY - 1].report.at, 1);
+    }
+}
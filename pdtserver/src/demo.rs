@@ -0,0 +1,169 @@
+//! simulated clients for `pdtserver --demo`, served in-process so the web
+//! interface can be developed and shown without deploying real clients
+
+use std::{
+    f64::consts::TAU,
+    time::{Duration, Instant, SystemTime},
+};
+
+use pdtcore::{
+    BuiltInfo, ClientIntroduction, ClientMessage, CommandExecution, DeviceInfo, Message,
+    NetworkInfo, ProtocolConfig, ServerMessage, Telemetry, Transport,
+};
+use tracing::*;
+
+use crate::server::Server;
+
+/// device name, operating system and version of every demo client
+const CLIENTS: [(&str, &str, &str); 6] = [
+    ("demo-reception", "Linux", "6.6.30"),
+    ("demo-meeting-room", "Linux", "6.1.0"),
+    ("demo-kiosk", "Linux", "6.8.12"),
+    ("demo-lobby-signage", "FreeBSD", "14.1-RELEASE"),
+    ("demo-warehouse", "Linux", "5.15.0"),
+    ("demo-office-mac", "Darwin", "23.5.0"),
+];
+
+const GIB: u64 = 1 << 30;
+
+/// start every demo client on its own thread
+pub fn start(server: &Server) {
+    for (index, (name, os, os_version)) in CLIENTS.into_iter().enumerate() {
+        let server = server.clone();
+
+        let device_info = DeviceInfo {
+            name: name.to_string(),
+            os: os.to_string(),
+            os_version: os_version.to_string(),
+            uptime: String::new(),
+            ..DeviceInfo::default()
+        };
+
+        std::thread::spawn(move || run(&server, index, device_info));
+    }
+
+    info!(
+        clients = CLIENTS.len(),
+        "demo mode, serving simulated clients"
+    );
+}
+
+fn run(server: &Server, index: usize, mut device_info: DeviceInfo) {
+    let network = NetworkInfo {
+        // documentation addresses, never a real machine
+        address: format!("192.0.2.{}:{}", 10 + index, 40_000 + index),
+        transport: Transport::Tcp,
+        connected_at: SystemTime::now(),
+    };
+
+    let (id, receiver) = match server.connect_in_process(network) {
+        Ok(connection) => connection,
+        Err(error) => {
+            error!(?error, "could not connect demo client");
+            return;
+        }
+    };
+
+    server.deliver(
+        id,
+        ServerMessage::Hello(Box::new(ClientIntroduction {
+            name: device_info.name.clone(),
+            pdtcore_built_info: BuiltInfo::default(),
+            protocol: ProtocolConfig::default(),
+        })),
+    );
+
+    let mut telemetry = Signal::new(index);
+
+    // ends once the server drops the client
+    while let Ok(message) = receiver.recv() {
+        let Message::Client(message) = message else {
+            continue;
+        };
+
+        match message {
+            ClientMessage::RequestDeviceInfo => {
+                device_info.uptime = humantime::format_duration(telemetry.uptime()).to_string();
+
+                server.deliver(id, ServerMessage::DeviceInfo(device_info.clone()));
+            }
+            ClientMessage::RequestTelemetry => {
+                server.deliver(id, ServerMessage::Telemetry(telemetry.sample()));
+            }
+            ClientMessage::Traced(trace, message) => {
+                server.deliver(
+                    id,
+                    ServerMessage::Executed(CommandExecution {
+                        trace,
+                        command: format!("{message:?}"),
+                        error: None,
+                    }),
+                );
+            }
+            ClientMessage::Custom { namespace, .. } => {
+                server.deliver(id, ServerMessage::Nack { namespace });
+            }
+            ClientMessage::Goodbye => break,
+            message => debug!(client_id =? id, ?message, "demo client ignored command"),
+        }
+    }
+
+    server.disconnect(id);
+}
+
+/// plausible telemetry of a demo client, slow waves offset per client so the
+/// charts differ and move without needing randomness
+struct Signal {
+    phase: f64,
+    booted: Duration,
+    started: Instant,
+    memory_total: u64,
+    network_received: u64,
+    network_transmitted: u64,
+    sampled: Instant,
+}
+
+impl Signal {
+    fn new(index: usize) -> Self {
+        let now = Instant::now();
+
+        Self {
+            phase: index as f64 * 1.3,
+            booted: Duration::from_secs(3_600 * (5 + 17 * index as u64)),
+            started: now,
+            memory_total: [4, 8, 16][index % 3] * GIB,
+            network_received: 40_000_000 * (index as u64 + 1),
+            network_transmitted: 9_000_000 * (index as u64 + 1),
+            sampled: now,
+        }
+    }
+
+    fn uptime(&self) -> Duration {
+        self.booted + Duration::from_secs(self.started.elapsed().as_secs())
+    }
+
+    /// a wave between 0 and 1 repeating every `period` seconds
+    fn wave(&self, period: f64) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+
+        0.5 + 0.5 * (TAU * elapsed / period + self.phase).sin()
+    }
+
+    fn sample(&mut self) -> Telemetry {
+        let interval = self.sampled.elapsed().as_secs_f64();
+        self.sampled = Instant::now();
+
+        let traffic = self.wave(180.0);
+
+        self.network_received += (interval * (20_000.0 + 180_000.0 * traffic)) as u64;
+        self.network_transmitted += (interval * (5_000.0 + 40_000.0 * traffic)) as u64;
+
+        Telemetry {
+            load: 0.1 + 2.4 * self.wave(300.0) * self.wave(47.0),
+            memory_total: self.memory_total,
+            memory_used: (self.memory_total as f64 * (0.35 + 0.3 * self.wave(600.0))) as u64,
+            network_received: self.network_received,
+            network_transmitted: self.network_transmitted,
+        }
+    }
+}
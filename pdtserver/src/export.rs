@@ -0,0 +1,158 @@
+use axum::{
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Deserialize, ToSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize, IntoParams, Debug, Clone, Default)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    #[param(inline)]
+    pub format: ExportFormat,
+}
+
+/// quote a csv field when it contains a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_line(fields: &[String]) -> String {
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+
+    fields.join(",") + "\r\n"
+}
+
+/// rows that can be exported as csv as well as json
+pub trait Export: Serialize {
+    const HEADER: &'static [&'static str];
+
+    fn row(&self) -> Vec<String>;
+}
+
+/// download of `rows` named `name` with the extension of `format`
+pub fn export<T: Export>(name: &str, format: ExportFormat, rows: Vec<T>) -> Response {
+    let (content_type, extension) = match format {
+        ExportFormat::Json => ("application/json", "json"),
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+    };
+
+    let disposition = format!("attachment; filename=\"{name}.{extension}\"");
+
+    let headers = [
+        (CONTENT_TYPE, content_type.to_string()),
+        (CONTENT_DISPOSITION, disposition),
+    ];
+
+    match format {
+        ExportFormat::Json => (headers, Json(rows)).into_response(),
+        ExportFormat::Csv => {
+            let header: Vec<String> = T::HEADER.iter().map(|name| name.to_string()).collect();
+
+            let body: String = std::iter::once(csv_line(&header))
+                .chain(rows.iter().map(|row| csv_line(&row.row())))
+                .collect();
+
+            (headers, body).into_response()
+        }
+    }
+}
+
+impl Export for pdtapi::ClientSummary {
+    const HEADER: &'static [&'static str] = &[
+        "id",
+        "state",
+        "name",
+        "os",
+        "os_version",
+        "uptime",
+        "vendor",
+        "model",
+        "serial",
+        "chassis",
+        "virtualization",
+        "last_seen",
+        "address",
+        "transport",
+        "connected_at",
+        "protocol_version",
+    ];
+
+    fn row(&self) -> Vec<String> {
+        let state = match self.state {
+            pdtapi::ConnectionState::Connecting => "connecting",
+            pdtapi::ConnectionState::Connected => "connected",
+        };
+
+        let transport = match self.network.transport {
+            pdtapi::Transport::Tcp => "tcp",
+            pdtapi::Transport::Unix => "unix",
+        };
+
+        let virtualization = match self.device_info.virtualization {
+            pdtapi::Virtualization::Unknown => "unknown",
+            pdtapi::Virtualization::Physical => "physical",
+            pdtapi::Virtualization::VirtualMachine => "virtual-machine",
+            pdtapi::Virtualization::Container => "container",
+        };
+
+        vec![
+            self.id.clone(),
+            state.to_string(),
+            self.device_info.name.clone(),
+            self.device_info.os.clone(),
+            self.device_info.os_version.clone(),
+            self.device_info.uptime.clone(),
+            self.device_info.vendor.clone(),
+            self.device_info.model.clone(),
+            self.device_info.serial.clone(),
+            self.device_info.chassis.clone(),
+            virtualization.to_string(),
+            self.last_seen.to_string(),
+            self.network.address.clone(),
+            transport.to_string(),
+            self.network.connected_at.to_string(),
+            self.protocol_version.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl Export for pdtapi::HistoryEntry {
+    const HEADER: &'static [&'static str] = &[
+        "timestamp",
+        "client_id",
+        "device_name",
+        "command",
+        "actor",
+        "outcome",
+        "previous_hash",
+        "hash",
+    ];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.timestamp.to_string(),
+            self.client_id.clone(),
+            self.device_name.clone(),
+            self.command.clone(),
+            self.actor.clone(),
+            self.outcome.clone(),
+            self.previous_hash.clone(),
+            self.hash.clone(),
+        ]
+    }
+}
@@ -0,0 +1,331 @@
+//! roll-up of other pdtservers, the upstreams in the settings, so a single
+//! dashboard covers the clients of every site with commands proxied to the
+//! server owning the client
+//!
+//! upstreams are asked through their json api for their own clients only, so
+//! servers rolling up each other do not loop
+
+use std::time::Duration;
+
+use pdtapi::{
+    ApiClient, ClientError, ClientSummary, Command, FederatedClient, FederatedClients,
+    UnreachableServer,
+};
+use tokio::task::JoinHandle;
+use tracing::*;
+
+use crate::{
+    auth::Access, command, server::SendError, settings::Upstream, AppError, AppStateReference,
+};
+
+/// an unreachable upstream must not hold up the roll-up for long
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// why an upstream could not be asked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamError {
+    pub server: String,
+    /// status the upstream answered with, none when it did not answer
+    pub status: Option<u16>,
+    pub error: String,
+}
+
+impl UpstreamError {
+    fn new(server: String, error: ClientError) -> Self {
+        let (status, error) = match error {
+            ClientError::Api(status, response) => (Some(status), response.error),
+            ClientError::Transport(transport) => (None, transport.to_string()),
+            ClientError::Decode(error) => (None, format!("invalid response: {error}")),
+            ClientError::Event(error) => (None, format!("invalid event: {error}")),
+        };
+
+        Self {
+            server,
+            status,
+            error,
+        }
+    }
+}
+
+fn api_client(upstream: &Upstream) -> ApiClient {
+    let client = ApiClient::new(upstream.url.as_str()).with_timeout(TIMEOUT);
+
+    match &upstream.token {
+        Some(token) => client.with_token(token.as_str()),
+        None => client,
+    }
+}
+
+/// start a blocking request to `upstream`
+fn start<T: Send + 'static>(
+    upstream: Upstream,
+    request: impl FnOnce(&ApiClient) -> Result<T, ClientError> + Send + 'static,
+) -> JoinHandle<Result<T, ClientError>> {
+    tokio::task::spawn_blocking(move || request(&api_client(&upstream)))
+}
+
+async fn finish<T>(
+    server: String,
+    request: JoinHandle<Result<T, ClientError>>,
+) -> Result<T, UpstreamError> {
+    match request.await {
+        Ok(answer) => answer.map_err(|error| UpstreamError::new(server, error)),
+        Err(error) => Err(UpstreamError {
+            server,
+            status: None,
+            error: format!("request did not finish: {error}"),
+        }),
+    }
+}
+
+/// the local clients under `local_name` followed by the clients of every
+/// upstream that answered
+fn combine(
+    local_name: &str,
+    local: Vec<ClientSummary>,
+    answers: Vec<(String, Result<Vec<ClientSummary>, UpstreamError>)>,
+) -> FederatedClients {
+    let mut clients: Vec<_> = local
+        .into_iter()
+        .map(|client| FederatedClient {
+            server: local_name.to_string(),
+            client,
+        })
+        .collect();
+
+    let mut unreachable = vec![];
+
+    for (server, answer) in answers {
+        match answer {
+            Ok(upstream_clients) => {
+                clients.extend(upstream_clients.into_iter().map(|client| FederatedClient {
+                    server: server.clone(),
+                    client,
+                }))
+            }
+            Err(error) => {
+                warn!(server, error = error.error, "upstream unreachable");
+
+                unreachable.push(UnreachableServer {
+                    server,
+                    error: error.error,
+                });
+            }
+        }
+    }
+
+    FederatedClients {
+        clients,
+        unreachable,
+    }
+}
+
+/// name of this server and the upstreams to ask, leaving out any named like
+/// this server
+pub fn servers(state: &AppStateReference) -> Result<(String, Vec<Upstream>), AppError> {
+    let upstreams = state
+        .settings
+        .lock()
+        .upstreams
+        .iter()
+        .filter(|upstream| upstream.name != state.server_name)
+        .cloned()
+        .collect();
+
+    Ok((state.server_name.clone(), upstreams))
+}
+
+/// clients of this server and of every upstream, asked concurrently, that
+/// the caller may see
+pub async fn clients(
+    state: &AppStateReference,
+    access: &Access,
+) -> Result<FederatedClients, AppError> {
+    let (local_name, upstreams) = servers(state)?;
+    let server = state.server.clone();
+
+    let requests: Vec<_> = upstreams
+        .into_iter()
+        .map(|upstream| (upstream.name.clone(), start(upstream, ApiClient::clients)))
+        .collect();
+
+    let local = access
+        .visible(server.clients().await?)
+        .into_iter()
+        .map(ClientSummary::from)
+        .collect();
+
+    let mut answers = Vec::with_capacity(requests.len());
+
+    for (name, request) in requests {
+        answers.push((name.clone(), finish(name, request).await));
+    }
+
+    let mut federated = combine(&local_name, local, answers);
+
+    federated
+        .clients
+        .retain(|client| access.may_see(&client.client.device_info.name));
+
+    Ok(federated)
+}
+
+/// send `command` to a client of the server named `server`, this one or an
+/// upstream, returning the name of its device
+///
+/// the caller needs to be allowed to send the command to the device here as
+/// well as the token of the upstream there, commands to upstreams are kept
+/// in the history of the upstream
+#[instrument(skip(state, access))]
+pub async fn send(
+    state: &AppStateReference,
+    access: &Access,
+    server: &str,
+    client_id: String,
+    request: Command,
+) -> Result<String, AppError> {
+    let (local_name, upstreams) = servers(state)?;
+
+    if server == local_name {
+        let client_id = client_id.parse().map_err(|_| AppError::InvalidClientId)?;
+
+        return command(state, access, client_id, request.into()).await;
+    }
+
+    access.check_csrf()?;
+
+    let Some(upstream) = upstreams
+        .into_iter()
+        .find(|upstream| upstream.name == server)
+    else {
+        return Err(AppError::UnknownServer);
+    };
+
+    let name = upstream.name.clone();
+
+    let client = {
+        let client_id = client_id.clone();
+
+        finish(
+            name.clone(),
+            start(upstream.clone(), move |api| api.client(&client_id)),
+        )
+        .await
+        .map_err(AppError::Upstream)?
+    };
+
+    let device_name = client.device_info.name;
+
+    if !access.may_see(&device_name) {
+        return Err(AppError::ServerSend(SendError::ClientNotFound));
+    }
+
+    if !access.may_send(&device_name, &request.into()) {
+        return Err(AppError::Forbidden);
+    }
+
+    info!(
+        server = name,
+        device_name,
+        actor = access.actor(),
+        "proxying command to upstream"
+    );
+
+    finish(
+        name,
+        start(upstream, move |api| api.command(&client_id, request)),
+    )
+    .await
+    .map_err(AppError::Upstream)?;
+
+    Ok(device_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use pdtapi::{
+        ConnectionState, DeviceInfo, ErrorResponse, NetworkInfo, Transport, Virtualization,
+    };
+
+    use super::*;
+
+    fn summary(id: &str, name: &str) -> ClientSummary {
+        ClientSummary {
+            id: id.to_string(),
+            state: ConnectionState::Connected,
+            device_info: DeviceInfo {
+                name: name.to_string(),
+                os: "Linux".to_string(),
+                os_version: "6.1".to_string(),
+                uptime: "1h".to_string(),
+                vendor: "LENOVO".to_string(),
+                model: "ThinkPad T14 Gen 3".to_string(),
+                serial: "PF3ABCDE".to_string(),
+                chassis: "notebook".to_string(),
+                virtualization: Virtualization::Physical,
+            },
+            last_seen: 1_700_000_000,
+            network: NetworkInfo {
+                address: "192.0.2.1:50000".to_string(),
+                transport: Transport::Tcp,
+                connected_at: 1_700_000_000,
+            },
+            protocol_version: None,
+        }
+    }
+
+    #[test]
+    fn local_clients_come_before_upstream_clients() {
+        let federated = combine(
+            "home",
+            vec![summary("1", "ash")],
+            vec![
+                ("office".to_string(), Ok(vec![summary("2", "birch")])),
+                (
+                    "parents".to_string(),
+                    Err(UpstreamError {
+                        server: "parents".to_string(),
+                        status: None,
+                        error: "connection refused".to_string(),
+                    }),
+                ),
+            ],
+        );
+
+        assert_eq!(
+            federated,
+            FederatedClients {
+                clients: vec![
+                    FederatedClient {
+                        server: "home".to_string(),
+                        client: summary("1", "ash"),
+                    },
+                    FederatedClient {
+                        server: "office".to_string(),
+                        client: summary("2", "birch"),
+                    },
+                ],
+                unreachable: vec![UnreachableServer {
+                    server: "parents".to_string(),
+                    error: "connection refused".to_string(),
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn upstream_errors_keep_the_status_of_the_upstream() {
+        let error = UpstreamError::new(
+            "office".to_string(),
+            ClientError::Api(
+                403,
+                ErrorResponse {
+                    error: "Forbidden".to_string(),
+                },
+            ),
+        );
+
+        assert_eq!(error.status, Some(403));
+        assert_eq!(error.error, "Forbidden");
+    }
+}
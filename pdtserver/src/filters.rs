@@ -0,0 +1,81 @@
+use std::time::{Duration, SystemTime};
+
+use pdtcore::{Client, ConnectionState, HEARTBEAT_INTERVAL};
+
+/// clients are online while they answered one of the last few telemetry
+/// requests
+const ONLINE_WINDOW: Duration = Duration::from_secs(HEARTBEAT_INTERVAL.as_secs() * 3);
+
+/// largest one or two units of a duration, e.g. `3m` or `2d 4h`
+fn humanize(duration: Duration) -> String {
+    const UNITS: [(u64, &str); 4] = [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+
+    let mut seconds = duration.as_secs();
+
+    let parts: Vec<String> = UNITS
+        .iter()
+        .filter_map(|(size, unit)| {
+            let count = seconds / size;
+            seconds %= size;
+
+            (count > 0).then(|| format!("{count}{unit}"))
+        })
+        .take(2)
+        .collect();
+
+    match parts.is_empty() {
+        true => "0s".to_string(),
+        false => parts.join(" "),
+    }
+}
+
+/// time since `at`, e.g. `3m ago`
+pub fn ago(at: &SystemTime) -> askama::Result<String> {
+    let elapsed = at.elapsed().unwrap_or_default();
+
+    if elapsed < Duration::from_secs(5) {
+        return Ok("just now".to_string());
+    }
+
+    Ok(format!("{} ago", humanize(elapsed)))
+}
+
+/// like `2024-05-01T12:00:00.250Z`, to the millisecond
+pub fn timestamp(at: &SystemTime) -> askama::Result<String> {
+    Ok(humantime::format_rfc3339_millis(*at).to_string())
+}
+
+/// like `2d 4h`
+pub fn duration(duration: &Duration) -> askama::Result<String> {
+    Ok(humanize(*duration))
+}
+
+/// bytes in gibibytes, e.g. `7.5 GiB`
+pub fn gibibytes(bytes: &u64) -> askama::Result<String> {
+    Ok(format!("{:.1} GiB", *bytes as f64 / (1u64 << 30) as f64))
+}
+
+/// shorten an uptime reported by a client, left as is when it does not parse
+pub fn uptime(uptime: &str) -> askama::Result<String> {
+    Ok(humantime::parse_duration(uptime)
+        .map(humanize)
+        .unwrap_or_else(|_| uptime.to_string()))
+}
+
+/// markdown notes as html, see [`crate::notes::render`]
+pub fn notes_html(notes: &str) -> askama::Result<String> {
+    Ok(crate::notes::render(notes))
+}
+
+/// `online` or `offline`, for use as a css class
+pub fn presence(client: &Client) -> askama::Result<&'static str> {
+    let recently_seen = client
+        .last_seen
+        .elapsed()
+        .is_ok_and(|elapsed| elapsed < ONLINE_WINDOW);
+
+    match client.state == ConnectionState::Connected && recently_seen {
+        true => Ok("online"),
+        false => Ok("offline"),
+    }
+}
@@ -0,0 +1,99 @@
+use std::cmp::Ordering;
+
+use pdtcore::{BuiltInfo, Client};
+
+/// how a client build relates to the build of the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Compatibility {
+    /// rejected by the server, major or minor version differs
+    Incompatible,
+    /// compatible but an older patch release
+    Outdated,
+    /// the client has not introduced itself yet
+    Unknown,
+    /// compatible and a newer patch release
+    Newer,
+    /// same version as the server
+    Current,
+}
+
+impl Compatibility {
+    fn of(server: &BuiltInfo, client: Option<&BuiltInfo>) -> Self {
+        let Some(client) = client else {
+            return Compatibility::Unknown;
+        };
+
+        if !server.compatible(client) {
+            return Compatibility::Incompatible;
+        }
+
+        match version(client).cmp(&version(server)) {
+            Ordering::Less => Compatibility::Outdated,
+            Ordering::Equal => Compatibility::Current,
+            Ordering::Greater => Compatibility::Newer,
+        }
+    }
+
+    /// lowercase name, for display and as a css class
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compatibility::Incompatible => "incompatible",
+            Compatibility::Outdated => "outdated",
+            Compatibility::Unknown => "unknown",
+            Compatibility::Newer => "newer",
+            Compatibility::Current => "current",
+        }
+    }
+}
+
+/// numeric major, minor and patch version, pre-releases sort with their
+/// release
+fn version(built_info: &BuiltInfo) -> (u64, u64, u64) {
+    let number = |part: &str| part.parse().unwrap_or(0);
+
+    (
+        number(&built_info.pkg_version_major),
+        number(&built_info.pkg_version_minor),
+        number(&built_info.pkg_version_patch),
+    )
+}
+
+/// clients running the same pdtcore build
+pub struct Deployment {
+    pub built_info: Option<BuiltInfo>,
+    pub compatibility: Compatibility,
+    pub clients: Vec<Client>,
+}
+
+/// clients grouped by build, the ones needing attention first and newer
+/// versions before older ones
+pub fn deployments(
+    server: &BuiltInfo,
+    builds: Vec<(Client, Option<BuiltInfo>)>,
+) -> Vec<Deployment> {
+    let mut deployments: Vec<Deployment> = Vec::new();
+
+    for (client, built_info) in builds {
+        match deployments
+            .iter_mut()
+            .find(|deployment| deployment.built_info == built_info)
+        {
+            Some(deployment) => deployment.clients.push(client),
+            None => deployments.push(Deployment {
+                compatibility: Compatibility::of(server, built_info.as_ref()),
+                built_info,
+                clients: vec![client],
+            }),
+        }
+    }
+
+    deployments.sort_by(|a, b| {
+        a.compatibility.cmp(&b.compatibility).then_with(|| {
+            let version = |deployment: &Deployment| deployment.built_info.as_ref().map(version);
+
+            version(b).cmp(&version(a))
+        })
+    });
+
+    deployments
+}
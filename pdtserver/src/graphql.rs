@@ -0,0 +1,369 @@
+use async_graphql::{
+    ComplexObject, Context, EmptySubscription, Enum, Object, Result, Schema, SimpleObject, ID,
+};
+use axum::{extract::State, routing, Extension, Json, Router};
+use ulid::Ulid;
+
+use crate::{
+    api::ApiAccess, arming, auth::Access, command, history::ChainedRecord, query::ClientQuery,
+    query::ClientSort, query::HistoryQuery, server::SendError, telemetry::Sample, AppError,
+    AppStateReference,
+};
+
+type PdtSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+pub fn router() -> Router<AppStateReference> {
+    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription).finish();
+
+    Router::new()
+        .route("/api/graphql", routing::post(graphql))
+        .layer(Extension(schema))
+}
+
+async fn graphql(
+    State(state): State<AppStateReference>,
+    Extension(schema): Extension<PdtSchema>,
+    ApiAccess(access): ApiAccess,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    Json(schema.execute(request.data(state).data(access)).await)
+}
+
+/// graphql error carrying the message the rest api would answer with
+fn error(error: AppError) -> async_graphql::Error {
+    async_graphql::Error::new(error.describe().1)
+}
+
+fn id(id: &ID) -> Result<Ulid> {
+    id.parse().map_err(|_| error(AppError::InvalidClientId))
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionState {
+    Connecting,
+    Connected,
+}
+
+impl From<pdtcore::ConnectionState> for ConnectionState {
+    fn from(value: pdtcore::ConnectionState) -> Self {
+        match value {
+            pdtcore::ConnectionState::Connecting => ConnectionState::Connecting,
+            pdtcore::ConnectionState::Connected => ConnectionState::Connected,
+        }
+    }
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    ScreenOff,
+    ScreenOn,
+    PowerOff,
+    Restart,
+}
+
+impl From<Command> for pdtapi::Command {
+    fn from(value: Command) -> Self {
+        match value {
+            Command::ScreenOff => pdtapi::Command::ScreenOff,
+            Command::ScreenOn => pdtapi::Command::ScreenOn,
+            Command::PowerOff => pdtapi::Command::PowerOff,
+            Command::Restart => pdtapi::Command::Restart,
+        }
+    }
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Virtualization {
+    Unknown,
+    Physical,
+    VirtualMachine,
+    Container,
+}
+
+impl From<pdtapi::Virtualization> for Virtualization {
+    fn from(value: pdtapi::Virtualization) -> Self {
+        match value {
+            pdtapi::Virtualization::Unknown => Virtualization::Unknown,
+            pdtapi::Virtualization::Physical => Virtualization::Physical,
+            pdtapi::Virtualization::VirtualMachine => Virtualization::VirtualMachine,
+            pdtapi::Virtualization::Container => Virtualization::Container,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct DeviceInfo {
+    name: String,
+    os: String,
+    os_version: String,
+    uptime: String,
+    vendor: String,
+    model: String,
+    serial: String,
+    chassis: String,
+    virtualization: Virtualization,
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+struct Client {
+    id: ID,
+    state: ConnectionState,
+    device_info: DeviceInfo,
+    /// seconds since the unix epoch the server last heard from the client
+    last_seen: u64,
+}
+
+impl From<pdtcore::Client> for Client {
+    fn from(value: pdtcore::Client) -> Self {
+        let state = value.state.into();
+        let summary = pdtapi::ClientSummary::from(value);
+
+        Self {
+            id: summary.id.into(),
+            state,
+            device_info: DeviceInfo {
+                name: summary.device_info.name,
+                os: summary.device_info.os,
+                os_version: summary.device_info.os_version,
+                uptime: summary.device_info.uptime,
+                vendor: summary.device_info.vendor,
+                model: summary.device_info.model,
+                serial: summary.device_info.serial,
+                chassis: summary.device_info.chassis,
+                virtualization: summary.device_info.virtualization.into(),
+            },
+            last_seen: summary.last_seen,
+        }
+    }
+}
+
+#[ComplexObject]
+impl Client {
+    /// telemetry samples, oldest first
+    async fn telemetry(&self, context: &Context<'_>) -> Result<Vec<TelemetrySample>> {
+        let id = id(&self.id)?;
+        let state = context.data::<AppStateReference>()?;
+
+        let server = state.server.clone();
+
+        let samples = server
+            .telemetry(id)
+            .await
+            .map_err(error)?
+            .ok_or_else(|| error(AppError::ServerSend(SendError::ClientNotFound)))?;
+
+        Ok(samples.into_iter().map(TelemetrySample::from).collect())
+    }
+}
+
+/// resource usage of a client at a point in time
+#[derive(SimpleObject)]
+struct TelemetrySample {
+    /// seconds since the unix epoch
+    timestamp: u64,
+    /// one minute load average
+    load: f64,
+    memory_total: u64,
+    memory_used: u64,
+    /// bytes received since the client booted
+    network_received: u64,
+    /// bytes transmitted since the client booted
+    network_transmitted: u64,
+}
+
+impl From<Sample> for TelemetrySample {
+    fn from(value: Sample) -> Self {
+        let sample = pdtapi::TelemetrySample::new(value.at, value.telemetry);
+
+        Self {
+            timestamp: sample.timestamp,
+            load: sample.load,
+            memory_total: sample.memory_total,
+            memory_used: sample.memory_used,
+            network_received: sample.network_received,
+            network_transmitted: sample.network_transmitted,
+        }
+    }
+}
+
+/// a window of the client listing
+#[derive(SimpleObject)]
+struct ClientPage {
+    clients: Vec<Client>,
+    /// number of matching clients before pagination
+    total: usize,
+}
+
+/// a window of the command history
+#[derive(SimpleObject)]
+struct HistoryPage {
+    entries: Vec<HistoryEntry>,
+    /// number of commands before pagination
+    total: usize,
+}
+
+/// a command sent through the web interface or api
+#[derive(SimpleObject)]
+struct HistoryEntry {
+    /// seconds since the unix epoch
+    timestamp: u64,
+    client_id: ID,
+    device_name: String,
+    command: String,
+    /// user or access token that sent the command
+    actor: String,
+    /// `sent`, `failed`, `denied` or `coalesced`
+    outcome: String,
+    /// hash of the entry before it
+    previous_hash: String,
+    /// blake2b-256 over the previous hash and the fields above
+    hash: String,
+}
+
+impl From<ChainedRecord> for HistoryEntry {
+    fn from(value: ChainedRecord) -> Self {
+        let entry = pdtapi::HistoryEntry::from(value);
+
+        Self {
+            timestamp: entry.timestamp,
+            client_id: entry.client_id.into(),
+            device_name: entry.device_name,
+            command: entry.command,
+            actor: entry.actor,
+            outcome: entry.outcome,
+            previous_hash: entry.previous_hash,
+            hash: entry.hash,
+        }
+    }
+}
+
+/// outcome of a command for a single client
+#[derive(SimpleObject)]
+struct CommandResult {
+    client_id: ID,
+    /// why the command was not sent, absent on success
+    error: Option<String>,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// connected clients, in the order they joined unless sorted otherwise
+    async fn clients(
+        &self,
+        context: &Context<'_>,
+        #[graphql(default)] q: String,
+        #[graphql(default)] state: String,
+        #[graphql(default)] sort: ClientSort,
+        #[graphql(default)] offset: usize,
+        limit: Option<usize>,
+    ) -> Result<ClientPage> {
+        let query = ClientQuery {
+            q,
+            state,
+            sort,
+            offset,
+            limit,
+        };
+
+        let app_state = context.data::<AppStateReference>()?;
+        let access = context.data::<Access>()?;
+
+        let server = app_state.server.clone();
+
+        let page = query.apply(access.visible(server.clients().await.map_err(error)?));
+
+        Ok(ClientPage {
+            clients: page.clients.into_iter().map(Client::from).collect(),
+            total: page.total,
+        })
+    }
+
+    async fn client(&self, context: &Context<'_>, id: ID) -> Result<Option<Client>> {
+        let id = self::id(&id)?;
+        let state = context.data::<AppStateReference>()?;
+        let access = context.data::<Access>()?;
+
+        let server = state.server.clone();
+
+        Ok(server
+            .client(id)
+            .await
+            .map_err(error)?
+            .filter(|client| access.may_see(&client.device_info.name))
+            .map(Client::from))
+    }
+
+    /// commands sent since the server started, oldest first, for admins
+    async fn history(
+        &self,
+        context: &Context<'_>,
+        #[graphql(default)] offset: usize,
+        limit: Option<usize>,
+    ) -> Result<HistoryPage> {
+        if !context.data::<Access>()?.is_admin() {
+            return Err(error(AppError::Forbidden));
+        }
+
+        let state = context.data::<AppStateReference>()?;
+
+        let records = state.web.lock().history.records();
+        let total = records.len();
+
+        let query = HistoryQuery { offset, limit };
+
+        Ok(HistoryPage {
+            entries: query
+                .apply(records)
+                .into_iter()
+                .map(HistoryEntry::from)
+                .collect(),
+            total,
+        })
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// send a command to each of the clients, reporting the outcome per
+    /// client, destructive commands to several clients need the
+    /// `confirmation` of arming them in the http api
+    async fn send_command(
+        &self,
+        context: &Context<'_>,
+        client_ids: Vec<ID>,
+        command: Command,
+        confirmation: Option<String>,
+    ) -> Result<Vec<CommandResult>> {
+        let state = context.data::<AppStateReference>()?;
+        let access = context.data::<Access>()?;
+
+        let message = pdtapi::Command::from(command);
+
+        let ids: Vec<String> = client_ids.iter().map(|id| id.to_string()).collect();
+
+        arming::confirm(state, access, message, &ids, confirmation.as_deref())
+            .await
+            .map_err(error)?;
+
+        let mut results = Vec::with_capacity(client_ids.len());
+
+        for client_id in client_ids {
+            let outcome = match id(&client_id) {
+                Ok(id) => self::command(state, access, id, message.into()).await,
+                Err(_) => Err(AppError::InvalidClientId),
+            };
+
+            results.push(CommandResult {
+                client_id,
+                error: outcome.err().map(|error| error.describe().1),
+            });
+        }
+
+        Ok(results)
+    }
+}
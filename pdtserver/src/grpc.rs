@@ -0,0 +1,235 @@
+use std::{net::SocketAddr, pin::Pin};
+
+use axum::http::StatusCode;
+use tokio::sync::mpsc;
+use tokio_stream::{
+    wrappers::{BroadcastStream, ReceiverStream},
+    Stream, StreamExt,
+};
+use tonic::{Code, Request, Response, Status};
+use tracing::*;
+use ulid::Ulid;
+
+use crate::{
+    arming, auth::Access, command, query::ClientQuery, server::ClientEvent, AppError,
+    AppStateReference,
+};
+
+mod proto {
+    tonic::include_proto!("pdt.v1");
+}
+
+use proto::{
+    client_event::Event,
+    management_server::{Management, ManagementServer},
+};
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// grpc status for the error the rest api would answer with
+fn status(error: AppError) -> Status {
+    let (status, message) = error.describe();
+
+    let code = match status {
+        StatusCode::BAD_REQUEST => Code::InvalidArgument,
+        StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+        StatusCode::FORBIDDEN => Code::PermissionDenied,
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+        StatusCode::PRECONDITION_REQUIRED => Code::FailedPrecondition,
+        _ => Code::Internal,
+    };
+
+    Status::new(code, message)
+}
+
+impl From<pdtcore::Client> for proto::Client {
+    fn from(value: pdtcore::Client) -> Self {
+        let summary = pdtapi::ClientSummary::from(value);
+
+        let state = match summary.state {
+            pdtapi::ConnectionState::Connecting => proto::ConnectionState::Connecting,
+            pdtapi::ConnectionState::Connected => proto::ConnectionState::Connected,
+        };
+
+        let virtualization = match summary.device_info.virtualization {
+            pdtapi::Virtualization::Unknown => proto::Virtualization::Unspecified,
+            pdtapi::Virtualization::Physical => proto::Virtualization::Physical,
+            pdtapi::Virtualization::VirtualMachine => proto::Virtualization::VirtualMachine,
+            pdtapi::Virtualization::Container => proto::Virtualization::Container,
+        };
+
+        Self {
+            id: summary.id,
+            state: state.into(),
+            device_info: Some(proto::DeviceInfo {
+                name: summary.device_info.name,
+                os: summary.device_info.os,
+                os_version: summary.device_info.os_version,
+                uptime: summary.device_info.uptime,
+                vendor: summary.device_info.vendor,
+                model: summary.device_info.model,
+                serial: summary.device_info.serial,
+                chassis: summary.device_info.chassis,
+                virtualization: virtualization.into(),
+            }),
+            last_seen: summary.last_seen,
+        }
+    }
+}
+
+impl From<ClientEvent> for proto::ClientEvent {
+    fn from(value: ClientEvent) -> Self {
+        let event = match value {
+            ClientEvent::Joined(client) => Event::Joined(client.into()),
+            ClientEvent::Updated(client) => Event::Updated(client.into()),
+            ClientEvent::Left(id) => Event::Left(id.to_string()),
+            ClientEvent::Heartbeat(client) => Event::Heartbeat(client.into()),
+            ClientEvent::CommandResult(id, _, result) => {
+                Event::CommandResult(proto::CommandOutcome {
+                    client_id: id.to_string(),
+                    result,
+                })
+            }
+        };
+
+        Self { event: Some(event) }
+    }
+}
+
+/// management operations of the rest api over grpc, authorized with the same
+/// bearer tokens
+pub struct ManagementService {
+    state: AppStateReference,
+}
+
+impl ManagementService {
+    fn access<T>(&self, request: &Request<T>) -> Result<Access, AppError> {
+        let headers = request.metadata().clone().into_headers();
+
+        Access::from_headers(&headers, &self.state)
+    }
+}
+
+#[tonic::async_trait]
+impl Management for ManagementService {
+    async fn list_clients(
+        &self,
+        request: Request<proto::ListClientsRequest>,
+    ) -> Result<Response<proto::ListClientsResponse>, Status> {
+        let access = self.access(&request).map_err(status)?;
+
+        let request = request.into_inner();
+
+        let query = ClientQuery {
+            q: request.q,
+            state: request.state,
+            ..ClientQuery::default()
+        };
+
+        let server = self.state.server.clone();
+
+        let clients = query
+            .apply(access.visible(server.clients().await.map_err(status)?))
+            .clients;
+
+        Ok(Response::new(proto::ListClientsResponse {
+            clients: clients.into_iter().map(proto::Client::from).collect(),
+        }))
+    }
+
+    type SendCommandStream = ResponseStream<proto::CommandResult>;
+
+    async fn send_command(
+        &self,
+        request: Request<proto::SendCommandRequest>,
+    ) -> Result<Response<Self::SendCommandStream>, Status> {
+        let access = self.access(&request).map_err(status)?;
+
+        let request = request.into_inner();
+
+        let message = match request.command() {
+            proto::Command::ScreenOff => pdtapi::Command::ScreenOff,
+            proto::Command::ScreenOn => pdtapi::Command::ScreenOn,
+            proto::Command::PowerOff => pdtapi::Command::PowerOff,
+            proto::Command::Restart => pdtapi::Command::Restart,
+            proto::Command::Unspecified => {
+                return Err(Status::invalid_argument("command is required"))
+            }
+        };
+
+        let state = self.state.clone();
+
+        arming::confirm(
+            &state,
+            &access,
+            message,
+            &request.client_ids,
+            Some(request.confirmation.as_str()).filter(|confirmation| !confirmation.is_empty()),
+        )
+        .await
+        .map_err(status)?;
+
+        // results are streamed as each command is handed to the server
+        let (sender, receiver) = mpsc::channel(request.client_ids.len().max(1));
+
+        tokio::spawn(async move {
+            for client_id in request.client_ids {
+                let outcome = match client_id.parse::<Ulid>() {
+                    Ok(id) => command(&state, &access, id, message.into()).await,
+                    Err(_) => Err(AppError::InvalidClientId),
+                };
+
+                let result = proto::CommandResult {
+                    client_id,
+                    error: outcome
+                        .err()
+                        .map(|error| error.describe().1)
+                        .unwrap_or_default(),
+                };
+
+                if sender.send(Ok(result)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+
+    type WatchEventsStream = ResponseStream<proto::ClientEvent>;
+
+    async fn watch_events(
+        &self,
+        request: Request<proto::WatchEventsRequest>,
+    ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        let access = self.access(&request).map_err(status)?;
+
+        let events = self.state.server.subscribe();
+
+        let stream = BroadcastStream::new(events).filter_map(move |event| match event {
+            Ok(event) if !access.may_see_event(&event) => None,
+            Ok(event) => Some(Ok(proto::ClientEvent::from(event))),
+            Err(error) => {
+                warn!(error =? error, "grpc event subscriber lagging behind");
+                None
+            }
+        });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+pub async fn serve(state: AppStateReference, address: SocketAddr) {
+    info!(address =? address, "starting grpc server");
+
+    let service = ManagementServer::new(ManagementService { state });
+
+    if let Err(error) = tonic::transport::Server::builder()
+        .add_service(service)
+        .serve(address)
+        .await
+    {
+        error!(error =? error, "grpc server");
+    }
+}
@@ -0,0 +1,278 @@
+//! the web interface's way to the server, requests are handed to a thread
+//! owning the [`Server`] and answered over a oneshot channel, so handlers
+//! never hold a server lock or block the runtime waiting for one
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use pdtcore::{BuiltInfo, Client, Containers, LogRecord, Message, TelemetryConfig, TraceContext};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::*;
+use ulid::Ulid;
+
+use crate::{
+    bandwidth::Usage,
+    client_logs::Batch,
+    crashes::Crash,
+    latency::LatencyStats,
+    registry::RegistryCounts,
+    server::{ClientEvent, SendError, Server},
+    telemetry::Sample,
+    watchdog::Watchdog,
+    AppError,
+};
+
+/// requests queued for the owner before callers have to wait
+const CAPACITY: usize = 256;
+
+enum Request {
+    Clients(oneshot::Sender<Vec<Client>>),
+    Client(Ulid, oneshot::Sender<Option<Client>>),
+    Builds(oneshot::Sender<Vec<(Client, Option<BuiltInfo>)>>),
+    Telemetry(Ulid, oneshot::Sender<Option<Vec<Sample>>>),
+    LatestTelemetry(oneshot::Sender<Vec<(Client, Option<Sample>)>>),
+    Bandwidth(Ulid, oneshot::Sender<Option<Usage>>),
+    Latency(Ulid, oneshot::Sender<Option<LatencyStats>>),
+    Logs(Ulid, oneshot::Sender<Option<Vec<LogRecord>>>),
+    Containers(Ulid, oneshot::Sender<Option<Containers>>),
+    Crashes(oneshot::Sender<Vec<Crash>>),
+    Counts(oneshot::Sender<RegistryCounts>),
+    Send(
+        Ulid,
+        Box<Message>,
+        TraceContext,
+        oneshot::Sender<Result<(), SendError>>,
+    ),
+    Tune(BTreeMap<String, TelemetryConfig>, oneshot::Sender<()>),
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerHandle {
+    requests: mpsc::Sender<Request>,
+    /// subscribing needs no round trip through the owner
+    events: broadcast::Sender<ClientEvent>,
+    log_batches: broadcast::Sender<Batch>,
+    watchdog: Arc<Watchdog>,
+}
+
+impl std::fmt::Debug for Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Request::Clients(_) => "Clients",
+            Request::Client(..) => "Client",
+            Request::Builds(_) => "Builds",
+            Request::Telemetry(..) => "Telemetry",
+            Request::LatestTelemetry(_) => "LatestTelemetry",
+            Request::Bandwidth(..) => "Bandwidth",
+            Request::Latency(..) => "Latency",
+            Request::Logs(..) => "Logs",
+            Request::Containers(..) => "Containers",
+            Request::Crashes(_) => "Crashes",
+            Request::Counts(_) => "Counts",
+            Request::Send(..) => "Send",
+            Request::Tune(..) => "Tune",
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl ServerHandle {
+    /// move the server to its own thread, which serves requests until every
+    /// handle is dropped
+    pub fn spawn(mut server: Server) -> Self {
+        let (requests, mut receiver) = mpsc::channel(CAPACITY);
+        let events = server.events();
+        let log_batches = server.log_batches();
+        let watchdog = server.watchdog();
+
+        std::thread::Builder::new()
+            .name("server".to_string())
+            .spawn(move || {
+                while let Some(request) = receiver.blocking_recv() {
+                    trace!(request = ?request, "serving request");
+
+                    // a caller that gave up waiting dropped its receiver
+                    let _ = match request {
+                        Request::Clients(reply) => reply.send(server.get_clients()).map_err(drop),
+                        Request::Client(id, reply) => {
+                            reply.send(server.get_client(id)).map_err(drop)
+                        }
+                        Request::Builds(reply) => reply.send(server.get_builds()).map_err(drop),
+                        Request::Telemetry(id, reply) => {
+                            reply.send(server.get_telemetry(id)).map_err(drop)
+                        }
+                        Request::LatestTelemetry(reply) => {
+                            reply.send(server.get_latest_telemetry()).map_err(drop)
+                        }
+                        Request::Bandwidth(id, reply) => {
+                            reply.send(server.get_bandwidth(id)).map_err(drop)
+                        }
+                        Request::Latency(id, reply) => {
+                            reply.send(server.get_latency(id)).map_err(drop)
+                        }
+                        Request::Logs(id, reply) => reply.send(server.get_logs(id)).map_err(drop),
+                        Request::Containers(id, reply) => {
+                            reply.send(server.get_containers(id)).map_err(drop)
+                        }
+                        Request::Crashes(reply) => reply.send(server.get_crashes()).map_err(drop),
+                        Request::Counts(reply) => reply.send(server.get_counts()).map_err(drop),
+                        Request::Send(id, message, trace, reply) => reply
+                            .send(server.send_traced(id, *message, trace))
+                            .map_err(drop),
+                        Request::Tune(tunings, reply) => {
+                            server.tune(tunings);
+                            reply.send(())
+                        }
+                    };
+                }
+            })
+            .expect("server thread can be spawned");
+
+        Self {
+            requests,
+            events,
+            log_batches,
+            watchdog,
+        }
+    }
+
+    async fn request<T>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<T>) -> Request,
+    ) -> Result<T, AppError> {
+        let (reply, response) = oneshot::channel();
+
+        self.requests
+            .send(request(reply))
+            .await
+            .map_err(|_| AppError::ServerStopped)?;
+
+        response.await.map_err(|_| AppError::ServerStopped)
+    }
+
+    /// clients in the order they joined
+    pub async fn clients(&self) -> Result<Vec<Client>, AppError> {
+        self.request(Request::Clients).await
+    }
+
+    pub async fn client(&self, id: Ulid) -> Result<Option<Client>, AppError> {
+        self.request(|reply| Request::Client(id, reply)).await
+    }
+
+    /// clients in join order along with the pdtcore build they introduced
+    /// themselves with, if they did yet
+    pub async fn builds(&self) -> Result<Vec<(Client, Option<BuiltInfo>)>, AppError> {
+        self.request(Request::Builds).await
+    }
+
+    /// stored telemetry samples of a client, oldest first
+    pub async fn telemetry(&self, id: Ulid) -> Result<Option<Vec<Sample>>, AppError> {
+        self.request(|reply| Request::Telemetry(id, reply)).await
+    }
+
+    /// clients in join order with their most recent telemetry sample
+    pub async fn latest_telemetry(&self) -> Result<Vec<(Client, Option<Sample>)>, AppError> {
+        self.request(Request::LatestTelemetry).await
+    }
+
+    pub async fn bandwidth(&self, id: Ulid) -> Result<Option<Usage>, AppError> {
+        self.request(|reply| Request::Bandwidth(id, reply)).await
+    }
+
+    /// round trips of the telemetry requests to a client
+    pub async fn latency(&self, id: Ulid) -> Result<Option<LatencyStats>, AppError> {
+        self.request(|reply| Request::Latency(id, reply)).await
+    }
+
+    /// log records a client forwarded, oldest first
+    pub async fn logs(&self, id: Ulid) -> Result<Option<Vec<LogRecord>>, AppError> {
+        self.request(|reply| Request::Logs(id, reply)).await
+    }
+
+    /// containers a client reported last, none for clients not reporting
+    /// them
+    pub async fn containers(&self, id: Ulid) -> Result<Option<Containers>, AppError> {
+        self.request(|reply| Request::Containers(id, reply)).await
+    }
+
+    /// crashes clients reported, newest first
+    pub async fn crashes(&self) -> Result<Vec<Crash>, AppError> {
+        self.request(Request::Crashes).await
+    }
+
+    pub async fn counts(&self) -> Result<RegistryCounts, AppError> {
+        self.request(Request::Counts).await
+    }
+
+    /// queue a message for a client, carrying the trace context of the
+    /// caller's span
+    pub async fn send(&self, to: Ulid, message: Message) -> Result<(), AppError> {
+        let trace = pdttracing::otel::current();
+
+        self.request(|reply| Request::Send(to, Box::new(message), trace, reply))
+            .await?
+            .map_err(AppError::ServerSend)
+    }
+
+    /// telemetry to collect from clients by device name, the connected ones
+    /// are told right away
+    pub async fn tune(&self, tunings: BTreeMap<String, TelemetryConfig>) -> Result<(), AppError> {
+        self.request(|reply| Request::Tune(tunings, reply)).await
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+
+    /// log records as clients forward them
+    pub fn subscribe_logs(&self) -> broadcast::Receiver<Batch> {
+        self.log_batches.subscribe()
+    }
+
+    /// times handling client messages failed and was restarted
+    pub fn handler_restarts(&self) -> u64 {
+        self.watchdog.restarts()
+    }
+
+    /// whether handling client messages failed and did not recover yet
+    pub fn is_degraded(&self) -> bool {
+        self.watchdog.is_degraded()
+    }
+
+    /// client events not yet received by every subscriber
+    pub fn event_queue_depth(&self) -> usize {
+        self.events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pdtcore::ClientMessage;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_for_the_owned_server() {
+        let handle = ServerHandle::spawn(Server::default());
+
+        assert!(handle
+            .clients()
+            .await
+            .is_ok_and(|clients| clients.is_empty()));
+        assert!(matches!(handle.client(Ulid::new()).await, Ok(None)));
+    }
+
+    #[tokio::test]
+    async fn sending_to_an_unknown_client_fails() {
+        let handle = ServerHandle::spawn(Server::default());
+
+        let result = handle
+            .send(Ulid::new(), ClientMessage::ScreenOff.into())
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(AppError::ServerSend(SendError::ClientNotFound))
+        ));
+    }
+}
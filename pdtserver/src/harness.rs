@@ -0,0 +1,343 @@
+//! a server and simulated clients connected in-process over in-memory
+//! streams, exercising the protocol without binding sockets
+
+use std::time::{Duration, Instant, SystemTime};
+
+use pdtcore::{
+    chaos::{Chaos, Faults},
+    duplex::{self, DuplexStream},
+    BuiltInfo, ClientIntroduction, ClientMessage, CommandExecution, DeviceInfo, Message,
+    NetworkInfo, Protocol, ProtocolConfig, ServerMessage, TraceContext, Transport,
+};
+use tokio::sync::broadcast::{self, error::TryRecvError};
+use ulid::Ulid;
+
+use crate::{
+    listener::Connection,
+    server::{ClientEvent, Server},
+};
+
+/// how long to wait for the server before failing a test
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Connection for DuplexStream {
+    fn try_clone_connection(&self) -> std::io::Result<Box<dyn Connection>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_idle_timeout(&self, _timeout: Option<Duration>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    // in-memory streams end when every clone is dropped
+    fn shutdown(&self) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+struct Harness {
+    server: Server,
+    events: broadcast::Receiver<ClientEvent>,
+}
+
+impl Harness {
+    fn start() -> Self {
+        let mut server = Server::default();
+        let events = server.events().subscribe();
+
+        server.run(vec![]);
+
+        Self { server, events }
+    }
+
+    /// connect a client that has not introduced itself yet
+    fn connect(&self) -> SimulatedClient {
+        self.connect_with(Faults::default())
+    }
+
+    /// connect a client whose end of the connection injects `faults`
+    fn connect_with(&self, faults: Faults) -> SimulatedClient {
+        let (client_end, server_end) = duplex::pair();
+
+        let network = NetworkInfo {
+            address: "memory".to_string(),
+            transport: Transport::Tcp,
+            connected_at: SystemTime::now(),
+        };
+
+        let id = self.server.connect(Box::new(server_end), network).unwrap();
+
+        SimulatedClient {
+            id,
+            stream: Chaos::new(client_end, faults, 0),
+        }
+    }
+
+    /// wait for the next event `matches` accepts, skipping any others
+    fn wait_for(&mut self, matches: impl Fn(&ClientEvent) -> bool) -> ClientEvent {
+        let deadline = Instant::now() + TIMEOUT;
+
+        loop {
+            match self.events.try_recv() {
+                Ok(event) if matches(&event) => return event,
+                Ok(_) | Err(TryRecvError::Lagged(_)) => {}
+                Err(TryRecvError::Empty) => {
+                    assert!(Instant::now() < deadline, "timed out waiting for event");
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(TryRecvError::Closed) => panic!("server stopped publishing events"),
+            }
+        }
+    }
+
+    /// wait until `condition` holds for the server
+    fn wait_until(&self, condition: impl Fn(&Server) -> bool) {
+        let deadline = Instant::now() + TIMEOUT;
+
+        while !condition(&self.server) {
+            assert!(Instant::now() < deadline, "timed out waiting for server");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// the client end of a connection, driven by the test
+struct SimulatedClient {
+    /// the id the server registered the connection under
+    id: Ulid,
+    stream: Chaos<DuplexStream>,
+}
+
+impl SimulatedClient {
+    fn send(&mut self, message: ServerMessage) {
+        self.try_send(message).unwrap();
+    }
+
+    fn try_send(&mut self, message: ServerMessage) -> Result<(), pdtcore::ProtocolError> {
+        Message::from(message).send(&mut self.stream)
+    }
+
+    fn receive(&mut self) -> ClientMessage {
+        match Message::receive(&mut self.stream).unwrap() {
+            Message::Client(message) => message,
+            message => panic!("client received {message:?}"),
+        }
+    }
+
+    /// introduce itself as `name` and answer the requests that follow
+    fn handshake(&mut self, name: &str) {
+        self.send(ServerMessage::Hello(Box::new(ClientIntroduction {
+            name: name.to_string(),
+            pdtcore_built_info: BuiltInfo::default(),
+            protocol: ProtocolConfig::default(),
+        })));
+
+        let ClientMessage::Welcome(welcome) = self.receive() else {
+            panic!("client was not welcomed");
+        };
+
+        assert_eq!(welcome.assigned_id, self.id.to_string());
+        assert_eq!(welcome.server.name, crate::state::DEFAULT_NAME);
+        assert_eq!(welcome.server.version, BuiltInfo::default().pkg_version);
+        assert!(welcome.server.up_since() <= SystemTime::now());
+
+        assert_eq!(self.receive(), ClientMessage::RequestDeviceInfo);
+        assert_eq!(self.receive(), ClientMessage::RequestTelemetry);
+
+        self.send(ServerMessage::DeviceInfo(DeviceInfo {
+            name: name.to_string(),
+            ..Default::default()
+        }));
+    }
+}
+
+#[test]
+fn clients_complete_handshake() {
+    let harness = Harness::start();
+
+    let mut clients: Vec<_> = (0..3).map(|_| harness.connect()).collect();
+
+    for (index, client) in clients.iter_mut().enumerate() {
+        client.handshake(&format!("client-{index}"));
+    }
+
+    harness.wait_until(|server| {
+        server
+            .get_clients()
+            .iter()
+            .filter(|client| client.device_info.name.starts_with("client-"))
+            .count()
+            == 3
+    });
+
+    let counts = harness.server.get_counts();
+
+    assert_eq!(counts.connected, 3);
+    assert_eq!(counts.connecting, 0);
+}
+
+#[test]
+fn commands_reach_client_and_results_come_back() {
+    let mut harness = Harness::start();
+    let mut client = harness.connect();
+    let id = client.id;
+
+    client.handshake("client");
+
+    harness
+        .server
+        .send_traced(id, ClientMessage::ScreenOff.into(), TraceContext::default())
+        .unwrap();
+
+    assert_eq!(client.receive(), ClientMessage::ScreenOff);
+
+    harness.wait_for(|event| {
+        matches!(event, ClientEvent::CommandResult(client_id, _, result)
+            if *client_id == id && result == "ScreenOff sent")
+    });
+
+    client.send(ServerMessage::Executed(CommandExecution {
+        trace: TraceContext::default(),
+        command: "ScreenOff".to_string(),
+        error: None,
+    }));
+
+    harness.wait_for(|event| {
+        matches!(event, ClientEvent::CommandResult(client_id, _, result)
+            if *client_id == id && result == "ScreenOff done")
+    });
+}
+
+#[test]
+fn goodbye_removes_client() {
+    let mut harness = Harness::start();
+    let mut client = harness.connect();
+    let id = client.id;
+
+    client.handshake("client");
+    client.send(ServerMessage::Goodbye);
+
+    harness.wait_for(|event| matches!(event, ClientEvent::Left(client_id) if *client_id == id));
+
+    assert!(harness.server.get_client(id).is_none());
+}
+
+#[test]
+fn dropped_connection_removes_client() {
+    let mut harness = Harness::start();
+    let mut client = harness.connect();
+    let id = client.id;
+
+    client.handshake("client");
+    drop(client);
+
+    harness.wait_for(|event| matches!(event, ClientEvent::Left(client_id) if *client_id == id));
+
+    assert!(harness.server.get_client(id).is_none());
+}
+
+#[test]
+fn duplicated_messages_are_handled() {
+    let harness = Harness::start();
+    let mut client = harness.connect_with(Faults {
+        duplicate: 1.0,
+        ..Faults::default()
+    });
+    let id = client.id;
+
+    client.handshake("client");
+
+    harness.wait_until(|server| {
+        server
+            .get_client(id)
+            .is_some_and(|client| client.device_info.name == "client")
+    });
+}
+
+#[test]
+fn disconnect_mid_message_leaves_server_serving_others() {
+    let mut harness = Harness::start();
+    let mut broken = harness.connect_with(Faults {
+        disconnect: 1.0,
+        ..Faults::default()
+    });
+    let broken_id = broken.id;
+
+    assert!(broken
+        .try_send(ServerMessage::Hello(Box::new(ClientIntroduction {
+            name: "broken".to_string(),
+            pdtcore_built_info: BuiltInfo::default(),
+            protocol: ProtocolConfig::default(),
+        })))
+        .is_err());
+
+    drop(broken);
+
+    harness
+        .wait_for(|event| matches!(event, ClientEvent::Left(client_id) if *client_id == broken_id));
+
+    let mut client = harness.connect();
+    let id = client.id;
+
+    client.handshake("client");
+
+    harness.wait_until(|server| {
+        server
+            .get_client(id)
+            .is_some_and(|client| client.device_info.name == "client")
+    });
+}
+
+#[test]
+fn stopped_server_says_goodbye_and_refuses_connections() {
+    let harness = Harness::start();
+    let mut client = harness.connect();
+
+    client.handshake("client");
+
+    harness.server.stop();
+
+    while client.receive() != ClientMessage::Goodbye {}
+
+    let (_client_end, server_end) = duplex::pair();
+
+    let network = NetworkInfo {
+        address: "memory".to_string(),
+        transport: Transport::Tcp,
+        connected_at: SystemTime::now(),
+    };
+
+    assert!(harness
+        .server
+        .connect(Box::new(server_end), network)
+        .is_err());
+}
+
+#[test]
+fn stopped_server_joins_once_clients_hang_up() {
+    let harness = Harness::start();
+    let mut client = harness.connect();
+
+    client.handshake("client");
+
+    harness.server.stop();
+
+    while client.receive() != ClientMessage::Goodbye {}
+
+    drop(client);
+
+    harness.server.join();
+}
+
+#[test]
+fn client_skipping_hello_is_dropped() {
+    let mut harness = Harness::start();
+    let mut client = harness.connect();
+    let id = client.id;
+
+    client.send(ServerMessage::DeviceInfo(DeviceInfo::default()));
+
+    harness.wait_for(|event| matches!(event, ClientEvent::Left(client_id) if *client_id == id));
+
+    assert_eq!(client.receive(), ClientMessage::Goodbye);
+}
@@ -0,0 +1,91 @@
+use std::{
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use axum::{extract::State, routing, Router};
+
+use crate::{AppError, AppStateReference};
+
+/// time the healthcheck waits for the web interface to connect and answer
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn router() -> Router<AppStateReference> {
+    Router::new()
+        .route("/healthz", routing::get(health))
+        .route("/readyz", routing::get(ready))
+}
+
+/// answers without authentication as long as the state can be locked and the
+/// server answers requests, a stuck server answers with an error
+async fn health(State(state): State<AppStateReference>) -> Result<&'static str, AppError> {
+    let server = state.server.clone();
+
+    server.counts().await?;
+
+    Ok("ok")
+}
+
+/// like the health check, failing as well while client messages are not
+/// handled, see [`crate::watchdog`]
+async fn ready(State(state): State<AppStateReference>) -> Result<&'static str, AppError> {
+    let server = state.server.clone();
+
+    if server.is_degraded() {
+        return Err(AppError::Degraded);
+    }
+
+    server.counts().await?;
+
+    Ok("ok")
+}
+
+/// ask the health endpoint of the web interface on this machine, for
+/// `pdtserver healthcheck`
+///
+/// the check speaks plain http, with tls configured only accepting the
+/// connection is checked
+pub fn check(address: SocketAddr, base_path: &str, tls: bool) -> Result<(), String> {
+    // a server listening on every address is asked on loopback
+    let address = match address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), address.port())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), address.port())
+        }
+        _ => address,
+    };
+
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)
+        .map_err(|error| format!("connecting to {address}: {error}"))?;
+
+    if tls {
+        return Ok(());
+    }
+
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+        .and_then(|_| {
+            write!(
+                stream,
+                "GET {base_path}/healthz HTTP/1.0\r\nHost: {address}\r\n\r\n"
+            )
+        })
+        .map_err(|error| format!("requesting health: {error}"))?;
+
+    let mut response = String::new();
+
+    stream
+        .read_to_string(&mut response)
+        .map_err(|error| format!("reading health: {error}"))?;
+
+    let status = response.lines().next().unwrap_or_default();
+
+    match status.split_whitespace().nth(1) {
+        Some("200") => Ok(()),
+        _ => Err(format!("unhealthy: {status}")),
+    }
+}
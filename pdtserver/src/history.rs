@@ -0,0 +1,226 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use blake2::{digest::consts::U32, Blake2b, Digest};
+use pdtapi::HistoryEntry;
+use ulid::Ulid;
+
+/// commands kept in memory, older ones are dropped
+const CAPACITY: usize = 1000;
+
+/// previous hash of the first command since the server started
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandOutcome {
+    Sent,
+    /// handing the command to the client connection failed
+    Failed,
+    /// the caller was not allowed to control the client
+    Denied,
+    /// the same command was sent to the client moments before, so this one
+    /// was not
+    Coalesced,
+    /// the command was armed to be sent to several clients, see
+    /// [`crate::arming`]
+    Armed,
+}
+
+impl Display for CommandOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandOutcome::Sent => write!(f, "sent"),
+            CommandOutcome::Failed => write!(f, "failed"),
+            CommandOutcome::Denied => write!(f, "denied"),
+            CommandOutcome::Coalesced => write!(f, "coalesced"),
+            CommandOutcome::Armed => write!(f, "armed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandRecord {
+    pub at: SystemTime,
+    pub client_id: Ulid,
+    pub device_name: String,
+    pub command: String,
+    /// user or access token name of the caller
+    pub actor: String,
+    pub outcome: CommandOutcome,
+}
+
+/// a command record linked to the one before it
+///
+/// every hash covers the previous hash, so editing, removing or reordering a
+/// record breaks the chain from there on
+#[derive(Debug, Clone)]
+pub struct ChainedRecord {
+    pub record: CommandRecord,
+    pub previous_hash: String,
+    pub hash: String,
+}
+
+/// commands sent from the web interface and api, oldest first
+#[derive(Debug, Default)]
+pub struct CommandHistory {
+    records: VecDeque<ChainedRecord>,
+}
+
+impl CommandHistory {
+    pub fn record(&mut self, record: CommandRecord) {
+        if self.records.len() == CAPACITY {
+            self.records.pop_front();
+        }
+
+        let previous_hash = self
+            .records
+            .back()
+            .map_or(GENESIS.to_string(), |previous| previous.hash.clone());
+
+        let mut chained = ChainedRecord {
+            record,
+            previous_hash,
+            hash: String::new(),
+        };
+
+        chained.hash = chain_hash(&HistoryEntry::from(chained.clone()));
+
+        self.records.push_back(chained);
+    }
+
+    pub fn records(&self) -> Vec<ChainedRecord> {
+        self.records.iter().cloned().collect()
+    }
+
+    /// whether `command` was sent to the client no more than `window` before
+    /// `at`
+    pub fn sent_within(
+        &self,
+        client_id: Ulid,
+        command: &str,
+        at: SystemTime,
+        window: Duration,
+    ) -> bool {
+        self.records
+            .iter()
+            .rev()
+            .take_while(|chained| {
+                at.duration_since(chained.record.at)
+                    .map_or(true, |age| age <= window)
+            })
+            .any(|chained| {
+                chained.record.client_id == client_id
+                    && chained.record.command == command
+                    && chained.record.outcome == CommandOutcome::Sent
+            })
+    }
+
+    /// the hash belongs to a record still kept in memory
+    pub fn contains(&self, hash: &str) -> bool {
+        self.records.iter().any(|record| record.hash == hash)
+    }
+}
+
+/// blake2b-256 of the previous hash and every field of the entry, hex encoded
+///
+/// fields are length prefixed so content can not move between them
+pub fn chain_hash(entry: &HistoryEntry) -> String {
+    let mut hasher = Blake2b::<U32>::new();
+
+    let timestamp = entry.timestamp.to_le_bytes();
+
+    for field in [
+        entry.previous_hash.as_bytes(),
+        timestamp.as_slice(),
+        entry.client_id.as_bytes(),
+        entry.device_name.as_bytes(),
+        entry.command.as_bytes(),
+        entry.actor.as_bytes(),
+        entry.outcome.as_bytes(),
+    ] {
+        hasher.update((field.len() as u64).to_le_bytes());
+        hasher.update(field);
+    }
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// index of the first entry that does not follow from the one before it or
+/// whose hash does not match its content
+pub fn first_broken_link(entries: &[HistoryEntry]) -> Option<usize> {
+    (0..entries.len()).find(|&index| {
+        let entry = &entries[index];
+        let linked = index == 0 || entries[index - 1].hash == entry.previous_hash;
+
+        !linked || chain_hash(entry) != entry.hash
+    })
+}
+
+impl From<ChainedRecord> for HistoryEntry {
+    fn from(value: ChainedRecord) -> Self {
+        let record = value.record;
+
+        Self {
+            timestamp: record
+                .at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            client_id: record.client_id.to_string(),
+            device_name: record.device_name,
+            command: record.command,
+            actor: record.actor,
+            outcome: record.outcome.to_string(),
+            previous_hash: value.previous_hash,
+            hash: value.hash,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(
+        client_id: Ulid,
+        command: &str,
+        at: SystemTime,
+        outcome: CommandOutcome,
+    ) -> CommandRecord {
+        CommandRecord {
+            at,
+            client_id,
+            device_name: "kitchen".to_string(),
+            command: command.to_string(),
+            actor: "admin".to_string(),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn only_commands_sent_within_the_window_count() {
+        let client_id = Ulid::new();
+        let at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let window = Duration::from_secs(5);
+
+        let mut history = CommandHistory::default();
+        history.record(record(client_id, "ScreenOff", at, CommandOutcome::Sent));
+        history.record(record(client_id, "PowerOff", at, CommandOutcome::Denied));
+
+        let soon = at + Duration::from_secs(3);
+        let later = at + Duration::from_secs(10);
+
+        assert!(history.sent_within(client_id, "ScreenOff", soon, window));
+        assert!(!history.sent_within(client_id, "ScreenOff", later, window));
+        assert!(!history.sent_within(client_id, "ScreenOn", soon, window));
+        assert!(!history.sent_within(client_id, "PowerOff", soon, window));
+        assert!(!history.sent_within(Ulid::new(), "ScreenOff", soon, window));
+    }
+}
@@ -0,0 +1,226 @@
+//! round trip times of the telemetry requests connected clients get every
+//! tick, kept per client to tell a device on flaky wifi from a dead one
+//!
+//! a request still unanswered when the next one goes out counts as lost
+
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    time::{Duration, SystemTime},
+};
+
+/// requests kept per client, ten minutes at the default interval
+const WINDOW: usize = 60;
+
+/// share of lost requests above which a link is flaky or poor
+const FLAKY_LOSS: f64 = 0.05;
+const POOR_LOSS: f64 = 0.25;
+
+/// 95th percentile round trips above which a link is flaky or poor
+const FLAKY_ROUND_TRIP: Duration = Duration::from_millis(250);
+const POOR_ROUND_TRIP: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default)]
+pub struct Latency {
+    /// when the request not yet answered went out
+    pending: Option<SystemTime>,
+    /// round trip of every request in the window, oldest first, none for
+    /// the lost ones
+    round_trips: VecDeque<Option<Duration>>,
+}
+
+impl Latency {
+    pub fn requested(&mut self, at: SystemTime) {
+        if self.pending.replace(at).is_some() {
+            self.push(None);
+        }
+    }
+
+    pub fn answered(&mut self, at: SystemTime) {
+        if let Some(requested) = self.pending.take() {
+            self.push(Some(at.duration_since(requested).unwrap_or_default()));
+        }
+    }
+
+    fn push(&mut self, round_trip: Option<Duration>) {
+        if self.round_trips.len() == WINDOW {
+            self.round_trips.pop_front();
+        }
+
+        self.round_trips.push_back(round_trip);
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        let mut answered: Vec<_> = self.round_trips.iter().flatten().copied().collect();
+        answered.sort();
+
+        let requests = self.round_trips.len();
+        let lost = requests - answered.len();
+
+        let average = (!answered.is_empty())
+            .then(|| answered.iter().sum::<Duration>() / answered.len() as u32);
+
+        // nearest rank
+        let p95 = (answered.len() * 95).div_ceil(100).checked_sub(1);
+
+        LatencyStats {
+            average,
+            p95: p95.map(|rank| answered[rank]),
+            loss: if requests == 0 {
+                0.0
+            } else {
+                lost as f64 / requests as f64
+            },
+            requests,
+        }
+    }
+}
+
+/// round trips of the requests in the window of a client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyStats {
+    /// of the answered requests, none when none were
+    pub average: Option<Duration>,
+    pub p95: Option<Duration>,
+    /// share of the requests that went unanswered
+    pub loss: f64,
+    pub requests: usize,
+}
+
+impl LatencyStats {
+    pub fn quality(&self) -> Quality {
+        let p95 = self.p95.unwrap_or(Duration::MAX);
+
+        if self.requests == 0 {
+            Quality::Unknown
+        } else if self.loss > POOR_LOSS || p95 > POOR_ROUND_TRIP {
+            Quality::Poor
+        } else if self.loss > FLAKY_LOSS || p95 > FLAKY_ROUND_TRIP {
+            Quality::Flaky
+        } else {
+            Quality::Good
+        }
+    }
+
+    /// like `12 ms average, 30 ms p95, 5% of 60 requests lost`
+    pub fn summary(&self) -> String {
+        let mut parts = vec![];
+
+        if let (Some(average), Some(p95)) = (self.average, self.p95) {
+            parts.push(format!(
+                "{} ms average, {} ms p95",
+                average.as_millis(),
+                p95.as_millis()
+            ));
+        }
+
+        parts.push(format!(
+            "{:.0}% of {} requests lost",
+            self.loss * 100.0,
+            self.requests
+        ));
+
+        parts.join(", ")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    /// no request went out yet
+    Unknown,
+    Good,
+    /// answers are slow or some go missing
+    Flaky,
+    /// most answers are very slow or go missing
+    Poor,
+}
+
+impl Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quality::Unknown => write!(f, "unknown"),
+            Quality::Good => write!(f, "good"),
+            Quality::Flaky => write!(f, "flaky"),
+            Quality::Poor => write!(f, "poor"),
+        }
+    }
+}
+
+impl From<Quality> for pdtapi::ConnectionQuality {
+    fn from(value: Quality) -> Self {
+        match value {
+            Quality::Unknown => pdtapi::ConnectionQuality::Unknown,
+            Quality::Good => pdtapi::ConnectionQuality::Good,
+            Quality::Flaky => pdtapi::ConnectionQuality::Flaky,
+            Quality::Poor => pdtapi::ConnectionQuality::Poor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    fn at(millis: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn quality_is_unknown_before_any_request() {
+        assert_eq!(Latency::default().stats().quality(), Quality::Unknown);
+    }
+
+    #[test]
+    fn quick_answers_are_good() {
+        let mut latency = Latency::default();
+
+        for tick in 0..20 {
+            latency.requested(at(tick * 10_000));
+            latency.answered(at(tick * 10_000 + 20 + tick));
+        }
+
+        let stats = latency.stats();
+
+        assert_eq!(stats.requests, 20);
+        assert_eq!(stats.loss, 0.0);
+        assert_eq!(stats.p95, Some(Duration::from_millis(38)));
+        assert_eq!(stats.quality(), Quality::Good);
+    }
+
+    #[test]
+    fn unanswered_requests_count_as_lost() {
+        let mut latency = Latency::default();
+
+        for tick in 0..10 {
+            latency.requested(at(tick * 10_000));
+
+            if tick % 5 != 0 {
+                latency.answered(at(tick * 10_000 + 30));
+            }
+        }
+
+        let stats = latency.stats();
+
+        assert_eq!(stats.requests, 10);
+        assert_eq!(stats.loss, 0.2);
+        assert_eq!(stats.average, Some(Duration::from_millis(30)));
+        assert_eq!(stats.quality(), Quality::Flaky);
+    }
+
+    #[test]
+    fn a_silent_client_is_poor() {
+        let mut latency = Latency::default();
+
+        for tick in 0..5 {
+            latency.requested(at(tick * 10_000));
+        }
+
+        let stats = latency.stats();
+
+        assert_eq!(stats.loss, 1.0);
+        assert_eq!(stats.average, None);
+        assert_eq!(stats.quality(), Quality::Poor);
+    }
+}
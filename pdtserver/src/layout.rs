@@ -0,0 +1,265 @@
+//! favorite devices and the order of the dashboard client list, arranged by
+//! every user for themselves and kept in a toml file of their own, see
+//! [`store`], so the machines someone controls daily stay on top across
+//! sessions and restarts
+//!
+//! favorites come first whatever the sort, the rest follow the arranged
+//! order when sorting by join order, with devices never arranged after those
+//! that were. without users or tokens everyone shares one layout
+
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    extract::{Path as UrlPath, State},
+    response::{IntoResponse, Response},
+    routing, Form, Router,
+};
+use pdtcore::Client;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+use ulid::Ulid;
+
+use crate::{auth::Access, store, visible_device, AppError, AppStateReference};
+
+/// event the dashboard fetches the client list again on
+const CHANGED: &str = "layout-changed";
+
+/// how a user arranged the client list, by device name
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Layout {
+    /// pinned to the top of the list
+    pub favorites: Vec<String>,
+    /// in the order they were dragged into
+    pub order: Vec<String>,
+}
+
+impl Layout {
+    pub fn is_favorite(&self, device_name: &str) -> bool {
+        self.favorites.iter().any(|name| name == device_name)
+    }
+
+    pub fn toggle_favorite(&mut self, device_name: &str) {
+        match self.is_favorite(device_name) {
+            true => self.favorites.retain(|name| name != device_name),
+            false => self.favorites.push(device_name.to_string()),
+        }
+    }
+
+    /// move the devices of `arranged` into its order, in the places they
+    /// took among the devices arranged before, so arranging a filtered or
+    /// paginated list leaves the other devices where they were
+    pub fn reorder(&mut self, arranged: &[String]) {
+        let mut moved: Vec<&String> = vec![];
+
+        for name in arranged {
+            if !moved.contains(&name) {
+                moved.push(name);
+            }
+        }
+
+        for name in &moved {
+            if !self.order.contains(*name) {
+                self.order.push(name.to_string());
+            }
+        }
+
+        let mut moved_in_order = moved.iter();
+
+        for slot in self.order.iter_mut() {
+            if moved.contains(&&*slot) {
+                if let Some(name) = moved_in_order.next() {
+                    *slot = name.to_string();
+                }
+            }
+        }
+    }
+
+    /// clients in the arranged order, the others after them as they were
+    pub fn arrange(&self, clients: &mut [Client]) {
+        let positions: HashMap<&str, usize> = self
+            .order
+            .iter()
+            .enumerate()
+            .map(|(position, name)| (name.as_str(), position))
+            .collect();
+
+        clients.sort_by_key(|client| {
+            positions
+                .get(client.device_info.name.as_str())
+                .copied()
+                .unwrap_or(usize::MAX)
+        });
+    }
+
+    /// favorites first, in the same order otherwise
+    pub fn pin(&self, clients: &mut [Client]) {
+        clients.sort_by_key(|client| !self.is_favorite(&client.device_info.name));
+    }
+}
+
+/// layouts by user or access token, see [`Access::actor`]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct Layouts {
+    layouts: BTreeMap<String, Layout>,
+}
+
+impl Layouts {
+    /// the default layout when the user never arranged the list
+    pub fn get(&self, actor: &str) -> Layout {
+        self.layouts.get(actor).cloned().unwrap_or_default()
+    }
+
+    /// change the layout of a user
+    pub fn update(&mut self, actor: &str, change: impl FnOnce(&mut Layout)) {
+        change(self.layouts.entry(actor.to_string()).or_default());
+    }
+}
+
+pub fn router() -> Router<AppStateReference> {
+    Router::new()
+        .route("/clients/:client_id/favorite", routing::post(favorite))
+        .route("/layout/order", routing::post(order))
+}
+
+/// pin a client to the top of the list of the caller, or unpin it
+#[instrument(skip(state, access))]
+async fn favorite(
+    UrlPath(client_id): UrlPath<Ulid>,
+    State(state): State<AppStateReference>,
+    access: Access,
+) -> Result<Response, AppError> {
+    access.check_csrf()?;
+
+    let server = state.server.clone();
+
+    let device_name = visible_device(&server, &access, client_id).await?;
+
+    state.web.lock().layouts.update(&access.actor(), |layout| {
+        layout.toggle_favorite(&device_name)
+    });
+
+    store::save(&state, |web| &web.layouts)
+        .await
+        .map_err(AppError::Layout)?;
+
+    Ok([("hx-trigger", CHANGED)].into_response())
+}
+
+/// arrange the `device` fields of the form in their order, as dragged on
+/// the dashboard
+#[instrument(skip_all)]
+async fn order(
+    State(state): State<AppStateReference>,
+    access: Access,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Result<Response, AppError> {
+    access.check_csrf()?;
+
+    let arranged: Vec<String> = form
+        .into_iter()
+        .filter(|(name, device_name)| name == "device" && access.may_see(device_name))
+        .map(|(_, device_name)| device_name)
+        .collect();
+
+    state
+        .web
+        .lock()
+        .layouts
+        .update(&access.actor(), |layout| layout.reorder(&arranged));
+
+    store::save(&state, |web| &web.layouts)
+        .await
+        .map_err(AppError::Layout)?;
+
+    Ok(().into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use pdtcore::{ConnectionState, DeviceInfo, NetworkInfo, Transport};
+
+    use super::*;
+
+    fn client(name: &str) -> Client {
+        Client {
+            id: "01H0000000000000000000000".to_string(),
+            state: ConnectionState::Connected,
+            device_info: DeviceInfo {
+                name: name.to_string(),
+                ..Default::default()
+            },
+            last_seen: SystemTime::UNIX_EPOCH,
+            network: NetworkInfo {
+                address: "192.0.2.1:50000".to_string(),
+                transport: Transport::Tcp,
+                connected_at: SystemTime::UNIX_EPOCH,
+            },
+            protocol_version: None,
+            identity: None,
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn reordering_part_of_the_list_keeps_the_rest_in_place() {
+        let mut layout = Layout {
+            order: names(&["nas", "kiosk", "tv", "laptop"]),
+            ..Layout::default()
+        };
+
+        layout.reorder(&names(&["laptop", "nas", "printer"]));
+
+        assert_eq!(
+            layout.order,
+            names(&["laptop", "kiosk", "tv", "nas", "printer"])
+        );
+    }
+
+    #[test]
+    fn favorites_come_first_then_the_arranged_order() {
+        let mut layout = Layout {
+            order: names(&["tv", "nas"]),
+            ..Layout::default()
+        };
+
+        layout.toggle_favorite("kiosk");
+        layout.toggle_favorite("laptop");
+        layout.toggle_favorite("laptop");
+
+        let mut clients: Vec<Client> = ["laptop", "nas", "kiosk", "tv"]
+            .into_iter()
+            .map(client)
+            .collect();
+
+        layout.arrange(&mut clients);
+        layout.pin(&mut clients);
+
+        let arranged: Vec<&str> = clients
+            .iter()
+            .map(|client| client.device_info.name.as_str())
+            .collect();
+
+        assert_eq!(arranged, ["kiosk", "tv", "nas", "laptop"]);
+    }
+
+    #[test]
+    fn every_user_arranges_their_own_list() {
+        let mut layouts = Layouts::default();
+
+        layouts.update("alice", |layout| layout.toggle_favorite("kiosk"));
+        layouts.update("token:wall", |layout| {
+            layout.reorder(&names(&["tv", "nas"]))
+        });
+
+        assert!(layouts.get("alice").is_favorite("kiosk"));
+        assert_eq!(layouts.get("token:wall").order, names(&["tv", "nas"]));
+        assert_eq!(layouts.get("bob"), Layout::default());
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::*;
+
+use crate::{proxy::Forwarded, AppError, AppStateReference};
+
+/// largest request body accepted, forms and api requests are far smaller
+pub const BODY_LIMIT: usize = 64 * 1024;
+/// time a handler gets to read the request and respond
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// time a client gets to send the request headers
+pub const HEADER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// requests a client may make in a burst
+const BURST: f64 = 60.0;
+/// requests per second a client is refilled with
+const RATE: f64 = 10.0;
+/// clients tracked before idle ones are forgotten, or the least recently
+/// seen one while this many are active
+const TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * RATE).min(BURST);
+        self.updated = now;
+    }
+
+    /// whether the bucket would be full by `now`, so forgetting it changes
+    /// nothing
+    fn idle(&self, now: Instant) -> bool {
+        self.tokens + now.duration_since(self.updated).as_secs_f64() * RATE >= BURST
+    }
+}
+
+/// what a client is limited by, its address or for ipv6 the /64 it is in,
+/// which a single host usually has to itself
+fn key(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V4(_) => client,
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(address) => address.into(),
+            None => Ipv6Addr::from(u128::from(address) & !(u64::MAX as u128)).into(),
+        },
+    }
+}
+
+/// token bucket per client address
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+impl RateLimiter {
+    /// take a token for `client`, false when it has none left
+    fn allow(&mut self, client: IpAddr, now: Instant) -> bool {
+        let client = key(client);
+
+        if self.buckets.len() >= TRACKED_CLIENTS && !self.buckets.contains_key(&client) {
+            self.buckets.retain(|_, bucket| !bucket.idle(now));
+
+            // forgetting an active client hands it a fresh burst, which
+            // beats refusing everyone new
+            if self.buckets.len() >= TRACKED_CLIENTS {
+                let least_recent = self
+                    .buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(address, _)| *address);
+
+                if let Some(address) = least_recent {
+                    debug!(client =? address, "too many clients to rate limit, forgetting");
+                    self.buckets.remove(&address);
+                }
+            }
+        }
+
+        let bucket = self.buckets.entry(client).or_insert(Bucket {
+            tokens: BURST,
+            updated: now,
+        });
+
+        bucket.refill(now);
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+/// middleware rejecting clients making too many requests
+pub async fn rate_limit(
+    State(state): State<AppStateReference>,
+    forwarded: Forwarded,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let Some(client) = forwarded.client else {
+        return next.run(request).await;
+    };
+
+    let allowed = state.web.lock().rate_limiter.allow(client, Instant::now());
+
+    if !allowed {
+        debug!(client =? client, "rate limited");
+        return AppError::TooManyRequests.into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(number: usize) -> IpAddr {
+        Ipv6Addr::from((number as u128) << 64).into()
+    }
+
+    #[test]
+    fn the_least_recent_client_is_forgotten_while_every_tracked_one_is_active() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for number in 0..TRACKED_CLIENTS {
+            assert!(limiter.allow(client(number), now + Duration::from_micros(number as u64)));
+        }
+
+        let later = now + Duration::from_millis(20);
+
+        assert!(limiter.allow(client(TRACKED_CLIENTS), later));
+        assert_eq!(limiter.buckets.len(), TRACKED_CLIENTS);
+        assert!(!limiter.buckets.contains_key(&client(0)));
+        assert!(limiter.buckets.contains_key(&client(1)));
+
+        let idle = now + Duration::from_secs(10);
+
+        assert!(limiter.allow(client(0), idle));
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+
+    #[test]
+    fn addresses_of_an_ipv6_network_share_a_bucket() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        let network = u128::from(Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 0));
+
+        for host in 0..BURST as u128 {
+            assert!(limiter.allow(Ipv6Addr::from(network | host).into(), now));
+        }
+
+        assert!(!limiter.allow(Ipv6Addr::from(network | 0xffff).into(), now));
+        assert!(limiter.allow("2001:db8:0:2::1".parse().unwrap(), now));
+
+        let mapped: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+
+        assert_eq!(key(mapped), "192.0.2.1".parse::<IpAddr>().unwrap());
+    }
+}
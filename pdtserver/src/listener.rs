@@ -0,0 +1,347 @@
+use std::{
+    fmt::Debug,
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    os::{
+        fd::OwnedFd,
+        unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use pdtcore::{NetworkInfo, Transport as ClientTransport};
+use socket2::{Domain, Socket, Type};
+use tracing::*;
+
+/// pause after a failed accept, which mostly fails for running out of file
+/// descriptors and would fail again right away
+pub const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// stream a client is connected through, independent of transport
+pub trait Connection: Read + Write + Send + Debug {
+    fn try_clone_connection(&self) -> std::io::Result<Box<dyn Connection>>;
+
+    /// fail reads and writes that make no progress for `timeout`, for clones
+    /// of the connection too
+    fn set_idle_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+
+    /// hang up, for clones of the connection too
+    fn shutdown(&self) -> std::io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn try_clone_connection(&self) -> std::io::Result<Box<dyn Connection>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn set_idle_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+
+    fn shutdown(&self) -> std::io::Result<()> {
+        TcpStream::shutdown(self, std::net::Shutdown::Both)
+    }
+}
+
+impl Connection for UnixStream {
+    fn try_clone_connection(&self) -> std::io::Result<Box<dyn Connection>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn set_idle_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+
+    fn shutdown(&self) -> std::io::Result<()> {
+        UnixStream::shutdown(self, std::net::Shutdown::Both)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl ListenAddress {
+    /// connect and hang up right away, waking a thread waiting to accept
+    pub fn poke(&self) -> std::io::Result<()> {
+        match self {
+            ListenAddress::Tcp(address) => TcpStream::connect(address).map(drop),
+            ListenAddress::Unix(path) => UnixStream::connect(path).map(drop),
+        }
+    }
+}
+
+/// a single listen address and its settings
+///
+/// parsed from `address[;option...]` where address is a socket address or
+/// `unix:<path>`, and options are `name=<name>`, `v6only` or `mode=<octal>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerConfig {
+    pub name: String,
+    pub address: ListenAddress,
+    /// only accept IPv6 on IPv6 addresses, allows binding IPv4 separately
+    pub v6only: bool,
+    /// file permissions for unix sockets
+    pub mode: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum ListenerConfigError {
+    Address(String),
+    Option(String),
+}
+
+impl std::fmt::Display for ListenerConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenerConfigError::Address(address) => write!(f, "invalid address {address:?}"),
+            ListenerConfigError::Option(option) => write!(f, "invalid option {option:?}"),
+        }
+    }
+}
+
+impl From<SocketAddr> for ListenerConfig {
+    fn from(value: SocketAddr) -> Self {
+        Self {
+            name: value.to_string(),
+            address: ListenAddress::Tcp(value),
+            v6only: false,
+            mode: None,
+        }
+    }
+}
+
+impl FromStr for ListenerConfig {
+    type Err = ListenerConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split(';');
+
+        let address = parts.next().unwrap_or_default();
+
+        let mut config = match address.strip_prefix("unix:") {
+            Some(path) if !path.is_empty() => Self {
+                name: address.to_string(),
+                address: ListenAddress::Unix(PathBuf::from(path)),
+                v6only: false,
+                mode: None,
+            },
+            Some(_) => return Err(ListenerConfigError::Address(address.to_string())),
+            None => SocketAddr::from_str(address)
+                .map_err(|_| ListenerConfigError::Address(address.to_string()))?
+                .into(),
+        };
+
+        for option in parts {
+            match option.split_once('=') {
+                None if option == "v6only" => config.v6only = true,
+                Some(("name", name)) => config.name = name.to_string(),
+                Some(("mode", mode)) => {
+                    config.mode = Some(
+                        u32::from_str_radix(mode, 8)
+                            .map_err(|_| ListenerConfigError::Option(option.to_string()))?,
+                    )
+                }
+                _ => return Err(ListenerConfigError::Option(option.to_string())),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl ListenerConfig {
+    /// parse a comma separated list of listener configurations
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, ListenerConfigError> {
+        s.split(',')
+            .filter(|part| !part.trim().is_empty())
+            .map(Self::from_str)
+            .collect()
+    }
+
+    pub fn bind(&self) -> std::io::Result<Listener> {
+        let transport = match &self.address {
+            ListenAddress::Tcp(address) => {
+                let socket = Socket::new(
+                    Domain::for_address(*address),
+                    Type::STREAM,
+                    Some(socket2::Protocol::TCP),
+                )?;
+
+                if address.is_ipv6() {
+                    socket.set_only_v6(self.v6only)?;
+                }
+
+                socket.set_reuse_address(true)?;
+                socket.bind(&(*address).into())?;
+                socket.listen(128)?;
+
+                Transport::Tcp(socket.into())
+            }
+            ListenAddress::Unix(path) => {
+                // only a socket left behind is replaced, never a file that
+                // happens to be at the path
+                match std::fs::symlink_metadata(path) {
+                    Ok(metadata) if metadata.file_type().is_socket() => {
+                        warn!(path =? path, "removing stale unix socket");
+                        std::fs::remove_file(path)?;
+                    }
+                    Ok(_) => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::AlreadyExists,
+                            format!("{} exists and is not a socket", path.display()),
+                        ))
+                    }
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(error) => return Err(error),
+                }
+
+                let listener = UnixListener::bind(path)?;
+
+                if let Some(mode) = self.mode {
+                    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+                }
+
+                Transport::Unix(listener)
+            }
+        };
+
+        Ok(Listener {
+            name: self.name.clone(),
+            transport,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Transport {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// bound listener accepting pdt client connections
+#[derive(Debug)]
+pub struct Listener {
+    pub name: String,
+    transport: Transport,
+}
+
+impl Listener {
+    /// take over a socket that is already bound and listening
+    pub fn from_fd(name: String, fd: OwnedFd) -> std::io::Result<Self> {
+        let socket = Socket::from(fd);
+
+        socket.set_nonblocking(false)?;
+
+        let transport = match socket.local_addr()?.as_socket() {
+            Some(_) => Transport::Tcp(socket.into()),
+            None => Transport::Unix(UnixListener::from(OwnedFd::from(socket))),
+        };
+
+        Ok(Self { name, transport })
+    }
+
+    /// where to connect to reach this listener, one listening on every
+    /// address is reached on loopback
+    pub fn local_address(&self) -> std::io::Result<ListenAddress> {
+        match &self.transport {
+            Transport::Tcp(listener) => {
+                let mut address = listener.local_addr()?;
+
+                match address.ip() {
+                    IpAddr::V4(ip) if ip.is_unspecified() => {
+                        address.set_ip(Ipv4Addr::LOCALHOST.into())
+                    }
+                    IpAddr::V6(ip) if ip.is_unspecified() => {
+                        address.set_ip(Ipv6Addr::LOCALHOST.into())
+                    }
+                    _ => {}
+                }
+
+                Ok(ListenAddress::Tcp(address))
+            }
+            Transport::Unix(listener) => listener
+                .local_addr()?
+                .as_pathname()
+                .map(|path| ListenAddress::Unix(path.to_path_buf()))
+                .ok_or_else(|| std::io::Error::other("unix socket has no path")),
+        }
+    }
+
+    /// wait for the next connection, along with where it came from
+    pub fn accept(&self) -> std::io::Result<(Box<dyn Connection>, NetworkInfo)> {
+        let connected_at = SystemTime::now();
+
+        match &self.transport {
+            Transport::Tcp(listener) => {
+                let (stream, address) = listener.accept()?;
+
+                let network = NetworkInfo {
+                    address: address.to_string(),
+                    transport: ClientTransport::Tcp,
+                    connected_at,
+                };
+
+                Ok((Box::new(stream), network))
+            }
+            Transport::Unix(listener) => {
+                let network = NetworkInfo {
+                    address: self.name.clone(),
+                    transport: ClientTransport::Unix,
+                    connected_at,
+                };
+
+                Ok((Box::new(listener.accept()?.0), network))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poking_wakes_a_waiting_accept() {
+        let listener = ListenerConfig::from_str("0.0.0.0:0")
+            .unwrap()
+            .bind()
+            .unwrap();
+        let address = listener.local_address().unwrap();
+
+        let accepting = std::thread::spawn(move || listener.accept().map(drop));
+
+        address.poke().unwrap();
+
+        assert!(accepting.join().unwrap().is_ok());
+    }
+    #[test]
+    fn only_stale_sockets_are_replaced() {
+        let path = std::env::temp_dir().join(format!("pdtserver-{}.sock", std::process::id()));
+        let config = ListenerConfig::from_str(&format!("unix:{}", path.display())).unwrap();
+
+        std::fs::write(&path, "not a socket").unwrap();
+
+        let error = config.bind().unwrap_err();
+
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+
+        std::fs::remove_file(&path).unwrap();
+
+        drop(config.bind().unwrap());
+
+        assert!(config.bind().is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
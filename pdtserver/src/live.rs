@@ -0,0 +1,169 @@
+use std::{convert::Infallible, str::FromStr};
+
+use askama::Template;
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
+    },
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tracing::*;
+
+use crate::{
+    approval::Approval, auth::Access, filters, server::ClientEvent, AppError, AppStateReference,
+};
+
+/// how the dashboard receives client events
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LiveUpdates {
+    #[default]
+    WebSocket,
+    ServerSentEvents,
+}
+
+impl FromStr for LiveUpdates {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "websocket" | "ws" => Ok(LiveUpdates::WebSocket),
+            "sse" => Ok(LiveUpdates::ServerSentEvents),
+            _ => Err(()),
+        }
+    }
+}
+
+/// out of band htmx swap patching the client list for a single event
+#[derive(Template)]
+#[template(path = "client_event.html")]
+struct ClientEventTemplate<'a> {
+    event: ClientEvent,
+    base_path: &'a str,
+    /// subscriber the event is rendered for
+    access: &'a Access,
+    approval: Approval,
+}
+
+/// server sent event name for a client event
+fn event_name(event: &ClientEvent) -> &'static str {
+    match event {
+        ClientEvent::Joined(_) => "client-connected",
+        ClientEvent::Updated(_) => "client-updated",
+        ClientEvent::Left(_) => "client-disconnected",
+        ClientEvent::Heartbeat(_) => "client-heartbeat",
+        ClientEvent::CommandResult(..) => "command-result",
+    }
+}
+
+fn render(
+    event: ClientEvent,
+    base_path: &str,
+    access: &Access,
+    state: &AppStateReference,
+) -> Option<String> {
+    if !access.may_see_event(&event) {
+        return None;
+    }
+
+    let approval = Approval::of(state);
+
+    let template = ClientEventTemplate {
+        event,
+        base_path,
+        access,
+        approval,
+    };
+
+    match template.render() {
+        Ok(html) => Some(html),
+        Err(error) => {
+            error!(error =? error, "rendering client event");
+            None
+        }
+    }
+}
+
+/// client events along with the base path to render them with
+fn subscribe(
+    state: &AppStateReference,
+) -> Result<(broadcast::Receiver<ClientEvent>, String), AppError> {
+    Ok((state.server.subscribe(), state.base_path.clone()))
+}
+
+pub async fn websocket(
+    ws: WebSocketUpgrade,
+    State(state): State<AppStateReference>,
+    access: Access,
+) -> Result<Response, AppError> {
+    let (events, base_path) = subscribe(&state)?;
+
+    Ok(ws.on_upgrade(|socket| forward_events(socket, events, base_path, access, state)))
+}
+
+pub async fn server_sent_events(
+    State(state): State<AppStateReference>,
+    access: Access,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let (events, base_path) = subscribe(&state)?;
+
+    let stream = BroadcastStream::new(events).filter_map(move |event| {
+        let event = match event {
+            Ok(event) => event,
+            Err(error) => {
+                warn!(error =? error, "event stream subscriber lagging behind");
+                return None;
+            }
+        };
+
+        let name = event_name(&event);
+
+        render(event, &base_path, &access, &state)
+            .map(|html| Ok(Event::default().event(name).data(html)))
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[instrument(skip_all)]
+async fn forward_events(
+    mut socket: WebSocket,
+    mut events: broadcast::Receiver<ClientEvent>,
+    base_path: String,
+    access: Access,
+    state: AppStateReference,
+) {
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!(skipped = skipped, "websocket subscriber lagging behind");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let Some(html) = render(event, &base_path, &access, &state) else {
+                    continue;
+                };
+
+                if socket.send(WsMessage::Text(html)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    trace!("websocket closed");
+}
@@ -0,0 +1,252 @@
+//! recent log events of the server itself, kept in memory behind the
+//! tracing subscriber and tailed live on the log page, so connection
+//! problems can be looked into without a shell on the server
+
+use std::{collections::VecDeque, convert::Infallible, fmt::Debug, sync::Arc, time::SystemTime};
+
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Redirect, Response,
+    },
+};
+use parking_lot::Mutex;
+use pdtcore::Particularity;
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tracing::{Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+use crate::{auth::Access, AppError, AppStateReference, SCRIPT, SSE_EXTENSION, STYLE};
+
+/// events kept in memory, older ones are dropped
+const CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEvent {
+    pub at: SystemTime,
+    pub level: Level,
+    pub target: String,
+    /// the message followed by the other fields as `name=value`
+    pub message: String,
+}
+
+impl LogEvent {
+    pub fn time(&self) -> String {
+        humantime::format_rfc3339_seconds(self.at).to_string()
+    }
+}
+
+/// the most recent log events and every new one as it happens
+#[derive(Debug, Clone)]
+pub struct RecentLogs {
+    events: Particularity<VecDeque<LogEvent>>,
+    live: broadcast::Sender<LogEvent>,
+}
+
+impl Default for RecentLogs {
+    fn default() -> Self {
+        Self {
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(CAPACITY))),
+            live: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl RecentLogs {
+    fn push(&self, event: LogEvent) {
+        let mut events = self.events.lock();
+
+        if events.len() == CAPACITY {
+            events.pop_front();
+        }
+
+        events.push_back(event.clone());
+        drop(events);
+
+        // nobody tailing the log
+        let _ = self.live.send(event);
+    }
+
+    /// events matching `filter`, oldest first
+    pub fn matching(&self, filter: &LogFilter) -> Vec<LogEvent> {
+        self.events
+            .lock()
+            .iter()
+            .filter(|event| filter.matches(event))
+            .cloned()
+            .collect()
+    }
+}
+
+/// events pass through the filters of the subscriber first, so only what
+/// would be written to the log is kept
+impl<S: Subscriber> Layer<S> for RecentLogs {
+    fn on_event(&self, event: &tracing::Event<'_>, _context: Context<'_, S>) {
+        self.push(LogEvent {
+            at: SystemTime::now(),
+            level: *event.metadata().level(),
+            target: event.metadata().target().to_string(),
+            message: pdttracing::fields::line(event),
+        });
+    }
+}
+
+/// which events to show, everything when empty
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LogFilter {
+    /// least severe level shown, like `warn`
+    pub level: String,
+    /// part of the module path events come from, like `pdtserver::server`
+    pub target: String,
+}
+
+impl LogFilter {
+    /// whether `level` is the one picked
+    pub fn selected(&self, level: &str) -> bool {
+        self.level == level
+    }
+
+    fn matches(&self, event: &LogEvent) -> bool {
+        // more verbose levels compare greater
+        let severe_enough = self
+            .level
+            .parse::<Level>()
+            .map_or(true, |level| event.level <= level);
+
+        severe_enough && event.target.contains(&self.target)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "logs.html")]
+struct LogsTemplate {
+    base_path: String,
+    style: String,
+    script: String,
+    filter: LogFilter,
+    events: Vec<LogEvent>,
+}
+
+/// a row appended to the log page
+#[derive(Template)]
+#[template(path = "log_event.html")]
+struct LogEventTemplate {
+    event: LogEvent,
+}
+
+fn admin(access: &Access) -> Result<(), AppError> {
+    match access.is_admin() {
+        true => Ok(()),
+        false => Err(AppError::Forbidden),
+    }
+}
+
+/// recent log events, for admins only as they mention every client
+pub async fn page(
+    State(state): State<AppStateReference>,
+    Query(filter): Query<LogFilter>,
+    access: Result<Access, AppError>,
+) -> Result<Response, AppError> {
+    let (base_path, logs) = (state.base_path.clone(), state.logs.clone());
+
+    match access {
+        Ok(access) => admin(&access)?,
+        Err(AppError::Unauthorized) => {
+            return Ok(Redirect::to(&format!("{base_path}/login")).into_response())
+        }
+        Err(error) => return Err(error),
+    };
+
+    let template = LogsTemplate {
+        base_path,
+        style: STYLE.into(),
+        script: [SCRIPT, SSE_EXTENSION].join("\n"),
+        events: logs.matching(&filter),
+        filter,
+    };
+
+    Ok(template.into_response())
+}
+
+/// new log events matching the filter as they happen, rendered as rows
+pub async fn tail(
+    State(state): State<AppStateReference>,
+    Query(filter): Query<LogFilter>,
+    access: Access,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    admin(&access)?;
+
+    let live = state.logs.live.subscribe();
+
+    // a lagging subscriber misses events rather than logging about it,
+    // which would only add more events to miss
+    let stream = BroadcastStream::new(live).filter_map(move |event| {
+        let event = event.ok().filter(|event| filter.matches(event))?;
+
+        LogEventTemplate { event }
+            .render()
+            .ok()
+            .map(|html| Ok(Event::default().event("log").data(html)))
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{debug, info, warn};
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    fn logged() -> RecentLogs {
+        let logs = RecentLogs::default();
+
+        let subscriber = tracing_subscriber::Registry::default().with(logs.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            info!(target: "pdtserver::server", client = 3, "connected");
+            warn!(target: "pdtserver::relay", address = "192.0.2.1:2041", "relay lost");
+            debug!(target: "pdtserver::server", "tick");
+        });
+
+        logs
+    }
+
+    #[test]
+    fn events_keep_their_fields() {
+        let events = logged().matching(&LogFilter::default());
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].level, Level::INFO);
+        assert_eq!(events[0].target, "pdtserver::server");
+        assert_eq!(events[0].message, "connected client=3");
+        assert_eq!(events[1].message, "relay lost address=192.0.2.1:2041");
+    }
+
+    #[test]
+    fn filters_pick_level_and_target() {
+        let logs = logged();
+
+        let at_least_info = logs.matching(&LogFilter {
+            level: "info".to_string(),
+            target: "::server".to_string(),
+        });
+
+        assert_eq!(at_least_info.len(), 1);
+        assert_eq!(at_least_info[0].message, "connected client=3");
+
+        let warnings = logs.matching(&LogFilter {
+            level: "warn".to_string(),
+            target: String::new(),
+        });
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].target, "pdtserver::relay");
+    }
+}
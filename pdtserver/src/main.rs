@@ -1,31 +1,150 @@
 use std::{
-    net::{SocketAddr, TcpListener},
+    net::{IpAddr, SocketAddr, TcpListener},
+    os::fd::{AsRawFd, OwnedFd},
+    path::PathBuf,
     str::FromStr,
-    sync::{Arc, Mutex, PoisonError},
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use askama::Template;
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Redirect, Response},
     routing, Router,
 };
+use axum_server::HttpConfig;
+use parking_lot::Mutex;
 
-use pdtcore::*;
+use opentelemetry_sdk::runtime;
+use pdtcore::{recording::Recorder, *};
+use pdttracing::{otel, TracingError};
+mod actions;
+mod activation;
+mod api;
+mod approval;
+mod arming;
+mod auth;
+mod automation;
+mod bandwidth;
+mod client_logs;
+mod cooldown;
+mod crashes;
+mod demo;
+mod export;
+mod federation;
+mod filters;
+mod fleet;
+mod graphql;
+mod grpc;
+mod handle;
+#[cfg(test)]
+mod harness;
+mod health;
+mod history;
+mod latency;
+mod layout;
+mod limits;
+mod listener;
+mod live;
+mod log_tail;
+mod notes;
+mod notifications;
+mod outgoing;
+mod proxy;
+mod query;
+mod registry;
+mod relay;
+mod rollup;
+mod routes;
 mod server;
+mod settings;
+mod state;
+mod status;
+mod store;
+mod telemetry;
+mod tls;
+mod tuning;
+mod watchdog;
+mod workers;
 
+use approval::{Approval, Approvals};
+use arming::Arming;
+use auth::{Access, Sessions};
+use automation::Inputs;
+use cooldown::Cooldowns;
+use fleet::Deployment;
+use handle::ServerHandle;
+use history::{CommandHistory, CommandOutcome, CommandRecord};
+use latency::LatencyStats;
+use layout::{Layout, Layouts};
+use limits::RateLimiter;
+use listener::{Listener, ListenerConfig};
+use live::LiveUpdates;
+use log_tail::RecentLogs;
+use notes::Notes;
+use notifications::{NotificationLog, SentNotification};
+use query::{ClientPage, ClientQuery, ClientSort};
+use registry::RegistryCounts;
+use rollup::Rollup;
 use server::{SendError, Server};
-use tracing::{metadata::LevelFilter, *};
-use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
+use settings::{CommandKind, Settings, SettingsError, SettingsReference};
+use status::{Endpoint, Status};
+use store::{Store, StoreError};
+use telemetry::Chart;
+use tls::{TlsConfig, TlsError};
+use tower_http::timeout::TimeoutLayer;
+use tracing::*;
+use tuning::{Family, Tuning, Tunings};
 use ulid::Ulid;
 
-type ServerReference = Particularity<Server>;
-type AppStateReference = Particularity<AppState>;
+type AppStateReference = Arc<AppState>;
+
+/// clients per dashboard page when the query does not set a limit
+const PAGE_SIZE: usize = 50;
+
+static STYLE: &str = include_str!("../static/style.min.css");
+static SCRIPT: &str = include_str!("../static/vendored/htmx.min.js");
+static WS_EXTENSION: &str = include_str!("../static/vendored/ws.js");
+static SSE_EXTENSION: &str = include_str!("../static/vendored/sse.js");
 
 struct Config {
-    server_address: SocketAddr,
+    server_listeners: Vec<ListenerConfig>,
     web_interface_address: SocketAddr,
+    settings_path: PathBuf,
+    /// notes about devices, see [`notes`]
+    notes_path: PathBuf,
+    /// favorites and order of the client list of every user, see [`layout`]
+    layouts_path: PathBuf,
+    /// clients approved in the web interface, see [`approval`]
+    approvals_path: PathBuf,
+    /// telemetry tuned per device, see [`tuning`]
+    tunings_path: PathBuf,
+    live_updates: LiveUpdates,
+    tls: Option<TlsConfig>,
+    base_path: String,
+    trust_forwarded: bool,
+    /// proxies in front of the one connecting to the web interface, see
+    /// [`proxy::Forwarded`]
+    trusted_proxies: Vec<IpAddr>,
+    /// the grpc api is only served when an address is configured
+    grpc_address: Option<SocketAddr>,
+    /// file every pdt message is recorded to, for replaying with pdt-replay
+    record_path: Option<PathBuf>,
+    /// heartbeats, idle timeout and message size reconciled with each client
+    protocol: ProtocolConfig,
+    /// told to clients when they are welcomed
+    server_name: String,
+    /// servers on private networks relay their clients through connections
+    /// they open to this address, see [`relay`]
+    relay_address: Option<SocketAddr>,
+    /// relay listener of a central server to relay every client to instead
+    /// of serving them
+    relay_to: Option<String>,
+    /// shared by relaying servers and the central server, required by both
+    relay_token: Option<String>,
 }
 
 impl Config {
@@ -40,16 +159,138 @@ impl Config {
                 .unwrap_or(default)
         };
 
-        let server_address = configure(env::var("SERVER_ADDRESS"), self.server_address);
+        let server_listeners =
+            match env::var("SERVER_ADDRESS").map(|s| ListenerConfig::parse_list(&s)) {
+                Ok(Ok(listeners)) if !listeners.is_empty() => listeners,
+                Ok(Err(error)) => {
+                    warn!(error = %error, "invalid SERVER_ADDRESS, using default");
+                    self.server_listeners
+                }
+                _ => self.server_listeners,
+            };
 
         let web_interface_address = configure(
             env::var("WEB_INTERFACE_ADDRESS"),
             self.web_interface_address,
         );
 
+        let settings_path = env::var("SETTINGS_PATH")
+            .map(PathBuf::from)
+            .unwrap_or(self.settings_path);
+
+        let notes_path = env::var("NOTES_PATH")
+            .map(PathBuf::from)
+            .unwrap_or(self.notes_path);
+
+        let layouts_path = env::var("LAYOUTS_PATH")
+            .map(PathBuf::from)
+            .unwrap_or(self.layouts_path);
+
+        let approvals_path = env::var("APPROVALS_PATH")
+            .map(PathBuf::from)
+            .unwrap_or(self.approvals_path);
+
+        let tunings_path = env::var("TUNINGS_PATH")
+            .map(PathBuf::from)
+            .unwrap_or(self.tunings_path);
+
+        let live_updates = env::var("LIVE_UPDATES")
+            .ok()
+            .and_then(|string| LiveUpdates::from_str(&string).ok())
+            .unwrap_or(self.live_updates);
+
+        let tls = TlsConfig::from_env().or(self.tls);
+
+        let base_path = env::var("WEB_BASE_PATH")
+            .map(|path| proxy::base_path(&path))
+            .unwrap_or(self.base_path);
+
+        let trust_forwarded = env::var("WEB_TRUST_FORWARDED")
+            .map(|value| matches!(value.as_str(), "1" | "true" | "yes"))
+            .unwrap_or(self.trust_forwarded);
+
+        let trusted_proxies = match env::var("WEB_TRUSTED_PROXIES") {
+            Ok(proxies) => proxies
+                .split(',')
+                .map(str::trim)
+                .filter(|proxy| !proxy.is_empty())
+                .filter_map(|proxy| match proxy.parse() {
+                    Ok(address) => Some(address),
+                    Err(_) => {
+                        warn!(proxy, "invalid address in WEB_TRUSTED_PROXIES, ignoring it");
+                        None
+                    }
+                })
+                .collect(),
+            Err(_) => self.trusted_proxies,
+        };
+
+        let grpc_address = env::var("GRPC_ADDRESS")
+            .ok()
+            .and_then(|string| SocketAddr::from_str(&string).ok())
+            .or(self.grpc_address);
+
+        let record_path = env::var("RECORD_PATH")
+            .ok()
+            .map(PathBuf::from)
+            .or(self.record_path);
+
+        let mut protocol = self.protocol;
+
+        if let Some(timeout) = env::var("CLIENT_IDLE_TIMEOUT")
+            .ok()
+            .and_then(|string| humantime::parse_duration(&string).ok())
+        {
+            protocol = protocol.with_idle_timeout(timeout);
+        }
+
+        if let Some(size) = env::var("MAX_FRAME_SIZE")
+            .ok()
+            .and_then(|string| string.parse().ok())
+        {
+            protocol = protocol.with_max_frame_size(size);
+        }
+
+        let server_name = env::var("SERVER_NAME")
+            .ok()
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or(self.server_name);
+
+        let relay_address = env::var("RELAY_ADDRESS")
+            .ok()
+            .and_then(|string| SocketAddr::from_str(&string).ok())
+            .or(self.relay_address);
+
+        let relay_to = env::var("RELAY_TO")
+            .ok()
+            .filter(|address| !address.trim().is_empty())
+            .or(self.relay_to);
+
+        let relay_token = env::var("RELAY_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty())
+            .or(self.relay_token);
+
         Self {
-            server_address,
+            server_listeners,
             web_interface_address,
+            settings_path,
+            notes_path,
+            layouts_path,
+            approvals_path,
+            tunings_path,
+            live_updates,
+            tls,
+            base_path,
+            trust_forwarded,
+            trusted_proxies,
+            grpc_address,
+            record_path,
+            protocol,
+            server_name,
+            relay_address,
+            relay_to,
+            relay_token,
         }
     }
 }
@@ -57,202 +298,1351 @@ impl Config {
 impl Default for Config {
     fn default() -> Self {
         Self {
-            server_address: SocketAddr::from(([0, 0, 0, 0], 2039)),
+            server_listeners: vec![SocketAddr::from(([0, 0, 0, 0], 2039)).into()],
             web_interface_address: SocketAddr::from(([0, 0, 0, 0], 2040)),
+            settings_path: PathBuf::from("pdtserver.toml"),
+            notes_path: PathBuf::from("pdtnotes.toml"),
+            layouts_path: PathBuf::from("pdtlayouts.toml"),
+            approvals_path: PathBuf::from("pdtapprovals.toml"),
+            tunings_path: PathBuf::from("pdttunings.toml"),
+            live_updates: LiveUpdates::default(),
+            tls: None,
+            base_path: String::new(),
+            trust_forwarded: false,
+            trusted_proxies: vec![],
+            grpc_address: None,
+            record_path: None,
+            protocol: ProtocolConfig::default(),
+            server_name: state::DEFAULT_NAME.to_string(),
+            relay_address: None,
+            relay_to: None,
+            relay_token: None,
         }
     }
 }
 
 impl AppState {
-    fn reference(server_reference: ServerReference) -> AppStateReference {
-        Arc::new(Mutex::new(Self {
-            server: server_reference,
-        }))
+    fn reference(
+        server: ServerHandle,
+        settings: SettingsReference,
+        config: &Config,
+        endpoints: Vec<Endpoint>,
+        logs: RecentLogs,
+        stores: Stores,
+    ) -> AppStateReference {
+        Arc::new(Self {
+            server,
+            settings,
+            settings_path: config.settings_path.clone(),
+            live_updates: config.live_updates,
+            secure_cookies: config.tls.is_some(),
+            base_path: config.base_path.clone(),
+            trust_forwarded: config.trust_forwarded,
+            trusted_proxies: config.trusted_proxies.clone(),
+            started: SystemTime::now(),
+            endpoints,
+            server_name: config.server_name.clone(),
+            logs,
+            web: Mutex::new(WebState {
+                sessions: Sessions::default(),
+                history: CommandHistory::default(),
+                notifications: NotificationLog::default(),
+                cooldowns: Cooldowns::default(),
+                arming: Arming::default(),
+                rate_limiter: RateLimiter::default(),
+                inputs: Inputs::default(),
+                notes: stores.notes,
+                layouts: stores.layouts,
+                approvals: stores.approvals,
+                tunings: stores.tunings,
+            }),
+        })
     }
 }
 
-impl From<Server> for ServerReference {
-    fn from(value: Server) -> Self {
-        Arc::new(Mutex::new(value))
-    }
+/// what the handlers share, the server is asked through its handle and only
+/// the state of the web interface itself is behind a lock
+///
+/// the lock is never held across an await nor while locking the settings,
+/// handlers read the settings before or after
+struct AppState {
+    server: ServerHandle,
+    settings: SettingsReference,
+    settings_path: PathBuf,
+    live_updates: LiveUpdates,
+    /// mark session cookies secure, the web interface is served over https
+    secure_cookies: bool,
+    /// url prefix of every route and link, see [`proxy::base_path`]
+    base_path: String,
+    /// honor the forwarded headers of a reverse proxy in front of the server
+    trust_forwarded: bool,
+    trusted_proxies: Vec<IpAddr>,
+    started: SystemTime,
+    /// sockets the server listens on, for the status page
+    endpoints: Vec<Endpoint>,
+    /// the name clients are welcomed with, also naming this server among
+    /// its upstreams
+    server_name: String,
+    /// recent log events of the server for the log page
+    logs: RecentLogs,
+    web: Mutex<WebState>,
 }
 
-struct AppState {
-    server: ServerReference,
+/// what the web interface changes as it is used
+struct WebState {
+    sessions: Sessions,
+    history: CommandHistory,
+    notifications: NotificationLog,
+    /// when commands with a cooldown were last sent to every device
+    cooldowns: Cooldowns,
+    arming: Arming,
+    rate_limiter: RateLimiter,
+    inputs: Inputs,
+    notes: Store<Notes>,
+    layouts: Store<Layouts>,
+    approvals: Store<Approvals>,
+    tunings: Store<Tunings>,
+}
+
+/// what the web interface edits, kept in files
+struct Stores {
+    notes: Store<Notes>,
+    layouts: Store<Layouts>,
+    approvals: Store<Approvals>,
+    tunings: Store<Tunings>,
 }
 
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate {
+    base_path: String,
     style: String,
     script: String,
-    clients: Vec<Client>,
+    page: ClientPage,
+    counts: RegistryCounts,
+    live_updates: LiveUpdates,
+    username: Option<String>,
+    csrf_token: Option<String>,
+    access: Access,
+    approval: Approval,
+    query: ClientQuery,
+    /// how the caller arranged the client list
+    layout: Layout,
+}
+
+#[derive(Template)]
+#[template(path = "client.html")]
+struct ClientTemplate {
+    base_path: String,
+    style: String,
+    script: String,
+    client: Client,
+    charts: Vec<Chart>,
+    latency: LatencyStats,
+    /// none unless the client reports its containers
+    containers: Option<Containers>,
+    /// markdown, empty when there are none
+    notes: String,
+    csrf_token: Option<String>,
+    controllable: bool,
+    /// telemetry tuned for the device
+    tuning: Tuning,
+    /// notes and tuning may be edited while commands wait for approval
+    notes_editable: bool,
+    notifications: Vec<SentNotification>,
+}
+
+#[derive(Template)]
+#[template(path = "fleet.html")]
+struct FleetTemplate {
+    base_path: String,
+    style: String,
+    script: String,
+    server: BuiltInfo,
+    deployments: Vec<Deployment>,
+    rollup: Rollup,
+}
+
+/// a client on the federation page
+struct FederationRow {
+    server: String,
+    client_id: String,
+    device_name: String,
+    os: String,
+    state: &'static str,
+    /// detail page on the server owning the client
+    link: String,
+    controllable: bool,
+}
+
+#[derive(Template)]
+#[template(path = "federation.html")]
+struct FederationTemplate {
+    base_path: String,
+    style: String,
+    script: String,
+    csrf_token: Option<String>,
+    servers: usize,
+    rows: Vec<FederationRow>,
+    unreachable: Vec<pdtapi::UnreachableServer>,
+}
+
+#[derive(Template)]
+#[template(path = "status.html")]
+struct StatusTemplate {
+    base_path: String,
+    style: String,
+    script: String,
+    status: Status,
+}
+
+/// client list of the dashboard, for htmx requests targeting it
+#[derive(Template)]
+#[template(path = "clients.html")]
+struct ClientsTemplate {
+    base_path: String,
+    page: ClientPage,
+    access: Access,
+    approval: Approval,
+    query: ClientQuery,
+    /// how the caller arranged the client list
+    layout: Layout,
 }
 
 enum AppError {
-    Deadlock,
+    /// the thread owning the server is gone
+    ServerStopped,
+    /// handling client messages failed and did not recover yet
+    Degraded,
     ServerSend(SendError),
+    Settings(SettingsError),
+    Unauthorized,
+    Forbidden,
+    InvalidClientId,
+    PendingApproval,
+    /// approving a client that presented no identity to approve
+    NoIdentity,
+    EmptyNotification,
+    TooManyRequests,
+    /// the same kind of command was sent to the device moments before, see
+    /// [`cooldown`]
+    CoolingDown {
+        command: CommandKind,
+        device_name: String,
+        remaining: Duration,
+    },
+    /// a destructive command to several clients was not armed, see
+    /// [`arming`]
+    NotArmed(pdtapi::Command),
+    /// no upstream by that name, see [`federation`]
+    UnknownServer,
+    Upstream(federation::UpstreamError),
+    Notes(StoreError),
+    Layout(StoreError),
+    Approvals(StoreError),
+    /// a telemetry interval that is not a duration of a second or more
+    InvalidTuning,
+    Tunings(StoreError),
 }
 
+// fields are only read through Debug when main returns
+#[allow(dead_code)]
 #[derive(Debug)]
 enum StartupError {
-    Tracing,
+    Tracing(TracingError),
+    Runtime(std::io::Error),
+    Settings(SettingsError),
     TcpBindAddress(std::io::Error),
-    Mutex,
     AxumServe,
+    HashPassword,
+    Tls(TlsError),
+    Notes(StoreError),
+    Layout(StoreError),
+    Approvals(StoreError),
+    Tunings(StoreError),
+    /// the thread owning the server is gone
+    ServerStopped,
+    Recording(std::io::Error),
+    Relay(std::io::Error),
+    /// relaying, or accepting relays, without `RELAY_TOKEN`
+    MissingRelayToken,
+    /// `CLIENT_IDLE_TIMEOUT` of zero or shorter than two heartbeats, which
+    /// would drop clients between heartbeats
+    IdleTimeout(Duration),
+    Unhealthy(String),
 }
 
-impl<T> From<PoisonError<T>> for AppError {
-    fn from(_value: PoisonError<T>) -> Self {
-        AppError::Deadlock
-    }
-}
-
-impl IntoResponse for AppError {
-    fn into_response(self) -> axum::response::Response {
+impl AppError {
+    /// log the error and describe it for the response
+    fn describe(self) -> (StatusCode, String) {
         match self {
-            AppError::Deadlock => {
-                error!("could not acquire lock");
+            AppError::ServerStopped => {
+                error!("server stopped");
 
                 (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Unexpected error".to_string(),
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Server stopped".to_string(),
                 )
             }
+            AppError::Degraded => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Handling client messages restarted".to_string(),
+            ),
+            AppError::ServerSend(SendError::ClientNotFound) => {
+                (StatusCode::NOT_FOUND, "Client not found".to_string())
+            }
             AppError::ServerSend(error) => {
                 error!(error =? error, "send");
 
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Unexpected error\n\nerror={:?}", error),
+                )
+            }
+            AppError::Settings(error) => {
+                error!(error =? error, "settings");
+
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Unexpected error\n\nerror={:?}", error),
+                )
+            }
+            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
+            AppError::Forbidden => (StatusCode::FORBIDDEN, "Forbidden".to_string()),
+            AppError::InvalidClientId => (StatusCode::BAD_REQUEST, "Invalid client id".to_string()),
+            AppError::PendingApproval => (
+                StatusCode::FORBIDDEN,
+                "Client is pending approval".to_string(),
+            ),
+            AppError::NoIdentity => (
+                StatusCode::CONFLICT,
+                "Client presented no identity to approve".to_string(),
+            ),
+            AppError::EmptyNotification => (
+                StatusCode::BAD_REQUEST,
+                "Notification title is empty".to_string(),
+            ),
+            AppError::TooManyRequests => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many requests".to_string(),
+            ),
+            AppError::CoolingDown {
+                command,
+                device_name,
+                remaining,
+            } => (
+                StatusCode::CONFLICT,
+                format!(
+                    "{command:?} was sent to {device_name} moments ago, try again in {}",
+                    humantime::format_duration(Duration::from_secs(remaining.as_secs().max(1)))
+                ),
+            ),
+            AppError::NotArmed(command) => (
+                StatusCode::PRECONDITION_REQUIRED,
+                format!("{command:?} to several clients needs the confirmation of arming it first"),
+            ),
+            AppError::UnknownServer => (StatusCode::NOT_FOUND, "Server not found".to_string()),
+            AppError::Upstream(error) => {
+                warn!(
+                    server = error.server,
+                    status =? error.status,
+                    error = error.error,
+                    "upstream"
+                );
+
+                // an upstream rejecting its own token is not the caller's fault
+                let status = match error.status {
+                    Some(403) => StatusCode::FORBIDDEN,
+                    Some(404) => StatusCode::NOT_FOUND,
+                    _ => StatusCode::BAD_GATEWAY,
+                };
+
+                (status, format!("{}: {}", error.server, error.error))
+            }
+            AppError::Notes(error) => {
+                error!(error =? error, "notes");
+
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Unexpected error\n\nerror={:?}", error),
+                )
+            }
+            AppError::Layout(error) => {
+                error!(error =? error, "layout");
+
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Unexpected error\n\nerror={:?}", error),
+                )
+            }
+            AppError::Approvals(error) => {
+                error!(error =? error, "approvals");
+
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Unexpected error\n\nerror={:?}", error),
+                )
+            }
+            AppError::InvalidTuning => (
+                StatusCode::BAD_REQUEST,
+                "Telemetry interval must be a duration of at least a second".to_string(),
+            ),
+            AppError::Tunings(error) => {
+                error!(error =? error, "tunings");
+
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     format!("Unexpected error\n\nerror={:?}", error),
                 )
             }
         }
-        .into_response()
     }
 }
 
-async fn index(State(state): State<AppStateReference>) -> Result<IndexTemplate, AppError> {
-    static STYLE: &str = include_str!("../static/style.min.css");
-    static SCRIPT: &str = include_str!("../static/vendored/htmx.min.js");
+impl IntoResponse for AppError {
+    fn into_response(self) -> axum::response::Response {
+        self.describe().into_response()
+    }
+}
+
+async fn index(
+    State(state): State<AppStateReference>,
+    Query(mut query): Query<ClientQuery>,
+    headers: HeaderMap,
+    access: Result<Access, AppError>,
+) -> Result<Response, AppError> {
+    let (base_path, live_updates, server) = (
+        state.base_path.clone(),
+        state.live_updates,
+        state.server.clone(),
+    );
+    let approval = Approval::of(&state);
+
+    let access = match access {
+        Ok(access) => access,
+        Err(AppError::Unauthorized) => {
+            return Ok(Redirect::to(&format!("{base_path}/login")).into_response())
+        }
+        Err(error) => return Err(error),
+    };
+
+    let username = access.session().map(|session| session.username.clone());
+    let csrf_token = access.session().map(|session| session.csrf_token.clone());
 
-    let app_state_guard = state.lock()?;
-    let app_state = &*app_state_guard;
+    query.limit.get_or_insert(PAGE_SIZE);
 
-    let server_guard = app_state.server.lock()?;
+    let layout = state.web.lock().layouts.get(&access.actor());
 
-    let server = &*server_guard;
+    let page = query.arranged(access.visible(server.clients().await?), &layout);
+    let counts = server.counts().await?;
 
-    let clients = server.get_clients();
+    if headers
+        .get("hx-target")
+        .is_some_and(|target| target == "clients")
+    {
+        return Ok(ClientsTemplate {
+            base_path,
+            page,
+            access,
+            approval,
+            query,
+            layout,
+        }
+        .into_response());
+    }
 
     let template = IndexTemplate {
-        clients,
+        base_path,
+        page,
+        counts,
         style: STYLE.into(),
-        script: SCRIPT.into(),
+        script: [SCRIPT, WS_EXTENSION, SSE_EXTENSION].join("\n"),
+        live_updates,
+        username,
+        csrf_token,
+        access,
+        approval,
+        query,
+        layout,
     };
 
-    Ok(template)
+    Ok(template.into_response())
 }
 
-async fn screen_off(
+/// detail page of a single client
+async fn client_detail(
     Path(client_id): Path<Ulid>,
     State(state): State<AppStateReference>,
+    access: Result<Access, AppError>,
+) -> Result<Response, AppError> {
+    let (base_path, server) = (state.base_path.clone(), state.server.clone());
+
+    let access = match access {
+        Ok(access) => access,
+        Err(AppError::Unauthorized) => {
+            return Ok(Redirect::to(&format!("{base_path}/login")).into_response())
+        }
+        Err(error) => return Err(error),
+    };
+
+    let (Some(client), Some(samples), Some(latency)) = (
+        server.client(client_id).await?,
+        server.telemetry(client_id).await?,
+        server.latency(client_id).await?,
+    ) else {
+        return Err(AppError::ServerSend(SendError::ClientNotFound));
+    };
+
+    if !access.may_see(&client.device_info.name) {
+        return Err(AppError::ServerSend(SendError::ClientNotFound));
+    }
+
+    let containers = server.containers(client_id).await?;
+
+    let pending = Approval::of(&state).pending(&client);
+
+    let web_guard = state.web.lock();
+
+    let template = ClientTemplate {
+        base_path,
+        style: STYLE.into(),
+        script: SCRIPT.into(),
+        csrf_token: access.session().map(|session| session.csrf_token.clone()),
+        controllable: access.may_control(&client.device_info.name) && !pending,
+        notes_editable: access.may_control(&client.device_info.name),
+        notes: web_guard.notes.get(&client.device_info.name).to_string(),
+        tuning: web_guard.tunings.get(&client.device_info.name),
+        notifications: web_guard.notifications.recent(client_id),
+        client,
+        charts: telemetry::charts(&samples),
+        latency,
+        containers,
+    };
+
+    Ok(template.into_response())
+}
+
+/// pdtcore builds deployed across the clients, compared to the server
+async fn fleet(
+    State(state): State<AppStateReference>,
+    access: Result<Access, AppError>,
+) -> Result<Response, AppError> {
+    let (base_path, server) = (state.base_path.clone(), state.server.clone());
+
+    let access = match access {
+        Ok(access) => access,
+        Err(AppError::Unauthorized) => {
+            return Ok(Redirect::to(&format!("{base_path}/login")).into_response())
+        }
+        Err(error) => return Err(error),
+    };
+
+    let builds = server
+        .builds()
+        .await?
+        .into_iter()
+        .filter(|(client, _)| access.may_see(&client.device_info.name))
+        .collect();
+
+    let latest: Vec<_> = server
+        .latest_telemetry()
+        .await?
+        .into_iter()
+        .filter(|(client, _)| access.may_see(&client.device_info.name))
+        .collect();
+
+    let server = BuiltInfo::default();
+    let deployments = fleet::deployments(&server, builds);
+
+    let template = FleetTemplate {
+        base_path,
+        style: STYLE.into(),
+        script: SCRIPT.into(),
+        server,
+        deployments,
+        rollup: rollup::rollup(&latest),
+    };
+
+    Ok(template.into_response())
+}
+
+/// clients of this server and its upstreams on one page
+async fn federation_page(
+    State(state): State<AppStateReference>,
+    access: Result<Access, AppError>,
+) -> Result<Response, AppError> {
+    let base_path = state.base_path.clone();
+
+    let access = match access {
+        Ok(access) => access,
+        Err(AppError::Unauthorized) => {
+            return Ok(Redirect::to(&format!("{base_path}/login")).into_response())
+        }
+        Err(error) => return Err(error),
+    };
+
+    let (_, upstreams) = federation::servers(&state)?;
+    let federated = federation::clients(&state, &access).await?;
+
+    let rows = federated
+        .clients
+        .into_iter()
+        .map(|federated| {
+            let client = federated.client;
+
+            let link = match upstreams
+                .iter()
+                .find(|upstream| upstream.name == federated.server)
+            {
+                Some(upstream) => format!(
+                    "{}/clients/{}",
+                    upstream.url.trim_end_matches('/'),
+                    client.id
+                ),
+                None => format!("{base_path}/clients/{}", client.id),
+            };
+
+            FederationRow {
+                controllable: access.may_control(&client.device_info.name),
+                server: federated.server,
+                state: match client.state {
+                    pdtapi::ConnectionState::Connecting => "connecting",
+                    pdtapi::ConnectionState::Connected => "connected",
+                },
+                link,
+                client_id: client.id,
+                device_name: client.device_info.name,
+                os: client.device_info.os,
+            }
+        })
+        .collect();
+
+    let template = FederationTemplate {
+        base_path,
+        style: STYLE.into(),
+        script: SCRIPT.into(),
+        csrf_token: access.session().map(|session| session.csrf_token.clone()),
+        servers: upstreams.len() + 1,
+        rows,
+        unreachable: federated.unreachable,
+    };
+
+    Ok(template.into_response())
+}
+
+/// build, uptime, endpoints and load of the server itself
+async fn status_page(
+    State(state): State<AppStateReference>,
+    access: Result<Access, AppError>,
+) -> Result<Response, AppError> {
+    let (base_path, server) = (state.base_path.clone(), state.server.clone());
+
+    match access {
+        Ok(_) => {}
+        Err(AppError::Unauthorized) => {
+            return Ok(Redirect::to(&format!("{base_path}/login")).into_response())
+        }
+        Err(error) => return Err(error),
+    };
+
+    let counts = server.counts().await?;
+
+    let template = StatusTemplate {
+        base_path,
+        style: STYLE.into(),
+        script: SCRIPT.into(),
+        status: Status::of(&state, counts),
+    };
+
+    Ok(template.into_response())
+}
+
+/// a client the caller may see
+async fn visible_client(
+    server: &ServerHandle,
+    access: &Access,
+    client_id: Ulid,
+) -> Result<Client, AppError> {
+    server
+        .client(client_id)
+        .await?
+        .filter(|client| access.may_see(&client.device_info.name))
+        .ok_or(AppError::ServerSend(SendError::ClientNotFound))
+}
+
+/// device name of a client the caller may see
+async fn visible_device(
+    server: &ServerHandle,
+    access: &Access,
+    client_id: Ulid,
 ) -> Result<String, AppError> {
-    let state_guard = state.lock()?;
+    Ok(visible_client(server, access, client_id)
+        .await?
+        .device_info
+        .name)
+}
+
+/// why `message` may not be sent to the client right now, if at all
+fn refusal(
+    state: &AppState,
+    access: &Access,
+    client: &Client,
+    message: &ClientMessage,
+) -> Result<Option<AppError>, AppError> {
+    if !access.may_send(&client.device_info.name, message) {
+        return Ok(Some(AppError::Forbidden));
+    }
+
+    if Approval::of(state).pending(client) {
+        return Ok(Some(AppError::PendingApproval));
+    }
+
+    Ok(None)
+}
+
+/// the kind of `message` and how long the settings hold the device back
+/// after it, none for commands without a cooldown
+fn cooldown(state: &AppState, message: &ClientMessage) -> Option<(CommandKind, Duration)> {
+    let command = CommandKind::of(message)?;
+    let seconds = *state.settings.lock().cooldowns.get(&command)?;
+
+    Some((command, Duration::from_secs(seconds)))
+}
+
+/// the error refusing a command to the device while its `cooldown` lasts
+fn cooling_down(
+    cooldowns: &Cooldowns,
+    device_name: &str,
+    (command, cooldown): (CommandKind, Duration),
+    at: SystemTime,
+) -> Option<AppError> {
+    let remaining = cooldowns.remaining(device_name, command, cooldown, at)?;
+
+    Some(AppError::CoolingDown {
+        command,
+        device_name: device_name.to_string(),
+        remaining,
+    })
+}
+
+/// send `message` to a client the caller may control, returning the name of
+/// its device
+#[instrument(skip(state, access, message))]
+async fn command(
+    state: &AppStateReference,
+    access: &Access,
+    client_id: Ulid,
+    message: ClientMessage,
+) -> Result<String, AppError> {
+    access.check_csrf()?;
+
+    let server = state.server.clone();
+
+    let client = visible_client(&server, access, client_id).await?;
+    let device_name = client.device_info.name.clone();
+
+    let mut record = CommandRecord {
+        at: SystemTime::now(),
+        client_id,
+        device_name: device_name.clone(),
+        command: format!("{message:?}"),
+        actor: access.actor(),
+        outcome: CommandOutcome::Denied,
+    };
 
-    let state = &*state_guard;
+    if let Some(error) = refusal(state, access, &client, &message)? {
+        state.web.lock().history.record(record);
+        return Err(error);
+    }
+
+    let window = Duration::from_secs(state.settings.lock().coalesce_seconds);
+    let cooldown = cooldown(state, &message);
+
+    {
+        let mut web_guard = state.web.lock();
 
-    let mut server_guard = state.server.lock()?;
+        if !window.is_zero()
+            && web_guard
+                .history
+                .sent_within(client_id, &record.command, record.at, window)
+        {
+            info!(device_name, "coalescing command with the one sent before");
 
-    let server = &mut *server_guard;
+            record.outcome = CommandOutcome::Coalesced;
+            web_guard.history.record(record);
+            return Ok(device_name);
+        }
+
+        if let Some(error) = cooldown.and_then(|cooldown| {
+            cooling_down(&web_guard.cooldowns, &device_name, cooldown, record.at)
+        }) {
+            web_guard.history.record(record);
+            return Err(error);
+        }
 
-    match server.send(client_id, Message::Client(ClientMessage::ScreenOff)) {
-        Ok(_) => Ok("OK".to_string()),
-        Err(error) => Err(AppError::ServerSend(error)),
+        // taken before sending so requests racing this one are refused too
+        if let Some(command) = CommandKind::of(&message) {
+            web_guard.cooldowns.sent(&device_name, command, record.at);
+        }
     }
+
+    let command = CommandKind::of(&message);
+
+    let result = server.send(client_id, Message::Client(message)).await;
+
+    let mut web_guard = state.web.lock();
+
+    record.outcome = match result {
+        Ok(_) => CommandOutcome::Sent,
+        Err(_) => {
+            // a command that never left does not hold the device back
+            if let Some(command) = command {
+                web_guard.cooldowns.failed(&device_name, command, record.at);
+            }
+
+            CommandOutcome::Failed
+        }
+    };
+    web_guard.history.record(record);
+    drop(web_guard);
+
+    result.map(|_| device_name)
 }
 
-async fn screen_on(
-    Path(client_id): Path<Ulid>,
-    State(state): State<AppStateReference>,
+/// what [`command`] would answer for `message`, without sending or recording
+/// anything
+#[instrument(skip(state, access, message))]
+async fn preview_command(
+    state: &AppStateReference,
+    access: &Access,
+    client_id: Ulid,
+    message: &ClientMessage,
 ) -> Result<String, AppError> {
-    let state_guard = state.lock()?;
+    access.check_csrf()?;
 
-    let state = &*state_guard;
+    let server = state.server.clone();
 
-    let mut server_guard = state.server.lock()?;
+    let client = visible_client(&server, access, client_id).await?;
+    let device_name = &client.device_info.name;
 
-    let server = &mut *server_guard;
+    if let Some(error) = refusal(state, access, &client, message)? {
+        return Err(error);
+    }
 
-    match server.send(client_id, Message::Client(ClientMessage::ScreenOn)) {
-        Ok(_) => Ok("OK".to_string()),
-        Err(error) => Err(AppError::ServerSend(error)),
+    let cooling_down = cooldown(state, message).and_then(|cooldown| {
+        cooling_down(
+            &state.web.lock().cooldowns,
+            device_name,
+            cooldown,
+            SystemTime::now(),
+        )
+    });
+
+    match cooling_down {
+        Some(error) => Err(error),
+        None => Ok(device_name.clone()),
     }
 }
 
-fn setup_tracing() -> Result<(), StartupError> {
-    let layer = tracing_logfmt::builder().with_target(false).layer();
+async fn reload_settings(
+    State(state): State<AppStateReference>,
+    access: Access,
+) -> Result<String, AppError> {
+    access.check_csrf()?;
 
-    let filter = match EnvFilter::try_from_default_env() {
-        Ok(filter) => filter,
-        _ => EnvFilter::builder()
-            .with_default_directive(LevelFilter::INFO.into())
-            .parse("")
-            .map_err(|_| StartupError::Tracing)?,
-    };
+    if !access.is_admin() {
+        return Err(AppError::Forbidden);
+    }
 
-    let subscriber = Registry::default().with(layer).with(filter);
-    tracing::subscriber::set_global_default(subscriber).map_err(|_| StartupError::Tracing)?;
+    settings::reload(&state.settings, &state.settings_path).map_err(AppError::Settings)?;
 
-    Ok(())
+    Ok("OK".to_string())
+}
+
+/// set up the log and return the recent events it keeps for the log page
+fn setup_tracing() -> Result<RecentLogs, StartupError> {
+    let tracer = otel::pipeline("pdtserver").and_then(|pipeline| {
+        pipeline
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+            .install_batch(runtime::Tokio)
+            .map_err(|error| eprintln!("otlp exporter: {error}"))
+            .ok()
+    });
+
+    let logs = RecentLogs::default();
+
+    pdttracing::init(tracer, logs.clone()).map_err(StartupError::Tracing)?;
+
+    Ok(logs)
 }
 
 async fn serve_web_interface(
-    server_reference: ServerReference,
-    web_interface_address: SocketAddr,
+    server: ServerHandle,
+    settings: SettingsReference,
+    config: Config,
+    activated_listener: Option<TcpListener>,
+    mut endpoints: Vec<Endpoint>,
+    logs: RecentLogs,
 ) -> Result<(), StartupError> {
-    let state = AppState::reference(server_reference);
+    let web_interface_address = config.web_interface_address;
+
+    let web_address = match &activated_listener {
+        Some(listener) => listener
+            .local_addr()
+            .map_err(StartupError::TcpBindAddress)?,
+        None => web_interface_address,
+    };
+
+    endpoints.push(Endpoint {
+        service: "web",
+        address: web_address.to_string(),
+    });
+
+    if let Some(address) = config.grpc_address {
+        endpoints.push(Endpoint {
+            service: "grpc",
+            address: address.to_string(),
+        });
+    }
+
+    let stores = Stores {
+        notes: Store::load(&config.notes_path).map_err(StartupError::Notes)?,
+        layouts: Store::load(&config.layouts_path).map_err(StartupError::Layout)?,
+        approvals: Store::load(&config.approvals_path).map_err(StartupError::Approvals)?,
+        tunings: Store::load(&config.tunings_path).map_err(StartupError::Tunings)?,
+    };
+
+    server
+        .tune(stores.tunings.configs())
+        .await
+        .map_err(|_| StartupError::ServerStopped)?;
+
+    let state = AppState::reference(server, settings, &config, endpoints, logs, stores);
+
+    if let Some(address) = config.grpc_address {
+        tokio::spawn(grpc::serve(state.clone(), address));
+    }
 
     let web = Router::new()
         .route("/", routing::get(index))
-        .route("/screen-off/:client_id", routing::get(screen_off))
-        .route("/screen-on/:client_id", routing::get(screen_on))
-        .with_state(state.clone());
+        .route("/login", routing::get(auth::login_page).post(auth::login))
+        .route("/logout", routing::post(auth::logout))
+        .route("/clients/:client_id", routing::get(client_detail))
+        .route("/clients/:client_id/logs", routing::get(client_logs::page))
+        .route(
+            "/clients/:client_id/logs/events",
+            routing::get(client_logs::tail),
+        )
+        .route("/fleet", routing::get(fleet))
+        .route("/federation", routing::get(federation_page))
+        .route("/status", routing::get(status_page))
+        .route("/crashes", routing::get(crashes::page))
+        .route("/logs", routing::get(log_tail::page))
+        .route("/logs/events", routing::get(log_tail::tail))
+        .route("/admin/reload", routing::post(reload_settings))
+        .route("/ws", routing::get(live::websocket))
+        .route("/events", routing::get(live::server_sent_events))
+        .merge(actions::router())
+        .merge(notifications::router())
+        .merge(notes::router())
+        .merge(tuning::router())
+        .merge(layout::router())
+        .merge(health::router())
+        .merge(api::router())
+        .merge(graphql::router());
+
+    // nesting only matches the index without a trailing slash, which is how
+    // reverse proxies usually forward it
+    let web = match config.base_path.is_empty() {
+        true => web,
+        false => Router::new()
+            .route(&format!("{}/", config.base_path), routing::get(index))
+            .nest(&config.base_path, web),
+    }
+    .layer(TimeoutLayer::new(limits::REQUEST_TIMEOUT))
+    .layer(DefaultBodyLimit::max(limits::BODY_LIMIT))
+    .layer(middleware::from_fn_with_state(
+        state.clone(),
+        limits::rate_limit,
+    ))
+    .with_state(state.clone());
+
+    if let Some(tls) = config.tls {
+        let rustls_config = tls.rustls_config().await.map_err(StartupError::Tls)?;
+
+        let server = match activated_listener {
+            Some(listener) => {
+                info!(address =? listener.local_addr().ok(), "starting activated https web interface server");
+                listener
+                    .set_nonblocking(true)
+                    .map_err(StartupError::TcpBindAddress)?;
+                axum_server::from_tcp_rustls(listener, rustls_config)
+            }
+            None => {
+                info!(address =? web_interface_address, "starting https web interface server");
+                axum_server::bind_rustls(web_interface_address, rustls_config)
+            }
+        };
+
+        return server
+            .http_config(
+                HttpConfig::new()
+                    .http1_header_read_timeout(limits::HEADER_READ_TIMEOUT)
+                    .build(),
+            )
+            .serve(web.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .map_err(|_| StartupError::AxumServe);
+    }
 
-    info!(address =? web_interface_address, "starting web interface server");
-    axum::Server::bind(&web_interface_address)
-        .serve(web.into_make_service())
+    let builder = match activated_listener {
+        Some(listener) => {
+            info!(address =? listener.local_addr().ok(), "starting activated web interface server");
+            axum::Server::from_tcp(listener).map_err(|_| StartupError::AxumServe)?
+        }
+        None => {
+            info!(address =? web_interface_address, "starting web interface server");
+            axum::Server::bind(&web_interface_address)
+        }
+    };
+
+    builder
+        .http1_header_read_timeout(limits::HEADER_READ_TIMEOUT)
+        .serve(web.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .map_err(|_| StartupError::AxumServe)?;
 
     Ok(())
 }
 
-fn spawn_tcp_server(
-    server_reference: ServerReference,
-    server_address: SocketAddr,
-) -> Result<(), StartupError> {
-    let tcp_server_listener =
-        TcpListener::bind(server_address).map_err(StartupError::TcpBindAddress)?;
+fn bind_listeners(server_listeners: &[ListenerConfig]) -> Result<Vec<Listener>, StartupError> {
+    server_listeners
+        .iter()
+        .map(|config| {
+            info!(listener = config.name, address = ?config.address, "binding pdt server listener");
+            config.bind().map_err(StartupError::TcpBindAddress)
+        })
+        .collect()
+}
+
+fn spawn_tcp_server(server: &mut Server, listeners: Vec<Listener>) {
+    info!(listeners = listeners.len(), "starting pdt server");
+    server.run(listeners);
+}
 
-    let mut guard = server_reference.lock().map_err(|_| StartupError::Mutex)?;
-    let server = &mut *guard;
+/// on SIGINT or SIGTERM stop the pdt server, telling clients goodbye, and
+/// exit once its threads are done
+async fn stop_on_terminate(server: Server) {
+    use tokio::signal::unix::{signal, SignalKind};
 
-    info!(address = ?server_address, "starting pdt server");
-    server.run(tcp_server_listener);
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(error) => {
+            error!(error =? error, "could not listen for SIGTERM");
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = terminate.recv() => info!("received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => info!("received SIGINT"),
+    }
+
+    let stopped = tokio::task::spawn_blocking(move || {
+        server.stop();
+        server.join();
+    });
+
+    if let Err(error) = stopped.await {
+        error!(error =? error, "stopping pdt server");
+    }
+
+    std::process::exit(0);
+}
+
+/// print an argon2 hash of the password read from stdin
+fn hash_password() -> Result<(), StartupError> {
+    let mut password = String::new();
+
+    std::io::stdin()
+        .read_line(&mut password)
+        .map_err(|_| StartupError::HashPassword)?;
+
+    let hash = auth::hash_password(password.trim_end_matches(['\r', '\n']))
+        .map_err(|_| StartupError::HashPassword)?;
+
+    println!("{hash}");
 
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), StartupError> {
-    setup_tracing()?;
+/// exit successfully only when the web interface of the server configured
+/// by the environment answers its health endpoint
+fn healthcheck() -> Result<(), StartupError> {
+    let config = Config::default().with_env();
+
+    health::check(
+        config.web_interface_address,
+        &config.base_path,
+        config.tls.is_some(),
+    )
+    .map_err(StartupError::Unhealthy)
+}
+
+fn main() -> Result<(), StartupError> {
+    match std::env::args().nth(1).as_deref() {
+        Some("hash-password") => return hash_password(),
+        Some("healthcheck") => return healthcheck(),
+        Some("--version" | "-V") => {
+            println!("pdtserver\n{}", BuiltInfo::default());
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // the activation environment is cleared while no other thread can read
+    // it, so before the runtime starts its workers
+    let sockets = activation::sockets();
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(StartupError::Runtime)?
+        .block_on(serve(sockets))
+}
+
+/// serve on `sockets` passed by socket activation, or on the configured
+/// listeners without them
+async fn serve(sockets: Vec<(String, OwnedFd)>) -> Result<(), StartupError> {
+    let logs = setup_tracing()?;
 
     let config = Config::default().with_env();
-    let server = Server::default();
 
-    let server_reference = ServerReference::from(server);
+    let protocol = config.protocol;
+
+    if protocol.idle_timeout == 0
+        || protocol.idle_timeout < protocol.heartbeat_interval.saturating_mul(2)
+    {
+        return Err(StartupError::IdleTimeout(protocol.idle_timeout()));
+    }
+
+    let mut server = Server::default();
+
+    server.set_name(config.server_name.clone());
+    server.set_protocol(config.protocol);
+
+    if let Some(path) = &config.record_path {
+        server.record_to(Recorder::create(path).map_err(StartupError::Recording)?);
+    }
+
+    let settings = SettingsReference::from(
+        Settings::load(&config.settings_path).map_err(StartupError::Settings)?,
+    );
+
+    server.use_settings(settings.clone());
+
+    tokio::spawn(settings::reload_on_hangup(
+        settings.clone(),
+        config.settings_path.clone(),
+    ));
+
+    let mut web_interface_listener = None;
+    let mut listeners = vec![];
+
+    for (name, fd) in sockets {
+        info!(fd = fd.as_raw_fd(), name, "received socket from systemd");
+
+        if name == activation::WEB_INTERFACE_NAME {
+            web_interface_listener = Some(TcpListener::from(fd));
+        } else {
+            listeners.push(Listener::from_fd(name, fd).map_err(StartupError::TcpBindAddress)?);
+        }
+    }
+
+    if listeners.is_empty() {
+        listeners = bind_listeners(&config.server_listeners)?;
+    }
+
+    if let Some(central) = config.relay_to.clone() {
+        info!(central, "relaying clients instead of serving them");
+
+        let name = config.server_name.clone();
+        let token = config
+            .relay_token
+            .clone()
+            .ok_or(StartupError::MissingRelayToken)?;
+        let relayed =
+            tokio::task::spawn_blocking(move || relay::relay(&central, &name, &token, listeners))
+                .await;
+
+        return match relayed {
+            Ok(result) => result.map_err(StartupError::Relay),
+            Err(error) => Err(StartupError::Relay(std::io::Error::other(error))),
+        };
+    }
+
+    let mut endpoints: Vec<_> = listeners
+        .iter()
+        .map(|listener| Endpoint {
+            service: "pdt",
+            address: listener.name.clone(),
+        })
+        .collect();
+
+    if std::env::args().skip(1).any(|arg| arg == "--demo") {
+        demo::start(&server);
+    }
+
+    spawn_tcp_server(&mut server, listeners);
+
+    if let Some(address) = config.relay_address {
+        info!(address =? address, "accepting relaying servers");
 
-    spawn_tcp_server(server_reference.clone(), config.server_address)?;
-    serve_web_interface(server_reference, config.web_interface_address).await
+        let token = config
+            .relay_token
+            .clone()
+            .ok_or(StartupError::MissingRelayToken)?;
+        let relays = TcpListener::bind(address).map_err(StartupError::TcpBindAddress)?;
+        relay::serve(server.clone(), relays, token);
+
+        endpoints.push(Endpoint {
+            service: "relay",
+            address: address.to_string(),
+        });
+    }
+
+    tokio::spawn(stop_on_terminate(server.clone()));
+
+    serve_web_interface(
+        ServerHandle::spawn(server),
+        settings,
+        config,
+        web_interface_listener,
+        endpoints,
+        logs,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// app state around `server` where restarts cool down for five minutes
+    fn cooling_state(server: &Server) -> AppStateReference {
+        state(
+            server,
+            Settings {
+                cooldowns: HashMap::from([(CommandKind::Restart, 300)]),
+                ..Settings::default()
+            },
+        )
+    }
+
+    fn state(server: &Server, settings: Settings) -> AppStateReference {
+        let missing = std::env::temp_dir().join(format!("pdtserver-{}-none", std::process::id()));
+
+        AppState::reference(
+            ServerHandle::spawn(server.clone()),
+            Arc::new(Mutex::new(settings)),
+            &Config::default(),
+            vec![],
+            RecentLogs::default(),
+            Stores {
+                notes: Store::load(&missing).unwrap(),
+                layouts: Store::load(&missing).unwrap(),
+                approvals: Store::load(&missing).unwrap(),
+                tunings: Store::load(&missing).unwrap(),
+            },
+        )
+    }
+
+    fn network() -> NetworkInfo {
+        NetworkInfo {
+            address: "memory".to_string(),
+            transport: Transport::Tcp,
+            connected_at: SystemTime::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_are_refused_while_cooling_down() {
+        let server = Server::default();
+        let (id, _receiver) = server.connect_in_process(network()).unwrap();
+        let state = cooling_state(&server);
+
+        assert!(command(&state, &Access::Open, id, ClientMessage::Restart)
+            .await
+            .is_ok());
+
+        assert!(matches!(
+            command(&state, &Access::Open, id, ClientMessage::Restart).await,
+            Err(AppError::CoolingDown { .. })
+        ));
+        assert!(command(&state, &Access::Open, id, ClientMessage::ScreenOff)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn commands_that_failed_to_send_do_not_cool_down() {
+        let server = Server::default();
+        let (id, receiver) = server.connect_in_process(network()).unwrap();
+        let state = cooling_state(&server);
+
+        // the connection is gone but the client not forgotten yet
+        drop(receiver);
+
+        for _ in 0..2 {
+            assert!(matches!(
+                command(&state, &Access::Open, id, ClientMessage::Restart).await,
+                Err(AppError::ServerSend(SendError::SendChannel))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn destructive_rules_for_several_clients_need_arming() {
+        let mut server = Server::default();
+        server.run(vec![]);
+
+        let _first = server.connect_in_process(network()).unwrap();
+        let _second = server.connect_in_process(network()).unwrap();
+
+        let state = state(
+            &server,
+            toml::from_str(
+                r#"
+                [[rules]]
+                name = "everything off when away"
+                input = "nobody-home"
+                command = "power-off"
+                "#,
+            )
+            .unwrap(),
+        );
+
+        let Ok(response) = automation::set(&state, &Access::Open, "nobody-home", true, None).await
+        else {
+            panic!("setting the input failed");
+        };
+
+        assert_eq!(response.rules.len(), 1);
+        assert_eq!(response.rules[0].results.len(), 2);
+        assert!(response.rules[0]
+            .results
+            .iter()
+            .all(|result| result.status == StatusCode::PRECONDITION_REQUIRED.as_u16()));
+
+        let history = state.web.lock().history.records();
+
+        assert_eq!(history.len(), 2);
+        assert!(history
+            .iter()
+            .all(|record| record.record.outcome == CommandOutcome::Denied));
+
+        server.stop();
+    }
 }
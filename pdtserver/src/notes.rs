@@ -0,0 +1,166 @@
+//! free-form markdown notes about a device, like quirks to keep in mind when
+//! controlling it, shown on its detail page
+//!
+//! notes are kept by device name in a toml file of their own, see [`store`],
+//! so they outlive both the connection and the server, and are rewritten
+//! whenever someone allowed to control the device edits them
+
+use std::collections::BTreeMap;
+
+use askama::Template;
+use axum::{
+    extract::{Path as UrlPath, State},
+    response::{IntoResponse, Response},
+    routing, Form, Router,
+};
+use pulldown_cmark::{html, Event, Parser, Tag};
+use serde::{Deserialize, Serialize};
+use tracing::*;
+use ulid::Ulid;
+
+use crate::{auth::Access, filters, store, visible_device, AppError, AppStateReference};
+
+/// link schemes kept in rendered notes, others like `javascript:` are dropped
+const SCHEMES: [&str; 3] = ["http:", "https:", "mailto:"];
+
+/// notes by device name
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct Notes {
+    notes: BTreeMap<String, String>,
+}
+
+impl Notes {
+    /// empty when the device has no notes
+    pub fn get(&self, device_name: &str) -> &str {
+        self.notes.get(device_name).map_or("", String::as_str)
+    }
+
+    /// replace the notes of a device, removing them when blank
+    pub fn set(&mut self, device_name: &str, notes: &str) {
+        match notes.trim() {
+            "" => self.notes.remove(device_name),
+            notes => self
+                .notes
+                .insert(device_name.to_string(), notes.to_string()),
+        };
+    }
+}
+
+/// whether a link in notes leads somewhere harmless, relative ones do
+fn safe_link(destination: &str) -> bool {
+    let scheme = destination
+        .find([':', '/', '?', '#'])
+        .filter(|&end| destination[end..].starts_with(':'))
+        .map(|end| destination[..=end].to_ascii_lowercase());
+
+    match scheme {
+        Some(scheme) => SCHEMES.contains(&scheme.as_str()),
+        None => true,
+    }
+}
+
+/// notes as html, with any html they contain escaped instead of rendered
+pub fn render(notes: &str) -> String {
+    let events = Parser::new(notes).map(|event| match event {
+        Event::Html(html) => Event::Text(html),
+        Event::Start(Tag::Link(kind, destination, title)) if !safe_link(&destination) => {
+            Event::Start(Tag::Link(kind, "".into(), title))
+        }
+        Event::Start(Tag::Image(kind, destination, title)) if !safe_link(&destination) => {
+            Event::Start(Tag::Image(kind, "".into(), title))
+        }
+        event => event,
+    });
+
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, events);
+    rendered
+}
+
+/// rendered notes of a client detail page, for htmx requests targeting them
+#[derive(Template)]
+#[template(path = "notes.html")]
+pub struct NotesTemplate {
+    pub notes: String,
+}
+
+pub fn router() -> Router<AppStateReference> {
+    Router::new().route("/clients/:client_id/notes", routing::post(edit))
+}
+
+#[derive(Deserialize)]
+struct NotesForm {
+    notes: String,
+}
+
+#[instrument(skip(state, access, form))]
+async fn edit(
+    UrlPath(client_id): UrlPath<Ulid>,
+    State(state): State<AppStateReference>,
+    access: Access,
+    Form(form): Form<NotesForm>,
+) -> Result<Response, AppError> {
+    access.check_csrf()?;
+
+    let server = state.server.clone();
+
+    let device_name = visible_device(&server, &access, client_id).await?;
+
+    if !access.may_control(&device_name) {
+        return Err(AppError::Forbidden);
+    }
+
+    state.web.lock().notes.set(&device_name, &form.notes);
+
+    store::save(&state, |web| &web.notes)
+        .await
+        .map_err(AppError::Notes)?;
+
+    info!(device_name, actor = access.actor(), "notes edited");
+
+    Ok(NotesTemplate {
+        notes: state.web.lock().notes.get(&device_name).to_string(),
+    }
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_in_notes_is_escaped() {
+        let rendered = render("HDMI-CEC **flaky**\n\n<script>alert(1)</script>");
+
+        assert!(rendered.starts_with("<p>HDMI-CEC <strong>flaky</strong></p>"));
+        assert!(rendered.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!rendered.contains("<script>"));
+    }
+
+    #[test]
+    fn only_web_and_mail_links_are_kept() {
+        assert!(render("[manual](https://example.org/tv.pdf)").contains("href=\"https://"));
+        assert!(render("[runbook](runbooks/tv.md)").contains("href=\"runbooks/tv.md\""));
+        assert!(!render("[click](JavaScript:alert(1))").contains("alert"));
+    }
+
+    #[test]
+    fn blank_notes_are_removed() {
+        let mut notes = Notes::default();
+
+        notes.set(
+            "living-room",
+            "HDMI-CEC flaky, don't power off during recordings\n",
+        );
+        notes.set("kiosk", "mounted upside down");
+        notes.set("kiosk", "  ");
+
+        assert_eq!(
+            notes.get("living-room"),
+            "HDMI-CEC flaky, don't power off during recordings"
+        );
+        assert_eq!(notes.get("kiosk"), "");
+        assert_eq!(notes.notes.len(), 1);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{collections::VecDeque, time::SystemTime};
+
+use askama::Template;
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    routing, Form, Router,
+};
+use pdtcore::{ClientMessage, Notification};
+use tracing::*;
+use ulid::Ulid;
+
+use crate::{
+    actions::{Toast, ToastTemplate},
+    auth::Access,
+    command, filters, AppError, AppStateReference,
+};
+
+/// notifications kept in memory across all clients, older ones are dropped
+const CAPACITY: usize = 1000;
+
+/// notifications listed on a client detail page
+const RECENT: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct SentNotification {
+    pub at: SystemTime,
+    pub client_id: Ulid,
+    pub title: String,
+    pub body: String,
+    /// user or access token name of the caller
+    pub actor: String,
+}
+
+/// notifications sent from the web interface, oldest first
+#[derive(Debug, Default)]
+pub struct NotificationLog {
+    sent: VecDeque<SentNotification>,
+}
+
+impl NotificationLog {
+    pub fn record(&mut self, notification: SentNotification) {
+        if self.sent.len() == CAPACITY {
+            self.sent.pop_front();
+        }
+
+        self.sent.push_back(notification);
+    }
+
+    /// latest notifications sent to a client, newest first
+    pub fn recent(&self, client_id: Ulid) -> Vec<SentNotification> {
+        self.sent
+            .iter()
+            .rev()
+            .filter(|notification| notification.client_id == client_id)
+            .take(RECENT)
+            .cloned()
+            .collect()
+    }
+}
+
+/// notification list of a client detail page, for htmx requests targeting it
+#[derive(Template)]
+#[template(path = "notifications.html")]
+pub struct NotificationsTemplate {
+    pub notifications: Vec<SentNotification>,
+}
+
+pub fn router() -> Router<AppStateReference> {
+    Router::new().route("/notifications", routing::post(notify))
+}
+
+/// send a notification to a client the caller may control and log it
+async fn send(
+    state: &AppStateReference,
+    access: &Access,
+    client_id: Ulid,
+    title: &str,
+    body: &str,
+) -> Result<String, AppError> {
+    let notification = Notification {
+        title: title.to_string(),
+        body: body.to_string(),
+    };
+
+    let device_name = command(
+        state,
+        access,
+        client_id,
+        ClientMessage::Notify(notification),
+    )
+    .await?;
+
+    state.web.lock().notifications.record(SentNotification {
+        at: SystemTime::now(),
+        client_id,
+        title: title.to_string(),
+        body: body.to_string(),
+        actor: access.actor(),
+    });
+
+    Ok(device_name)
+}
+
+/// send the `title` and `body` of the submitted form to every `client_id`
+/// field, answering with the notification list when a client detail page
+/// asks for it and with toasts otherwise
+#[instrument(skip(state, headers, access, form))]
+async fn notify(
+    State(state): State<AppStateReference>,
+    headers: HeaderMap,
+    access: Access,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Result<Response, AppError> {
+    let field = |name: &str| {
+        form.iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.trim().to_string())
+            .unwrap_or_default()
+    };
+
+    let title = field("title");
+    let body = field("body");
+
+    let client_ids = form
+        .iter()
+        .filter(|(name, _)| name == "client_id")
+        .map(|(_, client_id)| client_id.parse::<Ulid>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| AppError::InvalidClientId)?;
+
+    if title.is_empty() {
+        return Err(AppError::EmptyNotification);
+    }
+
+    if headers
+        .get("hx-target")
+        .is_some_and(|target| target == "notifications")
+    {
+        let [client_id] = client_ids[..] else {
+            return Err(AppError::InvalidClientId);
+        };
+
+        send(&state, &access, client_id, &title, &body).await?;
+
+        let notifications = state.web.lock().notifications.recent(client_id);
+
+        return Ok(NotificationsTemplate { notifications }.into_response());
+    }
+
+    if client_ids.is_empty() {
+        return Ok(ToastTemplate {
+            toasts: vec![Toast {
+                message: "no clients selected".to_string(),
+                error: true,
+            }],
+        }
+        .into_response());
+    }
+
+    let mut toasts = Vec::with_capacity(client_ids.len());
+
+    for client_id in client_ids {
+        toasts.push(
+            match send(&state, &access, client_id, &title, &body).await {
+                Ok(device_name) => Toast {
+                    message: format!("notification sent to {device_name}"),
+                    error: false,
+                },
+                Err(error) => Toast {
+                    message: format!(
+                        "notification failed for {}: {}",
+                        client_id,
+                        error.describe().1
+                    ),
+                    error: true,
+                },
+            },
+        );
+    }
+
+    Ok(ToastTemplate { toasts }.into_response())
+}
@@ -0,0 +1,253 @@
+//! per-client queue of outgoing messages, where commands jump ahead of
+//! telemetry requests and both jump ahead of bulk messages
+//!
+//! a lower priority message passed over [`STARVATION_LIMIT`] times in a row
+//! is sent next regardless, so a busy client still gets everything queued
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{RecvError, SendError},
+        Arc, Condvar, Mutex,
+    },
+};
+
+use pdtcore::{ClientMessage, Message};
+
+/// times a waiting message may be passed over before it is sent next
+pub const STARVATION_LIMIT: usize = 8;
+
+const LANES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// commands changing the state of the client
+    Control,
+    /// device info and telemetry requests, and what telemetry to collect
+    Telemetry,
+    /// notifications and anything large
+    Bulk,
+}
+
+impl Priority {
+    pub fn of(message: &Message) -> Self {
+        match message {
+            Message::Client(message) => Self::of_client_message(message),
+            Message::Server(_) => Priority::Bulk,
+        }
+    }
+
+    fn of_client_message(message: &ClientMessage) -> Self {
+        match message {
+            ClientMessage::ScreenOff
+            | ClientMessage::ScreenOn
+            | ClientMessage::PowerOff
+            | ClientMessage::Restart
+            | ClientMessage::Goodbye
+            | ClientMessage::Welcome(_)
+            | ClientMessage::Nack { .. } => Priority::Control,
+            ClientMessage::RequestDeviceInfo
+            | ClientMessage::RequestTelemetry
+            | ClientMessage::ConfigUpdate(_) => Priority::Telemetry,
+            ClientMessage::Notify(_) | ClientMessage::Custom { .. } => Priority::Bulk,
+            ClientMessage::Traced(_, message) => Self::of_client_message(message),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    lanes: [VecDeque<Message>; LANES],
+    /// times the first message of each lane was passed over
+    passed: [usize; LANES],
+    senders: usize,
+    receiving: bool,
+}
+
+impl Queue {
+    fn pop(&mut self) -> Option<Message> {
+        let starved = (0..LANES)
+            .rev()
+            .find(|&lane| !self.lanes[lane].is_empty() && self.passed[lane] >= STARVATION_LIMIT);
+
+        let lane = starved.or_else(|| (0..LANES).find(|&lane| !self.lanes[lane].is_empty()))?;
+
+        for other in 0..LANES {
+            if other != lane && !self.lanes[other].is_empty() {
+                self.passed[other] += 1;
+            }
+        }
+
+        self.passed[lane] = 0;
+        self.lanes[lane].pop_front()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    available: Condvar,
+}
+
+/// queues messages for a client, the receiver ends once every sender is
+/// dropped and the queue is drained
+#[derive(Debug)]
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+pub struct Receiver {
+    shared: Arc<Shared>,
+}
+
+pub fn channel() -> (Sender, Receiver) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            senders: 1,
+            receiving: true,
+            ..Queue::default()
+        }),
+        available: Condvar::new(),
+    });
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+impl Sender {
+    /// queue a message behind those of the same priority, failing once the
+    /// receiver is gone
+    pub fn send(&self, message: Message) -> Result<(), SendError<Box<Message>>> {
+        let Ok(mut queue) = self.shared.queue.lock() else {
+            return Err(SendError(Box::new(message)));
+        };
+
+        if !queue.receiving {
+            return Err(SendError(Box::new(message)));
+        }
+
+        queue.lanes[Priority::of(&message) as usize].push_back(message);
+        self.shared.available.notify_one();
+
+        Ok(())
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        if let Ok(mut queue) = self.shared.queue.lock() {
+            queue.senders += 1;
+        }
+
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        if let Ok(mut queue) = self.shared.queue.lock() {
+            queue.senders -= 1;
+        }
+
+        self.shared.available.notify_all();
+    }
+}
+
+impl Receiver {
+    /// wait for the most urgent queued message
+    pub fn recv(&self) -> Result<Message, RecvError> {
+        let mut queue = self.shared.queue.lock().map_err(|_| RecvError)?;
+
+        loop {
+            if let Some(message) = queue.pop() {
+                return Ok(message);
+            }
+
+            if queue.senders == 0 {
+                return Err(RecvError);
+            }
+
+            queue = self.shared.available.wait(queue).map_err(|_| RecvError)?;
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        if let Ok(mut queue) = self.shared.queue.lock() {
+            queue.receiving = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pdtcore::Notification;
+
+    use super::*;
+
+    fn notify() -> Message {
+        ClientMessage::Notify(Notification {
+            title: "title".to_string(),
+            body: "body".to_string(),
+        })
+        .into()
+    }
+
+    #[test]
+    fn control_jumps_ahead() {
+        let (sender, receiver) = channel();
+
+        sender.send(notify()).unwrap();
+        sender.send(ClientMessage::RequestTelemetry.into()).unwrap();
+        sender.send(ClientMessage::PowerOff.into()).unwrap();
+
+        assert_eq!(receiver.recv(), Ok(ClientMessage::PowerOff.into()));
+        assert_eq!(receiver.recv(), Ok(ClientMessage::RequestTelemetry.into()));
+        assert_eq!(receiver.recv(), Ok(notify()));
+    }
+
+    #[test]
+    fn waiting_messages_are_not_starved() {
+        let (sender, receiver) = channel();
+
+        sender.send(notify()).unwrap();
+
+        for _ in 0..STARVATION_LIMIT * 2 {
+            sender.send(ClientMessage::ScreenOn.into()).unwrap();
+        }
+
+        let position = (0..)
+            .map(|_| receiver.recv().unwrap())
+            .position(|message| message == notify());
+
+        assert_eq!(position, Some(STARVATION_LIMIT));
+    }
+
+    #[test]
+    fn ends_once_drained_and_every_sender_is_dropped() {
+        let (sender, receiver) = channel();
+
+        sender.clone().send(ClientMessage::Goodbye.into()).unwrap();
+        drop(sender);
+
+        assert_eq!(receiver.recv(), Ok(ClientMessage::Goodbye.into()));
+        assert_eq!(receiver.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn sending_fails_without_receiver() {
+        let (sender, receiver) = channel();
+
+        drop(receiver);
+
+        assert!(sender.send(ClientMessage::ScreenOff.into()).is_err());
+    }
+}
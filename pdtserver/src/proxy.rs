@@ -0,0 +1,135 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+
+use crate::{AppError, AppStateReference};
+
+const FORWARDED_FOR: &str = "x-forwarded-for";
+const FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// url prefix the web interface is served under, e.g. `/pdt` for `/pdt/`,
+/// empty when served from the root
+pub fn base_path(path: &str) -> String {
+    let path = path.trim_matches('/');
+
+    match path.is_empty() {
+        true => String::new(),
+        false => format!("/{path}"),
+    }
+}
+
+/// where a request came from, as reported by a trusted reverse proxy or the
+/// connection itself
+///
+/// the proxy connecting to the web interface is trusted when
+/// `WEB_TRUST_FORWARDED` is set, those in front of it when listed in
+/// `WEB_TRUSTED_PROXIES`
+#[derive(Debug, Clone, Copy)]
+pub struct Forwarded {
+    pub client: Option<IpAddr>,
+    /// the request reached the proxy over https
+    pub https: bool,
+}
+
+/// the client of a `x-forwarded-for` chain, the nearest address that is not
+/// a trusted proxy
+///
+/// every proxy appends the address it was connected from, so only the
+/// addresses from the right up to the first untrusted one are genuine, the
+/// client may have sent anything before them
+fn forwarded_client(forwarded_for: &str, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let mut client = None;
+
+    for address in forwarded_for.rsplit(',') {
+        let address: IpAddr = address.trim().parse().ok()?;
+
+        client = Some(address);
+
+        if !trusted_proxies.contains(&address) {
+            break;
+        }
+    }
+
+    client
+}
+
+#[async_trait]
+impl FromRequestParts<AppStateReference> for Forwarded {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppStateReference,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+
+        let (trust_forwarded, trusted_proxies) =
+            (state.trust_forwarded, state.trusted_proxies.clone());
+
+        if !trust_forwarded {
+            return Ok(Forwarded {
+                client: peer,
+                https: false,
+            });
+        }
+
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+
+        let client = header(FORWARDED_FOR)
+            .and_then(|value| forwarded_client(value, &trusted_proxies))
+            .or(peer);
+
+        let https =
+            header(FORWARDED_PROTO).is_some_and(|proto| proto.trim().eq_ignore_ascii_case("https"));
+
+        Ok(Forwarded { client, https })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(address: &str) -> Option<IpAddr> {
+        Some(address.parse().unwrap())
+    }
+
+    #[test]
+    fn clients_cannot_spoof_their_forwarded_address() {
+        assert_eq!(forwarded_client("203.0.113.7", &[]), address("203.0.113.7"));
+        assert_eq!(
+            forwarded_client("10.9.9.9, 203.0.113.7", &[]),
+            address("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn trusted_proxies_in_the_chain_are_skipped() {
+        let cdn = [
+            address("198.51.100.1").unwrap(),
+            address("198.51.100.2").unwrap(),
+        ];
+
+        assert_eq!(
+            forwarded_client("10.9.9.9, 203.0.113.7, 198.51.100.2, 198.51.100.1", &cdn),
+            address("203.0.113.7")
+        );
+        assert_eq!(
+            forwarded_client("198.51.100.2, 198.51.100.1", &cdn),
+            address("198.51.100.2")
+        );
+        assert_eq!(forwarded_client("203.0.113.7, not an address", &cdn), None);
+    }
+}
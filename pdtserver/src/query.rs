@@ -0,0 +1,195 @@
+use std::{cmp::Reverse, time::Duration};
+
+use pdtcore::{Client, ConnectionState};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::layout::Layout;
+
+/// order of a client listing
+#[derive(
+    Serialize,
+    Deserialize,
+    ToSchema,
+    async_graphql::Enum,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientSort {
+    #[default]
+    Joined,
+    Name,
+    /// most recently seen first
+    LastSeen,
+    /// longest running first, unknown uptimes last
+    Uptime,
+}
+
+/// filter and order of a client listing, taken from query parameters
+#[derive(Serialize, Deserialize, IntoParams, Debug, Clone, Default)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct ClientQuery {
+    /// case insensitive part of the device name
+    pub q: String,
+    /// `connecting` or `connected`, any state when empty
+    pub state: String,
+    #[param(inline)]
+    pub sort: ClientSort,
+    /// number of matching clients to skip
+    pub offset: usize,
+    /// maximum number of clients to return, all when absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+/// a window of a filtered and sorted client listing
+#[derive(Debug, Clone)]
+pub struct ClientPage {
+    pub clients: Vec<Client>,
+    /// number of matching clients before pagination
+    pub total: usize,
+}
+
+impl ClientQuery {
+    fn matches(&self, client: &Client) -> bool {
+        let name = self.q.trim().to_lowercase();
+
+        let state = match self.state.as_str() {
+            "connecting" => Some(ConnectionState::Connecting),
+            "connected" => Some(ConnectionState::Connected),
+            _ => None,
+        };
+
+        client.device_info.name.to_lowercase().contains(&name)
+            && state.is_none_or(|state| client.state == state)
+    }
+
+    /// keep the matching clients in the requested order, limited to the
+    /// requested window
+    pub fn apply(&self, clients: Vec<Client>) -> ClientPage {
+        self.arranged(clients, &Layout::default())
+    }
+
+    /// like [`Self::apply`], with the favorites of a user first and their
+    /// arranged order in place of the join order
+    pub fn arranged(&self, clients: Vec<Client>, layout: &Layout) -> ClientPage {
+        let mut clients: Vec<Client> = clients
+            .into_iter()
+            .filter(|client| self.matches(client))
+            .collect();
+
+        match self.sort {
+            ClientSort::Joined => layout.arrange(&mut clients),
+            ClientSort::Name => {
+                clients.sort_by_cached_key(|client| client.device_info.name.to_lowercase())
+            }
+            ClientSort::LastSeen => clients.sort_by_key(|client| Reverse(client.last_seen)),
+            ClientSort::Uptime => clients.sort_by_cached_key(|client| Reverse(uptime(client))),
+        }
+
+        layout.pin(&mut clients);
+
+        let total = clients.len();
+
+        ClientPage {
+            clients: window(clients, self.offset, self.limit),
+            total,
+        }
+    }
+
+    /// query string for the same listing starting at `offset`
+    fn at_offset(&self, offset: usize) -> String {
+        let query = ClientQuery {
+            offset,
+            ..self.clone()
+        };
+
+        serde_urlencoded::to_string(query).unwrap_or_default()
+    }
+
+    pub fn previous_page(&self) -> Option<String> {
+        let limit = self.limit?;
+
+        (self.offset > 0).then(|| self.at_offset(self.offset.saturating_sub(limit)))
+    }
+
+    pub fn next_page(&self, page: &ClientPage) -> Option<String> {
+        let next = self.offset.saturating_add(self.limit?);
+
+        (next < page.total).then(|| self.at_offset(next))
+    }
+
+    /// positions of the first and last client shown, counting from one,
+    /// none when the window holds no clients
+    pub fn shown(&self, page: &ClientPage) -> Option<(usize, usize)> {
+        (!page.clients.is_empty()).then(|| {
+            (
+                self.offset.saturating_add(1),
+                self.offset.saturating_add(page.clients.len()),
+            )
+        })
+    }
+}
+
+/// window of the command history, oldest first, taken from query parameters
+#[derive(Deserialize, IntoParams, Debug, Clone, Default)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryQuery {
+    /// number of commands to skip
+    pub offset: usize,
+    /// maximum number of commands to return, all when absent
+    pub limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    pub fn apply<T>(&self, entries: Vec<T>) -> Vec<T> {
+        window(entries, self.offset, self.limit)
+    }
+}
+
+fn window<T>(items: Vec<T>, offset: usize, limit: Option<usize>) -> Vec<T> {
+    items
+        .into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+/// uptime reported by the client, formatted by humantime on the client side
+fn uptime(client: &Client) -> Option<Duration> {
+    humantime::parse_duration(&client.device_info.uptime).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_far_past_the_end_do_not_overflow() {
+        let query = ClientQuery {
+            offset: usize::MAX,
+            limit: Some(usize::MAX),
+            ..Default::default()
+        };
+
+        let page = query.apply(vec![]);
+
+        assert_eq!(query.next_page(&page), None);
+        assert_eq!(query.shown(&page), None);
+        assert!(query.previous_page().is_some());
+
+        let history = HistoryQuery {
+            offset: 1,
+            limit: Some(2),
+        };
+
+        assert_eq!(history.apply(vec![1, 2, 3, 4]), vec![2, 3]);
+    }
+}
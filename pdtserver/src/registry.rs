@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use pdtcore::{Client, ConnectionState};
+use ulid::Ulid;
+
+use crate::state::ServerClient;
+
+/// number of clients per connection state and over the server lifetime
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegistryCounts {
+    pub connecting: usize,
+    pub connected: usize,
+    /// clients registered since the server started
+    pub joined: usize,
+    /// clients removed since the server started
+    pub left: usize,
+}
+
+/// connected clients of a server, in the order they joined
+#[derive(Debug, Default)]
+pub struct Registry {
+    clients: HashMap<Ulid, ServerClient>,
+    order: Vec<Ulid>,
+    joined: usize,
+    left: usize,
+}
+
+impl Registry {
+    pub fn insert(&mut self, client: ServerClient) {
+        if self.clients.insert(client.id, client.clone()).is_none() {
+            self.order.push(client.id);
+            self.joined += 1;
+        }
+    }
+
+    pub fn remove(&mut self, id: &Ulid) -> Option<ServerClient> {
+        let client = self.clients.remove(id)?;
+
+        self.order.retain(|candidate| candidate != id);
+        self.left += 1;
+
+        Some(client)
+    }
+
+    pub fn get(&self, id: &Ulid) -> Option<&ServerClient> {
+        self.clients.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &Ulid) -> Option<&mut ServerClient> {
+        self.clients.get_mut(id)
+    }
+
+    /// clients in join order
+    pub fn iter(&self) -> impl Iterator<Item = &ServerClient> {
+        self.order.iter().filter_map(|id| self.clients.get(id))
+    }
+
+    pub fn counts(&self) -> RegistryCounts {
+        let mut counts = RegistryCounts {
+            joined: self.joined,
+            left: self.left,
+            ..Default::default()
+        };
+
+        for client in self.clients.values() {
+            match ConnectionState::from(client.session) {
+                ConnectionState::Connecting => counts.connecting += 1,
+                ConnectionState::Connected => counts.connected += 1,
+            }
+        }
+
+        counts
+    }
+
+    /// web facing view of every client in join order
+    pub fn clients(&self) -> Vec<Client> {
+        self.iter().map(Client::from).collect()
+    }
+}
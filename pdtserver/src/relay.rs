@@ -0,0 +1,443 @@
+//! relaying the clients of a server on a private network through a single
+//! connection it opens to a central server, so nothing has to be forwarded
+//! to reach the private network
+//!
+//! a relaying server, started with `RELAY_TO`, accepts clients as usual and
+//! tunnels every client connection as a stream of a [`Mux`] over the relay
+//! connection, the central server, listening for relays on `RELAY_ADDRESS`,
+//! serves each stream as if the client had connected to it directly
+//!
+//! the relaying server names itself on the control stream followed by the
+//! token both servers are configured with, `RELAY_TOKEN`, and starts every
+//! stream with the address of its client
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use parking_lot::Mutex;
+use pdtcore::{
+    mux::{Mux, MuxStream, Side},
+    NetworkInfo, Transport,
+};
+use socket2::{SockRef, TcpKeepalive};
+use tracing::*;
+
+use crate::{
+    listener::{Connection, Listener, ACCEPT_BACKOFF},
+    server::Server,
+};
+
+/// wait before connecting to the central server again
+const RETRY: Duration = Duration::from_secs(5);
+
+/// wait for a relay to name itself and for the address of a relayed client
+const INTRODUCTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// probe an idle relay connection, keeping mappings of the network address
+/// translation alive and noticing a peer that is gone
+const KEEPALIVE: Duration = Duration::from_secs(60);
+
+impl Connection for MuxStream {
+    fn try_clone_connection(&self) -> std::io::Result<Box<dyn Connection>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_idle_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.set_read_timeout(timeout);
+        Ok(())
+    }
+
+    fn shutdown(&self) -> std::io::Result<()> {
+        MuxStream::shutdown(self)
+    }
+}
+
+/// a name or an address, prefixed by its length
+fn write_text(writer: &mut impl Write, text: &str) -> std::io::Result<()> {
+    let length = u16::try_from(text.len())
+        .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "text too long"))?;
+
+    let mut written = Vec::with_capacity(2 + text.len());
+
+    written.extend(length.to_be_bytes());
+    written.extend(text.as_bytes());
+
+    writer.write_all(&written)
+}
+
+fn read_text(reader: &mut impl Read) -> std::io::Result<String> {
+    let mut length = [0; 2];
+    reader.read_exact(&mut length)?;
+
+    let mut text = vec![0; u16::from_be_bytes(length) as usize];
+    reader.read_exact(&mut text)?;
+
+    String::from_utf8(text).map_err(|error| std::io::Error::new(ErrorKind::InvalidData, error))
+}
+
+fn keep_alive(stream: &TcpStream) -> std::io::Result<()> {
+    SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(KEEPALIVE))
+}
+
+/// copy what arrives on `from` to `to` until either ends, then hang up both,
+/// which ends the pump of the other direction too
+fn pump(mut from: Box<dyn Connection>, mut to: Box<dyn Connection>) {
+    let _ = std::io::copy(&mut from, &mut to);
+
+    let _ = from.shutdown();
+    let _ = to.shutdown();
+}
+
+/// serve the clients of every server relaying through `listener` with
+/// `token`, each relay on its own thread
+pub fn serve(server: Server, listener: TcpListener, token: String) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    error!(error =? error, "accepting relay");
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+
+            let server = server.clone();
+            let token = token.clone();
+
+            let spawned = thread::Builder::new()
+                .name("relay".to_string())
+                .spawn(move || {
+                    if let Err(error) = serve_relay(&server, stream, &token) {
+                        warn!(error =? error, "relay");
+                    }
+                });
+
+            if let Err(error) = spawned {
+                error!(error =? error, "spawning relay thread");
+            }
+        }
+    });
+}
+
+#[instrument(skip_all, fields(address = ?stream.peer_addr().ok()))]
+fn serve_relay(server: &Server, stream: TcpStream, token: &str) -> std::io::Result<()> {
+    keep_alive(&stream)?;
+
+    // the streams keep the connection open until the relay hangs up
+    let connection = stream.try_clone()?;
+    let (mux, mut control) = Mux::new(stream.try_clone()?, stream, Side::Server);
+
+    control.set_read_timeout(Some(INTRODUCTION_TIMEOUT));
+
+    let name = match introduction(&mut control, token) {
+        Ok(name) => name,
+        Err(error) => {
+            let _ = connection.shutdown(std::net::Shutdown::Both);
+            return Err(error);
+        }
+    };
+
+    control.set_read_timeout(None);
+
+    info!(relay = name, "relay connected");
+
+    // the server notices the clients are gone like hung up connections once
+    // the relay connection ends their streams
+    while let Some(stream) = mux.accept() {
+        let server = server.clone();
+        let relay = name.clone();
+
+        let spawned = thread::Builder::new()
+            .name("relay-session".to_string())
+            .spawn(move || open(&server, &relay, stream));
+
+        if let Err(error) = spawned {
+            error!(error =? error, relay = name, "spawning relayed session thread");
+        }
+    }
+
+    info!(relay = name, "relay disconnected");
+
+    Ok(())
+}
+
+/// the name of a relay presenting `token` on its control stream
+fn introduction(control: &mut MuxStream, token: &str) -> std::io::Result<String> {
+    let name = read_text(control)?;
+
+    if read_text(control)? != token {
+        return Err(std::io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!("relay {name} presented a wrong token"),
+        ));
+    }
+
+    Ok(name)
+}
+
+/// serve a client relayed by `relay` on `stream`, once it told where the
+/// client connected from
+fn open(server: &Server, relay: &str, mut stream: MuxStream) {
+    stream.set_read_timeout(Some(INTRODUCTION_TIMEOUT));
+
+    let address = match read_text(&mut stream) {
+        Ok(address) => address,
+        Err(error) => {
+            warn!(error =? error, relay, "reading relayed client address");
+            return;
+        }
+    };
+
+    let network = NetworkInfo {
+        address: format!("{address} via {relay}"),
+        transport: Transport::Tcp,
+        connected_at: SystemTime::now(),
+    };
+
+    // replaced by the idle timeout of the server
+    if let Err(error) = server.connect(Box::new(stream), network) {
+        warn!(error =? error, relay, "opening relayed session");
+    }
+}
+
+/// a server relaying its clients to a central server
+struct Relaying {
+    name: String,
+    /// address of the relay listener of the central server
+    central: String,
+    /// shared with the central server
+    token: String,
+    /// the relay connection, none while connecting
+    mux: Mutex<Option<Mux>>,
+}
+
+/// accept clients on `listeners` and relay them to the server listening for
+/// relays at `central`, reconnecting whenever the relay connection is lost
+///
+/// clients connecting while there is no relay connection are hung up on and
+/// retry like they would with an unreachable server
+pub fn relay(
+    central: &str,
+    name: &str,
+    token: &str,
+    listeners: Vec<Listener>,
+) -> std::io::Result<()> {
+    let relaying = Arc::new(Relaying {
+        name: name.to_string(),
+        central: central.to_string(),
+        token: token.to_string(),
+        mux: Mutex::new(None),
+    });
+
+    for listener in listeners {
+        let relaying = relaying.clone();
+
+        thread::Builder::new()
+            .name("accept".to_string())
+            .spawn(move || relaying.accept(listener))?;
+    }
+
+    loop {
+        match relaying.connect() {
+            Ok(()) => info!(central, "central server closed the relay"),
+            Err(error) => warn!(error =? error, central, "relaying"),
+        }
+
+        // the streams of the clients ended with the relay connection, their
+        // pumps hang up on them
+        *relaying.mux.lock() = None;
+
+        thread::sleep(RETRY);
+    }
+}
+
+impl Relaying {
+    #[instrument(skip_all, fields(listener = listener.name))]
+    fn accept(self: Arc<Self>, listener: Listener) {
+        loop {
+            let (stream, network) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    error!(error =? error, "accepting connection");
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+
+            if let Err(error) = self.open(stream, &network) {
+                warn!(error =? error, address = network.address, "relaying client");
+            }
+        }
+    }
+
+    fn open(&self, stream: Box<dyn Connection>, network: &NetworkInfo) -> std::io::Result<()> {
+        let mut session = self
+            .mux
+            .lock()
+            .as_ref()
+            .map(Mux::open)
+            .ok_or_else(|| std::io::Error::other("not connected to the central server"))??;
+
+        write_text(&mut session, &network.address)?;
+
+        let reading = stream.try_clone_connection()?;
+        let writing = session.try_clone_connection()?;
+
+        thread::Builder::new()
+            .name("relay-session".to_string())
+            .spawn(move || pump(reading, writing))?;
+
+        thread::Builder::new()
+            .name("relay-session".to_string())
+            .spawn(move || pump(Box::new(session), stream))?;
+
+        Ok(())
+    }
+
+    /// relay until the connection to the central server is lost
+    fn connect(&self) -> std::io::Result<()> {
+        let stream = TcpStream::connect(&self.central)?;
+        keep_alive(&stream)?;
+
+        let (mux, mut control) = Mux::new(stream.try_clone()?, stream, Side::Client);
+
+        write_text(&mut control, &self.name)?;
+        write_text(&mut control, &self.token)?;
+
+        *self.mux.lock() = Some(mux);
+
+        info!(central = self.central, "relaying clients");
+
+        // nothing arrives on the control stream, it ends with the connection
+        control.read_to_end(&mut vec![])?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, time::Instant};
+
+    use pdtcore::{
+        BuiltInfo, ClientIntroduction, ClientMessage, Message, Protocol, ProtocolConfig,
+        ServerMessage,
+    };
+
+    use super::*;
+    use crate::listener::{ListenAddress, ListenerConfig};
+
+    #[test]
+    fn texts_read_back() {
+        let mut written = vec![];
+
+        write_text(&mut written, "parents").unwrap();
+        write_text(&mut written, "192.0.2.1:50000").unwrap();
+
+        let mut reader = written.as_slice();
+
+        assert_eq!(read_text(&mut reader).unwrap(), "parents");
+        assert_eq!(read_text(&mut reader).unwrap(), "192.0.2.1:50000");
+    }
+
+    #[test]
+    fn overlong_text_is_refused() {
+        let error = write_text(&mut vec![], &"a".repeat(1 << 16)).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn relayed_client_is_served_by_the_central_server() {
+        let mut server = Server::default();
+        server.run(vec![]);
+
+        let relays = TcpListener::bind("127.0.0.1:0").unwrap();
+        let central = relays.local_addr().unwrap().to_string();
+
+        serve(server.clone(), relays, "secret".to_string());
+
+        let listener = ListenerConfig::from_str("127.0.0.1:0")
+            .unwrap()
+            .bind()
+            .unwrap();
+        let ListenAddress::Tcp(address) = listener.local_address().unwrap() else {
+            panic!("tcp listener without an address");
+        };
+
+        thread::spawn(move || relay(&central, "parents", "secret", vec![listener]));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        // the relaying server hangs up until its relay connection is up
+        let client = loop {
+            assert!(Instant::now() < deadline, "timed out relaying");
+
+            let mut client = TcpStream::connect(address).unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+
+            let hello = Message::from(ServerMessage::Hello(Box::new(ClientIntroduction {
+                name: "kitchen".to_string(),
+                pdtcore_built_info: BuiltInfo::default(),
+                protocol: ProtocolConfig::default(),
+            })));
+
+            if hello.send(&mut client).is_ok() {
+                if let Ok(Message::Client(ClientMessage::Welcome(_))) =
+                    Message::receive(&mut client)
+                {
+                    break client;
+                }
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        };
+
+        let clients = server.get_clients();
+
+        assert_eq!(clients.len(), 1);
+        assert!(clients[0].network.address.ends_with(" via parents"));
+
+        drop(client);
+
+        while !server.get_clients().is_empty() {
+            assert!(
+                Instant::now() < deadline,
+                "relayed client was not disconnected"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        server.stop();
+    }
+
+    #[test]
+    fn relay_with_a_wrong_token_is_refused() {
+        let mut server = Server::default();
+        server.run(vec![]);
+
+        let relays = TcpListener::bind("127.0.0.1:0").unwrap();
+        let central = relays.local_addr().unwrap();
+
+        serve(server.clone(), relays, "secret".to_string());
+
+        let stream = TcpStream::connect(central).unwrap();
+        let (_mux, mut control) = Mux::new(stream.try_clone().unwrap(), stream, Side::Client);
+
+        write_text(&mut control, "parents").unwrap();
+        write_text(&mut control, "guessed").unwrap();
+
+        // hung up on instead of waiting for streams
+        control.read_to_end(&mut vec![]).unwrap();
+
+        server.stop();
+    }
+}
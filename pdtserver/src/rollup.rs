@@ -0,0 +1,149 @@
+//! figures over the latest telemetry of every client, so the state of the
+//! whole fleet is seen without opening each client
+
+use std::time::Duration;
+
+use pdtcore::Client;
+
+use crate::telemetry::Sample;
+
+/// share of memory in use above which a client counts as under pressure
+pub const MEMORY_PRESSURE: f64 = 0.8;
+
+/// how far back uptime is counted
+const WEEK: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Rollup {
+    pub clients: usize,
+    /// clients that sent telemetry
+    pub reporting: usize,
+    /// sum of the one minute load averages
+    pub total_load: f64,
+    pub memory_used: u64,
+    pub memory_total: u64,
+    /// clients using more than [`MEMORY_PRESSURE`] of their memory
+    pub memory_pressure: usize,
+    /// time every client was up over the past week, counted from its boot
+    pub uptime_this_week: Duration,
+}
+
+impl Rollup {
+    pub fn average_load(&self) -> Option<f64> {
+        (self.reporting > 0).then(|| self.total_load / self.reporting as f64)
+    }
+}
+
+impl From<Rollup> for pdtapi::FleetRollup {
+    fn from(value: Rollup) -> Self {
+        Self {
+            clients: value.clients as u64,
+            reporting: value.reporting as u64,
+            total_load: value.total_load,
+            average_load: value.average_load(),
+            memory_used: value.memory_used,
+            memory_total: value.memory_total,
+            memory_pressure: value.memory_pressure as u64,
+            uptime_this_week: value.uptime_this_week.as_secs(),
+        }
+    }
+}
+
+/// roll up `clients` along with the latest telemetry sample of each
+pub fn rollup(clients: &[(Client, Option<Sample>)]) -> Rollup {
+    let mut rollup = Rollup {
+        clients: clients.len(),
+        ..Default::default()
+    };
+
+    for (client, sample) in clients {
+        if let Ok(uptime) = humantime::parse_duration(&client.device_info.uptime) {
+            rollup.uptime_this_week += uptime.min(WEEK);
+        }
+
+        let Some(sample) = sample else {
+            continue;
+        };
+
+        let telemetry = &sample.telemetry;
+
+        rollup.reporting += 1;
+        rollup.total_load += telemetry.load;
+        rollup.memory_used += telemetry.memory_used;
+        rollup.memory_total += telemetry.memory_total;
+
+        if telemetry.memory_total > 0
+            && telemetry.memory_used as f64 / telemetry.memory_total as f64 > MEMORY_PRESSURE
+        {
+            rollup.memory_pressure += 1;
+        }
+    }
+
+    rollup
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use pdtcore::{ConnectionState, DeviceInfo, NetworkInfo, Telemetry, Transport};
+
+    use super::*;
+
+    fn client(uptime: &str) -> Client {
+        Client {
+            id: "01H0000000000000000000000".to_string(),
+            state: ConnectionState::Connected,
+            device_info: DeviceInfo {
+                uptime: uptime.to_string(),
+                ..Default::default()
+            },
+            last_seen: SystemTime::UNIX_EPOCH,
+            network: NetworkInfo {
+                address: "192.0.2.1:50000".to_string(),
+                transport: Transport::Tcp,
+                connected_at: SystemTime::UNIX_EPOCH,
+            },
+            protocol_version: None,
+            identity: None,
+        }
+    }
+
+    fn sample(load: f64, memory_used: u64) -> Option<Sample> {
+        Some(Sample {
+            at: SystemTime::UNIX_EPOCH,
+            telemetry: Telemetry {
+                load,
+                memory_total: 8 << 30,
+                memory_used,
+                network_received: 0,
+                network_transmitted: 0,
+            },
+        })
+    }
+
+    #[test]
+    fn clients_roll_up() {
+        let rollup = rollup(&[
+            (client("2h"), sample(0.5, 2 << 30)),
+            (client("30days"), sample(1.5, 7 << 30)),
+            (client("unknown"), None),
+        ]);
+
+        assert_eq!(rollup.clients, 3);
+        assert_eq!(rollup.reporting, 2);
+        assert_eq!(rollup.total_load, 2.0);
+        assert_eq!(rollup.average_load(), Some(1.0));
+        assert_eq!(rollup.memory_used, 9 << 30);
+        assert_eq!(rollup.memory_pressure, 1);
+        assert_eq!(
+            rollup.uptime_this_week,
+            WEEK + Duration::from_secs(2 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn nothing_reported_has_no_average() {
+        assert_eq!(rollup(&[]).average_load(), None);
+    }
+}
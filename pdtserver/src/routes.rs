@@ -0,0 +1,531 @@
+//! handlers of the messages an introduced client sends, registered per kind
+//! of message so a new kind is one more handler instead of one more arm
+//!
+//! the introduction and the goodbye change the session and are handled by
+//! the state machine itself, see [`crate::state::Session`]
+//!
+//! custom messages are routed by their namespace instead, the ones of a
+//! namespace without a handler are answered with a nack
+
+use std::{collections::HashMap, time::SystemTime};
+
+use pdtcore::{Client, ClientMessage, ServerMessage};
+use tracing::*;
+
+use crate::{
+    approval,
+    client_logs::Batch,
+    crashes::Crash,
+    server::ClientEvent,
+    state::{Effect, ServerClient},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    Hello,
+    Goodbye,
+    DeviceInfo,
+    DeviceInfoDelta,
+    Telemetry,
+    Executed,
+    Logs,
+    CrashReport,
+    Custom,
+    Nack,
+    Containers,
+    Identity,
+    Alive,
+}
+
+impl MessageKind {
+    pub fn of(message: &ServerMessage) -> Self {
+        match message {
+            ServerMessage::Hello(_) => MessageKind::Hello,
+            ServerMessage::Goodbye => MessageKind::Goodbye,
+            ServerMessage::DeviceInfo(_) => MessageKind::DeviceInfo,
+            ServerMessage::DeviceInfoDelta(_) => MessageKind::DeviceInfoDelta,
+            ServerMessage::Telemetry(_) => MessageKind::Telemetry,
+            ServerMessage::Executed(_) => MessageKind::Executed,
+            ServerMessage::Logs(_) => MessageKind::Logs,
+            ServerMessage::CrashReport(_) => MessageKind::CrashReport,
+            ServerMessage::Custom { .. } => MessageKind::Custom,
+            ServerMessage::Nack { .. } => MessageKind::Nack,
+            ServerMessage::Containers(_) => MessageKind::Containers,
+            ServerMessage::Identity(_) => MessageKind::Identity,
+            ServerMessage::Alive => MessageKind::Alive,
+        }
+    }
+}
+
+/// what a handler gets to work with, the client the message came from and
+/// where to put what the server has to do about it
+#[derive(Debug)]
+pub struct Context<'a> {
+    pub client: &'a mut ServerClient,
+    /// when the message arrived
+    pub at: SystemTime,
+    effects: Vec<Effect>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(client: &'a mut ServerClient, at: SystemTime) -> Self {
+        Self {
+            client,
+            at,
+            effects: vec![],
+        }
+    }
+
+    pub fn emit(&mut self, effect: Effect) {
+        self.effects.push(effect);
+    }
+
+    /// tell web interface subscribers the client changed
+    pub fn updated(&mut self) {
+        let client = Client::from(&*self.client);
+
+        self.emit(Effect::Publish(Box::new(ClientEvent::Updated(client))));
+    }
+
+    pub fn into_effects(self) -> Vec<Effect> {
+        self.effects
+    }
+}
+
+/// handlers are handed only messages of the kind they are registered for
+pub type Handler = fn(&mut Context, ServerMessage);
+
+#[derive(Debug, Clone)]
+pub struct Routes {
+    handlers: HashMap<MessageKind, Handler>,
+    /// handlers of custom messages by namespace
+    namespaces: HashMap<String, Handler>,
+}
+
+impl Default for Routes {
+    fn default() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            namespaces: HashMap::new(),
+        }
+        .route(MessageKind::DeviceInfo, device_info)
+        .route(MessageKind::DeviceInfoDelta, device_info_delta)
+        .route(MessageKind::Telemetry, telemetry)
+        .route(MessageKind::Executed, executed)
+        .route(MessageKind::Logs, logs)
+        .route(MessageKind::CrashReport, crash_report)
+        .route(MessageKind::Nack, nack)
+        .route(MessageKind::Containers, containers)
+        .route(MessageKind::Identity, identity)
+        .route(MessageKind::Alive, alive)
+    }
+}
+
+impl Routes {
+    /// handle messages of `kind` with `handler`, replacing the one before
+    pub fn route(mut self, kind: MessageKind, handler: Handler) -> Self {
+        self.handlers.insert(kind, handler);
+        self
+    }
+
+    /// handle custom messages of `namespace` with `handler`, replacing the
+    /// one before
+    // the stock server knows no namespaces, forks register theirs here
+    #[allow(dead_code)]
+    pub fn route_custom(mut self, namespace: impl Into<String>, handler: Handler) -> Self {
+        self.namespaces.insert(namespace.into(), handler);
+        self
+    }
+
+    /// hand the message to its handler, messages without one are logged and
+    /// dropped, custom ones are answered with a nack
+    pub fn handle(&self, context: &mut Context, message: ServerMessage) {
+        if let ServerMessage::Custom { namespace, .. } = &message {
+            match self.namespaces.get(namespace) {
+                Some(handler) => handler(context, message),
+                None => {
+                    debug!(client_id =? context.client.id, namespace, "custom message of an unknown namespace");
+
+                    let id = context.client.id;
+                    let namespace = namespace.clone();
+
+                    context.emit(Effect::Send(id, ClientMessage::Nack { namespace }));
+                }
+            }
+
+            return;
+        }
+
+        let kind = MessageKind::of(&message);
+
+        match self.handlers.get(&kind) {
+            Some(handler) => handler(context, message),
+            None => warn!(client_id =? context.client.id, kind =? kind, "no handler for message"),
+        }
+    }
+}
+
+fn device_info(context: &mut Context, message: ServerMessage) {
+    let ServerMessage::DeviceInfo(info) = message else {
+        return;
+    };
+
+    context.client.device_info = Some(info);
+    context.updated();
+}
+
+/// changes only make sense on top of the full info the client sends first
+fn device_info_delta(context: &mut Context, message: ServerMessage) {
+    let ServerMessage::DeviceInfoDelta(delta) = message else {
+        return;
+    };
+
+    let Some(info) = &mut context.client.device_info else {
+        warn!(client_id =? context.client.id, "device info changes before device info");
+        return;
+    };
+
+    if delta.is_empty() {
+        return;
+    }
+
+    info.apply(delta);
+    context.updated();
+}
+
+fn telemetry(context: &mut Context, message: ServerMessage) {
+    let ServerMessage::Telemetry(telemetry) = message else {
+        return;
+    };
+
+    context.client.latency.answered(context.at);
+    context.client.telemetry.push(context.at, telemetry);
+}
+
+/// a telemetry request arriving before the tuned interval passed, still a
+/// round trip
+fn alive(context: &mut Context, _message: ServerMessage) {
+    context.client.latency.answered(context.at);
+}
+
+fn executed(context: &mut Context, message: ServerMessage) {
+    let ServerMessage::Executed(execution) = message else {
+        return;
+    };
+
+    let id = context.client.id;
+
+    let span = info_span!("executed", client_id = %id);
+    pdttracing::otel::set_parent(&span, &execution.trace);
+
+    let _entered = span.enter();
+
+    let outcome = match &execution.error {
+        Some(error) => format!("failed: {error}"),
+        None => "done".to_string(),
+    };
+
+    info!(command = execution.command, outcome, "command executed");
+
+    context.emit(Effect::Publish(Box::new(ClientEvent::CommandResult(
+        id,
+        context
+            .client
+            .device_info
+            .as_ref()
+            .map(|device_info| device_info.name.clone())
+            .unwrap_or_default(),
+        format!("{} {outcome}", execution.command),
+    ))));
+}
+
+fn logs(context: &mut Context, message: ServerMessage) {
+    let ServerMessage::Logs(records) = message else {
+        return;
+    };
+
+    context.client.logs.push(records.clone());
+    context.emit(Effect::PublishLogs(Batch {
+        client_id: context.client.id,
+        records,
+    }));
+}
+
+fn crash_report(context: &mut Context, message: ServerMessage) {
+    let ServerMessage::CrashReport(report) = message else {
+        return;
+    };
+
+    let device_name = Client::from(&*context.client).device_info.name;
+
+    warn!(
+        client_id =? context.client.id,
+        device_name,
+        version = report.built_info.pkg_version,
+        message = report.message,
+        "client crashed"
+    );
+
+    context.emit(Effect::KeepCrash(Box::new(Crash {
+        client_id: context.client.id,
+        device_name,
+        received_at: context.at,
+        report: *report,
+    })));
+}
+
+fn containers(context: &mut Context, message: ServerMessage) {
+    let ServerMessage::Containers(containers) = message else {
+        return;
+    };
+
+    context.client.containers = Some(containers);
+}
+
+/// only the fingerprint is kept, approvals are made for it
+fn identity(context: &mut Context, message: ServerMessage) {
+    let ServerMessage::Identity(key) = message else {
+        return;
+    };
+
+    context.client.identity = Some(approval::fingerprint(&key));
+    context.updated();
+}
+
+/// the client does not know a custom message the server sent
+fn nack(context: &mut Context, message: ServerMessage) {
+    let ServerMessage::Nack { namespace } = message else {
+        return;
+    };
+
+    debug!(client_id =? context.client.id, namespace, "custom message refused by the client");
+}
+
+#[cfg(test)]
+mod tests {
+    use pdtcore::{
+        ConnectionState, Containers, DeviceInfo, DeviceInfoDelta, LogLevel, LogRecord, NetworkInfo,
+        ProtocolConfig, Telemetry, TelemetryConfig, Transport,
+    };
+    use ulid::Ulid;
+
+    use super::*;
+    use crate::{
+        client_logs::ClientLogs, latency::Latency, state::Session, telemetry::TelemetrySeries,
+    };
+
+    fn client() -> ServerClient {
+        ServerClient {
+            id: Ulid::from(1),
+            session: Session::Active,
+            pdtcore_built_info: None,
+            device_info: None,
+            last_seen: SystemTime::UNIX_EPOCH,
+            network: NetworkInfo {
+                address: "192.0.2.1:50000".to_string(),
+                transport: Transport::Tcp,
+                connected_at: SystemTime::UNIX_EPOCH,
+            },
+            telemetry: TelemetrySeries::default(),
+            latency: Latency::default(),
+            logs: ClientLogs::default(),
+            protocol: ProtocolConfig::default(),
+            containers: None,
+            identity: None,
+            telemetry_config: TelemetryConfig::default(),
+            ticked_at: None,
+        }
+    }
+
+    #[test]
+    fn messages_reach_the_handler_of_their_kind() {
+        let mut client = client();
+        let mut context = Context::new(&mut client, SystemTime::UNIX_EPOCH);
+
+        Routes::default().handle(
+            &mut context,
+            ServerMessage::DeviceInfo(DeviceInfo::default()),
+        );
+
+        let effects = context.into_effects();
+
+        assert_eq!(client.device_info, Some(DeviceInfo::default()));
+        assert_eq!(
+            effects,
+            vec![Effect::Publish(Box::new(ClientEvent::Updated(
+                Client::from(&client)
+            )))]
+        );
+        assert_eq!(Client::from(&client).state, ConnectionState::Connected);
+    }
+
+    #[test]
+    fn device_info_changes_update_the_stored_info() {
+        let mut client = client();
+        client.device_info = Some(DeviceInfo::default());
+
+        let mut context = Context::new(&mut client, SystemTime::UNIX_EPOCH);
+
+        Routes::default().handle(
+            &mut context,
+            ServerMessage::DeviceInfoDelta(DeviceInfoDelta {
+                uptime: Some("3h".to_string()),
+                ..DeviceInfoDelta::default()
+            }),
+        );
+
+        let effects = context.into_effects();
+
+        assert_eq!(
+            client.device_info,
+            Some(DeviceInfo {
+                uptime: "3h".to_string(),
+                ..DeviceInfo::default()
+            })
+        );
+        assert_eq!(
+            effects,
+            vec![Effect::Publish(Box::new(ClientEvent::Updated(
+                Client::from(&client)
+            )))]
+        );
+    }
+
+    #[test]
+    fn device_info_changes_without_device_info_are_ignored() {
+        let mut client = client();
+        let mut context = Context::new(&mut client, SystemTime::UNIX_EPOCH);
+
+        Routes::default().handle(
+            &mut context,
+            ServerMessage::DeviceInfoDelta(DeviceInfoDelta {
+                name: Some("ash".to_string()),
+                ..DeviceInfoDelta::default()
+            }),
+        );
+
+        assert!(context.into_effects().is_empty());
+        assert_eq!(client.device_info, None);
+    }
+
+    #[test]
+    fn registered_handler_replaces_the_default() {
+        fn ignore(_: &mut Context, _: ServerMessage) {}
+
+        let mut client = client();
+        let mut context = Context::new(&mut client, SystemTime::UNIX_EPOCH);
+
+        Routes::default()
+            .route(MessageKind::Telemetry, ignore)
+            .handle(
+                &mut context,
+                ServerMessage::Telemetry(Telemetry {
+                    load: 0.5,
+                    memory_total: 8 << 30,
+                    memory_used: 2 << 30,
+                    network_received: 1000,
+                    network_transmitted: 100,
+                }),
+            );
+
+        assert!(context.into_effects().is_empty());
+        assert!(client.telemetry.samples().is_empty());
+    }
+
+    #[test]
+    fn forwarded_logs_are_kept_and_published() {
+        let mut client = client();
+        let mut context = Context::new(&mut client, SystemTime::UNIX_EPOCH);
+
+        let records = vec![LogRecord {
+            at: 1_700_000_000_000,
+            level: LogLevel::Warn,
+            target: "pdtclient".to_string(),
+            message: "connection lost".to_string(),
+        }];
+
+        Routes::default().handle(&mut context, ServerMessage::Logs(records.clone()));
+
+        assert_eq!(
+            context.into_effects(),
+            vec![Effect::PublishLogs(Batch {
+                client_id: Ulid::from(1),
+                records: records.clone(),
+            })]
+        );
+        assert_eq!(client.logs.records(), records);
+    }
+
+    #[test]
+    fn reported_containers_replace_the_ones_before() {
+        let mut client = client();
+        let mut context = Context::new(&mut client, SystemTime::UNIX_EPOCH);
+
+        let containers = Containers {
+            running: 0,
+            stopped: 1,
+            stats: vec![],
+        };
+
+        Routes::default().handle(
+            &mut context,
+            ServerMessage::Containers(Containers {
+                running: 1,
+                ..containers.clone()
+            }),
+        );
+        Routes::default().handle(&mut context, ServerMessage::Containers(containers.clone()));
+
+        assert!(context.into_effects().is_empty());
+        assert_eq!(client.containers, Some(containers));
+    }
+
+    fn custom(namespace: &str) -> ServerMessage {
+        ServerMessage::Custom {
+            namespace: namespace.to_string(),
+            payload: vec![1, 2, 3],
+        }
+    }
+
+    #[test]
+    fn custom_messages_of_unknown_namespaces_are_nacked() {
+        let mut client = client();
+        let mut context = Context::new(&mut client, SystemTime::UNIX_EPOCH);
+
+        Routes::default().handle(&mut context, custom("org.example.kiosk"));
+
+        assert_eq!(
+            context.into_effects(),
+            vec![Effect::Send(
+                Ulid::from(1),
+                ClientMessage::Nack {
+                    namespace: "org.example.kiosk".to_string()
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn custom_messages_reach_the_handler_of_their_namespace() {
+        fn kiosk(context: &mut Context, message: ServerMessage) {
+            if let ServerMessage::Custom { payload, .. } = message {
+                context.client.logs.push(vec![LogRecord {
+                    at: payload.len() as u64,
+                    level: LogLevel::Info,
+                    target: "kiosk".to_string(),
+                    message: String::new(),
+                }]);
+            }
+        }
+
+        let mut client = client();
+        let mut context = Context::new(&mut client, SystemTime::UNIX_EPOCH);
+
+        Routes::default()
+            .route_custom("org.example.kiosk", kiosk)
+            .handle(&mut context, custom("org.example.kiosk"));
+
+        assert!(context.into_effects().is_empty());
+        assert_eq!(client.logs.records()[0].at, 3);
+    }
+}
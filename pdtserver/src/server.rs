@@ -1,67 +1,75 @@
 use std::{
-    collections::HashMap,
-    io::{Read, Write},
-    net::TcpListener,
+    collections::{BTreeMap, HashMap},
+    io::Read,
+    panic::AssertUnwindSafe,
     sync::{
         mpsc::{self, RecvError},
-        Arc, Mutex, MutexGuard, PoisonError,
+        Arc,
     },
+    thread,
+    time::{Duration, SystemTime},
 };
 
+use parking_lot::{Condvar, Mutex};
 use pdtcore::{
-    BuiltInfo, Client, ClientMessage, DeviceInfo, Message, ProtocolError, ServerMessage,
+    codec::{MessageReader, MessageWriter},
+    recording::{Direction, Recorder},
+    Particularity,
 };
-use pdtcore::{Particularity, Protocol};
+use pdtcore::{
+    BuiltInfo, Client, ClientMessage, Containers, LogRecord, Message, NetworkInfo, ProtocolConfig,
+    ProtocolError, ServerMessage, TelemetryConfig, TraceContext,
+};
+use tokio::sync::broadcast;
 use tracing::*;
 
 use ulid::Ulid;
 
+use crate::bandwidth::{Bandwidth, Metered, Throttle, Usage};
+use crate::client_logs::Batch;
+use crate::crashes::{Crash, Crashes};
+use crate::latency::LatencyStats;
+use crate::listener::{Connection, ListenAddress, Listener, ACCEPT_BACKOFF};
+use crate::outgoing::{self, Priority};
+use crate::registry::RegistryCounts;
+use crate::settings::SettingsReference;
+use crate::state::{Effect, Event, ServerState};
+use crate::telemetry::Sample;
+use crate::watchdog::{self, Watchdog};
+use crate::workers::Workers;
+
 type AddressedMessage = (Ulid, Message);
-type ClientSender = mpsc::Sender<Message>;
-type ClientReceiver = mpsc::Receiver<Message>;
+type ClientSender = outgoing::Sender;
+type ClientReceiver = outgoing::Receiver;
 type ServerSender = mpsc::Sender<ServerEvent>;
 type ServerReceiver = mpsc::Receiver<ServerEvent>;
 type ServerSenderReference = Particularity<ServerSender>;
 type ServerReceiverReference = Particularity<ServerReceiver>;
-type ServerClientMap = Particularity<HashMap<Ulid, ServerClient>>;
+type StateReference = Particularity<ServerState>;
+type ConnectionsReference = Particularity<HashMap<Ulid, ClientSender>>;
 
 #[derive(Debug)]
 pub enum SendError {
     ClientNotFound,
     SendChannel,
-    Deadlock,
 }
 
 #[derive(Debug)]
 pub enum HandleError {
-    Deadlock,
     ReceiveChannel,
 }
 
 #[derive(Debug)]
 pub enum ReceiveError {
-    Deadlock,
     ChannelSend,
 }
 
-impl<T> From<PoisonError<T>> for HandleError {
-    fn from(_: PoisonError<T>) -> Self {
-        HandleError::Deadlock
-    }
-}
-
 impl From<RecvError> for HandleError {
     fn from(_value: RecvError) -> Self {
         HandleError::ReceiveChannel
     }
 }
 
-impl<T> From<PoisonError<T>> for ReceiveError {
-    fn from(_: PoisonError<T>) -> Self {
-        ReceiveError::Deadlock
-    }
-}
-
 impl<T> From<mpsc::SendError<T>> for ReceiveError {
     fn from(_value: mpsc::SendError<T>) -> Self {
         ReceiveError::ChannelSend
@@ -72,14 +80,21 @@ impl<T> From<mpsc::SendError<T>> for ReceiveError {
 pub enum ServerEvent {
     IncomingMessage(AddressedMessage),
     Unexpected(ProtocolError),
+    /// the server is stopping, no more events are handled
+    Stop,
 }
 
-#[derive(Debug, Clone)]
-pub struct ServerClient {
-    pub id: Ulid,
-    pub pdtcore_built_info: Option<BuiltInfo>,
-    device_info: Option<DeviceInfo>,
-    sender: ClientSender,
+/// change to the set of clients, published to web interface subscribers
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientEvent {
+    Joined(Client),
+    Updated(Client),
+    Left(Ulid),
+    /// periodic presence refresh, published with every telemetry request
+    Heartbeat(Client),
+    /// outcome of handing a command to a client connection, along with the
+    /// device name of the client deciding who may see it
+    CommandResult(Ulid, String, String),
 }
 
 #[derive(Clone)]
@@ -87,80 +102,218 @@ pub struct ServerClient {
 pub struct Server {
     incoming_server_event_sender: ServerSenderReference,
     incoming_server_event_receiver: ServerReceiverReference,
-    clients: ServerClientMap,
-    client_ids: Particularity<Vec<Ulid>>,
+    state: StateReference,
+    /// outgoing messages of each open client connection
+    connections: ConnectionsReference,
+    events: broadcast::Sender<ClientEvent>,
+    /// log records forwarded by clients, kept apart from the client events
+    /// as they are only of interest to the log page of their client
+    log_batches: broadcast::Sender<Batch>,
+    /// crashes clients reported
+    crashes: Particularity<Crashes>,
+    /// restarts of the message handler and whether it is degraded
+    watchdog: Arc<Watchdog>,
+    /// every message sent and received is recorded when set
+    recorder: Option<Recorder>,
+    /// bytes that went over each open client connection
+    bandwidth: Particularity<HashMap<Ulid, Arc<Bandwidth>>>,
+    /// per-device limits of bulk messages are looked up here when set
+    settings: Option<SettingsReference>,
+    /// telemetry is requested every heartbeat interval and clients silent
+    /// for the idle timeout are dropped
+    protocol: ProtocolConfig,
+    stopping: Arc<StopSignal>,
+    /// threads serving the server and its clients
+    workers: Arc<Workers>,
+    /// where the listeners of [`Server::run`] are reached, to wake them
+    listening: Particularity<Vec<ListenAddress>>,
 }
 
 impl Default for Server {
     fn default() -> Self {
         let (tx, rx) = mpsc::channel();
 
+        let mut state = ServerState::default();
+        state.set_up_since(SystemTime::now());
+
         Self {
             incoming_server_event_sender: Arc::new(Mutex::new(tx)),
             incoming_server_event_receiver: Arc::new(Mutex::new(rx)),
-            clients: Arc::new(Mutex::new(HashMap::new())),
-            client_ids: Arc::new(Mutex::new(vec![])),
+            state: Arc::new(Mutex::new(state)),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            events: broadcast::channel(64).0,
+            log_batches: broadcast::channel(64).0,
+            crashes: Arc::new(Mutex::new(Crashes::default())),
+            watchdog: Arc::new(Watchdog::default()),
+            recorder: None,
+            bandwidth: Arc::new(Mutex::new(HashMap::new())),
+            settings: None,
+            protocol: ProtocolConfig::default(),
+            stopping: Arc::new(StopSignal::default()),
+            workers: Arc::new(Workers::default()),
+            listening: Arc::new(Mutex::new(Vec::new())),
         }
     }
 }
 
+/// set once by [`Server::stop`], waking the threads waiting for it
+#[derive(Debug, Default)]
+struct StopSignal {
+    stopped: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl StopSignal {
+    /// returns whether it was stopped already
+    fn stop(&self) -> bool {
+        let mut stopped = self.stopped.lock();
+
+        let already = std::mem::replace(&mut *stopped, true);
+        self.changed.notify_all();
+
+        already
+    }
+
+    fn is_stopped(&self) -> bool {
+        *self.stopped.lock()
+    }
+
+    /// wait at most `timeout` for the stop, returning whether it came
+    fn wait(&self, timeout: Duration) -> bool {
+        let mut stopped = self.stopped.lock();
+
+        self.changed
+            .wait_while_for(&mut stopped, |stopped| !*stopped, timeout);
+
+        *stopped
+    }
+}
+
+/// record a message if recording, a failure is logged but does not affect
+/// the connection
+fn record(recorder: Option<&Recorder>, direction: Direction, id: &str, message: &Message) {
+    let Some(recorder) = recorder else {
+        return;
+    };
+
+    if let Err(error) = recorder.record(direction, id, message) {
+        warn!(error =? error, client_id = id, "recording message");
+    }
+}
+
 impl Server {
     #[instrument(skip_all)]
     pub fn handle_messages(&self) -> Result<(), HandleError> {
         loop {
-            let receiver = self.incoming_server_event_receiver.lock()?;
+            let receiver = self.incoming_server_event_receiver.lock();
             let event = receiver.recv()?;
 
             info!(event = ?event, "handling event");
 
             match event {
-                ServerEvent::IncomingMessage((id, message)) => match message {
-                    Message::Client(_) => unreachable!(),
-                    Message::Server(message) => match message {
-                        ServerMessage::Hello(introduction) => {
-                            let pdtcore_built_info = BuiltInfo::default();
+                ServerEvent::IncomingMessage((id, message)) => self.dispatch(Event::Received {
+                    id,
+                    message,
+                    at: SystemTime::now(),
+                }),
+                ServerEvent::Unexpected(error) => error!(error = ?error),
+                ServerEvent::Stop => return Ok(()),
+            }
 
-                            let mut client_guard = self.clients.lock().unwrap();
-                            let client = client_guard.get_mut(&id).unwrap();
+            self.watchdog.handled();
+        }
+    }
 
-                            if !pdtcore_built_info.compatible(&introduction.pdtcore_built_info) {
-                                client.sender.send(ClientMessage::Goodbye.into()).unwrap();
-                            }
+    /// handle messages until stopped, restarting the handler whenever it
+    /// fails or panics, see [`crate::watchdog`]
+    #[instrument(skip_all)]
+    fn supervise_messages(&self) {
+        let mut failures = 0;
 
-                            client.pdtcore_built_info = Some(introduction.pdtcore_built_info);
+        loop {
+            match std::panic::catch_unwind(AssertUnwindSafe(|| self.handle_messages())) {
+                Ok(Ok(())) => return,
+                Ok(Err(error)) => error!(error =? error, "handling messages failed"),
+                Err(_) => error!("handling messages panicked"),
+            }
 
-                            client
-                                .sender
-                                .send(ClientMessage::RequestDeviceInfo.into())
-                                .unwrap();
-                        }
-                        ServerMessage::Goodbye => todo!(),
-                        ServerMessage::DeviceInfo(info) => {
-                            let mut client_guard = self.clients.lock().unwrap();
-                            let client = client_guard.get_mut(&id).unwrap();
+            if self.stopping() {
+                return;
+            }
 
-                            client.device_info = Some(info);
+            // failures in a row back off further, one after handling an
+            // event again starts over
+            failures = match self.watchdog.is_degraded() {
+                true => failures + 1,
+                false => 1,
+            };
+
+            // the locks held by a panicking handler are released as they
+            // were, the state is kept as far as the event got
+            self.watchdog.failed();
+
+            let wait = watchdog::backoff(failures);
+
+            warn!(
+                restarts = self.watchdog.restarts(),
+                wait =? wait,
+                "restarting message handler"
+            );
+
+            if self.stopping.wait(wait) {
+                return;
+            }
+        }
+    }
+
+    /// apply an event to the server state and carry out what it asks for
+    fn dispatch(&self, event: Event) {
+        let effects = self.state.lock().apply(event);
+
+        for effect in effects {
+            match effect {
+                Effect::Send(id, message) => {
+                    if let Some(sender) = self.connections.lock().get(&id) {
+                        if let Err(error) = sender.send(message.into()) {
+                            debug!(error =? error, client_id =? id, "queueing message");
                         }
-                    },
-                },
-                ServerEvent::Unexpected(error) => error!(error = ?error),
+                    }
+                }
+                Effect::Publish(event) => self.publish(*event),
+                Effect::PublishLogs(batch) => {
+                    // nobody tailing the logs of a client
+                    let _ = self.log_batches.send(batch);
+                }
+                Effect::KeepCrash(crash) => self.crashes.lock().push(*crash),
+                Effect::Disconnect(id) => {
+                    // dropping the sender ends the client's outgoing
+                    // messages and with them the connection
+                    self.connections.lock().remove(&id);
+                }
             }
         }
     }
 
-    #[instrument(skip(read))]
+    #[instrument(skip(read, recorder))]
     fn handle_client_incoming_messages(
         id: Ulid,
         read: &mut dyn Read,
         sender: ServerSenderReference,
+        recorder: Option<Recorder>,
+        max_frame_size: usize,
     ) -> Result<(), ReceiveError> {
         let mut ended = false;
+        let client_id = id.to_string();
+        let mut reader = MessageReader::new(read);
+        reader.set_max_frame_size(max_frame_size);
 
         while !ended {
-            let receive_result = Message::receive(read);
+            let receive_result = reader.receive();
 
             let event = match receive_result {
                 Ok(message) => {
+                    record(recorder.as_ref(), Direction::Received, &client_id, &message);
+
                     if message == Message::from(ServerMessage::Goodbye) {
                         ended = true;
                     }
@@ -172,19 +325,24 @@ impl Server {
                 }
             };
 
-            let mut guard = sender.lock()?;
-
-            let sender = &mut *guard;
-
-            sender.send(event)?;
+            sender.lock().send(event)?;
         }
 
         Ok(())
     }
 
-    fn handle_client_outgoing_messages(id: Ulid, write: &mut dyn Write, receiver: ClientReceiver) {
+    fn handle_client_outgoing_messages(
+        &self,
+        client_id: Ulid,
+        write: &mut Metered<Box<dyn Connection>>,
+        receiver: ClientReceiver,
+        bandwidth: &Bandwidth,
+    ) {
         let mut ended = false;
-        let id = id.to_string();
+        let id = client_id.to_string();
+        let mut throttle = Throttle::default();
+        let mut writer = MessageWriter::new(write);
+        writer.set_max_frame_size(self.protocol.max_frame_size());
 
         while !ended {
             let receive_result = receiver.recv();
@@ -198,11 +356,44 @@ impl Server {
                 }
             };
 
-            let send_result = message.send(write);
+            let bulk_limit = match Priority::of(&message) {
+                Priority::Bulk => self.bulk_limit(client_id),
+                _ => None,
+            };
+
+            if bulk_limit.is_some() {
+                throttle.wait();
+            }
+
+            let sent_before = bandwidth.usage(None).sent;
+            let send_result = writer.send(&message);
+
+            if let Some(limit) = bulk_limit {
+                throttle.written(bandwidth.usage(None).sent - sent_before, limit);
+            }
 
             match send_result {
                 Ok(_) => {
-                    info!(message =? message, client_id =? id, "sent")
+                    info!(message =? message, client_id =? id, "sent");
+                    record(self.recorder.as_ref(), Direction::Sent, &id, &message);
+
+                    // larger messages than agreed on are not sent, and the
+                    // connection is kept for as long as agreed on, from the
+                    // welcome on
+                    if let Message::Client(ClientMessage::Welcome(welcome)) = &message {
+                        writer.set_max_frame_size(welcome.protocol.max_frame_size());
+
+                        let timeout = welcome.protocol.idle_timeout();
+
+                        if let Err(error) =
+                            writer.get_ref().get_ref().set_idle_timeout(Some(timeout))
+                        {
+                            warn!(error =? error, client_id =? id, "setting the agreed idle timeout");
+                        }
+                    }
+                }
+                Err(ProtocolError::FrameTooLarge(size)) => {
+                    warn!(size, message =? message, client_id =? id, "message larger than the client accepts, dropped")
                 }
                 Err(error) => {
                     ended = true;
@@ -212,120 +403,447 @@ impl Server {
         }
     }
 
-    pub fn run(&mut self, tcp_listener: TcpListener) {
-        let incoming_message_sender = self.incoming_server_event_sender.clone();
-        let outgoing_message_senders = self.clients.clone();
-        let client_ids = self.client_ids.clone();
+    /// name told to clients when welcoming them
+    pub fn set_name(&mut self, name: String) {
+        self.state.lock().set_name(name);
+    }
+
+    /// connection settings reconciled with the ones each client proposes,
+    /// applied to connections accepted from now on
+    pub fn set_protocol(&mut self, protocol: ProtocolConfig) {
+        self.protocol = protocol;
+        self.state.lock().set_protocol(protocol);
+    }
+
+    /// pace bulk messages to the limits in the settings, reloading the
+    /// settings applies changed limits to connected clients too
+    pub fn use_settings(&mut self, settings: SettingsReference) {
+        self.settings = Some(settings);
+    }
 
+    /// bytes per second bulk messages to a client are paced to
+    fn bulk_limit(&self, id: Ulid) -> Option<u64> {
+        let device_name = self.get_client(id)?.device_info.name;
+
+        let settings = self.settings.as_ref()?.lock();
+
+        settings.bandwidth_limits.get(&device_name).copied()
+    }
+
+    /// bytes that went over a client connection since it was accepted
+    pub fn get_bandwidth(&self, id: Ulid) -> Option<Usage> {
+        let bandwidth = self.bandwidth.lock().get(&id)?.clone();
+
+        Some(bandwidth.usage(self.bulk_limit(id)))
+    }
+
+    /// record every message of every connection from now on
+    pub fn record_to(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn run(&mut self, listeners: Vec<Listener>) {
         let handle_message_self = self.clone();
 
-        std::thread::spawn(
-            // TODO: figure out what to do if we stop handling messages due to errors
-            //       should we resume/retry? exit? drop associated client?
-            move || match handle_message_self.clone().handle_messages() {
-                Ok(_) => {}
+        self.spawn("handle-messages", None, move || {
+            handle_message_self.supervise_messages()
+        });
+
+        let telemetry_self = self.clone();
+
+        self.spawn("request-telemetry", None, move || {
+            telemetry_self.request_telemetry()
+        });
+
+        for listener in listeners {
+            match listener.local_address() {
+                Ok(address) => self.listening.lock().push(address),
                 Err(error) => {
-                    error!(error =? error, "handle message")
+                    warn!(error =? error, listener = listener.name, "listener can not be woken to stop")
                 }
-            },
-        );
-
-        std::thread::spawn(move || {
-            for mut stream in tcp_listener.incoming().flatten() {
-                trace!(stream = ?stream, "handle incoming tcp stream");
-
-                let mut write_stream = match stream.try_clone() {
-                    Ok(stream) => stream,
-                    Err(error) => {
-                        error!(error =? error, stream = ?stream, "failed copying tcp_stream for writing");
-                        continue;
-                    }
-                };
+            }
+
+            let accept_self = self.clone();
+
+            self.spawn("accept", None, move || {
+                accept_self.accept_connections(listener)
+            });
+        }
+    }
+
+    /// run a thread of the server, it is up and running even if this fails
+    fn spawn(&self, name: &str, client_id: Option<Ulid>, work: impl FnOnce() + Send + 'static) {
+        if let Err(error) = self.workers.spawn(name, client_id, work) {
+            error!(error =? error, thread = name, "spawning thread");
+        }
+    }
+
+    /// stop accepting connections and say goodbye to every client, the
+    /// server can not be run again
+    #[instrument(skip_all)]
+    pub fn stop(&self) {
+        if self.stopping.stop() {
+            return;
+        }
+
+        info!("stopping");
 
-                let id = Ulid::new();
+        let listening = std::mem::take(&mut *self.listening.lock());
 
-                {
-                    let mut guard = client_ids.lock().unwrap();
+        // listeners only notice once the connection they wait for arrives
+        for address in listening {
+            if let Err(error) = address.poke() {
+                warn!(error =? error, address =? address, "waking listener");
+            }
+        }
+
+        // dropping the senders ends the outgoing messages of every client
+        // with a goodbye
+        self.connections.lock().clear();
+
+        let _ = self
+            .incoming_server_event_sender
+            .lock()
+            .send(ServerEvent::Stop);
+    }
+
+    /// wait for every thread of the server after [`Server::stop`], which
+    /// takes until every client hung up or stayed silent for the idle
+    /// timeout
+    #[instrument(skip_all)]
+    pub fn join(&self) {
+        self.workers.join();
 
-                    let client_ids = &mut *guard;
+        info!("stopped");
+    }
+
+    fn stopping(&self) -> bool {
+        self.stopping.is_stopped()
+    }
+
+    /// ask every connected client for telemetry as it is due, letting
+    /// subscribers refresh presence as it goes, and forget clients whose
+    /// connection thread panicked
+    #[instrument(skip_all)]
+    fn request_telemetry(&self) {
+        loop {
+            let interval = self.state.lock().tick_interval();
 
-                    client_ids.push(id);
+            if self.stopping.wait(interval) {
+                return;
+            }
+
+            for id in self.workers.reap() {
+                self.disconnect(id);
+            }
+
+            self.dispatch(Event::Tick {
+                at: SystemTime::now(),
+            });
+        }
+    }
+
+    #[instrument(skip_all, fields(listener = listener.name))]
+    fn accept_connections(&self, listener: Listener) {
+        loop {
+            let accepted = listener.accept();
+
+            if self.stopping() {
+                info!("stopped accepting connections");
+                return;
+            }
+
+            let (stream, network) = match accepted {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    error!(error =? error, "accepting connection");
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
                 }
+            };
 
-                let sender = incoming_message_sender.clone();
-                let (tx, rx) = mpsc::channel();
+            trace!(stream = ?stream, address = %network.address, "handle incoming stream");
 
-                let client = ServerClient {
-                    id,
-                    pdtcore_built_info: None,
-                    sender: tx,
-                    device_info: None,
-                };
+            if let Err(error) = self.connect(stream, network) {
+                error!(error =? error, "failed copying stream for writing");
+            }
+        }
+    }
 
-                std::thread::spawn(move || {
-                    Server::handle_client_incoming_messages(id, &mut stream, sender)
-                });
+    /// register a client on an established connection and serve it until
+    /// the connection ends
+    pub fn connect(
+        &self,
+        stream: Box<dyn Connection>,
+        network: NetworkInfo,
+    ) -> std::io::Result<Ulid> {
+        // a half-open connection fails the reads and ends like a closed one
+        stream.set_idle_timeout(Some(self.protocol.idle_timeout()))?;
 
-                let server_client_map = outgoing_message_senders.clone();
+        let write_stream = stream.try_clone_connection()?;
 
-                std::thread::spawn(move || {
-                    {
-                        let mut guard = server_client_map.lock().unwrap();
+        let (id, rx) = self.register(network)?;
 
-                        let client_map = &mut *guard;
+        let bandwidth = Arc::new(Bandwidth::default());
 
-                        client_map.insert(id, client);
-                    }
-                    Server::handle_client_outgoing_messages(id, &mut write_stream, rx);
-                    {
-                        let mut guard = server_client_map.lock().unwrap();
+        self.bandwidth.lock().insert(id, bandwidth.clone());
 
-                        let senders = &mut *guard;
+        let mut read_stream = Metered::new(stream, bandwidth.clone());
+        let mut write_stream = Metered::new(write_stream, bandwidth.clone());
 
-                        senders.remove(&id)
-                    }
-                });
+        let sender = self.incoming_server_event_sender.clone();
+        let recorder = self.recorder.clone();
+        let max_frame_size = self.protocol.max_frame_size();
+        let server = self.clone();
+
+        self.workers.spawn("client-incoming", Some(id), move || {
+            let result = Server::handle_client_incoming_messages(
+                id,
+                &mut read_stream,
+                sender,
+                recorder,
+                max_frame_size,
+            );
+
+            if let Err(error) = result {
+                warn!(error =? error, client_id =? id, "forwarding incoming message");
             }
+
+            // nothing more is coming, also ends the outgoing messages
+            server.disconnect(id);
+        })?;
+
+        let server = self.clone();
+
+        self.workers.spawn("client-outgoing", Some(id), move || {
+            server.handle_client_outgoing_messages(id, &mut write_stream, rx, &bandwidth);
+
+            server.disconnect(id);
+        })?;
+
+        Ok(id)
+    }
+
+    /// register a client served in-process, its messages are handed over
+    /// with [`Server::deliver`] and read from the returned receiver
+    pub fn connect_in_process(
+        &self,
+        network: NetworkInfo,
+    ) -> std::io::Result<(Ulid, ClientReceiver)> {
+        self.register(network)
+    }
+
+    fn register(&self, network: NetworkInfo) -> std::io::Result<(Ulid, ClientReceiver)> {
+        let id = Ulid::new();
+
+        let (tx, rx) = outgoing::channel();
+
+        let mut connections = self.connections.lock();
+
+        // checked under the lock, stopping clears the connections after
+        if self.stopping() {
+            return Err(std::io::Error::other("server stopped"));
+        }
+
+        connections.insert(id, tx);
+        drop(connections);
+
+        // registered before reading, the introduction is handled as soon as
+        // it arrives and expects the client to be known
+        self.dispatch(Event::Connected {
+            id,
+            network,
+            at: SystemTime::now(),
         });
+
+        Ok((id, rx))
     }
 
-    pub fn get_client_ids(&self) -> Result<Vec<Ulid>, PoisonError<MutexGuard<'_, Vec<Ulid>>>> {
-        let guard = self.client_ids.lock()?;
+    /// handle a message as if it was received from a client
+    pub fn deliver(&self, id: Ulid, message: ServerMessage) {
+        let _ = self
+            .incoming_server_event_sender
+            .lock()
+            .send(ServerEvent::IncomingMessage((id, message.into())));
+    }
+
+    /// forget a client whose connection ended
+    pub fn disconnect(&self, id: Ulid) {
+        self.connections.lock().remove(&id);
+        self.bandwidth.lock().remove(&id);
+
+        self.dispatch(Event::Disconnected { id });
+    }
 
-        let client_ids = &*guard;
+    /// telemetry to collect from clients by device name, the connected ones
+    /// are told right away
+    pub fn tune(&self, tunings: BTreeMap<String, TelemetryConfig>) {
+        self.dispatch(Event::Tuned(tunings));
+    }
+
+    /// publish an event to web interface subscribers, if there are any
+    fn publish(&self, event: ClientEvent) {
+        let _ = self.events.send(event);
+    }
 
-        Ok(client_ids.clone())
+    /// the sender events are published on, for subscribing without the server
+    pub fn events(&self) -> broadcast::Sender<ClientEvent> {
+        self.events.clone()
     }
 
+    /// restarts of the message handler, shared with handles
+    pub fn watchdog(&self) -> Arc<Watchdog> {
+        self.watchdog.clone()
+    }
+
+    /// the sender forwarded log records are published on
+    pub fn log_batches(&self) -> broadcast::Sender<Batch> {
+        self.log_batches.clone()
+    }
+
+    /// clients in the order they joined
     pub fn get_clients(&self) -> Vec<Client> {
-        let client_guard = self.clients.lock().unwrap();
-        let mut output = vec![];
-
-        for (id, server_client) in client_guard.iter() {
-            output.push(Client {
-                id: id.to_string(),
-                device_info: server_client.clone().device_info.unwrap_or_default(),
-            })
-        }
+        let state_guard = self.state.lock();
 
-        output
+        state_guard.registry().clients()
     }
 
-    pub fn send(&mut self, to: Ulid, message: Message) -> Result<(), SendError> {
-        let Ok(mut clients_guard) = self.clients.lock() else {
-            return Err(SendError::Deadlock);
-        };
+    /// clients in join order along with the pdtcore build they introduced
+    /// themselves with, if they did yet
+    pub fn get_builds(&self) -> Vec<(Client, Option<BuiltInfo>)> {
+        let state_guard = self.state.lock();
 
-        let clients = &mut *clients_guard;
+        state_guard
+            .registry()
+            .iter()
+            .map(|client| (Client::from(client), client.pdtcore_built_info.clone()))
+            .collect()
+    }
+
+    pub fn get_client(&self, id: Ulid) -> Option<Client> {
+        let state_guard = self.state.lock();
+
+        state_guard.registry().get(&id).map(Client::from)
+    }
+
+    /// stored telemetry samples of a client, oldest first
+    pub fn get_telemetry(&self, id: Ulid) -> Option<Vec<Sample>> {
+        let state_guard = self.state.lock();
+
+        state_guard
+            .registry()
+            .get(&id)
+            .map(|client| client.telemetry.samples())
+    }
+
+    /// clients in join order with their most recent telemetry sample
+    pub fn get_latest_telemetry(&self) -> Vec<(Client, Option<Sample>)> {
+        let state_guard = self.state.lock();
+
+        state_guard
+            .registry()
+            .iter()
+            .map(|client| (Client::from(client), client.telemetry.latest()))
+            .collect()
+    }
+
+    /// round trips of the telemetry requests to a client
+    pub fn get_latency(&self, id: Ulid) -> Option<LatencyStats> {
+        let state_guard = self.state.lock();
 
-        let Some(client) = clients.get(&to) else {
+        state_guard
+            .registry()
+            .get(&id)
+            .map(|client| client.latency.stats())
+    }
+
+    /// log records a client forwarded, oldest first
+    pub fn get_logs(&self, id: Ulid) -> Option<Vec<LogRecord>> {
+        let state_guard = self.state.lock();
+
+        state_guard
+            .registry()
+            .get(&id)
+            .map(|client| client.logs.records())
+    }
+
+    /// containers a client reported last, none for clients not reporting
+    /// them
+    pub fn get_containers(&self, id: Ulid) -> Option<Containers> {
+        let state_guard = self.state.lock();
+
+        state_guard
+            .registry()
+            .get(&id)
+            .and_then(|client| client.containers.clone())
+    }
+
+    /// crashes clients reported, newest first
+    pub fn get_crashes(&self) -> Vec<Crash> {
+        self.crashes.lock().recent()
+    }
+
+    pub fn get_counts(&self) -> RegistryCounts {
+        let state_guard = self.state.lock();
+
+        state_guard.registry().counts()
+    }
+
+    /// commands carry the trace context captured by the caller, so the
+    /// client's execution joins the trace of the span that sent them
+    #[instrument(skip(self, message, trace))]
+    pub fn send_traced(
+        &mut self,
+        to: Ulid,
+        message: Message,
+        trace: TraceContext,
+    ) -> Result<(), SendError> {
+        let state_guard = self.state.lock();
+
+        let Some(device_name) = state_guard
+            .registry()
+            .get(&to)
+            .map(|client| Client::from(client).device_info.name)
+        else {
             return Err(SendError::ClientNotFound);
         };
 
-        let Ok(_) = client.sender.send(message) else {
-            return Err(SendError::SendChannel);
+        drop(state_guard);
+
+        // commands are described without the direction wrapping them
+        let described: &dyn std::fmt::Debug = match &message {
+            Message::Client(message) => message,
+            message => message,
         };
+        let description = format!("{described:?}");
 
-        Ok(())
+        let message = match message {
+            Message::Client(message) if !trace.traceparent.is_empty() => {
+                Message::Client(ClientMessage::Traced(trace, Box::new(message)))
+            }
+            message => message,
+        };
+
+        let connections = self.connections.lock();
+
+        let result = match connections.get(&to) {
+            Some(sender) => sender.send(message).map_err(|_| SendError::SendChannel),
+            None => Err(SendError::SendChannel),
+        };
+
+        drop(connections);
+
+        let outcome = match result {
+            Ok(_) => "sent",
+            Err(_) => "failed",
+        };
+
+        self.publish(ClientEvent::CommandResult(
+            to,
+            device_name,
+            format!("{description} {outcome}"),
+        ));
+
+        result
     }
 }
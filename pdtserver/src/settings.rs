@@ -0,0 +1,395 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+use pdtcore::{ClientMessage, Particularity};
+use serde::Deserialize;
+use tracing::*;
+
+pub type SettingsReference = Particularity<Settings>;
+
+/// runtime settings, reloadable without restarting the server
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub tokens: Vec<AccessToken>,
+    pub users: Vec<User>,
+    /// hold back commands to clients until an admin approves them
+    pub client_approval: bool,
+    /// identity fingerprints approved ahead of time, admins approve more from
+    /// the web interface, which are kept in the approvals file
+    pub approved_clients: Vec<String>,
+    /// bytes per second bulk messages to a device are paced to, by device
+    /// name, for clients on metered or slow links
+    pub bandwidth_limits: HashMap<String, u64>,
+    /// other pdtservers whose clients are shown and controlled through this
+    /// one, see [`crate::federation`]
+    pub upstreams: Vec<Upstream>,
+    /// commands sent when an input changes, see [`crate::automation`]
+    pub rules: Vec<Rule>,
+    /// seconds after sending a command to a client in which the same command
+    /// to the same client is not sent again but recorded as coalesced, like
+    /// a rule and someone at the dashboard both turning a screen off, every
+    /// command is sent when 0
+    pub coalesce_seconds: u64,
+    /// seconds after sending a command to a device in which the same kind
+    /// of command to it is refused, like no restart within 300 seconds of
+    /// the last one, by command. coalesced commands are not refused
+    pub cooldowns: HashMap<CommandKind, u64>,
+}
+
+/// what a user or access token is allowed to do
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// control every client and use administrative endpoints such as
+    /// reloading settings
+    Admin,
+    /// control the permitted clients
+    Operator,
+    /// watch the dashboard without controlling anything, the role of users
+    /// and tokens without one so nobody controls devices by omission
+    #[default]
+    Viewer,
+}
+
+/// role and client restrictions shared by users and access tokens
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Permissions {
+    #[serde(default)]
+    pub role: Role,
+    /// device names assigned to the user or token, the only ones it sees
+    /// and an operator may control, all devices when empty
+    #[serde(default)]
+    pub clients: Vec<String>,
+    /// the only commands an operator may send and to which of its devices,
+    /// every command to every device it controls when empty
+    #[serde(default)]
+    pub grants: Vec<Grant>,
+}
+
+/// what a command sent to a client does, named like in the api
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommandKind {
+    ScreenOff,
+    ScreenOn,
+    PowerOff,
+    Restart,
+    Notify,
+}
+
+impl CommandKind {
+    /// none for the requests the server sends on its own
+    pub fn of(message: &ClientMessage) -> Option<Self> {
+        match message {
+            ClientMessage::ScreenOff => Some(CommandKind::ScreenOff),
+            ClientMessage::ScreenOn => Some(CommandKind::ScreenOn),
+            ClientMessage::PowerOff => Some(CommandKind::PowerOff),
+            ClientMessage::Restart => Some(CommandKind::Restart),
+            ClientMessage::Notify(_) => Some(CommandKind::Notify),
+            ClientMessage::Traced(_, message) => Self::of(message),
+            ClientMessage::RequestDeviceInfo
+            | ClientMessage::RequestTelemetry
+            | ClientMessage::ConfigUpdate(_)
+            | ClientMessage::Goodbye
+            | ClientMessage::Welcome(_)
+            | ClientMessage::Custom { .. }
+            | ClientMessage::Nack { .. } => None,
+        }
+    }
+}
+
+/// commands allowed for some devices
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Grant {
+    /// every command when empty
+    #[serde(default)]
+    pub commands: Vec<CommandKind>,
+    /// device names, every device when empty
+    #[serde(default)]
+    pub clients: Vec<String>,
+}
+
+impl Grant {
+    fn allows(&self, device_name: &str, command: CommandKind) -> bool {
+        (self.commands.is_empty() || self.commands.contains(&command))
+            && (self.clients.is_empty() || self.clients.iter().any(|name| name == device_name))
+    }
+}
+
+impl Permissions {
+    /// admins see every device, everyone else only the assigned ones
+    pub fn may_see(&self, device_name: &str) -> bool {
+        self.role == Role::Admin
+            || self.clients.is_empty()
+            || self.clients.iter().any(|name| name == device_name)
+    }
+
+    pub fn may_control(&self, device_name: &str) -> bool {
+        match self.role {
+            Role::Admin => true,
+            Role::Operator => {
+                self.clients.is_empty() || self.clients.iter().any(|name| name == device_name)
+            }
+            Role::Viewer => false,
+        }
+    }
+
+    /// whether `message` may be sent to the device, admins are not limited
+    /// by grants
+    pub fn may_send(&self, device_name: &str, message: &ClientMessage) -> bool {
+        if !self.may_control(device_name) {
+            return false;
+        }
+
+        match CommandKind::of(message) {
+            Some(command) if self.role == Role::Operator && !self.grants.is_empty() => self
+                .grants
+                .iter()
+                .any(|grant| grant.allows(device_name, command)),
+            _ => true,
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+}
+
+/// web interface login
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub username: String,
+    /// argon2 hash in PHC string format, see `pdtserver hash-password`
+    pub password_hash: String,
+    #[serde(flatten)]
+    pub permissions: Permissions,
+}
+
+/// bearer token granting access to the web interface
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AccessToken {
+    pub name: String,
+    pub token: String,
+    #[serde(flatten)]
+    pub permissions: Permissions,
+}
+
+/// another pdtserver rolled up into this one
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    /// shown next to its clients, an upstream named like this server is
+    /// never asked
+    pub name: String,
+    /// web interface address, e.g. `https://pdt.example.org`
+    pub url: String,
+    /// access token on the upstream, what it may see and control there
+    /// limits what is shown and sent through this server
+    pub token: Option<String>,
+}
+
+/// send a command when an input changes to a value
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    /// shown in the logs and the answer to setting the input
+    pub name: String,
+    pub input: String,
+    /// on unless set otherwise
+    #[serde(default = "on")]
+    pub value: bool,
+    pub command: pdtapi::Command,
+    /// device names to send the command to, every client when empty
+    #[serde(default)]
+    pub clients: Vec<String>,
+}
+
+fn on() -> bool {
+    true
+}
+
+impl Rule {
+    pub fn applies_to(&self, device_name: &str) -> bool {
+        self.clients.is_empty() || self.clients.iter().any(|name| name == device_name)
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Read(std::io::Error),
+    Parse(toml::de::Error),
+}
+
+impl From<std::io::Error> for SettingsError {
+    fn from(value: std::io::Error) -> Self {
+        SettingsError::Read(value)
+    }
+}
+
+impl From<toml::de::Error> for SettingsError {
+    fn from(value: toml::de::Error) -> Self {
+        SettingsError::Parse(value)
+    }
+}
+
+impl Settings {
+    /// read settings from a toml file, a missing file yields the defaults
+    pub fn load(path: &Path) -> Result<Self, SettingsError> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                warn!(path =? path, "settings file not found, using defaults");
+                return Ok(Self::default());
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        Ok(toml::from_str(&contents)?)
+    }
+
+    pub fn user(&self, username: &str) -> Option<&User> {
+        self.users.iter().find(|user| user.username == username)
+    }
+
+    pub fn token(&self, token: &str) -> Option<&AccessToken> {
+        self.tokens
+            .iter()
+            .find(|candidate| candidate.token == token)
+    }
+}
+
+impl From<Settings> for SettingsReference {
+    fn from(value: Settings) -> Self {
+        Arc::new(Mutex::new(value))
+    }
+}
+
+/// replace the shared settings with the current contents of `path`
+///
+/// the previous settings are kept if the file can not be read or parsed
+#[instrument(skip(settings))]
+pub fn reload(settings: &SettingsReference, path: &Path) -> Result<(), SettingsError> {
+    let reloaded = Settings::load(path)?;
+
+    let mut guard = settings.lock();
+    *guard = reloaded;
+
+    info!(
+        tokens = guard.tokens.len(),
+        users = guard.users.len(),
+        "settings reloaded"
+    );
+
+    Ok(())
+}
+
+/// reload settings every time the process receives SIGHUP
+pub async fn reload_on_hangup(settings: SettingsReference, path: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(error) => {
+            error!(error =? error, "could not listen for SIGHUP");
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("received SIGHUP");
+
+        if let Err(error) = reload(&settings, &path) {
+            error!(error =? error, "reloading settings");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grants_limit_the_commands_of_an_operator() {
+        let permissions: Permissions = toml::from_str(
+            r#"
+            role = "operator"
+
+            [[grants]]
+            commands = ["screen-off", "screen-on"]
+
+            [[grants]]
+            commands = ["restart"]
+            clients = ["kiosk"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(permissions.may_send("nas", &ClientMessage::ScreenOff));
+        assert!(permissions.may_send("kiosk", &ClientMessage::Restart));
+        assert!(!permissions.may_send("nas", &ClientMessage::Restart));
+        assert!(!permissions.may_send("kiosk", &ClientMessage::PowerOff));
+
+        let admin = Permissions {
+            role: Role::Admin,
+            ..permissions
+        };
+
+        assert!(admin.may_send("nas", &ClientMessage::PowerOff));
+    }
+
+    #[test]
+    fn permissions_without_a_role_control_nothing() {
+        let permissions: Permissions = toml::from_str(r#"clients = ["kiosk"]"#).unwrap();
+
+        assert_eq!(permissions.role, Role::Viewer);
+        assert!(permissions.may_see("kiosk"));
+        assert!(!permissions.may_see("nas"));
+        assert!(!permissions.may_control("kiosk"));
+    }
+
+    #[test]
+    fn cooldowns_are_set_by_command() {
+        let settings: Settings = toml::from_str(
+            r#"
+            [cooldowns]
+            restart = 300
+            power-off = 600
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(settings.cooldowns.get(&CommandKind::Restart), Some(&300));
+        assert_eq!(settings.cooldowns.get(&CommandKind::ScreenOff), None);
+    }
+
+    #[test]
+    fn unknown_command_in_a_grant_is_rejected() {
+        assert!(toml::from_str::<Permissions>("[[grants]]\ncommands = [\"reboot\"]").is_err());
+    }
+
+    #[test]
+    fn settings_reload_after_a_worker_panicked_holding_them() {
+        let settings = SettingsReference::from(Settings {
+            client_approval: true,
+            ..Settings::default()
+        });
+
+        let held = settings.clone();
+
+        let worker = std::thread::spawn(move || {
+            let _settings = held.lock();
+            panic!("worker failed while holding the settings");
+        });
+
+        assert!(worker.join().is_err());
+        assert!(settings.lock().client_approval);
+
+        reload(&settings, Path::new("/nonexistent/pdtserver.toml")).unwrap();
+
+        assert!(!settings.lock().client_approval);
+    }
+}
@@ -0,0 +1,779 @@
+//! state transitions of the server, free of i/o so they can be driven
+//! deterministically and replayed from a recorded event log
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use pdtcore::{
+    BuiltInfo, Client, ClientMessage, ConnectionState, Containers, DeviceInfo, Message,
+    NetworkInfo, ProtocolConfig, ServerInfo, ServerMessage, TelemetryConfig, Welcome,
+};
+use tracing::*;
+use ulid::Ulid;
+
+use crate::{
+    client_logs::{Batch, ClientLogs},
+    crashes::Crash,
+    latency::Latency,
+    registry::Registry,
+    routes::{Context, Routes},
+    server::ClientEvent,
+    telemetry::TelemetrySeries,
+};
+
+/// where a connection is in the protocol, a client sending something out of
+/// order is dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Session {
+    /// accepted, nothing but an introduction or a goodbye may arrive
+    AwaitingHello,
+    Active,
+    /// told goodbye, waiting for the connection to end
+    Closing,
+}
+
+impl From<Session> for ConnectionState {
+    fn from(value: Session) -> Self {
+        match value {
+            Session::AwaitingHello => ConnectionState::Connecting,
+            Session::Active | Session::Closing => ConnectionState::Connected,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerClient {
+    pub id: Ulid,
+    pub session: Session,
+    pub pdtcore_built_info: Option<BuiltInfo>,
+    pub device_info: Option<DeviceInfo>,
+    pub last_seen: SystemTime,
+    pub network: NetworkInfo,
+    pub telemetry: TelemetrySeries,
+    /// round trips of the telemetry requests
+    pub latency: Latency,
+    /// log records the client forwarded
+    pub logs: ClientLogs,
+    /// connection settings told in the welcome, the ones of the server
+    /// until then
+    pub protocol: ProtocolConfig,
+    /// containers last reported, none unless the client reports them
+    pub containers: Option<Containers>,
+    /// fingerprint of the identity the client presented
+    pub identity: Option<String>,
+    /// telemetry the client was last told to collect
+    pub telemetry_config: TelemetryConfig,
+    /// when the presence of the client was last refreshed, and telemetry
+    /// requested if it is active
+    pub ticked_at: Option<SystemTime>,
+}
+
+impl From<&ServerClient> for Client {
+    fn from(value: &ServerClient) -> Self {
+        Client {
+            id: value.id.to_string(),
+            state: value.session.into(),
+            device_info: value.device_info.clone().unwrap_or_default(),
+            last_seen: value.last_seen,
+            network: value.network.clone(),
+            protocol_version: value
+                .pdtcore_built_info
+                .as_ref()
+                .map(|built_info| built_info.pkg_version.clone()),
+            identity: value.identity.clone(),
+        }
+    }
+}
+
+/// something that happened to the server
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// a connection was accepted
+    Connected {
+        id: Ulid,
+        network: NetworkInfo,
+        at: SystemTime,
+    },
+    /// a message arrived on a connection
+    Received {
+        id: Ulid,
+        message: Message,
+        at: SystemTime,
+    },
+    /// a connection can no longer be written to
+    Disconnected { id: Ulid },
+    /// the tick interval elapsed
+    Tick { at: SystemTime },
+    /// the telemetry to collect from clients changed, by device name
+    Tuned(BTreeMap<String, TelemetryConfig>),
+}
+
+/// what the server has to do after applying an event
+#[derive(Debug, PartialEq)]
+pub enum Effect {
+    /// queue a message on a client connection
+    Send(Ulid, ClientMessage),
+    /// publish to web interface subscribers
+    Publish(Box<ClientEvent>),
+    /// close a client connection
+    Disconnect(Ulid),
+    /// publish forwarded log records to the log pages of the client
+    PublishLogs(Batch),
+    /// keep a crash a client reported
+    KeepCrash(Box<Crash>),
+}
+
+/// what the server calls itself when welcoming clients unless named
+pub const DEFAULT_NAME: &str = "pdtserver";
+
+#[derive(Debug)]
+pub struct ServerState {
+    registry: Registry,
+    /// handlers of the messages of active clients
+    routes: Routes,
+    /// told to clients in their welcome, up since the epoch until set so
+    /// replays stay deterministic
+    info: ServerInfo,
+    /// reconciled with the settings each client proposes
+    protocol: ProtocolConfig,
+    /// telemetry collected from clients by device name, the default for
+    /// the others
+    tunings: BTreeMap<String, TelemetryConfig>,
+}
+
+impl Default for ServerState {
+    fn default() -> Self {
+        Self {
+            registry: Registry::default(),
+            routes: Routes::default(),
+            info: ServerInfo {
+                name: DEFAULT_NAME.to_string(),
+                version: BuiltInfo::default().pkg_version,
+                up_since: 0,
+            },
+            protocol: ProtocolConfig::default(),
+            tunings: BTreeMap::new(),
+        }
+    }
+}
+
+impl ServerState {
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.info.name = name;
+    }
+
+    pub fn set_up_since(&mut self, at: SystemTime) {
+        self.info.up_since = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    }
+
+    pub fn set_protocol(&mut self, protocol: ProtocolConfig) {
+        self.protocol = protocol;
+    }
+
+    /// time between ticks, a heartbeat unless a client is tuned to sample
+    /// more often, but no less than a second
+    pub fn tick_interval(&self) -> Duration {
+        self.tunings
+            .values()
+            .map(TelemetryConfig::interval)
+            .filter(|interval| !interval.is_zero())
+            .fold(self.protocol.heartbeat_interval(), Duration::min)
+            .max(Duration::from_secs(1))
+    }
+
+    pub fn apply(&mut self, event: Event) -> Vec<Effect> {
+        match event {
+            Event::Connected { id, network, at } => {
+                let client = ServerClient {
+                    id,
+                    session: Session::AwaitingHello,
+                    pdtcore_built_info: None,
+                    device_info: None,
+                    last_seen: at,
+                    network,
+                    telemetry: TelemetrySeries::default(),
+                    latency: Latency::default(),
+                    logs: ClientLogs::default(),
+                    protocol: self.protocol,
+                    containers: None,
+                    identity: None,
+                    telemetry_config: TelemetryConfig::default(),
+                    ticked_at: None,
+                };
+
+                let joined = Effect::Publish(Box::new(ClientEvent::Joined(Client::from(&client))));
+
+                self.registry.insert(client);
+
+                vec![joined]
+            }
+            Event::Received { id, message, at } => self.receive(id, message, at),
+            Event::Disconnected { id } => match self.registry.remove(&id) {
+                Some(_) => vec![Effect::Publish(Box::new(ClientEvent::Left(id)))],
+                None => vec![],
+            },
+            Event::Tick { at } => self.tick(at),
+            Event::Tuned(tunings) => {
+                self.tunings = tunings;
+
+                self.retune()
+            }
+        }
+    }
+
+    /// tell active clients whose tuning changed what to collect
+    fn retune(&mut self) -> Vec<Effect> {
+        let ids: Vec<_> = self.registry.iter().map(|client| client.id).collect();
+
+        let mut effects = vec![];
+
+        for id in ids {
+            let Some(client) = self.registry.get_mut(&id) else {
+                continue;
+            };
+
+            if client.session != Session::Active {
+                continue;
+            }
+
+            let wanted = client
+                .device_info
+                .as_ref()
+                .and_then(|device_info| self.tunings.get(&device_info.name))
+                .cloned()
+                .unwrap_or_default();
+
+            if wanted != client.telemetry_config {
+                client.telemetry_config = wanted.clone();
+
+                effects.push(Effect::Send(id, ClientMessage::ConfigUpdate(wanted)));
+            }
+        }
+
+        effects
+    }
+
+    /// refresh the presence of every client due and ask the active ones for
+    /// telemetry, timing the round trip
+    ///
+    /// clients are due every heartbeat, or every tuned interval if shorter,
+    /// with half a tick of slack so a late tick does not skip a round
+    fn tick(&mut self, at: SystemTime) -> Vec<Effect> {
+        let mut effects = self.retune();
+
+        let heartbeat = self.protocol.heartbeat_interval();
+        let slack = self.tick_interval() / 2;
+
+        let ids: Vec<_> = self.registry.iter().map(|client| client.id).collect();
+
+        for id in ids {
+            let Some(client) = self.registry.get_mut(&id) else {
+                continue;
+            };
+
+            let interval = match client.telemetry_config.interval() {
+                interval if interval.is_zero() => heartbeat,
+                interval => interval.min(heartbeat),
+            };
+
+            let due = client.ticked_at.is_none_or(|ticked_at| {
+                at.duration_since(ticked_at).unwrap_or_default() + slack >= interval
+            });
+
+            if !due {
+                continue;
+            }
+
+            client.ticked_at = Some(at);
+
+            effects.push(Effect::Publish(Box::new(ClientEvent::Heartbeat(
+                Client::from(&*client),
+            ))));
+
+            if client.session == Session::Active {
+                client.latency.requested(at);
+
+                effects.push(Effect::Send(id, ClientMessage::RequestTelemetry));
+            }
+        }
+
+        effects
+    }
+
+    fn receive(&mut self, id: Ulid, message: Message, at: SystemTime) -> Vec<Effect> {
+        let Some(client) = self.registry.get_mut(&id) else {
+            debug!(client_id =? id, "message from a removed client");
+            return vec![];
+        };
+
+        client.last_seen = at;
+
+        let message = match message {
+            Message::Server(message) => message,
+            Message::Client(_) => return self.drop_violator(id, "sent a client message"),
+        };
+
+        match (client.session, message) {
+            (_, ServerMessage::Goodbye) => {
+                self.registry.remove(&id);
+
+                vec![
+                    Effect::Disconnect(id),
+                    Effect::Publish(Box::new(ClientEvent::Left(id))),
+                ]
+            }
+            (Session::AwaitingHello, ServerMessage::Hello(introduction)) => {
+                let compatible = BuiltInfo::default().compatible(&introduction.pdtcore_built_info);
+
+                client.pdtcore_built_info = Some(introduction.pdtcore_built_info);
+
+                if !compatible {
+                    client.session = Session::Closing;
+
+                    return vec![
+                        Effect::Send(id, ClientMessage::Goodbye),
+                        Effect::Publish(Box::new(ClientEvent::Updated(Client::from(&*client)))),
+                    ];
+                }
+
+                client.session = Session::Active;
+                client.latency.requested(at);
+                client.protocol = self.protocol.reconcile(&introduction.protocol);
+
+                let welcome = Welcome {
+                    assigned_id: id.to_string(),
+                    server: self.info.clone(),
+                    protocol: client.protocol,
+                };
+
+                vec![
+                    Effect::Send(id, ClientMessage::Welcome(welcome)),
+                    Effect::Send(id, ClientMessage::RequestDeviceInfo),
+                    Effect::Send(id, ClientMessage::RequestTelemetry),
+                    Effect::Publish(Box::new(ClientEvent::Updated(Client::from(&*client)))),
+                ]
+            }
+            (Session::AwaitingHello, _) => self.drop_violator(id, "sent a message before hello"),
+            (Session::Closing, message) => {
+                debug!(client_id =? id, message =? message, "ignoring message of a closing client");
+
+                vec![]
+            }
+            // a lossy link may repeat the introduction
+            (Session::Active, ServerMessage::Hello(_)) => {
+                debug!(client_id =? id, "ignoring repeated hello");
+
+                vec![]
+            }
+            (Session::Active, message) => {
+                let mut context = Context::new(client, at);
+
+                self.routes.handle(&mut context, message);
+
+                context.into_effects()
+            }
+        }
+    }
+}
+
+impl ServerState {
+    /// forget a client that broke the protocol and close its connection
+    fn drop_violator(&mut self, id: Ulid, reason: &str) -> Vec<Effect> {
+        warn!(client_id =? id, reason, "dropping client violating the protocol");
+
+        self.registry.remove(&id);
+
+        vec![
+            Effect::Disconnect(id),
+            Effect::Publish(Box::new(ClientEvent::Left(id))),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use pdtcore::{ClientIntroduction, Telemetry, Transport, HEARTBEAT_INTERVAL};
+
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn connected(id: Ulid, seconds: u64) -> Event {
+        Event::Connected {
+            id,
+            network: NetworkInfo {
+                address: "192.0.2.1:50000".to_string(),
+                transport: Transport::Tcp,
+                connected_at: at(seconds),
+            },
+            at: at(seconds),
+        }
+    }
+
+    fn received(id: Ulid, message: ServerMessage, seconds: u64) -> Event {
+        Event::Received {
+            id,
+            message: message.into(),
+            at: at(seconds),
+        }
+    }
+
+    fn hello(id: Ulid, pdtcore_built_info: BuiltInfo, seconds: u64) -> Event {
+        hello_proposing(id, pdtcore_built_info, ProtocolConfig::default(), seconds)
+    }
+
+    fn hello_proposing(
+        id: Ulid,
+        pdtcore_built_info: BuiltInfo,
+        protocol: ProtocolConfig,
+        seconds: u64,
+    ) -> Event {
+        received(
+            id,
+            ServerMessage::Hello(Box::new(ClientIntroduction {
+                name: "client".to_string(),
+                pdtcore_built_info,
+                protocol,
+            })),
+            seconds,
+        )
+    }
+
+    /// a recorded session of two clients, one of which leaves
+    fn recorded_log() -> Vec<Event> {
+        let first = Ulid::from(1);
+        let second = Ulid::from(2);
+
+        vec![
+            connected(first, 0),
+            connected(second, 1),
+            hello(first, BuiltInfo::default(), 2),
+            received(
+                first,
+                ServerMessage::DeviceInfo(DeviceInfo {
+                    name: "first".to_string(),
+                    ..Default::default()
+                }),
+                3,
+            ),
+            received(
+                first,
+                ServerMessage::Telemetry(Telemetry {
+                    load: 0.5,
+                    memory_total: 8 << 30,
+                    memory_used: 2 << 30,
+                    network_received: 1000,
+                    network_transmitted: 100,
+                }),
+                4,
+            ),
+            Event::Tick { at: at(4) },
+            hello(second, BuiltInfo::default(), 5),
+            received(second, ServerMessage::Goodbye, 6),
+            Event::Disconnected { id: second },
+            Event::Tick { at: at(6) },
+        ]
+    }
+
+    fn replay(events: Vec<Event>) -> (ServerState, Vec<Effect>) {
+        let mut state = ServerState::default();
+
+        let effects = events
+            .into_iter()
+            .flat_map(|event| state.apply(event))
+            .collect();
+
+        (state, effects)
+    }
+
+    #[test]
+    fn connection_joins_as_connecting() {
+        let id = Ulid::from(1);
+        let mut state = ServerState::default();
+
+        let effects = state.apply(connected(id, 0));
+
+        let client = state.registry().get(&id).unwrap();
+
+        assert_eq!(client.session, Session::AwaitingHello);
+        assert_eq!(
+            effects,
+            vec![Effect::Publish(Box::new(ClientEvent::Joined(
+                Client::from(client)
+            )))]
+        );
+    }
+
+    #[test]
+    fn hello_connects_welcomes_and_requests_device_info_and_telemetry() {
+        let id = Ulid::from(1);
+        let mut state = ServerState::default();
+        state.set_name("home".to_string());
+        state.set_up_since(at(60));
+
+        state.apply(connected(id, 0));
+        let effects = state.apply(hello(id, BuiltInfo::default(), 1));
+
+        let client = state.registry().get(&id).unwrap();
+
+        assert_eq!(client.session, Session::Active);
+        assert_eq!(client.last_seen, at(1));
+        assert_eq!(
+            effects,
+            vec![
+                Effect::Send(
+                    id,
+                    ClientMessage::Welcome(Welcome {
+                        assigned_id: id.to_string(),
+                        server: ServerInfo {
+                            name: "home".to_string(),
+                            version: BuiltInfo::default().pkg_version,
+                            up_since: 60,
+                        },
+                        protocol: ProtocolConfig::default(),
+                    })
+                ),
+                Effect::Send(id, ClientMessage::RequestDeviceInfo),
+                Effect::Send(id, ClientMessage::RequestTelemetry),
+                Effect::Publish(Box::new(ClientEvent::Updated(Client::from(client)))),
+            ]
+        );
+    }
+
+    #[test]
+    fn welcome_tells_the_reconciled_protocol() {
+        let id = Ulid::from(1);
+        let mut state = ServerState::default();
+
+        let proposed = ProtocolConfig::default()
+            .with_idle_timeout(Duration::from_secs(90))
+            .with_max_frame_size(64 << 10);
+
+        state.apply(connected(id, 0));
+        let effects = state.apply(hello_proposing(id, BuiltInfo::default(), proposed, 1));
+
+        let Effect::Send(_, ClientMessage::Welcome(welcome)) = &effects[0] else {
+            panic!("not welcomed: {effects:?}");
+        };
+
+        assert_eq!(welcome.protocol.idle_timeout(), Duration::from_secs(90));
+        assert_eq!(welcome.protocol.max_frame_size(), 64 << 10);
+        assert_eq!(
+            welcome.protocol.heartbeat_interval(),
+            pdtcore::HEARTBEAT_INTERVAL
+        );
+        assert_eq!(
+            state.registry().get(&id).map(|client| client.protocol),
+            Some(welcome.protocol)
+        );
+    }
+
+    #[test]
+    fn incompatible_client_is_told_goodbye() {
+        let id = Ulid::from(1);
+        let mut state = ServerState::default();
+
+        let incompatible = BuiltInfo {
+            pkg_version_major: "999".to_string(),
+            ..BuiltInfo::default()
+        };
+
+        state.apply(connected(id, 0));
+        let effects = state.apply(hello(id, incompatible, 1));
+
+        assert_eq!(effects[0], Effect::Send(id, ClientMessage::Goodbye));
+        assert_eq!(
+            state.registry().get(&id).map(|client| client.session),
+            Some(Session::Closing)
+        );
+        assert_eq!(
+            state.apply(received(
+                id,
+                ServerMessage::DeviceInfo(DeviceInfo::default()),
+                2
+            )),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn messages_before_hello_drop_the_client() {
+        let id = Ulid::from(1);
+        let mut state = ServerState::default();
+
+        state.apply(connected(id, 0));
+
+        assert_eq!(
+            state.apply(received(
+                id,
+                ServerMessage::DeviceInfo(DeviceInfo::default()),
+                1
+            )),
+            vec![
+                Effect::Disconnect(id),
+                Effect::Publish(Box::new(ClientEvent::Left(id))),
+            ]
+        );
+        assert!(state.registry().get(&id).is_none());
+    }
+
+    #[test]
+    fn repeated_hello_is_ignored() {
+        let id = Ulid::from(1);
+        let mut state = ServerState::default();
+
+        state.apply(connected(id, 0));
+        state.apply(hello(id, BuiltInfo::default(), 1));
+
+        assert_eq!(state.apply(hello(id, BuiltInfo::default(), 2)), vec![]);
+        assert_eq!(
+            state.registry().get(&id).map(|client| client.session),
+            Some(Session::Active)
+        );
+    }
+
+    #[test]
+    fn goodbye_disconnects_once() {
+        let id = Ulid::from(1);
+        let mut state = ServerState::default();
+
+        state.apply(connected(id, 0));
+
+        assert_eq!(
+            state.apply(received(id, ServerMessage::Goodbye, 1)),
+            vec![
+                Effect::Disconnect(id),
+                Effect::Publish(Box::new(ClientEvent::Left(id))),
+            ]
+        );
+        assert_eq!(state.apply(Event::Disconnected { id }), vec![]);
+        assert!(state.registry().get(&id).is_none());
+    }
+
+    #[test]
+    fn tick_requests_telemetry_from_connected_clients_only() {
+        let connecting = Ulid::from(1);
+        let connected_id = Ulid::from(2);
+        let mut state = ServerState::default();
+
+        state.apply(connected(connecting, 0));
+        state.apply(connected(connected_id, 0));
+        state.apply(hello(connected_id, BuiltInfo::default(), 1));
+
+        let requests: Vec<_> = state
+            .apply(Event::Tick { at: at(2) })
+            .into_iter()
+            .filter(|effect| matches!(effect, Effect::Send(..)))
+            .collect();
+
+        assert_eq!(
+            requests,
+            vec![Effect::Send(connected_id, ClientMessage::RequestTelemetry)]
+        );
+    }
+
+    #[test]
+    fn tuned_clients_are_told_and_requested_at_their_interval() {
+        let id = Ulid::from(1);
+        let mut state = ServerState::default();
+
+        state.apply(connected(id, 0));
+        state.apply(hello(id, BuiltInfo::default(), 0));
+        state.apply(received(
+            id,
+            ServerMessage::DeviceInfo(DeviceInfo {
+                name: "kiosk".to_string(),
+                ..Default::default()
+            }),
+            0,
+        ));
+
+        let tuned = TelemetryConfig {
+            interval: 2_000,
+            families: vec![pdtcore::MetricFamily::Load],
+        };
+
+        assert_eq!(
+            state.apply(Event::Tuned(BTreeMap::from([(
+                "kiosk".to_string(),
+                tuned.clone()
+            )]))),
+            vec![Effect::Send(id, ClientMessage::ConfigUpdate(tuned))]
+        );
+        assert_eq!(state.tick_interval(), Duration::from_secs(2));
+
+        let requests = |effects: Vec<Effect>| {
+            effects
+                .into_iter()
+                .filter(|effect| matches!(effect, Effect::Send(..)))
+                .count()
+        };
+
+        assert_eq!(requests(state.apply(Event::Tick { at: at(2) })), 1);
+        // too soon after the request before
+        assert_eq!(
+            requests(state.apply(Event::Tick {
+                at: at(2) + Duration::from_millis(500)
+            })),
+            0
+        );
+        assert_eq!(requests(state.apply(Event::Tick { at: at(4) })), 1);
+
+        assert_eq!(
+            state.apply(Event::Tuned(BTreeMap::new())),
+            vec![Effect::Send(
+                id,
+                ClientMessage::ConfigUpdate(TelemetryConfig::default())
+            )]
+        );
+        assert_eq!(state.tick_interval(), HEARTBEAT_INTERVAL);
+    }
+
+    #[test]
+    fn messages_from_unknown_clients_are_ignored() {
+        let mut state = ServerState::default();
+
+        let effects = state.apply(received(Ulid::from(1), ServerMessage::Goodbye, 0));
+
+        assert_eq!(effects, vec![]);
+        assert_eq!(state.registry().counts().left, 0);
+    }
+
+    #[test]
+    fn replaying_a_recorded_log_is_deterministic() {
+        let (state, effects) = replay(recorded_log());
+        let (replayed_state, replayed_effects) = replay(recorded_log());
+
+        assert_eq!(effects, replayed_effects);
+        assert_eq!(
+            state.registry().clients(),
+            replayed_state.registry().clients()
+        );
+
+        let counts = state.registry().counts();
+
+        assert_eq!(counts.connected, 1);
+        assert_eq!(counts.joined, 2);
+        assert_eq!(counts.left, 1);
+        assert_eq!(
+            state
+                .registry()
+                .get(&Ulid::from(1))
+                .unwrap()
+                .telemetry
+                .samples()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use pdtcore::BuiltInfo;
+
+use crate::{registry::RegistryCounts, AppState};
+
+/// nothing is written to disk, clients, telemetry and command history are
+/// gone after a restart
+const PERSISTENCE: &str = "memory only";
+
+/// a socket the server listens on
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    /// `pdt`, `relay`, `web` or `grpc`
+    pub service: &'static str,
+    pub address: String,
+}
+
+/// the server itself, to sanity check a deployment
+pub struct Status {
+    pub built_info: BuiltInfo,
+    pub started: SystemTime,
+    pub endpoints: Vec<Endpoint>,
+    pub counts: RegistryCounts,
+    /// client events queued for subscribers that have not received them yet
+    pub event_queue_depth: usize,
+    /// times handling client messages failed and was restarted
+    pub handler_restarts: u64,
+    /// handling client messages failed and did not recover yet
+    pub degraded: bool,
+    pub persistence: &'static str,
+}
+
+impl Status {
+    /// the counts are asked of the server beforehand, see
+    /// [`crate::handle::ServerHandle::counts`]
+    pub(crate) fn of(state: &AppState, counts: RegistryCounts) -> Self {
+        Self {
+            built_info: BuiltInfo::default(),
+            started: state.started,
+            endpoints: state.endpoints.clone(),
+            counts,
+            event_queue_depth: state.server.event_queue_depth(),
+            handler_restarts: state.server.handler_restarts(),
+            degraded: state.server.is_degraded(),
+            persistence: PERSISTENCE,
+        }
+    }
+}
+
+impl From<Status> for pdtapi::ServerStatus {
+    fn from(value: Status) -> Self {
+        Self {
+            version: value.built_info.pkg_version,
+            target: value.built_info.target,
+            profile: value.built_info.profile,
+            started: value
+                .started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            endpoints: value
+                .endpoints
+                .into_iter()
+                .map(|endpoint| pdtapi::ServerEndpoint {
+                    service: endpoint.service.to_string(),
+                    address: endpoint.address,
+                })
+                .collect(),
+            connected: value.counts.connected,
+            connecting: value.counts.connecting,
+            event_queue_depth: value.event_queue_depth,
+            handler_restarts: value.handler_restarts,
+            degraded: value.degraded,
+            persistence: value.persistence.to_string(),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! small toml files the web interface keeps its own state in, like the notes
+//! about devices and the layouts of the client list
+//!
+//! the value of a file is kept in the web state and the file rewritten whole
+//! after every change, on a blocking thread once the web state is unlocked
+
+use std::{
+    io::ErrorKind,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{AppStateReference, WebState};
+
+// fields are only read through Debug when logging
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum StoreError {
+    Read(std::io::Error),
+    Parse(toml::de::Error),
+    Write(std::io::Error),
+}
+
+/// a value kept in a toml file
+#[derive(Debug)]
+pub struct Store<T> {
+    path: PathBuf,
+    value: T,
+    /// held while writing, so the file ends up with the latest value when
+    /// changes are saved concurrently
+    writing: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<T: DeserializeOwned + Default> Store<T> {
+    /// the value kept in `path`, the default when the file is missing
+    pub fn load(path: &Path) -> Result<Self, StoreError> {
+        let value = match std::fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(StoreError::Parse)?,
+            Err(error) if error.kind() == ErrorKind::NotFound => T::default(),
+            Err(error) => return Err(StoreError::Read(error)),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            value,
+            writing: Arc::default(),
+        })
+    }
+}
+
+impl<T: Serialize> Store<T> {
+    fn contents(&self) -> String {
+        toml::to_string(&self.value).expect("stored values serialize to toml")
+    }
+}
+
+impl<T> Deref for Store<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Store<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+/// write `contents` aside and rename it over `path`, so a crash never leaves
+/// half a file
+async fn write(path: PathBuf, contents: String) -> Result<(), StoreError> {
+    tokio::task::spawn_blocking(move || {
+        let written = path.with_extension("toml.tmp");
+
+        std::fs::write(&written, contents).and_then(|_| std::fs::rename(&written, &path))
+    })
+    .await
+    .expect("writing a store does not panic")
+    .map_err(StoreError::Write)
+}
+
+/// write the store `of` the web state to its file, with every change made
+/// to it by the time the write starts
+pub async fn save<T: Serialize>(
+    state: &AppStateReference,
+    of: impl Fn(&WebState) -> &Store<T>,
+) -> Result<(), StoreError> {
+    let writing = of(&state.web.lock()).writing.clone();
+    let _writing = writing.lock().await;
+
+    let (path, contents) = {
+        let web_guard = state.web.lock();
+        let store = of(&web_guard);
+
+        (store.path.clone(), store.contents())
+    };
+
+    write(path, contents).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stores_outlive_the_server() {
+        let path =
+            std::env::temp_dir().join(format!("pdtserver-{}-store.toml", std::process::id()));
+
+        let mut store = Store::<BTreeMap<String, String>>::load(&path).unwrap();
+
+        assert!(store.is_empty());
+
+        store.insert("kiosk".to_string(), "mounted upside down".to_string());
+
+        write(store.path.clone(), store.contents()).await.unwrap();
+
+        let loaded = Store::<BTreeMap<String, String>>::load(&path).unwrap();
+
+        assert_eq!(*loaded, *store);
+        assert!(!path.with_extension("toml.tmp").exists());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+use std::{collections::VecDeque, time::SystemTime};
+
+use pdtcore::Telemetry;
+
+/// samples kept per client, an hour at the default interval
+const HISTORY: usize = 360;
+
+const CHART_WIDTH: f64 = 300.0;
+const CHART_HEIGHT: f64 = 100.0;
+
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub at: SystemTime,
+    pub telemetry: Telemetry,
+}
+
+/// most recent telemetry samples of a client, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct TelemetrySeries {
+    samples: VecDeque<Sample>,
+}
+
+impl TelemetrySeries {
+    pub fn push(&mut self, at: SystemTime, telemetry: Telemetry) {
+        if self.samples.len() == HISTORY {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(Sample { at, telemetry });
+    }
+
+    pub fn samples(&self) -> Vec<Sample> {
+        self.samples.iter().cloned().collect()
+    }
+
+    pub fn latest(&self) -> Option<Sample> {
+        self.samples.back().cloned()
+    }
+}
+
+/// svg line chart of one or more series sharing a scale
+pub struct Chart {
+    pub title: &'static str,
+    /// most recent values, formatted for display
+    pub latest: String,
+    /// `points` attributes of the chart polylines
+    pub lines: Vec<String>,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Chart {
+    fn new(title: &'static str, latest: String, series: &[Vec<f64>], max: f64) -> Self {
+        let max = series.iter().flatten().copied().fold(max, f64::max);
+
+        let lines = series
+            .iter()
+            .map(|values| {
+                let step = CHART_WIDTH / (values.len().max(2) - 1) as f64;
+
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| {
+                        let x = index as f64 * step;
+                        let y = CHART_HEIGHT - value / max * CHART_HEIGHT;
+
+                        format!("{x:.1},{y:.1}")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+
+        Self {
+            title,
+            latest,
+            lines,
+            width: CHART_WIDTH,
+            height: CHART_HEIGHT,
+        }
+    }
+}
+
+/// load, memory and network charts for a client detail page
+pub fn charts(samples: &[Sample]) -> Vec<Chart> {
+    let Some(latest) = samples.last() else {
+        return Vec::new();
+    };
+
+    let load: Vec<f64> = samples.iter().map(|sample| sample.telemetry.load).collect();
+
+    let memory: Vec<f64> = samples
+        .iter()
+        .map(|sample| {
+            let telemetry = &sample.telemetry;
+            telemetry.memory_used as f64 / telemetry.memory_total.max(1) as f64 * 100.0
+        })
+        .collect();
+
+    let (received, transmitted): (Vec<f64>, Vec<f64>) = samples
+        .windows(2)
+        .map(|pair| {
+            let seconds = pair[1]
+                .at
+                .duration_since(pair[0].at)
+                .unwrap_or_default()
+                .as_secs_f64()
+                .max(1.0);
+
+            let rate = |counter: fn(&Telemetry) -> u64| {
+                counter(&pair[1].telemetry).saturating_sub(counter(&pair[0].telemetry)) as f64
+                    / seconds
+            };
+
+            (
+                rate(|telemetry| telemetry.network_received),
+                rate(|telemetry| telemetry.network_transmitted),
+            )
+        })
+        .unzip();
+
+    vec![
+        Chart::new(
+            "load",
+            format!("{:.2}", latest.telemetry.load),
+            &[load],
+            1.0,
+        ),
+        Chart::new(
+            "memory",
+            format!("{:.0}%", memory.last().copied().unwrap_or_default()),
+            &[memory],
+            100.0,
+        ),
+        Chart::new(
+            "network",
+            format!(
+                "{:.1} KiB/s in, {:.1} KiB/s out",
+                received.last().copied().unwrap_or_default() / 1024.0,
+                transmitted.last().copied().unwrap_or_default() / 1024.0
+            ),
+            &[received, transmitted],
+            1024.0,
+        ),
+    ]
+}
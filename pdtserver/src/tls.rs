@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+use axum_server::tls_rustls::RustlsConfig;
+use tracing::*;
+
+/// where the web interface gets its certificate from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TlsConfig {
+    /// pem encoded certificate chain and private key
+    Files { cert: PathBuf, key: PathBuf },
+    /// certificate generated at startup for the given host names
+    SelfSigned(Vec<String>),
+}
+
+// fields are only read through Debug when main returns
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum TlsError {
+    Read(std::io::Error),
+    Generate(rcgen::RcgenError),
+}
+
+impl From<std::io::Error> for TlsError {
+    fn from(value: std::io::Error) -> Self {
+        TlsError::Read(value)
+    }
+}
+
+impl From<rcgen::RcgenError> for TlsError {
+    fn from(value: rcgen::RcgenError) -> Self {
+        TlsError::Generate(value)
+    }
+}
+
+impl TlsConfig {
+    /// tls from the `WEB_TLS_CERT` and `WEB_TLS_KEY` paths, or a self signed
+    /// certificate for the comma separated host names in
+    /// `WEB_TLS_SELF_SIGNED`
+    pub fn from_env() -> Option<Self> {
+        use std::env;
+
+        if let (Ok(cert), Ok(key)) = (env::var("WEB_TLS_CERT"), env::var("WEB_TLS_KEY")) {
+            return Some(TlsConfig::Files {
+                cert: cert.into(),
+                key: key.into(),
+            });
+        }
+
+        let names: Vec<String> = env::var("WEB_TLS_SELF_SIGNED")
+            .ok()?
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect();
+
+        match names.is_empty() {
+            true => Some(TlsConfig::SelfSigned(vec!["localhost".to_string()])),
+            false => Some(TlsConfig::SelfSigned(names)),
+        }
+    }
+
+    pub async fn rustls_config(&self) -> Result<RustlsConfig, TlsError> {
+        match self {
+            TlsConfig::Files { cert, key } => Ok(RustlsConfig::from_pem_file(cert, key).await?),
+            TlsConfig::SelfSigned(names) => {
+                warn!(names =? names, "using a self signed certificate");
+
+                let certificate = rcgen::generate_simple_self_signed(names.clone())?;
+
+                let cert = certificate.serialize_pem()?.into_bytes();
+                let key = certificate.serialize_private_key_pem().into_bytes();
+
+                Ok(RustlsConfig::from_pem(cert, key).await?)
+            }
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! how often telemetry is sampled on a device and which metric families it
+//! collects, tuned on its detail page and pushed to the client with
+//! [`pdtcore::ClientMessage::ConfigUpdate`]
+//!
+//! tunings are kept by device name in a toml file of their own, see
+//! [`store`], and told to the server again when it starts, so a client is
+//! tuned whenever it connects
+
+use std::{collections::BTreeMap, time::Duration};
+
+use askama::Template;
+use axum::{
+    extract::{Path as UrlPath, State},
+    response::{IntoResponse, Response},
+    routing, Form, Router,
+};
+use pdtcore::{MetricFamily, TelemetryConfig};
+use serde::{Deserialize, Serialize};
+use tracing::*;
+use ulid::Ulid;
+
+use crate::{auth::Access, store, visible_device, AppError, AppStateReference};
+
+/// shortest interval between samples a device can be tuned to
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// a metric family, named like in the form
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Family {
+    Load,
+    Memory,
+    Network,
+}
+
+impl From<Family> for MetricFamily {
+    fn from(value: Family) -> Self {
+        match value {
+            Family::Load => MetricFamily::Load,
+            Family::Memory => MetricFamily::Memory,
+            Family::Network => MetricFamily::Network,
+        }
+    }
+}
+
+/// telemetry collected from a device
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Tuning {
+    /// seconds between samples, zero for a sample every heartbeat
+    #[serde(default)]
+    pub interval: u64,
+    #[serde(default = "every_family")]
+    pub families: Vec<Family>,
+}
+
+fn every_family() -> Vec<Family> {
+    vec![Family::Load, Family::Memory, Family::Network]
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            interval: 0,
+            families: every_family(),
+        }
+    }
+}
+
+impl Tuning {
+    pub fn collects(&self, family: Family) -> bool {
+        self.families.contains(&family)
+    }
+
+    /// the interval as shown on the detail page, like `5m`
+    pub fn every(&self) -> String {
+        humantime::format_duration(Duration::from_secs(self.interval)).to_string()
+    }
+
+    /// the families as shown on the detail page, like `load, network`
+    pub fn collected(&self) -> String {
+        self.families
+            .iter()
+            .map(|family| match family {
+                Family::Load => "load",
+                Family::Memory => "memory",
+                Family::Network => "network",
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn config(&self) -> TelemetryConfig {
+        TelemetryConfig {
+            interval: Duration::from_secs(self.interval).as_millis() as u64,
+            families: self.families.iter().copied().map(Into::into).collect(),
+        }
+    }
+}
+
+/// tunings by device name, devices without one collect everything every
+/// heartbeat
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct Tunings {
+    tunings: BTreeMap<String, Tuning>,
+}
+
+impl Tunings {
+    pub fn get(&self, device_name: &str) -> Tuning {
+        self.tunings.get(device_name).cloned().unwrap_or_default()
+    }
+
+    /// replace the tuning of a device, removing it when it is the default
+    pub fn set(&mut self, device_name: &str, tuning: Tuning) {
+        match tuning == Tuning::default() {
+            true => self.tunings.remove(device_name),
+            false => self.tunings.insert(device_name.to_string(), tuning),
+        };
+    }
+
+    /// what the server tells clients to collect, by device name
+    pub fn configs(&self) -> BTreeMap<String, TelemetryConfig> {
+        self.tunings
+            .iter()
+            .map(|(device_name, tuning)| (device_name.clone(), tuning.config()))
+            .collect()
+    }
+}
+
+/// tuning of a client detail page, for htmx requests targeting it
+#[derive(Template)]
+#[template(path = "tuning.html")]
+pub struct TuningTemplate {
+    pub tuning: Tuning,
+}
+
+pub fn router() -> Router<AppStateReference> {
+    Router::new().route("/clients/:client_id/telemetry", routing::post(tune))
+}
+
+/// unchecked boxes are left out of the form
+#[derive(Deserialize)]
+struct TuningForm {
+    /// like `30s` or `5m`, blank for every heartbeat
+    interval: String,
+    #[serde(default)]
+    load: bool,
+    #[serde(default)]
+    memory: bool,
+    #[serde(default)]
+    network: bool,
+}
+
+impl TuningForm {
+    fn tuning(&self) -> Result<Tuning, AppError> {
+        let interval = match self.interval.trim() {
+            "" => 0,
+            interval => match humantime::parse_duration(interval) {
+                Ok(interval) if interval >= MIN_INTERVAL => interval.as_secs(),
+                _ => return Err(AppError::InvalidTuning),
+            },
+        };
+
+        let families = [
+            (self.load, Family::Load),
+            (self.memory, Family::Memory),
+            (self.network, Family::Network),
+        ]
+        .into_iter()
+        .filter_map(|(checked, family)| checked.then_some(family))
+        .collect();
+
+        Ok(Tuning { interval, families })
+    }
+}
+
+#[instrument(skip(state, access, form))]
+async fn tune(
+    UrlPath(client_id): UrlPath<Ulid>,
+    State(state): State<AppStateReference>,
+    access: Access,
+    Form(form): Form<TuningForm>,
+) -> Result<Response, AppError> {
+    access.check_csrf()?;
+
+    let tuning = form.tuning()?;
+
+    let server = state.server.clone();
+
+    let device_name = visible_device(&server, &access, client_id).await?;
+
+    if !access.may_control(&device_name) {
+        return Err(AppError::Forbidden);
+    }
+
+    let configs = {
+        let mut web_guard = state.web.lock();
+
+        web_guard.tunings.set(&device_name, tuning.clone());
+        web_guard.tunings.configs()
+    };
+
+    store::save(&state, |web| &web.tunings)
+        .await
+        .map_err(AppError::Tunings)?;
+
+    server.tune(configs).await?;
+
+    info!(
+        device_name,
+        interval = tuning.interval,
+        families =? tuning.families,
+        actor = access.actor(),
+        "telemetry tuned"
+    );
+
+    Ok(TuningTemplate { tuning }.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(interval: &str, load: bool, memory: bool, network: bool) -> TuningForm {
+        TuningForm {
+            interval: interval.to_string(),
+            load,
+            memory,
+            network,
+        }
+    }
+
+    #[test]
+    fn forms_are_read_into_tunings() {
+        let tuning = form("5m", true, false, true).tuning().ok().unwrap();
+
+        assert_eq!(tuning.interval, 300);
+        assert_eq!(tuning.families, vec![Family::Load, Family::Network]);
+        assert_eq!(
+            tuning.config(),
+            TelemetryConfig {
+                interval: 300_000,
+                families: vec![MetricFamily::Load, MetricFamily::Network],
+            }
+        );
+
+        assert_eq!(
+            form(" ", true, true, true).tuning().ok(),
+            Some(Tuning::default())
+        );
+        assert!(form("500ms", true, true, true).tuning().is_err());
+        assert!(form("often", true, true, true).tuning().is_err());
+    }
+
+    #[test]
+    fn default_tunings_are_removed() {
+        let mut tunings = Tunings::default();
+
+        tunings.set(
+            "kiosk",
+            Tuning {
+                interval: 60,
+                families: vec![Family::Load],
+            },
+        );
+        tunings.set("living-room", Tuning::default());
+
+        assert_eq!(tunings.configs().len(), 1);
+        assert_eq!(tunings.get("kiosk").interval, 60);
+
+        tunings.set("kiosk", Tuning::default());
+
+        assert!(tunings.configs().is_empty());
+        assert_eq!(tunings.get("kiosk"), Tuning::default());
+    }
+}
@@ -0,0 +1,80 @@
+//! supervision of the thread handling client messages, restarted when it
+//! fails so the server does not go on accepting connections it never
+//! answers
+//!
+//! the server is degraded from the failure until the restarted handler got
+//! through an event, which `/readyz` reports
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// wait before the first restart, doubled with every failure in a row
+const FIRST_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+pub struct Watchdog {
+    restarts: AtomicU64,
+    degraded: AtomicBool,
+}
+
+impl Watchdog {
+    /// the handler failed and is about to be restarted
+    pub fn failed(&self) {
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+        self.degraded.store(true, Ordering::Relaxed);
+    }
+
+    /// the handler got through an event
+    pub fn handled(&self) {
+        self.degraded.store(false, Ordering::Relaxed);
+    }
+
+    /// times the handler was restarted since the server started
+    pub fn restarts(&self) -> u64 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+}
+
+/// wait before restarting after `failures` in a row
+pub fn backoff(failures: u32) -> Duration {
+    FIRST_BACKOFF
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degraded_until_an_event_is_handled() {
+        let watchdog = Watchdog::default();
+
+        assert!(!watchdog.is_degraded());
+
+        watchdog.failed();
+        watchdog.failed();
+
+        assert!(watchdog.is_degraded());
+        assert_eq!(watchdog.restarts(), 2);
+
+        watchdog.handled();
+
+        assert!(!watchdog.is_degraded());
+        assert_eq!(watchdog.restarts(), 2);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        assert_eq!(backoff(1), FIRST_BACKOFF);
+        assert_eq!(backoff(3), FIRST_BACKOFF * 4);
+        assert_eq!(backoff(40), MAX_BACKOFF);
+    }
+}
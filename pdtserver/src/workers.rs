@@ -0,0 +1,137 @@
+//! threads of the server, kept to notice the ones that panicked and to wait
+//! for all of them when stopping
+
+use std::thread::{self, JoinHandle};
+
+use parking_lot::Mutex;
+use tracing::*;
+use ulid::Ulid;
+
+#[derive(Debug)]
+struct Worker {
+    /// client served by the thread, none for threads of the server itself
+    client_id: Option<Ulid>,
+    handle: JoinHandle<()>,
+}
+
+#[derive(Debug, Default)]
+pub struct Workers {
+    threads: Mutex<Vec<Worker>>,
+}
+
+impl Workers {
+    /// run `work` on a thread named `name`
+    pub fn spawn(
+        &self,
+        name: &str,
+        client_id: Option<Ulid>,
+        work: impl FnOnce() + Send + 'static,
+    ) -> std::io::Result<()> {
+        let handle = thread::Builder::new().name(name.to_string()).spawn(work)?;
+
+        self.threads.lock().push(Worker { client_id, handle });
+
+        Ok(())
+    }
+
+    /// join the threads that finished, returning the clients whose thread
+    /// panicked
+    pub fn reap(&self) -> Vec<Ulid> {
+        let finished = {
+            let mut threads = self.threads.lock();
+
+            let (finished, running) = std::mem::take(&mut *threads)
+                .into_iter()
+                .partition(|worker| worker.handle.is_finished());
+
+            *threads = running;
+            finished
+        };
+
+        Self::join_all(finished)
+    }
+
+    /// wait for every thread, including those spawned while waiting,
+    /// returning the clients whose thread panicked
+    pub fn join(&self) -> Vec<Ulid> {
+        let mut panicked = vec![];
+
+        loop {
+            let threads = std::mem::take(&mut *self.threads.lock());
+
+            if threads.is_empty() {
+                return panicked;
+            }
+
+            panicked.extend(Self::join_all(threads));
+        }
+    }
+
+    fn join_all(workers: Vec<Worker>) -> Vec<Ulid> {
+        let mut panicked = vec![];
+
+        for worker in workers {
+            let name = worker
+                .handle
+                .thread()
+                .name()
+                .unwrap_or_default()
+                .to_string();
+
+            if worker.handle.join().is_ok() {
+                continue;
+            }
+
+            error!(thread = name, client_id =? worker.client_id, "thread panicked");
+
+            panicked.extend(worker.client_id);
+        }
+
+        panicked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn panicked_clients_are_reaped() {
+        let workers = Workers::default();
+        let id = Ulid::new();
+
+        workers.spawn("fine", Some(Ulid::new()), || {}).unwrap();
+        workers
+            .spawn("panics", Some(id), || panic!("worker"))
+            .unwrap();
+
+        let started = Instant::now();
+        let mut panicked = vec![];
+
+        while panicked.is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            panicked = workers.reap();
+        }
+
+        assert_eq!(panicked, vec![id]);
+        assert!(workers.join().is_empty());
+    }
+
+    #[test]
+    fn join_waits_for_every_thread() {
+        let workers = Workers::default();
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        workers
+            .spawn("sleeps", None, move || {
+                thread::sleep(Duration::from_millis(50));
+                sender.send(()).unwrap();
+            })
+            .unwrap();
+
+        assert!(workers.join().is_empty());
+        assert!(receiver.try_recv().is_ok());
+    }
+}
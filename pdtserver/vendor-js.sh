@@ -0,0 +1,6 @@
+#!/bin/bash
+
+mkdir -p static/vendored
+curl -sSfL https://unpkg.com/htmx.org@1.9.6/dist/htmx.min.js -o static/vendored/htmx.min.js
+curl -sSfL https://unpkg.com/htmx.org@1.9.6/dist/ext/ws.js -o static/vendored/ws.js
+curl -sSfL https://unpkg.com/htmx.org@1.9.6/dist/ext/sse.js -o static/vendored/sse.js
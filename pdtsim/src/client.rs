@@ -0,0 +1,264 @@
+use std::{
+    io::Write,
+    net::{Shutdown, SocketAddr, TcpStream},
+    ops::Range,
+    time::Duration,
+};
+
+use pdtcore::{
+    chaos::{Chaos, Faults},
+    BuiltInfo, ClientIntroduction, ClientMessage, CommandExecution, DeviceInfo, Message, Protocol,
+    ProtocolConfig, ProtocolError, ServerMessage, Telemetry, Virtualization,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+use tracing::{info, instrument, warn};
+
+/// operating systems and versions simulated clients report
+const OPERATING_SYSTEMS: &[(&str, &[&str])] = &[
+    ("Linux", &["6.1.0", "6.6.30", "6.8.12"]),
+    ("FreeBSD", &["13.3-RELEASE", "14.1-RELEASE"]),
+    ("Darwin", &["23.5.0"]),
+];
+
+/// vendors, models, chassis and virtualization simulated clients report
+const HARDWARE: &[(&str, &str, &str, Virtualization)] = &[
+    (
+        "LENOVO",
+        "ThinkPad T14 Gen 3",
+        "notebook",
+        Virtualization::Physical,
+    ),
+    (
+        "Dell Inc.",
+        "OptiPlex 7010",
+        "desktop",
+        Virtualization::Physical,
+    ),
+    (
+        "Intel Corporation",
+        "NUC12WSHi7",
+        "mini pc",
+        Virtualization::Physical,
+    ),
+    (
+        "QEMU",
+        "Standard PC (Q35 + ICH9, 2009)",
+        "other",
+        Virtualization::VirtualMachine,
+    ),
+];
+
+/// memory of simulated clients, in gibibytes
+const MEMORY: &[u64] = &[4, 8, 16, 32];
+
+/// seconds a slow reader waits before reading each message
+const SLOW_READ: Range<u64> = 5..30;
+
+/// seconds a client waits before reconnecting
+const RECONNECT_DELAY: Range<u64> = 1..5;
+
+/// messages a misbehaving client handles before acting up
+const ACTING_UP: Range<u32> = 1..20;
+
+/// chance a command fails on the client
+const COMMAND_FAILURE: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Behavior {
+    /// answers every message after its latency
+    WellBehaved,
+    /// waits seconds before reading each message
+    SlowReader,
+    /// drops its connection without saying goodbye every few messages
+    AbruptDisconnect,
+    /// writes bytes that are not pdt messages every few messages
+    Garbage,
+}
+
+impl Behavior {
+    pub fn misbehaving(rng: &mut StdRng) -> Self {
+        *[
+            Behavior::SlowReader,
+            Behavior::AbruptDisconnect,
+            Behavior::Garbage,
+        ]
+        .choose(rng)
+        .unwrap()
+    }
+}
+
+/// why a connection ended
+enum Ended {
+    Goodbye,
+    Dropped,
+}
+
+/// a fake client answering the server like pdtclient does, without acting
+/// on any commands
+pub struct SimulatedClient {
+    address: SocketAddr,
+    behavior: Behavior,
+    /// longest delay before answering a message
+    latency: Duration,
+    /// injected into every connection on top of the behavior
+    faults: Faults,
+    rng: StdRng,
+    device_info: DeviceInfo,
+    memory_total: u64,
+    network_received: u64,
+    network_transmitted: u64,
+}
+
+impl SimulatedClient {
+    pub fn new(
+        index: usize,
+        address: SocketAddr,
+        behavior: Behavior,
+        latency: Duration,
+        faults: Faults,
+        mut rng: StdRng,
+    ) -> Self {
+        let (os, versions) = OPERATING_SYSTEMS.choose(&mut rng).unwrap();
+        let (vendor, model, chassis, virtualization) = HARDWARE.choose(&mut rng).unwrap();
+
+        let device_info = DeviceInfo {
+            name: format!("sim-{index:04}"),
+            os: os.to_string(),
+            os_version: versions.choose(&mut rng).unwrap().to_string(),
+            uptime: format!("{}h {}m", rng.gen_range(0..2000), rng.gen_range(0..60)),
+            vendor: vendor.to_string(),
+            model: model.to_string(),
+            serial: format!("SIM{index:05}"),
+            chassis: chassis.to_string(),
+            virtualization: *virtualization,
+        };
+
+        Self {
+            address,
+            behavior,
+            latency,
+            faults,
+            memory_total: MEMORY.choose(&mut rng).unwrap() << 30,
+            network_received: 0,
+            network_transmitted: 0,
+            device_info,
+            rng,
+        }
+    }
+
+    /// connect and answer the server, reconnecting after failures until the
+    /// server says goodbye
+    #[instrument(skip(self), fields(name = self.device_info.name, behavior = ?self.behavior))]
+    pub fn run(mut self) {
+        loop {
+            match self.session() {
+                Ok(Ended::Goodbye) => {
+                    info!("server said goodbye");
+                    return;
+                }
+                Ok(Ended::Dropped) => info!("dropped connection"),
+                Err(error) => warn!(error =? error, "connection failed"),
+            }
+
+            let delay = self.rng.gen_range(RECONNECT_DELAY);
+
+            std::thread::sleep(Duration::from_secs(delay));
+        }
+    }
+
+    /// a single connection, from the introduction until it ends
+    fn session(&mut self) -> Result<Ended, ProtocolError> {
+        let mut stream = Chaos::new(
+            TcpStream::connect(self.address)?,
+            self.faults,
+            self.rng.gen(),
+        );
+
+        Message::from(ServerMessage::Hello(Box::new(ClientIntroduction {
+            name: self.device_info.name.clone(),
+            pdtcore_built_info: BuiltInfo::default(),
+            protocol: ProtocolConfig::default(),
+        })))
+        .send(&mut stream)?;
+
+        let mut countdown = self.rng.gen_range(ACTING_UP);
+
+        loop {
+            if let Behavior::SlowReader = self.behavior {
+                std::thread::sleep(Duration::from_secs(self.rng.gen_range(SLOW_READ)));
+            }
+
+            let message = Message::receive(&mut stream)?;
+
+            countdown -= 1;
+
+            if countdown == 0 {
+                countdown = self.rng.gen_range(ACTING_UP);
+
+                match self.behavior {
+                    Behavior::AbruptDisconnect => {
+                        let _ = stream.get_ref().shutdown(Shutdown::Both);
+                        return Ok(Ended::Dropped);
+                    }
+                    Behavior::Garbage => {
+                        let length = self.rng.gen_range(1..256);
+                        let garbage: Vec<u8> = (0..length).map(|_| self.rng.gen()).collect();
+
+                        stream.write_all(&garbage)?;
+                    }
+                    Behavior::WellBehaved | Behavior::SlowReader => {}
+                }
+            }
+
+            std::thread::sleep(self.latency.mul_f64(self.rng.gen()));
+
+            match message {
+                Message::Client(ClientMessage::Goodbye) => return Ok(Ended::Goodbye),
+                Message::Client(message) => {
+                    if let Some(reply) = self.answer(message) {
+                        Message::from(reply).send(&mut stream)?;
+                    }
+                }
+                Message::Server(_) => {}
+            }
+        }
+    }
+
+    fn answer(&mut self, message: ClientMessage) -> Option<ServerMessage> {
+        match message {
+            ClientMessage::RequestDeviceInfo => {
+                Some(ServerMessage::DeviceInfo(self.device_info.clone()))
+            }
+            ClientMessage::RequestTelemetry => Some(ServerMessage::Telemetry(self.telemetry())),
+            ClientMessage::Traced(trace, message) => {
+                let error = self
+                    .rng
+                    .gen_bool(COMMAND_FAILURE)
+                    .then(|| "simulated failure".to_string());
+
+                Some(ServerMessage::Executed(CommandExecution {
+                    trace,
+                    command: format!("{message:?}"),
+                    error,
+                }))
+            }
+            ClientMessage::Custom { namespace, .. } => Some(ServerMessage::Nack { namespace }),
+            _ => None,
+        }
+    }
+
+    fn telemetry(&mut self) -> Telemetry {
+        self.network_received += self.rng.gen_range(0..10_000_000);
+        self.network_transmitted += self.rng.gen_range(0..1_000_000);
+
+        Telemetry {
+            load: self.rng.gen_range(0.0..4.0),
+            memory_total: self.memory_total,
+            memory_used: self
+                .rng
+                .gen_range(self.memory_total / 10..self.memory_total),
+            network_received: self.network_received,
+            network_transmitted: self.network_transmitted,
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::{collections::HashMap, net::SocketAddr, process::ExitCode, str::FromStr, time::Duration};
+
+mod client;
+
+use client::{Behavior, SimulatedClient};
+use pdtcore::chaos::Faults;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tracing::info;
+use tracing_subscriber::{filter::LevelFilter, prelude::*, EnvFilter, Registry};
+
+const USAGE: &str = "usage: pdtsim [--address ADDRESS] [--clients COUNT] [--failures SHARE]
+              [--latency MILLISECONDS] [--chaos CHANCE] [--seed SEED]
+
+options:
+  --address    pdt server to connect to, defaults to PDT_ADDRESS or 127.0.0.1:2039
+  --clients    number of simulated clients, defaults to 100
+  --failures   share of clients that misbehave, from 0 to 1, defaults to 0.2
+  --latency    longest delay before a client answers, defaults to 500
+  --chaos      chance of delaying, dropping, duplicating or cutting off each
+               message a client reads or writes, from 0 to 1, defaults to 0
+  --seed       seed for device info, latencies and failure modes, random by default
+
+misbehaving clients read slowly, drop their connection abruptly or send
+bytes that are not pdt messages, and reconnect after failing";
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:2039";
+
+/// delay between starting clients, so they do not all connect at once
+const RAMP_UP: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+enum SimError {
+    Usage(String),
+}
+
+impl SimError {
+    fn describe(&self) -> String {
+        match self {
+            SimError::Usage(message) => format!("{message}\n\n{USAGE}"),
+        }
+    }
+}
+
+/// `--name value` options
+struct Arguments {
+    options: HashMap<String, String>,
+    help: bool,
+}
+
+impl Arguments {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, SimError> {
+        let mut options = HashMap::new();
+        let mut help = false;
+
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                help = true;
+                continue;
+            }
+
+            let Some(name) = arg.strip_prefix("--") else {
+                return Err(SimError::Usage(format!("unexpected argument {arg}")));
+            };
+
+            let value = args
+                .next()
+                .ok_or_else(|| SimError::Usage(format!("--{name} needs a value")))?;
+
+            options.insert(name.to_string(), value);
+        }
+
+        Ok(Self { options, help })
+    }
+
+    /// parsed value of an option, `default` when it is not given
+    fn option<T: FromStr>(&self, name: &str, default: T) -> Result<T, SimError> {
+        match self.options.get(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| SimError::Usage(format!("invalid --{name} {value}"))),
+            None => Ok(default),
+        }
+    }
+}
+
+fn setup_tracing() {
+    let layer = tracing_logfmt::builder().with_target(false).layer();
+
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        _ => EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .parse("")
+            .unwrap(),
+    };
+
+    let subscriber = Registry::default().with(layer).with(filter);
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+}
+
+fn run() -> Result<(), SimError> {
+    let arguments = Arguments::parse(std::env::args().skip(1))?;
+
+    if arguments.help {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let default_address = std::env::var("PDT_ADDRESS")
+        .ok()
+        .and_then(|address| SocketAddr::from_str(&address).ok())
+        .unwrap_or_else(|| SocketAddr::from_str(DEFAULT_ADDRESS).unwrap());
+
+    let address: SocketAddr = arguments.option("address", default_address)?;
+    let clients: usize = arguments.option("clients", 100)?;
+    let failures: f64 = arguments.option("failures", 0.2)?;
+    let latency: u64 = arguments.option("latency", 500)?;
+    let chaos: f64 = arguments.option("chaos", 0.0)?;
+    let seed: u64 = arguments.option("seed", rand::random())?;
+
+    if !(0.0..=1.0).contains(&failures) {
+        return Err(SimError::Usage(format!("invalid --failures {failures}")));
+    }
+
+    if !(0.0..=1.0).contains(&chaos) {
+        return Err(SimError::Usage(format!("invalid --chaos {chaos}")));
+    }
+
+    setup_tracing();
+
+    info!(%address, clients, failures, latency, chaos, seed, "starting simulation");
+
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let handles: Vec<_> = (0..clients)
+        .map(|index| {
+            let behavior = if rng.gen_bool(failures) {
+                Behavior::misbehaving(&mut rng)
+            } else {
+                Behavior::WellBehaved
+            };
+
+            let client = SimulatedClient::new(
+                index,
+                address,
+                behavior,
+                Duration::from_millis(latency),
+                Faults::uniform(chaos),
+                StdRng::seed_from_u64(rng.gen()),
+            );
+
+            std::thread::sleep(RAMP_UP);
+
+            std::thread::spawn(move || client.run())
+        })
+        .collect();
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error.describe());
+            ExitCode::FAILURE
+        }
+    }
+}
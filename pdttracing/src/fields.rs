@@ -0,0 +1,42 @@
+//! events written out as single lines, for the records of their own logs the
+//! server and clients keep
+
+use std::fmt::{Debug, Write};
+
+use tracing::{
+    field::{Field, Visit},
+    Event,
+};
+
+/// message of the event followed by its other fields as ` name=value`
+pub fn line(event: &Event<'_>) -> String {
+    let mut fields = Fields::default();
+    event.record(&mut fields);
+
+    fields.message + &fields.others
+}
+
+/// collects the fields of an event into a single line
+#[derive(Default)]
+struct Fields {
+    message: String,
+    others: String,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.others, " {}={value:?}", field.name());
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.others, " {}={value}", field.name());
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! log output and span export set up the same way by the server and clients
+
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, EnvFilter, Layer, Registry};
+
+pub mod fields;
+mod logging;
+pub mod otel;
+
+use logging::LogFormat;
+
+#[derive(Debug)]
+pub enum TracingError {
+    /// neither `--log-format` nor `LOG_FORMAT` is json, logfmt or pretty
+    Format,
+    Filter,
+    /// a global subscriber was set before
+    Subscriber,
+}
+
+/// log in the configured format at the level of `RUST_LOG`, info unless
+/// set, exporting spans with `tracer` when there is one
+///
+/// `layer` sees the same events as the log, like the records a program keeps
+/// to show or forward
+pub fn init<L>(tracer: Option<otel::Tracer>, layer: L) -> Result<(), TracingError>
+where
+    L: Layer<Registry> + Send + Sync + 'static,
+{
+    let format = LogFormat::configured().map_err(|_| TracingError::Format)?;
+
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        _ => EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .parse("")
+            .map_err(|_| TracingError::Filter)?,
+    };
+
+    let subscriber = Registry::default()
+        .with(layer)
+        .with(
+            (format == LogFormat::Logfmt)
+                .then(|| tracing_logfmt::builder().with_target(false).layer()),
+        )
+        .with((format == LogFormat::Json).then(|| tracing_subscriber::fmt::layer().json()))
+        .with((format == LogFormat::Pretty).then(|| tracing_subscriber::fmt::layer().pretty()))
+        .with(filter)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
+
+    tracing::subscriber::set_global_default(subscriber).map_err(|_| TracingError::Subscriber)
+}
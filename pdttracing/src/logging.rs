@@ -0,0 +1,47 @@
+use std::str::FromStr;
+
+/// how log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    Json,
+    #[default]
+    Logfmt,
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(LogFormat::Json),
+            "logfmt" => Ok(LogFormat::Logfmt),
+            "pretty" => Ok(LogFormat::Pretty),
+            _ => Err(()),
+        }
+    }
+}
+
+impl LogFormat {
+    /// the `--log-format` argument, falling back to the `LOG_FORMAT` variable
+    pub fn configured() -> Result<Self, ()> {
+        let mut args = std::env::args().skip(1);
+
+        let argument = loop {
+            match args.next() {
+                Some(arg) if arg == "--log-format" => break Some(args.next().unwrap_or_default()),
+                Some(arg) => {
+                    if let Some(format) = arg.strip_prefix("--log-format=") {
+                        break Some(format.to_string());
+                    }
+                }
+                None => break None,
+            }
+        };
+
+        match argument.or_else(|| std::env::var("LOG_FORMAT").ok()) {
+            Some(format) => format.parse(),
+            None => Ok(LogFormat::default()),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! spans exported over otlp, and the trace context carried by commands and
+//! their results so the server and clients join the same traces
+//!
+//! the exporter is picked by the program, the server batches on its tokio
+//! runtime and clients send over blocking http
+
+use std::collections::HashMap;
+
+use opentelemetry::{propagation::TextMapPropagator, KeyValue};
+use opentelemetry_otlp::{NoExporterConfig, OtlpTracePipeline};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace, Resource};
+use pdtcore::TraceContext;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub use opentelemetry_sdk::trace::Tracer;
+
+const TRACEPARENT: &str = "traceparent";
+
+/// pipeline exporting the spans of `service` when
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` is set, the exporter reads the endpoint and
+/// the other standard variables itself
+pub fn pipeline(service: &'static str) -> Option<OtlpTracePipeline<NoExporterConfig>> {
+    std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT")?;
+
+    Some(
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_trace_config(
+                trace::config()
+                    .with_resource(Resource::new([KeyValue::new("service.name", service)])),
+            ),
+    )
+}
+
+/// export the spans still queued before the program exits
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// trace context of the current span, empty when spans are not exported
+pub fn current() -> TraceContext {
+    let mut carrier = HashMap::new();
+
+    TraceContextPropagator::new().inject_context(&Span::current().context(), &mut carrier);
+
+    TraceContext {
+        traceparent: carrier.remove(TRACEPARENT).unwrap_or_default(),
+    }
+}
+
+/// continue the trace a message was sent in
+pub fn set_parent(span: &Span, trace: &TraceContext) {
+    if trace.traceparent.is_empty() {
+        return;
+    }
+
+    let carrier = HashMap::from([(TRACEPARENT.to_string(), trace.traceparent.clone())]);
+
+    span.set_parent(TraceContextPropagator::new().extract(&carrier));
+}